@@ -7,11 +7,49 @@ pub mod instruction;
 pub mod modrm;
 pub mod io;
 pub mod bios;
+pub mod exception;
+pub mod decode;
+pub mod segment;
+pub mod descriptor;
+pub mod mmu;
+pub mod interrupt;
+pub mod task;
+pub mod pic;
+pub mod pit;
+pub mod clock;
+pub mod uart;
+pub mod vga;
+pub mod disk;
+pub mod keyboard;
+pub mod cmos;
+pub mod elf;
+pub mod linux;
+
+use self::exception::Exception;
+use self::decode::Decode;
+use self::segment::Segment;
+use self::descriptor::DescriptorTable;
+use self::mmu::Tlb;
+use self::pic::Pic;
+use self::pit::Pit;
+use self::clock::Clock;
+use self::io::IoBus;
+use self::uart::{Serial, Stdio};
+use self::vga::{Vga, FRAME_TICKS};
+use self::disk::Disks;
+use self::keyboard::Keyboard;
+use self::cmos::Cmos;
+use self::elf::Symbols;
+use self::linux::Linux;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 pub struct RunFlags {
     pub verbose:    bool,
     pub with_name:  bool,
-    pub quiet:      bool
+    pub quiet:      bool,
+    pub limit:      Option<u64>,
+    pub display:    bool,
 }
 
 #[derive(Debug)] 
@@ -43,6 +81,32 @@ pub enum RegisterHigh {
     BH = RegisterLow::BL as isize + 4,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SegmentRegister {
+    ES,
+    CS,
+    SS,
+    DS,
+    FS,
+    GS,
+    SegmentRegistersCount,
+}
+
+impl fmt::Display for SegmentRegister {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::SegmentRegister::*;
+        match self {
+            ES => write!(f, "ES"),
+            CS => write!(f, "CS"),
+            SS => write!(f, "SS"),
+            DS => write!(f, "DS"),
+            FS => write!(f, "FS"),
+            GS => write!(f, "GS"),
+            SegmentRegistersCount => write!(f, "SegmentRegistersCount"),
+        }
+    }
+}
+
 use self::Register::*;
 impl fmt::Display for Register {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -60,71 +124,211 @@ impl fmt::Display for Register {
     }
 }
 
+/*
+ *  EFLAGS
+ *  +--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+--+
+ *  |21|20|19|18|17|16|15|14|13|12|11|10| 9| 8| 7| 6| 5| 4| 3| 2| 1| 0|
+ *  +--+--+--+--+--+--+--+--+-----+--+--+--+--+--+--+--+--+--+--+--+--+
+ *  |ID|VP|VF|AC|VM|RF| 0|NT|IOPL |OF|DF|IF|TF|SF|ZF| 0|AF| 0|PF| 1|CF|
+ *  +--+--+--+--+--+--+--+--+-----+--+--+--+--+--+--+--+--+--+--+--+--+
+ */
+pub const CARRY_FLAG: u32       = 1;
+pub const RESERVED_FLAG: u32    = 1 << 1;
+pub const PARITY_FLAG: u32      = 1 << 2;
+pub const AUX_CARRY_FLAG: u32   = 1 << 4;
+pub const ZERO_FLAG: u32        = 1 << 6;
+pub const SIGN_FLAG: u32        = 1 << 7;
+pub const TRAP_FLAG: u32        = 1 << 8;
+pub const INTERRUPT_FLAG: u32   = 1 << 9;
+pub const DIRECTION_FLAG: u32   = 1 << 10;
+pub const OVERFLOW_FLAG: u32    = 1 << 11;
+pub const IOPL_MASK: u32        = 3 << 12;
+pub const NESTED_TASK_FLAG: u32 = 1 << 14;
+pub const VIRTUAL_8086_FLAG: u32 = 1 << 17;
+
+/// Bits software can actually change. Bits 3, 5, 15 and 22-31 always read as 0.
+const EFLAGS_WRITABLE: u32 = 0x003F7FD5;
+
+/*
+ *  CR0
+ *  +--+--+--+-----+--+--+--+-----+--+--+--+--+--+--+
+ *  |31|30|29|28-19|18|17|16|15-6 | 5| 4| 3| 2| 1| 0|
+ *  +--+--+--+-----+--+--+--+-----+--+--+--+--+--+--+
+ *  |PG|CD|NW|  0  |AM| 0|WP|  0  |NE|ET|TS|EM|MP|PE|
+ *  +--+--+--+-----+--+--+--+-----+--+--+--+--+--+--+
+ *  CR2 is the faulting address of a #PF, CR3 the page directory, CR4 the extensions.
+ */
+pub const CR0_PE: u32 = 1;
+pub const CR0_MP: u32 = 1 << 1;
+pub const CR0_EM: u32 = 1 << 2;
+pub const CR0_TS: u32 = 1 << 3;
+pub const CR0_ET: u32 = 1 << 4;
+pub const CR0_NE: u32 = 1 << 5;
+pub const CR0_WP: u32 = 1 << 16;
+pub const CR0_AM: u32 = 1 << 18;
+pub const CR0_NW: u32 = 1 << 29;
+pub const CR0_CD: u32 = 1 << 30;
+pub const CR0_PG: u32 = 1 << 31;
+pub const CR4_PSE: u32 = 1 << 4;
+
 #[derive(Debug, Clone)]
 pub struct Eflags {
     pub raw: u32
 }
 
+impl Default for Eflags {
+    fn default() -> Eflags {
+        Eflags::new()
+    }
+}
+
 impl Eflags {
-    pub fn set_carry(&mut self, is_carry: bool) {
-        let carry = 1;
-        if is_carry {
-            self.raw |= carry;
+    /// The value after reset: everything cleared except the always-one bit 1.
+    pub fn new() -> Eflags {
+        Eflags { raw: RESERVED_FLAG }
+    }
+
+    /// The architectural value, as seen by PUSHF.
+    pub fn value(&self) -> u32 {
+        (self.raw & EFLAGS_WRITABLE) | RESERVED_FLAG
+    }
+
+    /// Load the whole register, as POPF does. Reserved bits are forced to their fixed values.
+    pub fn set_value(&mut self, value: u32) {
+        self.raw = (value & EFLAGS_WRITABLE) | RESERVED_FLAG;
+    }
+
+    fn set_flag(&mut self, flag: u32, is_set: bool) {
+        if is_set {
+            self.raw |= flag;
         } else {
-            self.raw &= !carry;
+            self.raw &= !flag;
         }
     }
 
+    fn is_flag(&self, flag: u32) -> bool {
+        self.raw & flag != 0
+    }
+
+    pub fn set_carry(&mut self, is_carry: bool) {
+        self.set_flag(CARRY_FLAG, is_carry);
+    }
+
+    pub fn set_parity(&mut self, is_parity: bool) {
+        self.set_flag(PARITY_FLAG, is_parity);
+    }
+
+    pub fn set_aux_carry(&mut self, is_aux_carry: bool) {
+        self.set_flag(AUX_CARRY_FLAG, is_aux_carry);
+    }
+
     pub fn set_zero(&mut self, is_zero: bool) {
-        let zero = 1 << 6;
-        if is_zero {
-            self.raw |= zero;
-        } else {
-            self.raw &= !zero;
-        }
+        self.set_flag(ZERO_FLAG, is_zero);
     }
 
     pub fn set_sign(&mut self, is_sign: bool) {
-        let sign = 1 << 7;
-        if is_sign {
-            self.raw |= sign;
-        } else {
-            self.raw &= !sign;
-        }
+        self.set_flag(SIGN_FLAG, is_sign);
+    }
+
+    pub fn set_trap(&mut self, is_trap: bool) {
+        self.set_flag(TRAP_FLAG, is_trap);
+    }
+
+    pub fn set_interrupt(&mut self, is_interrupt: bool) {
+        self.set_flag(INTERRUPT_FLAG, is_interrupt);
+    }
+
+    pub fn set_direction(&mut self, is_direction: bool) {
+        self.set_flag(DIRECTION_FLAG, is_direction);
     }
 
     pub fn set_overflow(&mut self, is_overflow: bool) {
-        let overflow = 1 << 11;
-        if is_overflow {
-            self.raw |= overflow;
-        } else {
-            self.raw &= !overflow;
-        }
+        self.set_flag(OVERFLOW_FLAG, is_overflow);
+    }
+
+    pub fn set_iopl(&mut self, iopl: u8) {
+        self.raw = (self.raw & !IOPL_MASK) | (((iopl & 3) as u32) << 12);
+    }
+
+    pub fn set_nested_task(&mut self, is_nested_task: bool) {
+        self.set_flag(NESTED_TASK_FLAG, is_nested_task);
     }
 
     pub fn is_carry(&self) -> bool {
-        self.raw & 1 != 0
+        self.is_flag(CARRY_FLAG)
+    }
+
+    pub fn is_parity(&self) -> bool {
+        self.is_flag(PARITY_FLAG)
+    }
+
+    pub fn is_aux_carry(&self) -> bool {
+        self.is_flag(AUX_CARRY_FLAG)
     }
 
     pub fn is_zero(&self) -> bool {
-        self.raw & (1 << 6) != 0
+        self.is_flag(ZERO_FLAG)
     }
 
     pub fn is_sign(&self) -> bool {
-        self.raw & (1 << 7) != 0
+        self.is_flag(SIGN_FLAG)
+    }
+
+    pub fn is_trap(&self) -> bool {
+        self.is_flag(TRAP_FLAG)
+    }
+
+    pub fn is_interrupt(&self) -> bool {
+        self.is_flag(INTERRUPT_FLAG)
+    }
+
+    pub fn is_direction(&self) -> bool {
+        self.is_flag(DIRECTION_FLAG)
     }
 
     pub fn is_overflow(&self) -> bool {
-        self.raw & (1 << 11) != 0
+        self.is_flag(OVERFLOW_FLAG)
+    }
+
+    pub fn iopl(&self) -> u8 {
+        ((self.raw & IOPL_MASK) >> 12) as u8
+    }
+
+    pub fn is_nested_task(&self) -> bool {
+        self.is_flag(NESTED_TASK_FLAG)
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Emulator {
     pub registers: [u32; Register::RegistersCount as usize],
     pub eflags: Eflags,
     pub memory: Vec<u8>,
     pub eip: u32,
+    pub segments: [Segment; SegmentRegister::SegmentRegistersCount as usize],
+    pub cr: [u32; 5],
+    pub gdtr: DescriptorTable,
+    pub idtr: DescriptorTable,
+    pub tr: Segment,
+    pub tlb: Tlb,
+    pub halted: bool,
+    pub interrupt_shadow: bool,         // STI or a load of SS holds interrupts off for one instruction
+    pub exception: Option<Exception>,
+    pub shutdown: Option<Exception>,    // the exception that couldn't be delivered (triple fault)
+    pub pic: Pic,
+    pub pit: Pit,
+    pub clock: Clock,
+    pub io: IoBus,
+    pub serial: Serial,
+    pub vga: Vga,
+    pub disks: Disks,
+    pub keyboard: Keyboard,
+    pub cmos: Cmos,
+    pub system_control: u8,             // port 0x92: the fast A20 gate
+    pub symbols: Symbols,
+    pub linux: Option<Linux>,          // a Linux program's process, whose INT 0x80 the emulator serves
+    pub decode: Decode,
+    pub instruction_count: u64,
 }
 
 const ORG: usize = 0x7C00;
@@ -143,6 +347,21 @@ impl fmt::Display for Emulator {
         \t\tEBP: 0x{EBP:08X}\n\
         \t\tESI: 0x{ESI:08X}\n\
         \t\tEDI: 0x{EDI:08X}\n\
+        \tsegments\n\
+        \t\tES:  0x{ES:04X}\n\
+        \t\tCS:  0x{CS:04X}\n\
+        \t\tSS:  0x{SS:04X}\n\
+        \t\tDS:  0x{DS:04X}\n\
+        \t\tFS:  0x{FS:04X}\n\
+        \t\tGS:  0x{GS:04X}\n\
+        \tcontrol registers\n\
+        \t\tCR0: 0x{CR0:08X}\n\
+        \t\tCR2: 0x{CR2:08X}\n\
+        \t\tCR3: 0x{CR3:08X}\n\
+        \t\tCR4: 0x{CR4:08X}\n\
+        \tgdtr:    0x{gdtr_base:08X} limit 0x{gdtr_limit:04X}\n\
+        \tidtr:    0x{idtr_base:08X} limit 0x{idtr_limit:04X}\n\
+        \ttr:      0x{tr:04X}\n\
         \teflags:  0x{eflags:08X}\n\
         \tmemory:  {memory}\n\
         \teip:     0x{eip:08X}\n",
@@ -155,7 +374,22 @@ impl fmt::Display for Emulator {
         EBP=self.registers[EBP as usize],
         ESI=self.registers[ESI as usize],
         EDI=self.registers[EDI as usize],
-        eflags=self.eflags.raw,
+        ES=self.get_segment(SegmentRegister::ES).selector,
+        CS=self.get_segment(SegmentRegister::CS).selector,
+        SS=self.get_segment(SegmentRegister::SS).selector,
+        DS=self.get_segment(SegmentRegister::DS).selector,
+        FS=self.get_segment(SegmentRegister::FS).selector,
+        GS=self.get_segment(SegmentRegister::GS).selector,
+        CR0=self.cr[0],
+        CR2=self.cr[2],
+        CR3=self.cr[3],
+        CR4=self.cr[4],
+        gdtr_base=self.gdtr.base,
+        gdtr_limit=self.gdtr.limit,
+        idtr_base=self.idtr.base,
+        idtr_limit=self.idtr.limit,
+        tr=self.tr.selector,
+        eflags=self.eflags.value(),
         memory="<Ommited>",
         eip=self.eip);
 
@@ -164,8 +398,9 @@ impl fmt::Display for Emulator {
 }
 
 impl Emulator {
+    /// The terminal is on COM1.
     pub fn new(size: usize, eip: u32, esp: u32) -> Emulator {
-        Emulator {
+        let mut emu = Emulator {
             registers: [
                 /* EAX */ 0,
                 /* ECX */ 0,
@@ -176,129 +411,209 @@ impl Emulator {
                 /* ESI */ 0,
                 /* EDI */ 0
             ],
-            eflags: Eflags { raw: 0 },
+            eflags: Eflags::new(),
             memory: Vec::with_capacity(ORG + size),
-            eip: eip,
-        }
-    }
-
-    pub fn load(&mut self, file: &mut std::fs::File) {
-        use std::io::{Read};
+            eip,
+            segments: Default::default(),
+            // flat protected mode, as left behind by a loader
+            cr: [CR0_PE | CR0_ET, 0, 0, 0, 0],
+            gdtr: DescriptorTable::default(),
+            idtr: DescriptorTable::default(),
+            tr: Segment::unusable(0),
+            tlb: Tlb::default(),
+            halted: false,
+            interrupt_shadow: false,
+            exception: None,
+            shutdown: None,
+            pic: Pic::default(),
+            pit: Pit::default(),
+            clock: Clock::default(),
+            io: IoBus::default(),
+            serial: Serial::default(),
+            vga: Vga::default(),
+            disks: Disks::default(),
+            keyboard: Keyboard::default(),
+            cmos: Cmos::default(),
+            system_control: 0,
+            symbols: Symbols::default(),
+            linux: None,
+            decode: Decode::default(),
+            instruction_count: 0,
+        };
+        emu.serial.ports[0].attach(Rc::new(RefCell::new(Stdio::default())));
+        emu
+    }
+
+    pub fn load<R: std::io::Read>(&mut self, file: &mut R) {
         let mut bios = [0; ORG].to_vec();
         self.memory.append(&mut bios);
 
         let mut buf = Vec::new();
         file.read_to_end(&mut buf).expect("Can't read file");
         self.raw_load(&mut buf);
+
+        // the program may use any memory, not just the bytes it was loaded into
+        let size = self.memory.capacity();
+        self.memory.resize(size, 0);
     }
 
     pub fn raw_load(&mut self, bytes: &mut Vec<u8>) {
         self.memory.append(bytes);
     }
 
-    pub fn run(&self, flag: RunFlags) {
-        if flag.quiet {
-            self.quiet();
-        } else if flag.verbose {
-            self.verbose();
-        } else if flag.with_name {
-            self.with_name();
-        } else {
-            self.default();
-        }
-    }
-
-    fn quiet(&self) {
-        let mut emu = self.to_owned();  
+    /// Returns the exit status of a Linux program, 128 and a signal number if it stopped without exiting.
+    pub fn run(&self, flag: RunFlags) -> Option<i32> {
+        let mut emu = self.to_owned();
+        let mut next_frame = 0;
+        // every step counts against the limit, halted ones and BIOS services too, so it always ends the run
+        let mut steps = 0;
+        // a Linux program that stops without exiting gets the status of the signal Linux would have killed it with
+        let mut signal = linux::SIGSEGV;
         while (emu.eip as usize) < (emu.memory.capacity()) {
-            let code = emu.get_code8(0);
-            
-            if let Some(inst) = instruction::instructions(code) {
-                inst(&mut emu);
-            } else {
-                eprintln!("{}", format!("Not implimented: 0x{:X}", code).red());
-                break;
+            if let Some(limit) = flag.limit {
+                if steps >= limit {
+                    eprintln!("{}", format!("Instruction limit reached: {}", limit).red());
+                    signal = linux::SIGXCPU;
+                    break;
+                }
             }
-        
-            if emu.eip == 0x00 {
-                break;
+
+            if !flag.quiet {
+                match emu.peek_code8() {
+                    Some(code) => println!("EIP = 0x{:X}, Code = 0x{:X}", emu.eip, code),
+                    None => println!("EIP = 0x{:X}, Code = ??", emu.eip),
+                }
             }
-        }
-    }
 
-    fn verbose(&self) {
-        let mut emu = self.to_owned();
-        while (emu.eip as usize) < (emu.memory.capacity()) {
-            let code = emu.get_code8(0);
+            steps += 1;
+            match emu.execute() {
+                Ok(_) if flag.quiet => (),
+                Ok(name) if flag.verbose => {
+                    println!("\t - {}", name);
+                    println!("{}", emu);
+                },
+                Ok(name) if flag.with_name => eprintln!("\t - {}", name.bold()),
+                Ok(_) => (),
+                Err(code) => {
+                    eprintln!("{}", format!("Not implimented: 0x{:X} at EIP = {}", code, emu.symbolize(emu.eip)).red());
+                    signal = linux::SIGILL;
+                    break;
+                },
+            }
 
-            println!("EIP = 0x{:X}, Code = 0x{:X}", emu.eip, code);
-            
-            let iwn = instruction::instructions_with_name(code);
+            if flag.display && emu.clock.pit_ticks() >= next_frame {
+                next_frame = emu.clock.pit_ticks() + FRAME_TICKS;
+                if emu.vga.take_dirty() {
+                    let _ = emu.vga.render(&mut std::io::stdout());
+                }
+            }
 
-            if let Some(inst) = iwn.0 {
-                inst(&mut emu);
-                println!("\t - {}", iwn.1);
-                println!("{}", emu);
-            } else {
-                eprintln!("{}", format!("Not implimented: 0x{:X}", code).red());
+            if let Some(exception) = emu.shutdown {
+                eprintln!("{}", format!("{} at EIP = {}", exception, emu.symbolize(emu.eip)).red());
+                eprintln!("{}", "Triple fault, shutting down.".red());
+                signal = linux::fault_signal(exception);
                 break;
             }
-        
-            if emu.eip == 0x00 {
-                println!("\nEnd of program.\n");
+
+            if let Some(status) = emu.linux.as_ref().and_then(|linux| linux.exit_status) {
+                if !flag.quiet {
+                    println!("\nExited with status {}.\n", status);
+                }
                 break;
             }
-        }
-
-        emu.dump_verbose();
 
-    }
-
-    fn with_name(&self) {
-        let mut emu = self.to_owned();  
-        while (emu.eip as usize) < (emu.memory.capacity()) {
-            let code = emu.get_code8(0);
-            println!("EIP = 0x{:X}, Code = 0x{:X}", emu.eip, code);
-            
-            let iwn = instruction::instructions_with_name(code);
-
-            if let Some(inst) = iwn.0 {
-                inst(&mut emu);
-                eprintln!("\t - {}", format!("{}", iwn.1).bold());
-            } else {
-                eprintln!("{}", format!("Not implimented: 0x{:X}", code).red());
+            if emu.halted && !emu.can_wake() {
+                if !flag.quiet {
+                    println!("\nHalted.\n");
+                }
                 break;
             }
-        
-            if emu.eip == 0x00 {
-                println!("\nEnd of program.\n");
+
+            if emu.is_end_of_program() {
+                if !flag.quiet {
+                    println!("\nEnd of program.\n");
+                }
                 break;
             }
         }
 
-        emu.dump();
-    }
+        if flag.display {
+            let _ = emu.vga.render(&mut std::io::stdout());
+        }
 
-    fn default(&self) {
-        let mut emu = self.to_owned();  
-        while (emu.eip as usize) < (emu.memory.capacity()) {
-            let code = emu.get_code8(0);
-            println!("EIP = 0x{:X}, Code = 0x{:X}", emu.eip, code);
-            
-            if let Some(inst) = instruction::instructions(code) {
-                inst(&mut emu);
+        if !flag.quiet {
+            if flag.verbose {
+                emu.dump_verbose();
             } else {
-                eprintln!("{}", format!("Not implimented: 0x{:X}", code).red());
-                break;
-            }
-        
-            if emu.eip == 0x00 {
-                println!("\nEnd of program.\n");
-                break;
+                emu.dump();
             }
         }
 
-        emu.dump();
+        emu.linux.map(|linux| linux.exit_status.unwrap_or(128 + signal))
+    }
+
+    /// A flat program that returns off its stack lands at address 0, where there's no code but the IVT.
+    /// A CS with a base, as after `jmp 0800:0000`, is the start of something instead.
+    pub fn is_end_of_program(&self) -> bool {
+        self.eip == 0 && self.get_segment(SegmentRegister::CS).base == 0
+    }
+
+    /*
+     * One instruction as the CPU runs it.
+     * A pending external interrupt is taken first, unless the last instruction holds it off,
+     * and a halted CPU does nothing else until one arrives.
+     * A BIOS entry point is served instead of fetched,
+     * and an instruction that raises an exception is rolled back and the exception delivered.
+     * Either way, and while halted too, the virtual clock moves on by one instruction.
+     */
+    pub fn execute(&mut self) -> Result<&'static str, u8> {
+        let shadow = self.interrupt_shadow;
+        self.interrupt_shadow = false;
+        if !shadow && self.interrupt_pending() {
+            self.external_interrupt();
+        }
+
+        let result = if self.halted {
+            Ok("hlt")
+        } else if self.bios_entry() {
+            Ok("bios")
+        } else {
+            let context = self.save_context();
+            let result = self.step();
+            if let Some(exception) = self.exception.take() {
+                self.restore_context(&context);
+                self.deliver_exception(exception);
+            }
+            result
+        };
+
+        self.advance_clock(self.clock.cycles_per_instruction);
+        result
+    }
+
+    /*
+     * One instruction: the prefix bytes first, then the opcode they apply to.
+     * A REP string instruction executes a single iteration per step.
+     * An exception it raises is left pending in `exception`, see `execute`.
+     * Returns the name of the executed instruction, or the opcode that isn't implemented.
+     */
+    pub fn step(&mut self) -> Result<&'static str, u8> {
+        self.decode_prefix();
+        let result = if self.exception.is_some() {
+            Ok("prefix")
+        } else {
+            let code = self.get_code8(0);
+            match instruction::instructions_with_name(code) {
+                (Some(inst), name) => {
+                    inst(self);
+                    self.instruction_count += 1;
+                    Ok(name)
+                },
+                (None, _) => Err(code),
+            }
+        };
+        self.decode.fetching = false;
+        result
     }
 
     pub fn dump(&self) {
@@ -312,38 +627,86 @@ impl Emulator {
      * Emulator instructions
      */
 
+    /*
+     * `addr` is a linear address, translated by the MMU with the privilege of the current CPL.
+     * Writes are dropped while an exception is pending, see `exception`.
+     */
     pub fn set_memory8(&mut self, addr: u32, value: u32) {
-        self.memory[addr as usize] = (value & 0xFF) as u8;
+        let user = self.cpl() == 3;
+        self.write_linear8(addr, (value & 0xFF) as u8, user);
     }
     
-    pub fn get_memory8(&self, addr: u32) -> u8 {
-        self.memory[addr as usize]
+    pub fn get_memory8(&mut self, addr: u32) -> u8 {
+        let user = self.cpl() == 3;
+        self.read_linear8(addr, user)
     }
     
+    pub fn set_memory16(&mut self, addr: u32, value: u32) {
+        for i in 0..2 {
+            self.set_memory8(addr.wrapping_add(i), value >> (i * 8));
+        }
+    }
+
+    pub fn get_memory16(&mut self, addr: u32) -> u16 {
+        let mut ret: u16 = 0;
+        for i in 0..2 {
+            ret |= (self.get_memory8(addr.wrapping_add(i)) as u16) << (i * 8);
+        }
+
+        ret
+    }
+
     pub fn set_memory32(&mut self, addr: u32, value: u32) {
         for i in 0..4 {
-            self.set_memory8(addr + i, value >> (i * 8));
+            self.set_memory8(addr.wrapping_add(i), value >> (i * 8));
         }
     }
     
-    pub fn get_memory32(&self, addr: u32) -> u32 {
+    pub fn get_memory32(&mut self, addr: u32) -> u32 {
         let mut ret: u32 = 0;
         for i in 0..4 {
-            ret |= (self.get_memory8(addr + i) as u32) << (i * 8);
+            ret |= (self.get_memory8(addr.wrapping_add(i)) as u32) << (i * 8);
         }
     
         ret
     }
 
-    pub fn get_code8(&self, index: u32) -> u8 {
-        self.memory[(self.eip + index) as usize] as u8
+    /*
+     * Instruction bytes are fetched from CS:EIP. A byte past the CS limit in protected mode,
+     * or more than 15 bytes into the instruction, raises #GP(0).
+     */
+    pub fn get_code8(&mut self, index: u32) -> u8 {
+        let offset = self.eip.wrapping_add(index);
+        let length = offset.wrapping_sub(self.decode.eip).wrapping_add(1);
+        if (self.decode.fetching && length > decode::MAX_INSTRUCTION_LENGTH)
+            || (self.is_protected_mode() && !self.get_segment(SegmentRegister::CS).contains(offset, 1)) {
+            self.raise(exception::Exception::GeneralProtection(0));
+            return 0;
+        }
+        let addr = self.linear_address(SegmentRegister::CS, offset);
+        self.get_memory8(addr)
+    }
+
+    /// The opcode byte at CS:EIP for tracing, without touching the MMU state.
+    pub fn peek_code8(&self) -> Option<u8> {
+        let linear = self.linear_address(SegmentRegister::CS, self.eip);
+        self.peek_translate(linear).map(|addr| self.read_physical8(addr))
     }
 
-    pub fn get_sign_code8(&self, index: u32) -> i8 {
-        self.memory[(self.eip + index) as usize] as i8
+    pub fn get_sign_code8(&mut self, index: u32) -> i8 {
+        self.get_code8(index) as i8
     }
 
-    pub fn get_code32(&self, index: u32) -> u32 {
+    pub fn get_code16(&mut self, index: u32) -> u16 {
+        let mut ret: u16 = 0;
+        for i in 0..2 {
+            ret |= (self.get_code8(index + i) as u16) << (i * 8);
+        }
+
+        ret
+    }
+
+    pub fn get_code32(&mut self, index: u32) -> u32 {
         let mut ret: u32 = 0;
         for i in 0..4 {
             ret |= (self.get_code8(index + i) as u32) << (i * 8);
@@ -352,7 +715,7 @@ impl Emulator {
         ret
     }
 
-    pub fn get_sign_code32(&self, index: u32) -> i32 {
+    pub fn get_sign_code32(&mut self, index: u32) -> i32 {
         self.get_code32(index) as i32
     }
 
@@ -366,6 +729,11 @@ impl Emulator {
         }
     }
 
+    pub fn set_register16(&mut self, index: usize, value: u16) {
+        let r = self.registers[index] & 0xFFFF0000;
+        self.registers[index] = r | value as u32;
+    }
+
     pub fn set_register32(&mut self, index: usize, value: u32) {
         self.registers[index] = value;
     }
@@ -378,32 +746,212 @@ impl Emulator {
         }
     }
     
+    pub fn get_register16(&self, index: usize) -> u16 {
+        (self.registers[index] & 0xFFFF) as u16
+    }
+
     pub fn get_register32(&self, index: usize) -> u32 {
         self.registers[index]
     }
 
+    /*
+     * Operand-size ("v") accessors.
+     * They work on 16 or 32 bits depending on the operand size of the current instruction,
+     * so one handler serves both the r/m16 and r/m32 form of an opcode.
+     * The default size comes from CS and the 0x66/0x67 prefixes switch to the other one.
+     */
+    pub fn operand_bits(&self) -> u32 {
+        if self.get_segment(SegmentRegister::CS).big != self.decode.operand_size {
+            32
+        } else {
+            16
+        }
+    }
+
+    pub fn operand_bytes(&self) -> u32 {
+        self.operand_bits() / 8
+    }
+
+    pub fn address_bits(&self) -> u32 {
+        if self.get_segment(SegmentRegister::CS).big != self.decode.address_size {
+            32
+        } else {
+            16
+        }
+    }
+
+    pub fn get_registerv(&self, index: usize) -> u32 {
+        match self.operand_bits() {
+            16 => self.get_register16(index) as u32,
+            _ => self.get_register32(index),
+        }
+    }
+
+    pub fn set_registerv(&mut self, index: usize, value: u32) {
+        match self.operand_bits() {
+            16 => self.set_register16(index, value as u16),
+            _ => self.set_register32(index, value),
+        }
+    }
+
+    pub fn get_memoryv(&mut self, addr: u32) -> u32 {
+        match self.operand_bits() {
+            16 => self.get_memory16(addr) as u32,
+            _ => self.get_memory32(addr),
+        }
+    }
+
+    pub fn set_memoryv(&mut self, addr: u32, value: u32) {
+        match self.operand_bits() {
+            16 => self.set_memory16(addr, value),
+            _ => self.set_memory32(addr, value),
+        }
+    }
+
+    pub fn get_codev(&mut self, index: u32) -> u32 {
+        match self.operand_bits() {
+            16 => self.get_code16(index) as u32,
+            _ => self.get_code32(index),
+        }
+    }
+
+    pub fn get_sign_codev(&mut self, index: u32) -> i32 {
+        match self.operand_bits() {
+            16 => self.get_code16(index) as i16 as i32,
+            _ => self.get_sign_code32(index),
+        }
+    }
+
+    pub fn pushv(&mut self, value: u32) {
+        match self.operand_bits() {
+            16 => self.push16(value as u16),
+            _ => self.push32(value),
+        }
+    }
+
+    pub fn popv(&mut self) -> u32 {
+        match self.operand_bits() {
+            16 => self.pop16() as u32,
+            _ => self.pop32(),
+        }
+    }
+
+    /*
+     * The stack lives at SS:ESP, or SS:SP when SS is a 16-bit segment.
+     */
+    pub fn push16(&mut self, value: u16) {
+        let sp = self.get_stack_pointer().wrapping_sub(2);
+        self.set_stack_pointer(sp);
+        let addr = self.segment_address(SegmentRegister::SS, self.get_stack_pointer(), 2, true);
+        self.set_memory16(addr, value as u32);
+    }
+
+    pub fn pop16(&mut self) -> u16 {
+        let sp = self.get_stack_pointer();
+        let addr = self.segment_address(SegmentRegister::SS, sp, 2, false);
+        let ret = self.get_memory16(addr);
+        self.set_stack_pointer(sp.wrapping_add(2));
+        ret
+    }
+
     pub fn push32(&mut self, value: u32) {
-        let addr = self.get_register32(ESP as usize) - 4;
-        self.set_register32(ESP as usize, addr);
+        let sp = self.get_stack_pointer().wrapping_sub(4);
+        self.set_stack_pointer(sp);
+        let addr = self.segment_address(SegmentRegister::SS, self.get_stack_pointer(), 4, true);
         self.set_memory32(addr, value);
     }
 
     pub fn pop32(&mut self) -> u32 {
-        let addr = self.get_register32(ESP as usize);
+        let sp = self.get_stack_pointer();
+        let addr = self.segment_address(SegmentRegister::SS, sp, 4, false);
         let ret = self.get_memory32(addr);
-        self.set_register32(ESP as usize, addr + 4);
+        self.set_stack_pointer(sp.wrapping_add(4));
         ret
     }
 
     pub fn update_eflags_sub(&mut self, v1: u32, v2: u32, result: u64) {
-        let sign1 = v1 >> 31;
-        let sign2 = v2 >> 31;
-        let signr = (result >> 31) as u32 & 1;
+        self.update_eflags_sub_sized(v1, v2, result, 32);
+    }
+
+    pub fn update_eflags_add(&mut self, v1: u32, v2: u32, result: u64) {
+        self.update_eflags_add_sized(v1, v2, result, 32);
+    }
 
-        self.eflags.set_carry(result >> 32 != 0);
-        self.eflags.set_zero(result == 0);
-        self.eflags.set_sign(signr != 0);
+    pub fn update_eflags_logic(&mut self, result: u32) {
+        self.update_eflags_logic_sized(result, 32);
+    }
+
+    /*
+     * `bits` is the operand width (8, 16 or 32).
+     * `result` is the untruncated result, so anything above `bits` is the carry/borrow.
+     */
+    pub fn update_eflags_sub_sized(&mut self, v1: u32, v2: u32, result: u64, bits: u32) {
+        let sign1 = (v1 >> (bits - 1)) & 1;
+        let sign2 = (v2 >> (bits - 1)) & 1;
+        let signr = (result >> (bits - 1)) as u32 & 1;
+
+        self.eflags.set_carry(result >> bits != 0);
+        self.update_eflags_result(result as u32, bits);
+        self.eflags.set_aux_carry((v1 ^ v2 ^ result as u32) & 0x10 != 0);
         self.eflags.set_overflow(sign1 != sign2 && sign1 != signr);
     }
+
+    pub fn update_eflags_add_sized(&mut self, v1: u32, v2: u32, result: u64, bits: u32) {
+        let sign1 = (v1 >> (bits - 1)) & 1;
+        let sign2 = (v2 >> (bits - 1)) & 1;
+        let signr = (result >> (bits - 1)) as u32 & 1;
+
+        self.eflags.set_carry(result >> bits != 0);
+        self.update_eflags_result(result as u32, bits);
+        self.eflags.set_aux_carry((v1 ^ v2 ^ result as u32) & 0x10 != 0);
+        self.eflags.set_overflow(sign1 == sign2 && sign1 != signr);
+    }
+
+    pub fn update_eflags_logic_sized(&mut self, result: u32, bits: u32) {
+        self.eflags.set_carry(false);
+        self.update_eflags_result(result, bits);
+        self.eflags.set_aux_carry(false);
+        self.eflags.set_overflow(false);
+    }
+
+    /// INC leaves CF untouched.
+    pub fn update_eflags_inc_sized(&mut self, v1: u32, result: u32, bits: u32) {
+        self.update_eflags_result(result, bits);
+        self.eflags.set_aux_carry((v1 ^ 1 ^ result) & 0x10 != 0);
+        self.eflags.set_overflow(v1 & mask(bits) == mask(bits) >> 1);
+    }
+
+    /// DEC leaves CF untouched.
+    pub fn update_eflags_dec_sized(&mut self, v1: u32, result: u32, bits: u32) {
+        self.update_eflags_result(result, bits);
+        self.eflags.set_aux_carry((v1 ^ 1 ^ result) & 0x10 != 0);
+        self.eflags.set_overflow(v1 & mask(bits) == (mask(bits) >> 1) + 1);
+    }
+
+    /*
+     * Shifts and rotates compute CF and OF themselves, since both depend on the operation.
+     * AF is undefined after a shift and is left alone.
+     */
+    pub fn update_eflags_shift_sized(&mut self, result: u32, carry: bool, overflow: bool, bits: u32) {
+        self.eflags.set_carry(carry);
+        self.update_eflags_result(result, bits);
+        self.eflags.set_overflow(overflow);
+    }
+
+    /// ZF, SF and PF, which only depend on the result.
+    pub fn update_eflags_result(&mut self, result: u32, bits: u32) {
+        self.eflags.set_zero(result & mask(bits) == 0);
+        self.eflags.set_sign((result >> (bits - 1)) & 1 != 0);
+        self.eflags.set_parity((result as u8).count_ones() & 1 == 0);
+    }
+}
+
+/// All-ones mask for an operand of `bits` width.
+pub fn mask(bits: u32) -> u32 {
+    if bits >= 32 {
+        0xFFFFFFFF
+    } else {
+        (1 << bits) - 1
+    }
 }
 
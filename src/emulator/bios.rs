@@ -1,33 +1,137 @@
 use super::*;
-use crate::emulator::io;
-use crate::emulator::RegisterLow::*;
-use crate::emulator::RegisterHigh::*;
+use crate::emulator::segment::Segment;
+use crate::emulator::SegmentRegister::*;
+use crate::emulator::cmos::STATUS_C;
 
-const BIOS_TO_TERMINAL: [i32;8] = [30, 34, 32, 36, 31, 35, 33, 37];
+pub mod video;
+pub mod disk;
+pub mod keyboard;
+pub mod system;
+pub mod time;
+pub mod boot;
 
-fn put_string(s: &str) {
-    s.bytes().for_each(move |c| io::io_out8(0x03F8, c));
-}
+/*
+ * The real mode IVT points every vector at an entry point in the BIOS segment, F000:vector.
+ * There's no BIOS code behind them: reaching one runs the service in Rust
+ * and returns to the caller the way the IRET at the end of a real handler would.
+ * A program can still hook a vector by replacing its IVT entry, and chain to the old one.
+ */
+pub const BIOS_SEGMENT: u16 = 0xF000;
+
+const BDA_TIMER_TICKS: u32 = 0x46C;
+const BDA_MIDNIGHT: u32    = 0x470;
+const TICKS_PER_DAY: u32   = 0x1800B0;
 
 impl Emulator {
-    fn bios_video_teletype(&mut self) {
-        let color: u8 = self.get_register8(BL as usize) & 0x0F;
-        let ch: u8 = self.get_register8(AL as usize);
+    /// BIOS console output goes to whatever is connected to COM1, past the UART the guest drives.
+    fn put_char(&mut self, ch: u8) {
+        self.serial.ports[0].send(ch);
+    }
+
+    /// What the BIOS sets up before it boots: the IVT and the BIOS data area, after the disks are attached.
+    pub fn install_bios(&mut self) {
+        self.install_ivt();
+        self.video_init();
+        self.disk_init();
+        self.keyboard_init();
+        self.system_init();
+        self.time_init();
+    }
+
+    pub fn install_ivt(&mut self) {
+        for vector in 0..=0xFF {
+            self.write_physical32(vector << 2, ((BIOS_SEGMENT as u32) << 16) | vector);
+        }
+    }
+
+    /// The BIOS interrupt services. Returns false for a vector the BIOS doesn't serve.
+    pub fn bios_service(&mut self, vector: u8) -> bool {
+        match vector {
+            0x08 ..= 0x0F | 0x70 ..= 0x77 => self.bios_irq(vector),
+            0x10 => self.bios_video(),
+            0x11 => self.bios_equipment(),
+            0x12 => self.bios_memory_size(),
+            0x13 => self.bios_disk(),
+            0x15 => self.bios_system(),
+            0x16 => self.bios_keyboard(),
+            0x1A => self.bios_time(),
+            _ => return false,
+        }
+        true
+    }
 
-        let terminal_color = BIOS_TO_TERMINAL[(color & 0x07) as usize];
-        let bright = if (color & 0x08) >0 {
-            1
-        } else {
-            0
-        };
-        let s = format!("\x1b[{};{}m{}\x1b[0m", bright, terminal_color, ch as char);
-        put_string(&s);
+    /*
+     * The default handler of a hardware interrupt just ends it, at the slave too for IRQ 8-15.
+     * The timer's also counts the ticks since midnight in the BIOS data area, 0x1800B0 of them a day.
+     * The keyboard's takes the scancode, and the RTC's reads status C so the RTC can interrupt again.
+     */
+    fn bios_irq(&mut self, vector: u8) {
+        if vector == 0x09 {
+            self.bios_keyboard_irq();
+        }
+        if vector == 0x70 {
+            self.port_out8(0x70, STATUS_C);
+            self.port_in8(0x71);
+        }
+        if vector == 0x08 {
+            let ticks = self.read_physical32(BDA_TIMER_TICKS) + 1;
+            if ticks >= TICKS_PER_DAY {
+                self.write_physical32(BDA_TIMER_TICKS, 0);
+                self.write_physical8(BDA_MIDNIGHT, 1);
+            } else {
+                self.write_physical32(BDA_TIMER_TICKS, ticks);
+            }
+        }
+        if vector >= 0x70 {
+            self.pic.write(0xA0, 0x20);
+        }
+        self.pic.write(0x20, 0x20);
     }
 
-    pub fn bios_video(&mut self) {
-        match self.get_register8(AH as usize) {
-            0x0E => self.bios_video_teletype(),
-            n    => eprintln!("not implemented BIOS video function 0x{:x}", n),
+    /*
+     * Serve the vector whose entry point CS:IP is at, if it is at one.
+     * Vectors 0-7 are CPU exceptions in real mode: without a service for them
+     * the faulting instruction would just run again, so the machine stops instead,
+     * in the handler with interrupts disabled so the timer can't wake it up.
+     * A service that has to wait halts in the handler too, but with interrupts enabled:
+     * when one wakes the machine up and returns, the service runs again.
+     */
+    pub fn bios_entry(&mut self) -> bool {
+        if self.is_protected_mode() || self.get_segment(CS).base != (BIOS_SEGMENT as u32) << 4 || self.eip > 0xFF {
+            return false;
+        }
+
+        let vector = self.eip as u8;
+        if !self.bios_service(vector) {
+            if vector < 0x08 {
+                eprintln!("unhandled exception: vector 0x{:x}", vector);
+                self.eflags.set_interrupt(false);
+                self.halted = true;
+                return true;
+            } else {
+                eprintln!("unknown interrupt: 0x{:x}", vector);
+            }
         }
+        if !self.halted {
+            self.bios_return();
+        }
+        true
+    }
+
+    fn bios_wait(&mut self) {
+        self.eflags.set_interrupt(true);
+        self.halted = true;
+    }
+
+    /// IRET, except that CF and ZF keep the values the service returns its status in.
+    fn bios_return(&mut self) {
+        let ip = self.pop16();
+        let cs = self.pop16();
+        let flags = self.pop16() as u32;
+        let status = CARRY_FLAG | ZERO_FLAG;
+        let value = (self.eflags.value() & (0xFFFF0000 | status)) | (flags & !status & 0xFFFF);
+        self.eflags.set_value(value);
+        self.segments[CS as usize] = Segment::real(cs);
+        self.eip = ip as u32;
     }
 }
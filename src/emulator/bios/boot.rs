@@ -0,0 +1,62 @@
+use super::*;
+use std::io;
+use crate::emulator::disk::SECTOR_SIZE;
+use crate::emulator::Register::*;
+
+/*
+ * Booting from a disk, the last thing the BIOS does after setting up the machine.
+ * Sector 0 of the boot drive goes to 0000:7C00, and if it ends in the 0x55 0xAA signature
+ * the BIOS jumps to it in real mode with
+ *  DL     the boot drive
+ *  AX     0xAA55, the signature it checked
+ *  CS DS ES FS GS SS  0000
+ *  SP     0x7C00, the stack right below the boot sector
+ *  FLAGS  interrupts enabled
+ * and every other register 0. The rest of the disk is there through INT 13h.
+ */
+pub const BOOT_ADDRESS: u32 = 0x7C00;
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+#[derive(Debug)]
+pub enum BootError {
+    NoDrive(u8),
+    Read(io::Error),
+    NotBootable,
+}
+
+impl fmt::Display for BootError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BootError::NoDrive(drive) => write!(f, "No disk in drive 0x{:02X}", drive),
+            BootError::Read(e) => write!(f, "Can't read the boot sector: {}", e),
+            BootError::NotBootable => write!(f, "Not a bootable disk: no 0x55AA signature"),
+        }
+    }
+}
+
+impl Emulator {
+    /// Set up the BIOS with the disks attached, and load and enter the boot sector of `drive`.
+    pub fn boot(&mut self, drive: u8) -> Result<(), BootError> {
+        let mut sector = [0; SECTOR_SIZE];
+        let disk = self.disks.get(drive).ok_or(BootError::NoDrive(drive))?;
+        disk.read(0, &mut sector).map_err(BootError::Read)?;
+        if sector[SECTOR_SIZE - 2 ..] != BOOT_SIGNATURE {
+            return Err(BootError::NotBootable);
+        }
+
+        self.enter_real_mode();
+        self.install_bios();
+        for (i, byte) in sector.iter().enumerate() {
+            self.write_physical8(BOOT_ADDRESS + i as u32, *byte);
+        }
+
+        self.registers = [0; Register::RegistersCount as usize];
+        self.set_register16(EAX as usize, 0xAA55);
+        self.set_register16(EDX as usize, drive as u16);
+        self.set_register16(ESP as usize, BOOT_ADDRESS as u16);
+        self.eflags = Eflags::new();
+        self.eflags.set_interrupt(true);
+        self.eip = BOOT_ADDRESS;
+        Ok(())
+    }
+}
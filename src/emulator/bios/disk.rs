@@ -0,0 +1,227 @@
+use super::*;
+use crate::emulator::disk::SECTOR_SIZE;
+use crate::emulator::Register::*;
+use crate::emulator::RegisterLow::*;
+use crate::emulator::RegisterHigh::*;
+
+/*
+ * INT 13h, the disk BIOS, on the drive in DL. CF clear and AH 0 is success,
+ * CF set and AH the status is failure, and the BDA keeps the last status of floppies and hard disks.
+ *
+ *  disk address packet (AH=42/43)      drive parameters (AH=48)
+ *  0x00  1  size, 0x10                 0x00  2  size, 0x1A
+ *  0x02  2  sectors                    0x02  2  flags
+ *  0x04  4  buffer, offset:segment     0x04  4  cylinders
+ *  0x08  8  LBA                        0x08  4  heads
+ *                                      0x0C  4  sectors per track
+ *                                      0x10  8  sectors
+ *                                      0x18  2  bytes per sector
+ */
+const BDA_FLOPPY_STATUS: u32 = 0x441;
+const BDA_DISK_STATUS: u32   = 0x474;
+const BDA_HARD_DISKS: u32    = 0x475;
+
+const DISK_BAD_COMMAND: u8     = 0x01;
+const DISK_WRITE_PROTECTED: u8 = 0x03;
+const DISK_NOT_FOUND: u8       = 0x04;  // no such sector
+const DISK_CONTROLLER: u8      = 0x20;  // the image couldn't be read or written
+const DISK_TIMEOUT: u8         = 0x80;  // no such drive
+
+const EDD_VERSION: u8 = 0x30;
+const EDD_PACKET_ACCESS: u16 = 0x0001;
+const EDD_CHS_VALID: u16 = 0x0002;
+const MAX_SECTORS: u32 = 128;
+
+/// The result of a disk service: what goes in AH on success, the status on failure.
+type DiskResult = Result<u8, u8>;
+
+impl Emulator {
+    /// The BDA's count of hard disks.
+    pub fn disk_init(&mut self) {
+        let hard_disks = self.disks.hard_disk_count();
+        self.write_physical8(BDA_HARD_DISKS, hard_disks);
+    }
+
+    pub fn bios_disk(&mut self) {
+        let drive = self.get_register8(DL as usize);
+        let result = match self.get_register8(AH as usize) {
+            0x00 => self.disk_present(drive).map(|_| 0),
+            0x01 => match self.read_physical8(self.disk_status_address(drive)) {
+                0 => Ok(0),
+                status => Err(status),
+            },
+            0x02 => self.bios_disk_chs(drive, false),
+            0x03 => self.bios_disk_chs(drive, true),
+            0x08 => self.bios_disk_parameters(drive),
+            0x41 => self.bios_disk_extensions(drive),
+            0x42 => self.bios_disk_packet(drive, false),
+            0x43 => self.bios_disk_packet(drive, true),
+            0x48 => self.bios_disk_drive_parameters(drive),
+            n => {
+                eprintln!("not implemented BIOS disk function 0x{:x}", n);
+                Err(DISK_BAD_COMMAND)
+            },
+        };
+
+        let (ah, status) = match result {
+            Ok(ah) => (ah, 0),
+            Err(status) => (status, status),
+        };
+        self.set_register8(AH as usize, ah);
+        self.eflags.set_carry(status != 0);
+        self.write_physical8(self.disk_status_address(drive), status);
+    }
+
+    fn disk_status_address(&self, drive: u8) -> u32 {
+        if drive & 0x80 != 0 { BDA_DISK_STATUS } else { BDA_FLOPPY_STATUS }
+    }
+
+    fn disk_present(&self, drive: u8) -> Result<(), u8> {
+        self.disks.get(drive).map(|_| ()).ok_or(DISK_TIMEOUT)
+    }
+
+    /*
+     * Move `count` sectors from `lba` on between the drive and memory at `address`.
+     * Returns how many made it, and the status of the one that didn't.
+     */
+    fn disk_transfer(&mut self, drive: u8, lba: u64, count: u32, address: u32, write: bool) -> (u32, Result<(), u8>) {
+        let disk = match self.disks.get(drive) {
+            Some(disk) => disk.clone(),
+            None => return (0, Err(DISK_TIMEOUT)),
+        };
+        if write && disk.read_only {
+            return (0, Err(DISK_WRITE_PROTECTED));
+        }
+
+        let mut buffer = [0; SECTOR_SIZE];
+        for i in 0..count {
+            let address = address.wrapping_add(i * SECTOR_SIZE as u32);
+            let result = if write {
+                for (j, byte) in buffer.iter_mut().enumerate() {
+                    *byte = self.read_physical8(address.wrapping_add(j as u32));
+                }
+                disk.write(lba + i as u64, &buffer)
+            } else {
+                disk.read(lba + i as u64, &mut buffer).map(|_| {
+                    for (j, byte) in buffer.iter().enumerate() {
+                        self.write_physical8(address.wrapping_add(j as u32), *byte);
+                    }
+                })
+            };
+            if result.is_err() {
+                return (i, Err(DISK_CONTROLLER));
+            }
+        }
+        (count, Ok(()))
+    }
+
+    /*
+     * AH=02 reads and AH=03 writes AL sectors from cylinder CH (bits 8-9 in bits 6-7 of CL),
+     * head DH, sector CL (bits 0-5) on, at ES:BX. AL returns how many were transferred.
+     */
+    fn bios_disk_chs(&mut self, drive: u8, write: bool) -> DiskResult {
+        let count = self.get_register8(AL as usize) as u32;
+        let cl = self.get_register8(CL as usize) as u32;
+        let cylinder = self.get_register8(CH as usize) as u32 | (cl & 0xC0) << 2;
+        let head = self.get_register8(DH as usize) as u32;
+        let sector = cl & 0x3F;
+        self.set_register8(AL as usize, 0);
+
+        let disk = self.disks.get(drive).ok_or(DISK_TIMEOUT)?;
+        if count == 0 || count > MAX_SECTORS {
+            return Err(DISK_BAD_COMMAND);
+        }
+        let lba = disk.lba(cylinder, head, sector).ok_or(DISK_NOT_FOUND)?;
+        let last = disk.geometry.cylinders * disk.geometry.heads * disk.geometry.sectors;
+        let available = (last as u64 - lba).min(count as u64) as u32;
+
+        let address = self.get_segment(ES).base + self.get_register16(EBX as usize) as u32;
+        let (done, result) = self.disk_transfer(drive, lba, available, address, write);
+        self.set_register8(AL as usize, done as u8);
+        result?;
+        if done < count {
+            return Err(DISK_NOT_FOUND);
+        }
+        Ok(0)
+    }
+
+    /*
+     * AH=08: the last cylinder in CH and bits 6-7 of CL, the sectors per track in bits 0-5 of CL,
+     * the last head in DH and the number of drives of the kind in DL.
+     * A floppy also gets its type in BL and the diskette parameter table, vector 0x1E, in ES:DI.
+     */
+    fn bios_disk_parameters(&mut self, drive: u8) -> DiskResult {
+        let disk = self.disks.get(drive).ok_or(DISK_TIMEOUT)?.clone();
+        let cylinder = disk.geometry.cylinders - 1;
+        let (count, floppy) = if drive & 0x80 != 0 {
+            (self.disks.hard_disk_count(), false)
+        } else {
+            (self.disks.floppy_count(), true)
+        };
+
+        self.set_register16(EAX as usize, 0);
+        self.set_register16(ECX as usize, ((cylinder & 0xFF) << 8 | (cylinder >> 8) << 6 | disk.geometry.sectors) as u16);
+        self.set_register16(EDX as usize, ((disk.geometry.heads - 1) << 8) as u16 | count as u16);
+        if floppy {
+            self.set_register8(BL as usize, disk.floppy_type);
+            let table = self.read_physical32(0x1E * 4);
+            self.load_segment(ES, (table >> 16) as u16);
+            self.set_register16(EDI as usize, table as u16);
+        }
+        Ok(0)
+    }
+
+    /// AH=41 with BX 0x55AA: BX 0xAA55 if the extensions are there, the version in AH and what they do in CX.
+    fn bios_disk_extensions(&mut self, drive: u8) -> DiskResult {
+        if self.get_register16(EBX as usize) != 0x55AA {
+            return Err(DISK_BAD_COMMAND);
+        }
+        self.disk_present(drive)?;
+        self.set_register16(EBX as usize, 0xAA55);
+        self.set_register16(ECX as usize, EDD_PACKET_ACCESS);
+        Ok(EDD_VERSION)
+    }
+
+    /// AH=42 reads and AH=43 writes what the disk address packet at DS:SI says. Its count becomes how many were.
+    fn bios_disk_packet(&mut self, drive: u8, write: bool) -> DiskResult {
+        let packet = self.get_segment(DS).base + self.get_register16(ESI as usize) as u32;
+        if self.read_physical8(packet) < 0x10 {
+            return Err(DISK_BAD_COMMAND);
+        }
+        let count = self.read_physical16(packet + 2) as u32;
+        let buffer = self.read_physical32(packet + 4);
+        let address = (buffer >> 16 << 4) + (buffer & 0xFFFF);
+        let lba = self.read_physical32(packet + 8) as u64 | (self.read_physical32(packet + 12) as u64) << 32;
+        self.write_physical16(packet + 2, 0);
+
+        let sectors = self.disks.get(drive).ok_or(DISK_TIMEOUT)?.sectors();
+        if count > MAX_SECTORS {
+            return Err(DISK_BAD_COMMAND);
+        }
+        if lba.saturating_add(count as u64) > sectors {
+            return Err(DISK_NOT_FOUND);
+        }
+        let (done, result) = self.disk_transfer(drive, lba, count, address, write);
+        self.write_physical16(packet + 2, done as u16);
+        result.map(|_| 0)
+    }
+
+    /// AH=48 fills the drive parameters at DS:SI.
+    fn bios_disk_drive_parameters(&mut self, drive: u8) -> DiskResult {
+        let table = self.get_segment(DS).base + self.get_register16(ESI as usize) as u32;
+        let disk = self.disks.get(drive).ok_or(DISK_TIMEOUT)?.clone();
+        if self.read_physical16(table) < 0x1A {
+            return Err(DISK_BAD_COMMAND);
+        }
+        let sectors = disk.sectors();
+        self.write_physical16(table, 0x1A);
+        self.write_physical16(table + 0x02, EDD_CHS_VALID);
+        self.write_physical32(table + 0x04, disk.geometry.cylinders);
+        self.write_physical32(table + 0x08, disk.geometry.heads);
+        self.write_physical32(table + 0x0C, disk.geometry.sectors);
+        self.write_physical32(table + 0x10, sectors as u32);
+        self.write_physical32(table + 0x14, (sectors >> 32) as u32);
+        self.write_physical16(table + 0x18, SECTOR_SIZE as u16);
+        Ok(0)
+    }
+}
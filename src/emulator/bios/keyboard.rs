@@ -0,0 +1,228 @@
+use super::*;
+use crate::emulator::keyboard::{KEYMAP, KEYMAP_SHIFTED, SCANCODE_EXTENDED, SCANCODE_RELEASE};
+use crate::emulator::Register::*;
+use crate::emulator::RegisterLow::*;
+use crate::emulator::RegisterHigh::*;
+
+/*
+ * The keyboard BIOS. The IRQ 1 handler, INT 09h, reads each scancode, keeps the shift state
+ * in the BDA and puts the keys it makes into the BDA's circular buffer, a word each:
+ * the scancode in the high byte, the character in the low one. INT 16h takes them out.
+ *
+ *  BDA
+ *  0x417  shift flags: right shift, left shift, ctrl, alt, scroll, num and caps lock, insert
+ *  0x418  left ctrl and alt held, the locks held
+ *  0x41A  head, the next key to read, as an offset from 0x400
+ *  0x41C  tail, where the next key goes
+ *  0x41E  the buffer, 16 words
+ *  0x480  start and end of the buffer
+ *  0x496  bit 1: the last scancode was 0xE0
+ */
+const BDA_SHIFT_FLAGS: u32   = 0x417;
+const BDA_SHIFT_FLAGS2: u32  = 0x418;
+const BDA_KEYBOARD_HEAD: u32 = 0x41A;
+const BDA_KEYBOARD_TAIL: u32 = 0x41C;
+const BDA_KEYBOARD_START: u32 = 0x480;
+const BDA_KEYBOARD_END: u32  = 0x482;
+const BDA_KEYBOARD_MODE: u32 = 0x496;
+
+const KEYBOARD_BUFFER: u16 = 0x1E;
+const KEYBOARD_BUFFER_END: u16 = 0x3E;
+
+const RIGHT_SHIFT: u8 = 1;
+const LEFT_SHIFT: u8  = 1 << 1;
+const CTRL: u8        = 1 << 2;
+const ALT: u8         = 1 << 3;
+const SCROLL_LOCK: u8 = 1 << 4;
+const NUM_LOCK: u8    = 1 << 5;
+const CAPS_LOCK: u8   = 1 << 6;
+const INSERT: u8      = 1 << 7;
+const LAST_E0: u8     = 1 << 1;
+
+/// The keypad from 7 to ., what it types with num lock.
+const KEYPAD: &[u8; 13] = b"789-456+1230.";
+
+impl Emulator {
+    /// An empty keyboard buffer.
+    pub fn keyboard_init(&mut self) {
+        self.write_physical16(BDA_KEYBOARD_HEAD, KEYBOARD_BUFFER);
+        self.write_physical16(BDA_KEYBOARD_TAIL, KEYBOARD_BUFFER);
+        self.write_physical16(BDA_KEYBOARD_START, KEYBOARD_BUFFER);
+        self.write_physical16(BDA_KEYBOARD_END, KEYBOARD_BUFFER_END);
+    }
+
+    /// INT 09h: a scancode arrived.
+    pub fn bios_keyboard_irq(&mut self) {
+        let scancode = self.port_in8(0x60);
+        let mode = self.read_physical8(BDA_KEYBOARD_MODE);
+        if scancode == SCANCODE_EXTENDED {
+            self.write_physical8(BDA_KEYBOARD_MODE, mode | LAST_E0);
+            return;
+        }
+        self.write_physical8(BDA_KEYBOARD_MODE, mode & !LAST_E0);
+        let extended = mode & LAST_E0 != 0;
+        let pressed = scancode & SCANCODE_RELEASE == 0;
+        let key = scancode & !SCANCODE_RELEASE;
+
+        let flags = self.read_physical8(BDA_SHIFT_FLAGS);
+        let held = |flag: u8| if pressed { flags | flag } else { flags & !flag };
+        let toggled = |flag: u8| if pressed { flags ^ flag } else { flags };
+        let flags = match key {
+            0x2A if !extended => held(LEFT_SHIFT),
+            0x36 if !extended => held(RIGHT_SHIFT),
+            0x1D => held(CTRL),
+            0x38 => held(ALT),
+            0x3A => toggled(CAPS_LOCK),
+            0x45 => toggled(NUM_LOCK),
+            0x46 => toggled(SCROLL_LOCK),
+            _ => {
+                if pressed {
+                    if key == 0x52 {
+                        self.write_physical8(BDA_SHIFT_FLAGS, flags ^ INSERT);
+                    }
+                    if let Some(code) = self.translate_key(key, extended, flags) {
+                        self.keyboard_push(code);
+                    }
+                }
+                return;
+            },
+        };
+        self.write_physical8(BDA_SHIFT_FLAGS, flags);
+
+        let flags2 = self.read_physical8(BDA_SHIFT_FLAGS2);
+        let bit = match key {
+            0x1D if !extended => 1,
+            0x38 if !extended => 1 << 1,
+            0x46 => SCROLL_LOCK,
+            0x45 => NUM_LOCK,
+            0x3A => CAPS_LOCK,
+            _ => 0,
+        };
+        self.write_physical8(BDA_SHIFT_FLAGS2, if pressed { flags2 | bit } else { flags2 & !bit });
+    }
+
+    /// The key word a key makes with the shift state, None for one that makes none.
+    fn translate_key(&self, key: u8, extended: bool, flags: u8) -> Option<u16> {
+        let shift = flags & (LEFT_SHIFT | RIGHT_SHIFT) != 0;
+        let word = |scan: u8, character: u8| Some((scan as u16) << 8 | character as u16);
+
+        if extended {
+            return match key {
+                0x1C => word(key, b'\r'),
+                0x35 => word(key, b'/'),
+                0x47 ..= 0x53 => word(key, SCANCODE_EXTENDED),
+                _ => None,
+            };
+        }
+        match key {
+            0x01 ..= 0x39 => {
+                let character = KEYMAP[key as usize];
+                if flags & ALT != 0 {
+                    return word(key, 0);
+                }
+                if flags & CTRL != 0 {
+                    return match character {
+                        b'a' ..= b'z' => word(key, character & 0x1F),
+                        b'\r' => word(key, b'\n'),
+                        0x08 => word(key, 0x7F),
+                        _ => None,
+                    };
+                }
+                let letter = character.is_ascii_lowercase();
+                let shifted = shift ^ (letter && flags & CAPS_LOCK != 0);
+                let character = if shifted { KEYMAP_SHIFTED[key as usize] } else { character };
+                if key == 0x0F && shift {
+                    return word(key, 0);
+                }
+                if character == 0 { None } else { word(key, character) }
+            },
+            // F1-F10, shifted, with ctrl and with alt
+            0x3B ..= 0x44 => {
+                let scan = if flags & ALT != 0 {
+                    key + 0x2D
+                } else if flags & CTRL != 0 {
+                    key + 0x23
+                } else if shift {
+                    key + 0x19
+                } else {
+                    key
+                };
+                word(scan, 0)
+            },
+            0x57 | 0x58 => word(key + 0x2E, 0),
+            0x4A | 0x4E => word(key, KEYPAD[(key - 0x47) as usize]),
+            0x47 ..= 0x53 => {
+                if (flags & NUM_LOCK != 0) ^ shift {
+                    word(key, KEYPAD[(key - 0x47) as usize])
+                } else {
+                    word(key, 0)
+                }
+            },
+            _ => None,
+        }
+    }
+
+    fn keyboard_next(&self, offset: u16) -> u16 {
+        let end = self.read_physical16(BDA_KEYBOARD_END);
+        if offset + 2 >= end { self.read_physical16(BDA_KEYBOARD_START) } else { offset + 2 }
+    }
+
+    /// Put a key at the tail of the buffer. Returns false if it is full.
+    fn keyboard_push(&mut self, code: u16) -> bool {
+        let tail = self.read_physical16(BDA_KEYBOARD_TAIL);
+        let next = self.keyboard_next(tail);
+        if next == self.read_physical16(BDA_KEYBOARD_HEAD) {
+            return false;
+        }
+        self.write_physical16(0x400 + tail as u32, code);
+        self.write_physical16(BDA_KEYBOARD_TAIL, next);
+        true
+    }
+
+    /// The key at the head of the buffer, taken out if `remove`.
+    fn keyboard_peek(&mut self, remove: bool) -> Option<u16> {
+        let head = self.read_physical16(BDA_KEYBOARD_HEAD);
+        if head == self.read_physical16(BDA_KEYBOARD_TAIL) {
+            return None;
+        }
+        if remove {
+            let next = self.keyboard_next(head);
+            self.write_physical16(BDA_KEYBOARD_HEAD, next);
+        }
+        Some(self.read_physical16(0x400 + head as u32))
+    }
+
+    /*
+     * INT 16h. AH=00 and 10 wait for a key and take it, AH=01 and 11 say in ZF whether there's one
+     * and which, AH=02 and 12 return the shift flags, AH=05 puts the key in CX in the buffer.
+     * The functions below 10 report the gray keys as keypad ones, with 0 for the character.
+     */
+    pub fn bios_keyboard(&mut self) {
+        let function = self.get_register8(AH as usize);
+        let standard = |code: u16| if function < 0x10 && code & 0xFF == SCANCODE_EXTENDED as u16 && code > 0xFF { code & 0xFF00 } else { code };
+        match function {
+            0x00 | 0x10 => match self.keyboard_peek(true) {
+                Some(code) => self.set_register16(EAX as usize, standard(code)),
+                None => self.bios_wait(),
+            },
+            0x01 | 0x11 => match self.keyboard_peek(false) {
+                Some(code) => {
+                    self.set_register16(EAX as usize, standard(code));
+                    self.eflags.set_zero(false);
+                },
+                None => self.eflags.set_zero(true),
+            },
+            0x02 => self.set_register8(AL as usize, self.read_physical8(BDA_SHIFT_FLAGS)),
+            0x12 => {
+                self.set_register8(AL as usize, self.read_physical8(BDA_SHIFT_FLAGS));
+                self.set_register8(AH as usize, self.read_physical8(BDA_SHIFT_FLAGS2));
+            },
+            0x05 => {
+                let code = self.get_register16(ECX as usize);
+                let full = !self.keyboard_push(code);
+                self.set_register8(AL as usize, full as u8);
+            },
+            n => eprintln!("not implemented BIOS keyboard function 0x{:x}", n),
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use super::*;
+use crate::emulator::uart::COM_PORTS;
+use crate::emulator::Register::*;
+use crate::emulator::RegisterLow::*;
+use crate::emulator::RegisterHigh::*;
+
+/*
+ * The system services: INT 11h, the equipment list, INT 12h, the conventional memory size,
+ * and the INT 15h functions that tell how much memory there is and open and close the A20 gate.
+ * All the sizes come from the memory the machine was given.
+ *
+ *  BDA
+ *  0x400  base port of COM1-COM4
+ *  0x410  equipment word
+ *  0x413  conventional memory in KB
+ *
+ *  equipment word
+ *  +-----+-----+-----+--+-----+-----+----+---+--+
+ *  |15 14|13 12|11  9| 8|7   6|5   4|3  2| 1 | 0|
+ *  +-----+-----+-----+--+-----+-----+----+---+--+
+ *  |LPTs |     |COMs |  |FDs-1|video|    |FPU|FD|
+ *  +-----+-----+-----+--+-----+-----+----+---+--+
+ *  video 2 is 80x25 colour.
+ *
+ *  E820 entry
+ *  0x00  8  base
+ *  0x08  8  length
+ *  0x10  4  type: 1 usable, 2 reserved
+ */
+const BDA_COM_PORTS: u32   = 0x400;
+const BDA_EQUIPMENT: u32   = 0x410;
+const BDA_MEMORY_SIZE: u32 = 0x413;
+
+const EQUIPMENT_FLOPPY: u16 = 1;
+const EQUIPMENT_COLOR_80: u16 = 2 << 4;
+
+const CONVENTIONAL_END: u64 = 0xA0000;
+const BIOS_AREA: u64 = 0xF0000;
+const EXTENDED_START: u64 = 0x100000;
+const SIXTEEN_MB: u64 = 0x1000000;
+
+pub const E820_USABLE: u32   = 1;
+pub const E820_RESERVED: u32 = 2;
+const E820_ENTRY_SIZE: u32 = 20;
+const SMAP: u32 = 0x534D4150;
+
+const UNSUPPORTED: u8 = 0x86;
+
+impl Emulator {
+    /// The memory size, the equipment word and the serial ports in the BDA.
+    pub fn system_init(&mut self) {
+        let conventional = (self.memory.len() as u64).min(CONVENTIONAL_END) / 1024;
+        self.write_physical16(BDA_MEMORY_SIZE, conventional as u16);
+
+        let mut equipment = EQUIPMENT_COLOR_80 | (self.serial.ports.len() as u16) << 9;
+        let floppies = self.disks.floppy_count() as u16;
+        if floppies > 0 {
+            equipment |= EQUIPMENT_FLOPPY | (floppies - 1) << 6;
+        }
+        self.write_physical16(BDA_EQUIPMENT, equipment);
+
+        for (i, (port, _)) in COM_PORTS.iter().enumerate() {
+            self.write_physical16(BDA_COM_PORTS + i as u32 * 2, *port);
+        }
+    }
+
+    /// The memory map E820 reports: base, length and type of each range, in order.
+    pub fn memory_map(&self) -> Vec<(u64, u64, u32)> {
+        let size = self.memory.len() as u64;
+        let mut map = vec![(0, size.min(CONVENTIONAL_END), E820_USABLE), (BIOS_AREA, EXTENDED_START - BIOS_AREA, E820_RESERVED)];
+        if size > EXTENDED_START {
+            map.push((EXTENDED_START, size - EXTENDED_START, E820_USABLE));
+        }
+        map
+    }
+
+    /// KB of memory above 1 MB.
+    fn extended_memory(&self) -> u64 {
+        (self.memory.len() as u64).saturating_sub(EXTENDED_START) / 1024
+    }
+
+    /// INT 11h.
+    pub fn bios_equipment(&mut self) {
+        let equipment = self.read_physical16(BDA_EQUIPMENT);
+        self.set_register16(EAX as usize, equipment);
+    }
+
+    /// INT 12h, from the BDA, where a program that takes the top of conventional memory lowers it.
+    pub fn bios_memory_size(&mut self) {
+        let size = self.read_physical16(BDA_MEMORY_SIZE);
+        self.set_register16(EAX as usize, size);
+    }
+
+    /*
+     * INT 15h. CF clear is success, CF set with AH 0x86 an unsupported function.
+     *  AX=2400/2401  close/open the A20 gate
+     *  AX=2402       AL: whether it's open
+     *  AX=2403       BX: how it can be opened, by the keyboard controller and port 0x92
+     *  AX=E820       the memory map, an entry at a time
+     *  AX=E801       AX and CX: KB between 1 and 16 MB, BX and DX: 64K blocks above 16 MB
+     *  AH=88         AX: KB above 1 MB
+     */
+    pub fn bios_system(&mut self) {
+        let ax = self.get_register16(EAX as usize);
+        let result = match ax {
+            0x2400 | 0x2401 => {
+                self.set_a20(ax == 0x2401);
+                self.set_register8(AH as usize, 0);
+                Ok(())
+            },
+            0x2402 => {
+                let enabled = self.a20_enabled() as u8;
+                self.set_register8(AL as usize, enabled);
+                self.set_register8(AH as usize, 0);
+                Ok(())
+            },
+            0x2403 => {
+                self.set_register16(EBX as usize, 0x0003);
+                self.set_register8(AH as usize, 0);
+                Ok(())
+            },
+            0xE820 => self.bios_memory_map(),
+            0xE801 => {
+                let kb = self.extended_memory().min((SIXTEEN_MB - EXTENDED_START) / 1024) as u16;
+                let blocks = ((self.memory.len() as u64).saturating_sub(SIXTEEN_MB) >> 16).min(0xFFFF) as u16;
+                self.set_register16(EAX as usize, kb);
+                self.set_register16(ECX as usize, kb);
+                self.set_register16(EBX as usize, blocks);
+                self.set_register16(EDX as usize, blocks);
+                Ok(())
+            },
+            _ if ax >> 8 == 0x88 => {
+                let kb = self.extended_memory().min(0xFFFF) as u16;
+                self.set_register16(EAX as usize, kb);
+                Ok(())
+            },
+            _ => {
+                eprintln!("not implemented BIOS system function 0x{:x}", ax);
+                Err(UNSUPPORTED)
+            },
+        };
+
+        if let Err(status) = result {
+            self.set_register8(AH as usize, status);
+        }
+        self.eflags.set_carry(result.is_err());
+    }
+
+    /*
+     * E820: the entry EBX says, to ES:DI, which has room for ECX bytes, with EDX 'SMAP'.
+     * Returns 'SMAP' in EAX, the size in ECX and in EBX the entry to ask for next, 0 after the last.
+     */
+    fn bios_memory_map(&mut self) -> Result<(), u8> {
+        let map = self.memory_map();
+        let index = self.get_register32(EBX as usize) as usize;
+        if self.get_register32(EDX as usize) != SMAP || self.get_register32(ECX as usize) < E820_ENTRY_SIZE || index >= map.len() {
+            return Err(UNSUPPORTED);
+        }
+
+        let (base, length, kind) = map[index];
+        let address = self.get_segment(ES).base + self.get_register16(EDI as usize) as u32;
+        self.write_physical32(address, base as u32);
+        self.write_physical32(address + 4, (base >> 32) as u32);
+        self.write_physical32(address + 8, length as u32);
+        self.write_physical32(address + 12, (length >> 32) as u32);
+        self.write_physical32(address + 16, kind);
+
+        let next = if index + 1 < map.len() { index as u32 + 1 } else { 0 };
+        self.set_register32(EAX as usize, SMAP);
+        self.set_register32(EBX as usize, next);
+        self.set_register32(ECX as usize, E820_ENTRY_SIZE);
+        Ok(())
+    }
+}
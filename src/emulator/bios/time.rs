@@ -0,0 +1,81 @@
+use super::*;
+use crate::emulator::cmos::{to_bcd, from_bcd, DateTime, STATUS_A, STATUS_B, STATUS_C, DEFAULT_STATUS_A, B_24_HOUR, B_DAYLIGHT};
+use crate::emulator::Register::*;
+use crate::emulator::RegisterLow::*;
+use crate::emulator::RegisterHigh::*;
+
+/*
+ * INT 1Ah, the time of day: the timer ticks since midnight the IRQ 0 handler counts in the BDA,
+ * and the RTC's date and time, in BCD whatever mode the RTC counts in.
+ *  AH=00  CX:DX ticks, AL whether midnight has passed since the last call
+ *  AH=01  set the ticks to CX:DX
+ *  AH=02  CH hours, CL minutes, DH seconds, DL daylight saving
+ *  AH=03  set them
+ *  AH=04  CH century, CL year, DH month, DL day
+ *  AH=05  set them
+ */
+impl Emulator {
+    /// The RTC in 24-hour BCD, as the BIOS leaves it, and the ticks since midnight taken from it.
+    pub fn time_init(&mut self) {
+        self.cmos.write_register(STATUS_A, DEFAULT_STATUS_A);
+        self.cmos.write_register(STATUS_B, B_24_HOUR);
+        self.cmos.read_register(STATUS_C);
+
+        let time = self.cmos.date_time();
+        let seconds = time.hour as u64 * 3600 + time.minute as u64 * 60 + time.second as u64;
+        self.write_physical32(BDA_TIMER_TICKS, (seconds * TICKS_PER_DAY as u64 / 86400) as u32);
+        self.write_physical8(BDA_MIDNIGHT, 0);
+    }
+
+    pub fn bios_time(&mut self) {
+        let mut time = self.cmos.date_time();
+        let bcd = |emu: &Emulator, register: usize| from_bcd(emu.get_register8(register));
+        match self.get_register8(AH as usize) {
+            0x00 => {
+                let ticks = self.read_physical32(BDA_TIMER_TICKS);
+                self.set_register16(ECX as usize, (ticks >> 16) as u16);
+                self.set_register16(EDX as usize, ticks as u16);
+                self.set_register8(AL as usize, self.read_physical8(BDA_MIDNIGHT));
+                self.write_physical8(BDA_MIDNIGHT, 0);
+            },
+            0x01 => {
+                let ticks = (self.get_register16(ECX as usize) as u32) << 16 | self.get_register16(EDX as usize) as u32;
+                self.write_physical32(BDA_TIMER_TICKS, ticks);
+                self.write_physical8(BDA_MIDNIGHT, 0);
+            },
+            0x02 => {
+                let daylight = self.cmos.read_register(STATUS_B) & B_DAYLIGHT;
+                self.set_register8(CH as usize, to_bcd(time.hour));
+                self.set_register8(CL as usize, to_bcd(time.minute));
+                self.set_register8(DH as usize, to_bcd(time.second));
+                self.set_register8(DL as usize, daylight);
+            },
+            0x03 => {
+                time.hour = bcd(self, CH as usize);
+                time.minute = bcd(self, CL as usize);
+                time.second = bcd(self, DH as usize);
+                self.cmos.set_date_time(time);
+                let status = self.cmos.read_register(STATUS_B) & !B_DAYLIGHT;
+                let daylight = self.get_register8(DL as usize) & B_DAYLIGHT;
+                self.cmos.write_register(STATUS_B, status | daylight);
+            },
+            0x04 => {
+                self.set_register8(CH as usize, to_bcd((time.year / 100) as u8));
+                self.set_register8(CL as usize, to_bcd((time.year % 100) as u8));
+                self.set_register8(DH as usize, to_bcd(time.month));
+                self.set_register8(DL as usize, to_bcd(time.day));
+            },
+            0x05 => {
+                let year = bcd(self, CH as usize) as u16 * 100 + bcd(self, CL as usize) as u16;
+                let date = DateTime { year, month: bcd(self, DH as usize), day: bcd(self, DL as usize), ..time };
+                self.cmos.set_date_time(date);
+            },
+            n => {
+                eprintln!("not implemented BIOS time function 0x{:x}", n);
+                self.eflags.set_carry(true);
+                return;
+            },
+        }
+        self.eflags.set_carry(false);
+    }
+}
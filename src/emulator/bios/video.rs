@@ -0,0 +1,258 @@
+use super::*;
+use crate::emulator::vga::{Window, TEXT_COLUMNS, TEXT_ROWS, PAGE_SIZE, DEFAULT_ATTRIBUTE};
+use crate::emulator::Register::*;
+use crate::emulator::RegisterLow::*;
+use crate::emulator::RegisterHigh::*;
+
+/*
+ * INT 10h, the video BIOS, for 80x25 colour text. The BIOS data area keeps the mode,
+ * the cursor of each of the 8 pages and the displayed page, and the CRTC shows them.
+ *
+ *  BDA   size
+ *  0x449  1   mode
+ *  0x44A  2   columns
+ *  0x44C  2   page size in bytes
+ *  0x44E  2   start of the displayed page
+ *  0x450 16   cursor of each page, column then row
+ *  0x460  2   cursor shape, end then start scan line
+ *  0x462  1   displayed page
+ *  0x463  2   CRTC port
+ *  0x484  1   rows - 1
+ */
+const BDA_VIDEO_MODE: u32   = 0x449;
+const BDA_COLUMNS: u32      = 0x44A;
+const BDA_PAGE_SIZE: u32    = 0x44C;
+const BDA_PAGE_START: u32   = 0x44E;
+const BDA_CURSOR: u32       = 0x450;
+const BDA_CURSOR_SHAPE: u32 = 0x460;
+const BDA_ACTIVE_PAGE: u32  = 0x462;
+const BDA_CRTC_PORT: u32    = 0x463;
+const BDA_ROWS: u32         = 0x484;
+
+pub const TEXT_MODE: u8 = 0x03;
+
+impl Emulator {
+    /// The video state the BIOS boots with, mode 3 on a blank screen.
+    pub fn video_init(&mut self) {
+        self.set_video_mode(TEXT_MODE, true);
+    }
+
+    pub fn bios_video(&mut self) {
+        match self.get_register8(AH as usize) {
+            0x00 => {
+                let mode = self.get_register8(AL as usize);
+                self.set_video_mode(mode & 0x7F, mode & 0x80 == 0);
+            },
+            0x01 => self.bios_video_set_cursor_shape(),
+            0x02 => {
+                let page = self.get_register8(BH as usize);
+                let (row, column) = (self.get_register8(DH as usize), self.get_register8(DL as usize));
+                self.set_video_cursor(page, row as usize, column as usize);
+            },
+            0x03 => self.bios_video_get_cursor(),
+            0x05 => self.set_video_page(self.get_register8(AL as usize)),
+            0x06 | 0x07 => self.bios_video_scroll(),
+            0x08 => {
+                let page = self.get_register8(BH as usize);
+                let (row, column) = self.video_cursor(page);
+                let (character, attribute) = self.vga.get(page, row, column);
+                self.set_register16(EAX as usize, (attribute as u16) << 8 | character as u16);
+            },
+            0x09 | 0x0A => self.bios_video_write_character(),
+            0x0E => self.bios_video_teletype(),
+            0x0F => {
+                let mode = self.read_physical8(BDA_VIDEO_MODE);
+                let columns = self.read_physical8(BDA_COLUMNS);
+                self.set_register16(EAX as usize, (columns as u16) << 8 | mode as u16);
+                self.set_register8(BH as usize, self.read_physical8(BDA_ACTIVE_PAGE));
+            },
+            0x13 => self.bios_video_write_string(),
+            n    => eprintln!("not implemented BIOS video function 0x{:x}", n),
+        }
+    }
+
+    /// Modes 2 and 3 are both 80x25 colour text. Unless told not to, setting one clears the screen.
+    pub fn set_video_mode(&mut self, mode: u8, clear: bool) {
+        if mode != 0x02 && mode != 0x03 {
+            eprintln!("unsupported video mode 0x{:x}", mode);
+            return;
+        }
+        self.write_physical8(BDA_VIDEO_MODE, mode);
+        self.write_physical16(BDA_COLUMNS, TEXT_COLUMNS as u16);
+        self.write_physical16(BDA_PAGE_SIZE, PAGE_SIZE as u16);
+        self.write_physical16(BDA_CRTC_PORT, 0x3D4);
+        self.write_physical8(BDA_ROWS, TEXT_ROWS as u8 - 1);
+        for page in 0..8 {
+            self.write_physical16(BDA_CURSOR + page * 2, 0);
+        }
+        self.set_cursor_shape(0x06, 0x07);
+        self.set_video_page(0);
+        if clear {
+            self.vga.clear(DEFAULT_ATTRIBUTE);
+        }
+    }
+
+    /// Display a page, and its cursor.
+    fn set_video_page(&mut self, page: u8) {
+        let page = page & 7;
+        self.write_physical8(BDA_ACTIVE_PAGE, page);
+        self.write_physical16(BDA_PAGE_START, (page as u32 * PAGE_SIZE) as u16);
+        self.vga.set_start_address((page as u32 * PAGE_SIZE / 2) as u16);
+        let (row, column) = self.video_cursor(page);
+        self.set_video_cursor(page, row, column);
+    }
+
+    fn set_cursor_shape(&mut self, start: u8, end: u8) {
+        self.write_physical8(BDA_CURSOR_SHAPE, end);
+        self.write_physical8(BDA_CURSOR_SHAPE + 1, start);
+        self.vga.set_cursor_shape(start, end);
+    }
+
+    /// The cursor of a page as the BDA keeps it, row and column.
+    fn video_cursor(&self, page: u8) -> (usize, usize) {
+        let addr = BDA_CURSOR + (page as u32 & 7) * 2;
+        (self.read_physical8(addr + 1) as usize, self.read_physical8(addr) as usize)
+    }
+
+    /// Move the cursor of a page, and the one on the screen if the page is displayed.
+    fn set_video_cursor(&mut self, page: u8, row: usize, column: usize) {
+        let addr = BDA_CURSOR + (page as u32 & 7) * 2;
+        self.write_physical8(addr, column as u8);
+        self.write_physical8(addr + 1, row as u8);
+        if page & 7 == self.read_physical8(BDA_ACTIVE_PAGE) & 7 {
+            let cell = (page as u32 & 7) * PAGE_SIZE / 2 + (row * TEXT_COLUMNS + column) as u32;
+            self.vga.set_cursor_address(cell as u16);
+        }
+    }
+
+    /// CH and CL: the start and end scan lines, bit 5 of the start hiding the cursor.
+    fn bios_video_set_cursor_shape(&mut self) {
+        let start = self.get_register8(CH as usize) & 0x3F;
+        let end = self.get_register8(CL as usize) & 0x1F;
+        self.set_cursor_shape(start, end);
+    }
+
+    /// DH and DL: the row and column of the cursor of page BH. CH and CL: its shape.
+    fn bios_video_get_cursor(&mut self) {
+        let page = self.get_register8(BH as usize);
+        let (row, column) = self.video_cursor(page);
+        self.set_register16(EDX as usize, (row as u16) << 8 | column as u16);
+        let shape = self.read_physical16(BDA_CURSOR_SHAPE);
+        self.set_register16(ECX as usize, shape);
+        self.set_register16(EAX as usize, 0);
+    }
+
+    /*
+     * AH=06 scrolls up and AH=07 down, AL lines of the window from CH,CL to DH,DL of the displayed page.
+     * The lines uncovered get the attribute BH. Scrolling 0 lines blanks the whole window.
+     */
+    fn bios_video_scroll(&mut self) {
+        let window = Window {
+            top: self.get_register8(CH as usize) as usize,
+            left: self.get_register8(CL as usize) as usize,
+            bottom: self.get_register8(DH as usize) as usize,
+            right: self.get_register8(DL as usize) as usize,
+        };
+        let lines = match self.get_register8(AL as usize) {
+            0 => TEXT_ROWS,
+            n => n as usize,
+        };
+        let attribute = self.get_register8(BH as usize);
+        let page = self.read_physical8(BDA_ACTIVE_PAGE);
+        if self.get_register8(AH as usize) == 0x06 {
+            self.vga.scroll_up(page, window, lines, attribute);
+        } else {
+            self.vga.scroll_down(page, window, lines, attribute);
+        }
+    }
+
+    /*
+     * AH=09 writes the character AL with the attribute BL, AH=0A the character alone,
+     * CX times from the cursor of page BH on. The cursor stays where it is.
+     */
+    fn bios_video_write_character(&mut self) {
+        let page = self.get_register8(BH as usize);
+        let character = self.get_register8(AL as usize);
+        let attribute = self.get_register8(BL as usize);
+        let keep_attribute = self.get_register8(AH as usize) == 0x0A;
+        let (row, column) = self.video_cursor(page);
+
+        let start = row * TEXT_COLUMNS + column;
+        let end = (start + self.get_register16(ECX as usize) as usize).min(TEXT_COLUMNS * TEXT_ROWS);
+        for cell in start .. end {
+            let (row, column) = (cell / TEXT_COLUMNS, cell % TEXT_COLUMNS);
+            let attribute = if keep_attribute { self.vga.get(page, row, column).1 } else { attribute };
+            self.vga.put(page, row, column, character, attribute);
+        }
+    }
+
+    /*
+     * Teletype output: write the character at the cursor of page BH, keeping the attribute already there,
+     * and move on. It also goes to COM1, for the terminal to see without a display.
+     */
+    fn bios_video_teletype(&mut self) {
+        let ch: u8 = self.get_register8(AL as usize);
+        let page = self.get_register8(BH as usize);
+        self.video_output(page, ch, None);
+        self.put_char(ch);
+    }
+
+    /*
+     * AH=13 writes the CX characters at ES:BP from row DH, column DL of page BH on, like teletype output.
+     * Bit 1 of AL says each is followed by its attribute, otherwise they all get BL.
+     * Bit 0 says the cursor stays after the string, otherwise it goes back to where it was.
+     */
+    fn bios_video_write_string(&mut self) {
+        let mode = self.get_register8(AL as usize);
+        let page = self.get_register8(BH as usize);
+        let attribute = self.get_register8(BL as usize);
+        let count = self.get_register16(ECX as usize) as u32;
+        let base = self.get_segment(ES).base;
+        let bp = self.get_register16(EBP as usize) as u32;
+
+        let cursor = self.video_cursor(page);
+        self.set_video_cursor(page, self.get_register8(DH as usize) as usize, self.get_register8(DL as usize) as usize);
+        let step = if mode & 0x02 != 0 { 2 } else { 1 };
+        for i in 0..count {
+            let offset = bp + i * step;
+            let ch = self.read_physical8(base + (offset & 0xFFFF));
+            let attribute = if step == 2 { self.read_physical8(base + ((offset + 1) & 0xFFFF)) } else { attribute };
+            self.video_output(page, ch, Some(attribute));
+            self.put_char(ch);
+        }
+        if mode & 0x01 == 0 {
+            self.set_video_cursor(page, cursor.0, cursor.1);
+        }
+    }
+
+    /*
+     * Write a character at the cursor of a page and move it on, with `attribute` or the one already there.
+     * BEL, BS, LF and CR are controls, the end of a line wraps and the bottom line scrolls.
+     */
+    fn video_output(&mut self, page: u8, ch: u8, attribute: Option<u8>) {
+        let page = page & 7;
+        let (mut row, mut column) = self.video_cursor(page);
+        match ch {
+            0x07 => (),
+            0x08 => column = column.saturating_sub(1),
+            b'\n' => row += 1,
+            b'\r' => column = 0,
+            _ => {
+                let attribute = attribute.unwrap_or(self.vga.get(page, row, column).1);
+                self.vga.put(page, row, column, ch, attribute);
+                column += 1;
+                if column >= TEXT_COLUMNS {
+                    column = 0;
+                    row += 1;
+                }
+            },
+        }
+        if row >= TEXT_ROWS {
+            row = TEXT_ROWS - 1;
+            let (_, attribute) = self.vga.get(page, row, column);
+            self.vga.scroll_up(page, Window::SCREEN, 1, attribute);
+        }
+        self.set_video_cursor(page, row, column);
+    }
+}
+
@@ -0,0 +1,71 @@
+use super::*;
+use crate::emulator::pit::PIT_FREQUENCY;
+
+/*
+ * Virtual time. Every instruction takes `cycles_per_instruction` cycles of a CPU running at CPU_FREQUENCY,
+ * however long the host takes to execute it, so timer driven code does the same thing on every run.
+ * As on the original PC, the CPU clock is four times the PIT's.
+ */
+pub const CYCLES_PER_PIT_TICK: u64 = 4;
+pub const CPU_FREQUENCY: u64 = PIT_FREQUENCY * CYCLES_PER_PIT_TICK;
+pub const DEFAULT_CYCLES_PER_INSTRUCTION: u64 = 4;
+
+#[derive(Debug, Clone)]
+pub struct Clock {
+    pub cycles: u64,
+    pub cycles_per_instruction: u64,
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock {
+            cycles: 0,
+            cycles_per_instruction: DEFAULT_CYCLES_PER_INSTRUCTION,
+        }
+    }
+}
+
+impl Clock {
+    pub fn pit_ticks(&self) -> u64 {
+        self.cycles / CYCLES_PER_PIT_TICK
+    }
+
+    /// Virtual time since reset.
+    pub fn nanoseconds(&self) -> u64 {
+        (self.cycles as u128 * 1_000_000_000 / CPU_FREQUENCY as u128) as u64
+    }
+}
+
+impl Emulator {
+    /*
+     * Let `cycles` go by: the PIT counts its share of them and channel 0's output drives IRQ 0.
+     * The UARTs, the keyboard and the RTC go by the same ticks.
+     */
+    pub fn advance_clock(&mut self, cycles: u64) {
+        let before = self.clock.pit_ticks();
+        self.clock.cycles += cycles;
+        for _ in before..self.clock.pit_ticks() {
+            let output = self.pit.channels[0].output;
+            self.pit.clock();
+            if self.pit.channels[0].output != output {
+                self.pic.set_irq(0, !output);
+            }
+            self.serial.clock();
+            self.update_serial_irqs();
+            self.keyboard.clock();
+            self.update_keyboard_irq();
+            self.cmos.clock();
+            self.update_cmos_irq();
+        }
+    }
+
+    /// Whether a halted CPU will ever wake up: an interrupt is pending, or the timer, a key or the RTC will raise one.
+    pub fn can_wake(&self) -> bool {
+        let master = &self.pic.master;
+        let slave = &self.pic.slave;
+        let timer = (master.imr | master.isr) & 1 == 0 && self.pit.channels[0].will_fire();
+        let keyboard = (master.imr | master.isr) & 2 == 0 && self.keyboard.will_type();
+        let rtc = (master.imr | master.isr) & 4 == 0 && (slave.imr | slave.isr) & 1 == 0 && self.cmos.will_interrupt();
+        self.eflags.is_interrupt() && (self.pic.has_interrupt() || timer || keyboard || rtc)
+    }
+}
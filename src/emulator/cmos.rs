@@ -0,0 +1,357 @@
+use super::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::emulator::io::{PortDevice, OPEN_BUS};
+use crate::emulator::pit::PIT_FREQUENCY;
+
+/*
+ * The MC146818 real-time clock and its CMOS RAM, 128 bytes behind an index port.
+ *
+ *  port  read            write
+ *  0x70                  index, bit 7 disables NMI
+ *  0x71  the register    the register
+ *
+ *  0x00  seconds   0x01  alarm seconds     0x0A  status A
+ *  0x02  minutes   0x03  alarm minutes     0x0B  status B
+ *  0x04  hours     0x05  alarm hours       0x0C  status C, cleared by reading it
+ *  0x06  weekday, 1 is Sunday              0x0D  status D
+ *  0x07  day       0x08  month             0x09  year in the century
+ *  0x32  century
+ *
+ *  status A                          status B
+ *  +---+-------+--------+            +---+---+---+---+----+---+---+---+
+ *  | 7 |6     4|3      0|            | 7 | 6 | 5 | 4 | 3  | 2 | 1 | 0 |
+ *  +---+-------+--------+            +---+---+---+---+----+---+---+---+
+ *  |UIP|divider|  rate  |            |SET|PIE|AIE|UIE|SQWE|DM |24h|DSE|
+ *  +---+-------+--------+            +---+---+---+---+----+---+---+---+
+ *
+ * The time counts in binary or BCD, as DM says, and the hours in 12-hour mode have bit 7 for PM.
+ * It goes by the PIT's ticks, so it is as deterministic as the rest of the machine:
+ * the host's time at power-on, or a fixed one.
+ * Status C has a flag for each interrupt source: the periodic one at 32768 >> (rate - 1) Hz,
+ * the alarm, and the end of each update, once a second. IRQ 8 is raised while an enabled one is set.
+ */
+const SECONDS: u8 = 0x00;
+const ALARM_SECONDS: u8 = 0x01;
+const MINUTES: u8 = 0x02;
+const ALARM_MINUTES: u8 = 0x03;
+const HOURS: u8 = 0x04;
+const ALARM_HOURS: u8 = 0x05;
+const WEEKDAY: u8 = 0x06;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+pub const STATUS_A: u8 = 0x0A;
+pub const STATUS_B: u8 = 0x0B;
+pub const STATUS_C: u8 = 0x0C;
+const STATUS_D: u8 = 0x0D;
+const CENTURY: u8 = 0x32;
+
+const A_UPDATE: u8 = 1 << 7;
+const A_DIVIDER: u8 = 0x70;
+const A_RATE: u8 = 0x0F;
+const DIVIDER_32K: u8 = 0x20;
+pub const DEFAULT_STATUS_A: u8 = 0x26;
+
+pub const B_SET: u8 = 1 << 7;
+pub const B_PERIODIC: u8 = 1 << 6;
+pub const B_ALARM: u8 = 1 << 5;
+pub const B_UPDATE: u8 = 1 << 4;
+pub const B_BINARY: u8 = 1 << 2;
+pub const B_24_HOUR: u8 = 1 << 1;
+pub const B_DAYLIGHT: u8 = 1;
+
+const C_IRQ: u8 = 1 << 7;
+const D_VALID: u8 = 1 << 7;
+
+const NMI_DISABLE: u8 = 1 << 7;
+const PM: u8 = 1 << 7;
+/// An alarm register with both top bits set matches any value.
+const DONT_CARE: u8 = 0xC0;
+
+/// The update takes 244us at the end of each second, UIP is set for that long.
+const UPDATE_TICKS: u64 = PIT_FREQUENCY * 244 / 1_000_000;
+const OSCILLATOR: u64 = 32768;
+const SECONDS_PER_DAY: u64 = 86400;
+
+pub fn to_bcd(value: u8) -> u8 {
+    ((value / 10) << 4) | (value % 10)
+}
+
+pub fn from_bcd(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// The date and time `seconds` after 1970-01-01 00:00:00, the civil from days algorithm.
+    pub fn from_seconds(seconds: u64) -> DateTime {
+        let days = (seconds / SECONDS_PER_DAY) as i64 + 719468;
+        let era = days / 146097;
+        let day_of_era = days - era * 146097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u8;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u8;
+        let year = (year_of_era + era * 400 + (month <= 2) as i64) as u16;
+        let time = seconds % SECONDS_PER_DAY;
+        DateTime { year, month, day, hour: (time / 3600) as u8, minute: (time / 60 % 60) as u8, second: (time % 60) as u8 }
+    }
+
+    /// Seconds since 1970, the other way around. Days past the end of a month run into the next.
+    pub fn to_seconds(&self) -> u64 {
+        let year = self.year as i64 - (self.month <= 2) as i64;
+        let era = year / 400;
+        let year_of_era = year - era * 400;
+        let month = self.month as i64;
+        let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + self.day as i64 - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = (era * 146097 + day_of_era - 719468).max(0) as u64;
+        days * SECONDS_PER_DAY + self.hour as u64 * 3600 + self.minute as u64 * 60 + self.second as u64
+    }
+
+    /// 1 for Sunday to 7 for Saturday. 1970-01-01 was a Thursday.
+    pub fn weekday(&self) -> u8 {
+        ((self.to_seconds() / SECONDS_PER_DAY + 4) % 7 + 1) as u8
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Cmos {
+    ram: [u8; 128],
+    index: u8,
+    pub nmi_disabled: bool,
+    seconds: u64,       // since 1970
+    ticks: u64,         // PIT ticks into the second
+    periodic: u64,      // the periodic interrupt's share of the ticks, in oscillator cycles
+    flags: u8,          // status C
+}
+
+impl Default for Cmos {
+    /// Set to the host's time.
+    fn default() -> Cmos {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+        Cmos::at(now)
+    }
+}
+
+impl Cmos {
+    /// Set to `seconds` after 1970-01-01 00:00:00.
+    pub fn at(seconds: u64) -> Cmos {
+        let mut ram = [0; 128];
+        ram[STATUS_A as usize] = DEFAULT_STATUS_A;
+        ram[STATUS_B as usize] = B_24_HOUR;
+        Cmos {
+            ram,
+            index: 0,
+            nmi_disabled: false,
+            seconds,
+            ticks: 0,
+            periodic: 0,
+            flags: 0,
+        }
+    }
+
+    pub fn date_time(&self) -> DateTime {
+        DateTime::from_seconds(self.seconds)
+    }
+
+    pub fn set_date_time(&mut self, time: DateTime) {
+        self.seconds = time.to_seconds();
+    }
+
+    /// Seconds and nanoseconds since 1970.
+    pub fn unix_time(&self) -> (u64, u32) {
+        (self.seconds, (self.ticks * 1_000_000_000 / PIT_FREQUENCY) as u32)
+    }
+
+    pub fn read(&mut self, port: u16) -> u8 {
+        match port {
+            0x71 => self.read_register(self.index),
+            _ => OPEN_BUS,
+        }
+    }
+
+    pub fn write(&mut self, port: u16, value: u8) {
+        match port {
+            0x70 => {
+                self.index = value & 0x7F;
+                self.nmi_disabled = value & NMI_DISABLE != 0;
+            },
+            0x71 => self.write_register(self.index, value),
+            _ => (),
+        }
+    }
+
+    pub fn read_register(&mut self, index: u8) -> u8 {
+        let time = self.date_time();
+        match index & 0x7F {
+            SECONDS => self.encode(time.second),
+            MINUTES => self.encode(time.minute),
+            HOURS => self.encode_hour(time.hour),
+            WEEKDAY => self.encode(time.weekday()),
+            DAY => self.encode(time.day),
+            MONTH => self.encode(time.month),
+            YEAR => self.encode((time.year % 100) as u8),
+            CENTURY => self.encode((time.year / 100) as u8),
+            STATUS_A => {
+                let updating = self.running() && self.ticks >= PIT_FREQUENCY - UPDATE_TICKS;
+                self.ram[STATUS_A as usize] | if updating { A_UPDATE } else { 0 }
+            },
+            STATUS_C => std::mem::take(&mut self.flags),
+            STATUS_D => D_VALID,
+            n => self.ram[n as usize],
+        }
+    }
+
+    /// Writing a field of the time sets it, the others staying as they are.
+    pub fn write_register(&mut self, index: u8, value: u8) {
+        let mut time = self.date_time();
+        match index & 0x7F {
+            SECONDS => time.second = self.decode(value),
+            MINUTES => time.minute = self.decode(value),
+            HOURS => time.hour = self.decode_hour(value),
+            DAY => time.day = self.decode(value),
+            MONTH => time.month = self.decode(value),
+            YEAR => time.year = time.year / 100 * 100 + self.decode(value) as u16,
+            CENTURY => time.year = self.decode(value) as u16 * 100 + time.year % 100,
+            // the weekday follows from the date
+            WEEKDAY => return,
+            STATUS_A => {
+                self.ram[STATUS_A as usize] = value & !A_UPDATE;
+                return;
+            },
+            STATUS_B => {
+                // SET stops the updates and ends the one in progress
+                if value & B_SET != 0 {
+                    self.ticks = 0;
+                }
+                self.ram[STATUS_B as usize] = if value & B_SET != 0 { value & !B_UPDATE } else { value };
+                return;
+            },
+            STATUS_C | STATUS_D => return,
+            n => {
+                self.ram[n as usize] = value;
+                return;
+            },
+        }
+        self.set_date_time(time);
+    }
+
+    fn binary(&self) -> bool {
+        self.ram[STATUS_B as usize] & B_BINARY != 0
+    }
+
+    fn encode(&self, value: u8) -> u8 {
+        if self.binary() { value } else { to_bcd(value) }
+    }
+
+    fn decode(&self, value: u8) -> u8 {
+        if self.binary() { value } else { from_bcd(value) }
+    }
+
+    fn encode_hour(&self, hour: u8) -> u8 {
+        if self.ram[STATUS_B as usize] & B_24_HOUR != 0 {
+            return self.encode(hour);
+        }
+        let twelve = if hour.is_multiple_of(12) { 12 } else { hour % 12 };
+        self.encode(twelve) | if hour >= 12 { PM } else { 0 }
+    }
+
+    fn decode_hour(&self, value: u8) -> u8 {
+        if self.ram[STATUS_B as usize] & B_24_HOUR != 0 {
+            return self.decode(value);
+        }
+        self.decode(value & !PM) % 12 + if value & PM != 0 { 12 } else { 0 }
+    }
+
+    /// The time counts while the divider is the normal one and SET is clear.
+    fn running(&self) -> bool {
+        self.ram[STATUS_A as usize] & A_DIVIDER == DIVIDER_32K && self.ram[STATUS_B as usize] & B_SET == 0
+    }
+
+    /// The periodic interrupt's frequency, none for rate 0. Rates 1 and 2 are the same as 8 and 9.
+    fn periodic_frequency(&self) -> u64 {
+        match self.ram[STATUS_A as usize] & A_RATE {
+            0 => 0,
+            rate @ 1 ..= 2 => OSCILLATOR >> (rate + 6),
+            rate => OSCILLATOR >> (rate - 1),
+        }
+    }
+
+    /// A PIT tick.
+    pub fn clock(&mut self) {
+        let frequency = self.periodic_frequency();
+        if frequency != 0 && self.ram[STATUS_A as usize] & A_DIVIDER == DIVIDER_32K {
+            self.periodic += frequency;
+            if self.periodic >= PIT_FREQUENCY {
+                self.periodic -= PIT_FREQUENCY;
+                self.raise(B_PERIODIC);
+            }
+        }
+
+        if !self.running() {
+            return;
+        }
+        self.ticks += 1;
+        if self.ticks < PIT_FREQUENCY {
+            return;
+        }
+        self.ticks = 0;
+        self.seconds += 1;
+        self.raise(B_UPDATE);
+
+        let time = self.date_time();
+        let matches = |alarm: u8, value: u8| alarm & DONT_CARE == DONT_CARE || alarm == value;
+        if matches(self.ram[ALARM_SECONDS as usize], self.encode(time.second))
+            && matches(self.ram[ALARM_MINUTES as usize], self.encode(time.minute))
+            && matches(self.ram[ALARM_HOURS as usize], self.encode_hour(time.hour)) {
+            self.raise(B_ALARM);
+        }
+    }
+
+    /// Set the flag of an interrupt source, which has the same bit in status C as its enable in B.
+    fn raise(&mut self, source: u8) {
+        self.flags |= source;
+        if self.ram[STATUS_B as usize] & source != 0 {
+            self.flags |= C_IRQ;
+        }
+    }
+
+    /// Whether an enabled interrupt will come, for a halted CPU to wait for.
+    pub fn will_interrupt(&self) -> bool {
+        let enabled = self.ram[STATUS_B as usize];
+        (enabled & B_PERIODIC != 0 && self.periodic_frequency() != 0) || (enabled & (B_UPDATE | B_ALARM) != 0 && self.running())
+    }
+
+    /// IRQ 8 is raised until status C is read.
+    pub fn interrupt(&self) -> bool {
+        self.flags & C_IRQ != 0
+    }
+}
+
+impl PortDevice for Cmos {
+    fn in8(&mut self, port: u16) -> u8 {
+        self.read(port)
+    }
+
+    fn out8(&mut self, port: u16, value: u8) {
+        self.write(port, value);
+    }
+}
+
+impl Emulator {
+    pub fn update_cmos_irq(&mut self) {
+        let high = self.cmos.interrupt();
+        self.pic.set_irq(8, high);
+    }
+}
@@ -0,0 +1,58 @@
+use super::*;
+
+pub const MAX_INSTRUCTION_LENGTH: u32 = 15;
+
+/// F3 is REP/REPE/REPZ, F2 is REPNE/REPNZ.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Rep {
+    Rep,
+    Repne,
+}
+
+/*
+ * Per-instruction decode state.
+ * `decode_prefix` fills it in before the opcode is dispatched,
+ * and every handler reads it instead of looking at prefix bytes itself.
+ */
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Decode {
+    pub eip: u32,           // address of the first byte of the instruction, prefixes included
+    pub operand_size: bool, // 0x66
+    pub address_size: bool, // 0x67
+    pub segment: Option<SegmentRegister>,
+    pub rep: Option<Rep>,
+    pub lock: bool,         // 0xF0
+    pub fetching: bool,     // between decode_prefix and the end of step, when the 15-byte limit applies
+}
+
+impl Emulator {
+    /// Consume the prefix bytes at EIP and leave EIP at the opcode.
+    /// An instruction is at most 15 bytes, so fetching a 16th raises #GP(0), see `get_code8`.
+    pub fn decode_prefix(&mut self) {
+        use crate::emulator::SegmentRegister::*;
+
+        self.decode = Decode {
+            eip: self.eip,
+            fetching: true,
+            ..Decode::default()
+        };
+
+        loop {
+            match self.get_code8(0) {
+                0x26 => self.decode.segment = Some(ES),
+                0x2E => self.decode.segment = Some(CS),
+                0x36 => self.decode.segment = Some(SS),
+                0x3E => self.decode.segment = Some(DS),
+                0x64 => self.decode.segment = Some(FS),
+                0x65 => self.decode.segment = Some(GS),
+                0x66 => self.decode.operand_size = true,
+                0x67 => self.decode.address_size = true,
+                0xF0 => self.decode.lock = true,
+                0xF2 => self.decode.rep = Some(Rep::Repne),
+                0xF3 => self.decode.rep = Some(Rep::Rep),
+                _ => break,
+            }
+            self.eip += 1;
+        }
+    }
+}
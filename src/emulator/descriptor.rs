@@ -0,0 +1,188 @@
+use super::*;
+use crate::emulator::segment::Segment;
+use crate::emulator::SegmentRegister::*;
+use crate::emulator::exception::Exception::*;
+
+/// GDTR or IDTR: linear address of the table and the offset of its last byte.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct DescriptorTable {
+    pub base: u32,
+    pub limit: u16,
+}
+
+/*
+ *  segment descriptor
+ *  +-------+-+-+-+-+-------+--------+--------------------+
+ *  |63   56|G|D|0|A|51   48|47    40|39                16|15     0
+ *  +-------+-+-+-+-+-------+--------+--------------------+-------+
+ *  | base  |flags  | limit | access |        base        | limit |
+ *  | 31-24 |       | 19-16 |        |        23-0        | 15-0  |
+ *  +-------+-------+-------+--------+--------------------+-------+
+ *  G: the limit counts 4KiB pages, D: 32-bit segment, A: available to software
+ */
+impl Segment {
+    pub fn from_descriptor(selector: u16, raw: u64) -> Segment {
+        let high = (raw >> 32) as u32;
+        let limit = (raw as u32 & 0xFFFF) | (high & 0x000F0000);
+        let limit = if high & (1 << 23) != 0 {
+            (limit << 12) | 0xFFF
+        } else {
+            limit
+        };
+
+        Segment {
+            selector,
+            base: ((raw >> 16) as u32 & 0x00FFFFFF) | (high & 0xFF000000),
+            limit,
+            access: (high >> 8) as u8,
+            big: high & (1 << 22) != 0,
+        }
+    }
+}
+
+/*
+ *  selector
+ *  +---------------------+--+-----+
+ *  |15                  3| 2| 1  0|
+ *  +---------------------+--+-----+
+ *  |        index        |TI| RPL |
+ *  +---------------------+--+-----+
+ *  TI selects the LDT, which isn't supported, so it always faults.
+ */
+pub fn selector_error_code(selector: u16) -> u16 {
+    selector & 0xFFFC
+}
+
+impl Emulator {
+    /// Fetch the descriptor for `selector` from the GDT, #GP(selector) if it's outside the table.
+    pub fn read_descriptor(&mut self, selector: u16) -> Option<u64> {
+        let offset = (selector & 0xFFF8) as u32;
+        if selector & 4 != 0 || offset + 7 > self.gdtr.limit as u32 {
+            self.raise(GeneralProtection(selector_error_code(selector)));
+            return None;
+        }
+
+        let addr = self.gdtr.base.wrapping_add(offset);
+        let low = self.read_system32(addr) as u64;
+        let high = self.read_system32(addr.wrapping_add(4)) as u64;
+        Some(low | (high << 32))
+    }
+
+    /// Loading a descriptor sets its accessed bit in the table.
+    fn mark_accessed(&mut self, selector: u16) {
+        let addr = self.gdtr.base.wrapping_add((selector & 0xFFF8) as u32 + 5);
+        let access = self.read_linear8(addr, false);
+        self.write_system8(addr, access | 1);
+    }
+
+    /*
+     * SS needs a present writable data segment at exactly CPL.
+     * DS, ES, FS and GS take readable segments the current privilege may use,
+     * and a null selector, which leaves the register unusable until it's reloaded.
+     */
+    pub fn load_protected_segment(&mut self, sreg: SegmentRegister, selector: u16) {
+        let error = selector_error_code(selector);
+        let cpl = self.cpl();
+        let rpl = (selector & 3) as u8;
+
+        if error == 0 {
+            if sreg == SS {
+                self.raise(GeneralProtection(0));
+            } else {
+                self.segments[sreg as usize] = Segment::unusable(selector);
+            }
+            return;
+        }
+
+        let raw = match self.read_descriptor(selector) {
+            Some(raw) => raw,
+            None => return,
+        };
+        let segment = Segment::from_descriptor(selector, raw);
+
+        if sreg == SS {
+            if rpl != cpl || segment.dpl() != cpl || !segment.is_writable() {
+                self.raise(GeneralProtection(error));
+                return;
+            }
+            if !segment.is_present() {
+                self.raise(StackFault(error));
+                return;
+            }
+        } else {
+            let privileged = segment.is_data() || !segment.is_conforming();
+            if !segment.is_readable() || (privileged && segment.dpl() < cpl.max(rpl)) {
+                self.raise(GeneralProtection(error));
+                return;
+            }
+            if !segment.is_present() {
+                self.raise(SegmentNotPresent(error));
+                return;
+            }
+        }
+
+        self.mark_accessed(selector);
+        self.segments[sreg as usize] = Segment {
+            access: segment.access | 1,
+            ..segment
+        };
+    }
+
+    /*
+     * CS for a far JMP, CALL or RET, which runs the new code at privilege `cpl`.
+     * A non-conforming segment has to be at exactly that level,
+     * a conforming one at that level or a more privileged one.
+     * The loaded selector's RPL becomes the new CPL.
+     */
+    pub fn load_code_segment(&mut self, selector: u16, cpl: u8) {
+        if !self.is_protected_mode() {
+            self.segments[CS as usize] = Segment::real(selector);
+            return;
+        }
+
+        let error = selector_error_code(selector);
+        if error == 0 {
+            self.raise(GeneralProtection(0));
+            return;
+        }
+
+        let raw = match self.read_descriptor(selector) {
+            Some(raw) => raw,
+            None => return,
+        };
+        let segment = Segment::from_descriptor(selector, raw);
+
+        let allowed = segment.is_code() && if segment.is_conforming() {
+            segment.dpl() <= cpl
+        } else {
+            segment.dpl() == cpl && (selector & 3) as u8 <= cpl
+        };
+        if !allowed {
+            self.raise(GeneralProtection(error));
+            return;
+        }
+        if !segment.is_present() {
+            self.raise(SegmentNotPresent(error));
+            return;
+        }
+
+        self.mark_accessed(selector);
+        self.segments[CS as usize] = Segment {
+            selector: error | cpl as u16,
+            access: segment.access | 1,
+            ..segment
+        };
+    }
+
+    /// After a return to an outer level, data segments the new CPL can't use are nulled.
+    pub fn invalidate_data_segments(&mut self) {
+        let cpl = self.cpl();
+        for sreg in [ES, DS, FS, GS].iter() {
+            let segment = self.get_segment(*sreg);
+            let privileged = segment.is_data() || !segment.is_conforming();
+            if privileged && segment.dpl() < cpl {
+                self.segments[*sreg as usize] = Segment::unusable(0);
+            }
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use super::*;
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
+
+/*
+ * Disks are raw images, sector 0 first, addressed by the BIOS drive number:
+ * 0x00 and 0x01 are floppies, 0x80 and 0x81 hard disks.
+ * A CHS address reaches a sector through the geometry, an LBA one directly.
+ *
+ *  LBA = (cylinder * heads + head) * sectors + sector - 1
+ */
+pub const SECTOR_SIZE: usize = 512;
+
+pub trait Storage {
+    /// Fill `buffer` from `offset`, with zeros past the end of the image.
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<()>;
+    fn write_at(&mut self, offset: u64, buffer: &[u8]) -> io::Result<()>;
+    fn size(&self) -> u64;
+}
+
+pub type SharedStorage = Rc<RefCell<dyn Storage>>;
+
+impl Storage for File {
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        let mut read = 0;
+        while read < buffer.len() {
+            match self.read(&mut buffer[read ..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        buffer[read ..].iter_mut().for_each(|byte| *byte = 0);
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, buffer: &[u8]) -> io::Result<()> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.write_all(buffer)
+    }
+
+    fn size(&self) -> u64 {
+        self.metadata().map(|metadata| metadata.len()).unwrap_or(0)
+    }
+}
+
+/// An image in memory, for tests.
+impl Storage for Vec<u8> {
+    fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+        for (i, byte) in buffer.iter_mut().enumerate() {
+            *byte = self.get(offset as usize + i).copied().unwrap_or(0);
+        }
+        Ok(())
+    }
+
+    fn write_at(&mut self, offset: u64, buffer: &[u8]) -> io::Result<()> {
+        let end = offset as usize + buffer.len();
+        if end > self.len() {
+            self.resize(end, 0);
+        }
+        self[offset as usize .. end].copy_from_slice(buffer);
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Geometry {
+    pub cylinders: u32,
+    pub heads: u32,
+    pub sectors: u32,       // per track
+}
+
+/// The standard floppy formats, by size in KB, and the drive type INT 13h AH=08 reports for each.
+const FLOPPY_FORMATS: [(u64, Geometry, u8); 7] = [
+    (160,  Geometry { cylinders: 40, heads: 1, sectors: 8 },  1),
+    (180,  Geometry { cylinders: 40, heads: 1, sectors: 9 },  1),
+    (320,  Geometry { cylinders: 40, heads: 2, sectors: 8 },  1),
+    (360,  Geometry { cylinders: 40, heads: 2, sectors: 9 },  1),
+    (720,  Geometry { cylinders: 80, heads: 2, sectors: 9 },  3),
+    (1200, Geometry { cylinders: 80, heads: 2, sectors: 15 }, 2),
+    (1440, Geometry { cylinders: 80, heads: 2, sectors: 18 }, 4),
+];
+const FLOPPY_2880: (Geometry, u8) = (Geometry { cylinders: 80, heads: 2, sectors: 36 }, 6);
+
+/// Hard disks get the usual translated geometry, as many cylinders of 16 heads of 63 sectors as fit in CHS.
+const DISK_HEADS: u32 = 16;
+const DISK_SECTORS: u32 = 63;
+const MAX_CYLINDERS: u32 = 1024;
+
+#[derive(Clone)]
+pub struct Disk {
+    pub image: SharedStorage,
+    pub geometry: Geometry,
+    pub floppy_type: u8,    // 0 for a hard disk
+    pub read_only: bool,
+}
+
+impl fmt::Debug for Disk {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Disk")
+            .field("geometry", &self.geometry)
+            .field("floppy_type", &self.floppy_type)
+            .field("read_only", &self.read_only)
+            .finish()
+    }
+}
+
+impl Disk {
+    /// A floppy in the smallest standard format that holds the image.
+    pub fn floppy(image: SharedStorage) -> Disk {
+        let kilobytes = image.borrow().size().div_ceil(1024);
+        let (geometry, floppy_type) = FLOPPY_FORMATS.iter()
+            .find(|(size, _, _)| kilobytes <= *size)
+            .map(|(_, geometry, floppy_type)| (*geometry, *floppy_type))
+            .unwrap_or(FLOPPY_2880);
+        Disk { image, geometry, floppy_type, read_only: false }
+    }
+
+    pub fn hard_disk(image: SharedStorage) -> Disk {
+        let sectors = image.borrow().size().div_ceil(SECTOR_SIZE as u64);
+        let track = (DISK_HEADS * DISK_SECTORS) as u64;
+        let cylinders = sectors.div_ceil(track).clamp(1, MAX_CYLINDERS as u64) as u32;
+        let geometry = Geometry { cylinders, heads: DISK_HEADS, sectors: DISK_SECTORS };
+        Disk { image, geometry, floppy_type: 0, read_only: false }
+    }
+
+    /// Open an image file, read-only if it can't be written.
+    pub fn open(path: &str, floppy: bool) -> io::Result<Disk> {
+        let (file, read_only) = match OpenOptions::new().read(true).write(true).open(path) {
+            Ok(file) => (file, false),
+            Err(_) => (File::open(path)?, true),
+        };
+        let image: SharedStorage = Rc::new(RefCell::new(file));
+        let disk = if floppy { Disk::floppy(image) } else { Disk::hard_disk(image) };
+        Ok(Disk { read_only, ..disk })
+    }
+
+    /// Sectors in the image, a partial last one included.
+    pub fn sectors(&self) -> u64 {
+        self.image.borrow().size().div_ceil(SECTOR_SIZE as u64)
+    }
+
+    /// The LBA of a CHS address, None if the geometry has no such sector.
+    pub fn lba(&self, cylinder: u32, head: u32, sector: u32) -> Option<u64> {
+        let Geometry { cylinders, heads, sectors } = self.geometry;
+        if cylinder >= cylinders || head >= heads || sector == 0 || sector > sectors {
+            return None;
+        }
+        Some(((cylinder * heads + head) * sectors + sector - 1) as u64)
+    }
+
+    pub fn read(&self, lba: u64, buffer: &mut [u8]) -> io::Result<()> {
+        self.image.borrow_mut().read_at(lba * SECTOR_SIZE as u64, buffer)
+    }
+
+    pub fn write(&self, lba: u64, buffer: &[u8]) -> io::Result<()> {
+        self.image.borrow_mut().write_at(lba * SECTOR_SIZE as u64, buffer)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Disks {
+    pub floppies: [Option<Disk>; 2],
+    pub hard_disks: [Option<Disk>; 2],
+}
+
+impl Disks {
+    pub fn attach(&mut self, drive: u8, disk: Disk) {
+        if let Some(slot) = self.slot(drive) {
+            *slot = Some(disk);
+        }
+    }
+
+    pub fn get(&self, drive: u8) -> Option<&Disk> {
+        match drive {
+            0x00 | 0x01 => self.floppies[drive as usize].as_ref(),
+            0x80 | 0x81 => self.hard_disks[drive as usize - 0x80].as_ref(),
+            _ => None,
+        }
+    }
+
+    fn slot(&mut self, drive: u8) -> Option<&mut Option<Disk>> {
+        match drive {
+            0x00 | 0x01 => Some(&mut self.floppies[drive as usize]),
+            0x80 | 0x81 => Some(&mut self.hard_disks[drive as usize - 0x80]),
+            _ => None,
+        }
+    }
+
+    pub fn floppy_count(&self) -> u8 {
+        self.floppies.iter().filter(|disk| disk.is_some()).count() as u8
+    }
+
+    pub fn hard_disk_count(&self) -> u8 {
+        self.hard_disks.iter().filter(|disk| disk.is_some()).count() as u8
+    }
+}
@@ -0,0 +1,257 @@
+use super::*;
+
+/*
+ * ELF32 executables for i386, the way `gcc -m32 -static -nostdlib` links them.
+ * Each PT_LOAD segment is copied to its virtual address, which is its physical one
+ * in the flat protected mode the emulator starts in, and the rest of it up to p_memsz, the BSS, zeroed.
+ * The symbol table is kept to put names on addresses.
+ *
+ *  ELF header              program header          section header          symbol
+ *  0x00 16  e_ident        0x00  p_type            0x00  sh_name           0x00  st_name
+ *  0x10  2  e_type         0x04  p_offset          0x04  sh_type           0x04  st_value
+ *  0x12  2  e_machine      0x08  p_vaddr           0x10  sh_offset         0x08  st_size
+ *  0x18  4  e_entry        0x10  p_filesz          0x14  sh_size           0x0C  st_info
+ *  0x1C  4  e_phoff        0x14  p_memsz           0x18  sh_link           0x0E  st_shndx
+ *  0x20  4  e_shoff
+ *  0x2C  2  e_phnum, 0x30  e_shnum, with the entry sizes right before each
+ */
+const ELF_MAGIC: &[u8; 4] = b"\x7FELF";
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const ET_EXEC: u16 = 2;
+const EM_386: u16 = 3;
+
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+
+/// Where segments may go: memory grows to hold them, but not past this.
+pub const MAX_MEMORY: u32 = 256 << 20;
+
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+const SHN_UNDEF: u16 = 0;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ElfError {
+    NotElf,
+    Unsupported(&'static str),
+    Truncated,
+    TooLarge(u32),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ElfError::NotElf => write!(f, "Not an ELF file"),
+            ElfError::Unsupported(what) => write!(f, "Unsupported ELF file: {}", what),
+            ElfError::Truncated => write!(f, "Truncated ELF file"),
+            ElfError::TooLarge(address) => write!(f, "Segment at 0x{:X} doesn't fit in memory", address),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u32,
+    pub size: u32,
+}
+
+/// The symbols of the program, by address.
+#[derive(Debug, Clone, Default)]
+pub struct Symbols {
+    symbols: Vec<Symbol>,
+}
+
+impl Symbols {
+    pub fn new(mut symbols: Vec<Symbol>) -> Symbols {
+        symbols.sort_by_key(|symbol| symbol.address);
+        Symbols { symbols }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|symbol| symbol.name == name)
+    }
+
+    /// The symbol an address is in and the offset into it: the last one at or below it,
+    /// as long as the address is inside it, or it has no size.
+    pub fn lookup(&self, address: u32) -> Option<(&Symbol, u32)> {
+        let index = self.symbols.partition_point(|symbol| symbol.address <= address);
+        let symbol = self.symbols[.. index].last()?;
+        let offset = address - symbol.address;
+        if symbol.size != 0 && offset >= symbol.size {
+            return None;
+        }
+        Some((symbol, offset))
+    }
+
+    /// `name+0x12`, or nothing for an address outside every symbol.
+    pub fn describe(&self, address: u32) -> Option<String> {
+        self.lookup(address).map(|(symbol, offset)| match offset {
+            0 => symbol.name.clone(),
+            _ => format!("{}+0x{:x}", symbol.name, offset),
+        })
+    }
+}
+
+/// A PT_LOAD segment: where it goes, the bytes of the file that go there, and its size in memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadSegment {
+    pub address: u32,
+    pub data: Vec<u8>,
+    pub size: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Elf {
+    pub entry: u32,
+    pub segments: Vec<LoadSegment>,
+    pub symbols: Symbols,
+    pub program_headers: u32,       // where a segment loads them, 0 if none does
+    pub program_header_count: u16,
+}
+
+pub fn is_elf(bytes: &[u8]) -> bool {
+    bytes.starts_with(ELF_MAGIC)
+}
+
+fn read16(bytes: &[u8], offset: usize) -> Result<u16, ElfError> {
+    let field = bytes.get(offset .. offset + 2).ok_or(ElfError::Truncated)?;
+    Ok(u16::from_le_bytes([field[0], field[1]]))
+}
+
+fn read32(bytes: &[u8], offset: usize) -> Result<u32, ElfError> {
+    let field = bytes.get(offset .. offset + 4).ok_or(ElfError::Truncated)?;
+    Ok(u32::from_le_bytes([field[0], field[1], field[2], field[3]]))
+}
+
+fn slice(bytes: &[u8], offset: u32, size: u32) -> Result<&[u8], ElfError> {
+    let end = offset.checked_add(size).ok_or(ElfError::Truncated)?;
+    bytes.get(offset as usize .. end as usize).ok_or(ElfError::Truncated)
+}
+
+/// The NUL terminated string at `offset` in a string table.
+fn string(table: &[u8], offset: u32) -> String {
+    let bytes = table.get(offset as usize ..).unwrap_or(&[]);
+    let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[.. end]).into_owned()
+}
+
+impl Elf {
+    pub fn parse(bytes: &[u8]) -> Result<Elf, ElfError> {
+        if !is_elf(bytes) {
+            return Err(ElfError::NotElf);
+        }
+        if bytes.get(4) != Some(&ELFCLASS32) {
+            return Err(ElfError::Unsupported("not 32-bit"));
+        }
+        if bytes.get(5) != Some(&ELFDATA2LSB) {
+            return Err(ElfError::Unsupported("not little-endian"));
+        }
+        if read16(bytes, 0x10)? != ET_EXEC {
+            return Err(ElfError::Unsupported("not an executable"));
+        }
+        if read16(bytes, 0x12)? != EM_386 {
+            return Err(ElfError::Unsupported("not for i386"));
+        }
+
+        let entry = read32(bytes, 0x18)?;
+        let phoff = read32(bytes, 0x1C)? as usize;
+        let phentsize = read16(bytes, 0x2A)? as usize;
+        let phnum = read16(bytes, 0x2C)?;
+        let mut segments = vec![];
+        let mut program_headers = 0;
+        for i in 0..phnum as usize {
+            let header = phoff + i * phentsize;
+            if read32(bytes, header)? != PT_LOAD {
+                continue;
+            }
+            let offset = read32(bytes, header + 0x04)?;
+            let address = read32(bytes, header + 0x08)?;
+            let file_size = read32(bytes, header + 0x10)?;
+            let size = read32(bytes, header + 0x14)?;
+            if file_size > size {
+                return Err(ElfError::Unsupported("segment larger in the file than in memory"));
+            }
+            let data = slice(bytes, offset, file_size)?.to_vec();
+            // the program headers, if they're in what this segment loads
+            if let Some(into) = (phoff as u32).checked_sub(offset) {
+                if into as usize + phnum as usize * phentsize <= file_size as usize {
+                    program_headers = address.wrapping_add(into);
+                }
+            }
+            segments.push(LoadSegment { address, data, size });
+        }
+
+        Ok(Elf { entry, segments, symbols: Elf::parse_symbols(bytes)?, program_headers, program_header_count: phnum })
+    }
+
+    /// The defined symbols in the SHT_SYMTAB section, named from the string table it links to.
+    fn parse_symbols(bytes: &[u8]) -> Result<Symbols, ElfError> {
+        let shoff = read32(bytes, 0x20)? as usize;
+        let shentsize = read16(bytes, 0x2E)? as usize;
+        let shnum = read16(bytes, 0x30)? as usize;
+        // a section's contents are only read when needed: .bss has none in the file
+        let header = |index: usize| shoff + index * shentsize;
+        let contents = |index: usize| slice(bytes, read32(bytes, header(index) + 0x10)?, read32(bytes, header(index) + 0x14)?);
+
+        let mut symbols = vec![];
+        for i in 0..shnum {
+            if read32(bytes, header(i) + 0x04)? != SHT_SYMTAB {
+                continue;
+            }
+            let table = contents(i)?;
+            let names = contents(read32(bytes, header(i) + 0x18)? as usize)?;
+            for entry in table.chunks_exact(16) {
+                let kind = entry[12] & 0x0F;
+                let name = string(names, read32(entry, 0)?);
+                if name.is_empty() || kind == STT_SECTION || kind == STT_FILE || read16(entry, 14)? == SHN_UNDEF {
+                    continue;
+                }
+                symbols.push(Symbol { name, address: read32(entry, 4)?, size: read32(entry, 8)? });
+            }
+        }
+        Ok(Symbols::new(symbols))
+    }
+}
+
+impl Emulator {
+    /// An address for a trace or a crash report: `0x8049000 <main+0x10>`, or just the number.
+    pub fn symbolize(&self, address: u32) -> String {
+        match self.symbols.describe(address) {
+            Some(name) => format!("0x{:X} <{}>", address, name),
+            None => format!("0x{:X}", address),
+        }
+    }
+
+    /*
+     * Load an ELF executable and start at its entry point, memory growing to hold the segments if it has to,
+     * up to MAX_MEMORY. The registers and segments stay as they are, the flat 32-bit ones by default.
+     */
+    pub fn load_elf(&mut self, bytes: &[u8]) -> Result<Elf, ElfError> {
+        let elf = Elf::parse(bytes)?;
+        let mut end = 0;
+        for segment in elf.segments.iter() {
+            match segment.address.checked_add(segment.size) {
+                Some(last) if last <= MAX_MEMORY => end = end.max(last as usize),
+                _ => return Err(ElfError::TooLarge(segment.address)),
+            }
+        }
+        if end > self.memory.len() {
+            self.memory.resize(end, 0);
+        }
+        for segment in elf.segments.iter() {
+            for i in 0..segment.size {
+                let byte = segment.data.get(i as usize).copied().unwrap_or(0);
+                self.write_physical8(segment.address + i, byte);
+            }
+        }
+        self.eip = elf.entry;
+        self.symbols = elf.symbols.clone();
+        Ok(elf)
+    }
+}
@@ -0,0 +1,108 @@
+use super::*;
+
+/*
+ * CPU exceptions raised by instruction handlers.
+ * A handler calls `raise` and returns, `execute` rolls the instruction back and delivers it.
+ * Memory writes are dropped from the moment an exception is pending,
+ * so a faulting instruction doesn't leave half of its stores behind.
+ * The u16 is the error code, a selector or 0.
+ */
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Exception {
+    DivideError,
+    InvalidOpcode,
+    DoubleFault,
+    InvalidTss(u16),
+    SegmentNotPresent(u16),
+    StackFault(u16),
+    GeneralProtection(u16),
+    PageFault(u16),
+}
+
+use self::Exception::*;
+
+impl Exception {
+    pub fn vector(self) -> u8 {
+        match self {
+            DivideError => 0x00,
+            InvalidOpcode => 0x06,
+            DoubleFault => 0x08,
+            InvalidTss(_) => 0x0A,
+            SegmentNotPresent(_) => 0x0B,
+            StackFault(_) => 0x0C,
+            GeneralProtection(_) => 0x0D,
+            PageFault(_) => 0x0E,
+        }
+    }
+
+    /// #DF always pushes 0.
+    pub fn error_code(self) -> Option<u16> {
+        match self {
+            DivideError | InvalidOpcode => None,
+            DoubleFault => Some(0),
+            InvalidTss(code) | SegmentNotPresent(code) | StackFault(code) | GeneralProtection(code) | PageFault(code) => Some(code),
+        }
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            DivideError => "#DE",
+            InvalidOpcode => "#UD",
+            DoubleFault => "#DF",
+            InvalidTss(_) => "#TS",
+            SegmentNotPresent(_) => "#NP",
+            StackFault(_) => "#SS",
+            GeneralProtection(_) => "#GP",
+            PageFault(_) => "#PF",
+        }
+    }
+
+    pub fn is_contributory(self) -> bool {
+        matches!(self, DivideError | InvalidTss(_) | SegmentNotPresent(_) | StackFault(_) | GeneralProtection(_))
+    }
+
+    /*
+     * Whether `second`, raised while delivering this exception, turns into a double fault.
+     * Otherwise the two are handled serially and the second one replaces the first.
+     *
+     *                     second: benign  contributory  page fault
+     *  first: benign              serial  serial        serial
+     *         contributory        serial  #DF           serial
+     *         page fault          serial  #DF           #DF
+     */
+    pub fn is_double_fault(self, second: Exception) -> bool {
+        match self {
+            PageFault(_) => second.is_contributory() || matches!(second, PageFault(_)),
+            _ => self.is_contributory() && second.is_contributory(),
+        }
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            DivideError => "Divide error",
+            InvalidOpcode => "Invalid opcode",
+            DoubleFault => "Double fault",
+            InvalidTss(_) => "Invalid TSS",
+            SegmentNotPresent(_) => "Segment not present",
+            StackFault(_) => "Stack fault",
+            GeneralProtection(_) => "General protection",
+            PageFault(_) => "Page fault",
+        };
+
+        match self.error_code() {
+            Some(code) => write!(f, "{} {} (error code 0x{:04X})", self.mnemonic(), name, code),
+            None => write!(f, "{} {}", self.mnemonic(), name),
+        }
+    }
+}
+
+impl Emulator {
+    /// The first exception of an instruction wins, later ones are consequences of it.
+    pub fn raise(&mut self, exception: Exception) {
+        if self.exception.is_none() {
+            self.exception = Some(exception);
+        }
+    }
+}
@@ -2,361 +2,2206 @@ use super::*;
 use crate::emulator::modrm::*;
 #[allow(unused_imports)]
 use crate::emulator::bios::*;
-#[allow(unused_imports)]
 use crate::emulator::RegisterHigh::*;
 use crate::emulator::RegisterLow::*;
-use crate::emulator::io;
+use crate::emulator::exception::Exception::*;
+use crate::emulator::decode::Rep;
+use crate::emulator::segment::segment_register;
+use crate::emulator::descriptor::DescriptorTable;
+use crate::emulator::SegmentRegister::*;
+
+type Instruction = fn(&mut Emulator);
+
+const ALU_ADD: u8 = 0;
+const ALU_OR:  u8 = 1;
+const ALU_ADC: u8 = 2;
+const ALU_SBB: u8 = 3;
+const ALU_AND: u8 = 4;
+const ALU_SUB: u8 = 5;
+const ALU_XOR: u8 = 6;
+const ALU_CMP: u8 = 7;
+
+const SHIFT_ROL: u8 = 0;
+const SHIFT_ROR: u8 = 1;
+const SHIFT_RCL: u8 = 2;
+const SHIFT_RCR: u8 = 3;
+const SHIFT_SHL: u8 = 4;
+const SHIFT_SHR: u8 = 5;
+const SHIFT_SAL: u8 = 6;
+const SHIFT_SAR: u8 = 7;
+
+impl Emulator {
+    fn mov_r32_imm32(&mut self) {
+        let reg = self.get_code8(0) - 0xB8; // 0xB8 == registers[0]
+        let value = self.get_codev(1);
+        self.set_registerv(reg as usize, value);
+        self.eip += 1 + self.operand_bytes();
+    }
+
+    fn mov_rm32_imm32(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let value = self.get_codev(0);
+
+        self.eip += self.operand_bytes();
+        self.set_rmv(&modrm, value);
+    }
+
+    fn mov_rm32_r32(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let r32 = self.get_rv(&modrm);
+
+        self.set_rmv(&modrm, r32);
+    }
+
+    fn mov_r32_rm32(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let rm32 = self.get_rmv(&modrm);
+        self.set_rv(&modrm, rm32);
+    }
+
+    fn mov_r8_imm8(&mut self) {
+        let reg = self.get_code8(0) - 0xB0;
+        let imm8 = self.get_code8(1);
+        self.set_register8(reg as usize, imm8);
+        self.eip += 2;
+    }
+
+    fn mov_r8_rm8(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let rm8 = self.get_rm8(&modrm);
+        self.set_r8(&modrm, rm8);
+    }
+
+    fn mov_rm8_r8(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let r8 = self.get_r8(&modrm);
+        self.set_rm8(&modrm, r8);
+    }
+
+    fn mov_rm8_imm8(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let value = self.get_code8(0);
+
+        self.eip += 1;
+        self.set_rm8(&modrm, value);
+    }
+
+    fn lea_r32_m32(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        if !self.check_memory_operand(&modrm) {
+            return;
+        }
+        let addr = self.calc_effective_address(&modrm);
+        self.set_rv(&modrm, addr);
+    }
+
+    /// Stores the selector. A register destination is written with the full operand size.
+    fn mov_rm16_sreg(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let sreg = match self.sreg_operand(&modrm) {
+            Some(sreg) => sreg,
+            None => return,
+        };
+        let selector = self.get_segment(sreg).selector;
+        if modrm.mod_byte == 0b11 {
+            self.set_registerv(modrm.rm as usize, selector as u32);
+        } else {
+            self.set_rm16(&modrm, selector);
+        }
+    }
+
+    /// CS can only be loaded by far transfers, MOV CS is invalid.
+    fn mov_sreg_rm16(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let sreg = match self.sreg_operand(&modrm) {
+            Some(CS) => return self.raise(InvalidOpcode),
+            Some(sreg) => sreg,
+            None => return,
+        };
+        let selector = self.get_rm16(&modrm);
+        self.load_segment(sreg, selector);
+        if sreg == SS {
+            self.interrupt_shadow = true;
+        }
+    }
+
+    /// A direct memory offset (moffs) as wide as the address size, in DS unless overridden.
+    fn moffs_address(&mut self, bytes: u32, write: bool) -> u32 {
+        let offset = match self.address_bits() {
+            16 => self.get_code16(1) as u32,
+            _ => self.get_code32(1),
+        };
+        let sreg = self.data_segment(DS);
+        self.segment_address(sreg, offset, bytes, write)
+    }
+
+    fn mov_al_moffs8(&mut self) {
+        let addr = self.moffs_address(1, false);
+        let value = self.get_memory8(addr);
+        self.set_register8(AL as usize, value);
+        self.eip += 1 + self.address_bits() / 8;
+    }
+
+    fn mov_eax_moffs32(&mut self) {
+        let addr = self.moffs_address(self.operand_bytes(), false);
+        let value = self.get_memoryv(addr);
+        self.set_registerv(EAX as usize, value);
+        self.eip += 1 + self.address_bits() / 8;
+    }
+
+    fn mov_moffs8_al(&mut self) {
+        let addr = self.moffs_address(1, true);
+        self.set_memory8(addr, self.get_register8(AL as usize) as u32);
+        self.eip += 1 + self.address_bits() / 8;
+    }
+
+    fn mov_moffs32_eax(&mut self) {
+        let addr = self.moffs_address(self.operand_bytes(), true);
+        self.set_memoryv(addr, self.get_registerv(EAX as usize));
+        self.eip += 1 + self.address_bits() / 8;
+    }
+
+    /*
+     * LDS, LES, LSS, LFS, LGS: load a far pointer m16:16 or m16:32,
+     * the offset into r32 and the selector that follows it into the segment register.
+     */
+    fn load_far_pointer(&mut self, sreg: SegmentRegister) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let (selector, offset) = self.read_far_pointer(&modrm);
+        self.load_segment(sreg, selector);
+        self.set_rv(&modrm, offset);
+    }
+
+    fn les(&mut self) {
+        self.load_far_pointer(ES);
+    }
+
+    fn lds(&mut self) {
+        self.load_far_pointer(DS);
+    }
+
+    fn lss(&mut self) {
+        self.load_far_pointer(SS);
+    }
+
+    fn lfs(&mut self) {
+        self.load_far_pointer(FS);
+    }
+
+    fn lgs(&mut self) {
+        self.load_far_pointer(GS);
+    }
+
+    fn xchg_rm8_r8(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let r8 = self.get_r8(&modrm);
+        let rm8 = self.get_rm8(&modrm);
+        self.set_rm8(&modrm, r8);
+        self.set_r8(&modrm, rm8);
+    }
+
+    fn xchg_rm32_r32(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let r32 = self.get_rv(&modrm);
+        let rm32 = self.get_rmv(&modrm);
+        self.set_rmv(&modrm, r32);
+        self.set_rv(&modrm, rm32);
+    }
+
+    fn xchg_eax_r32(&mut self) {
+        let reg = self.get_code8(0) - 0x90;
+        let eax = self.get_registerv(EAX as usize);
+        self.set_registerv(EAX as usize, self.get_registerv(reg as usize));
+        self.set_registerv(reg as usize, eax);
+        self.eip += 1;
+    }
+
+    fn nop(&mut self) {
+        self.eip += 1;
+    }
+
+    /// CBW with a 16-bit operand size.
+    fn cwde(&mut self) {
+        if self.operand_bits() == 16 {
+            let al = self.get_register8(AL as usize);
+            self.set_register16(EAX as usize, al as i8 as i16 as u16);
+        } else {
+            let ax = self.get_register16(EAX as usize);
+            self.set_register32(EAX as usize, ax as i16 as i32 as u32);
+        }
+        self.eip += 1;
+    }
+
+    /// CWD with a 16-bit operand size.
+    fn cdq(&mut self) {
+        if self.operand_bits() == 16 {
+            let ax = self.get_register16(EAX as usize);
+            self.set_register16(EDX as usize, ((ax as i16) >> 15) as u16);
+        } else {
+            let eax = self.get_register32(EAX as usize);
+            self.set_register32(EDX as usize, ((eax as i32) >> 31) as u32);
+        }
+        self.eip += 1;
+    }
+
+    fn in_al_dx(&mut self) {
+        if !self.check_io_privilege() {
+            return;
+        }
+        let addr = self.get_register32(EDX as usize) & 0xFFFF;
+        let value = self.port_in8(addr as u16);
+        self.set_register8(AL as usize, value);
+        self.eip += 1;
+    }
+
+    fn out_dx_al(&mut self) {
+        if !self.check_io_privilege() {
+            return;
+        }
+        let addr = self.get_register32(EDX as usize) & 0xFFFF;
+        let value = self.get_register8(AL as usize);
+        self.port_out8(addr as u16, value);
+        self.eip += 1;
+    }
+
+    fn in_al_imm8(&mut self) {
+        if !self.check_io_privilege() {
+            return;
+        }
+        let addr = self.get_code8(1);
+        let value = self.port_in8(addr as u16);
+        self.set_register8(AL as usize, value);
+        self.eip += 2;
+    }
+
+    fn out_imm8_al(&mut self) {
+        if !self.check_io_privilege() {
+            return;
+        }
+        let addr = self.get_code8(1);
+        let value = self.get_register8(AL as usize);
+        self.port_out8(addr as u16, value);
+        self.eip += 2;
+    }
+
+    /// AX or EAX, whichever the operand size picks.
+    fn in_eax(&mut self, port: u16) {
+        let value = self.port_in(port, self.operand_bytes());
+        self.set_registerv(EAX as usize, value);
+    }
+
+    fn out_eax(&mut self, port: u16) {
+        let value = self.get_registerv(EAX as usize);
+        self.port_out(port, value, self.operand_bytes());
+    }
+
+    fn in_eax_dx(&mut self) {
+        if !self.check_io_privilege() {
+            return;
+        }
+        let port = self.get_register16(EDX as usize);
+        self.in_eax(port);
+        self.eip += 1;
+    }
+
+    fn out_dx_eax(&mut self) {
+        if !self.check_io_privilege() {
+            return;
+        }
+        let port = self.get_register16(EDX as usize);
+        self.out_eax(port);
+        self.eip += 1;
+    }
+
+    fn in_eax_imm8(&mut self) {
+        if !self.check_io_privilege() {
+            return;
+        }
+        let port = self.get_code8(1) as u16;
+        self.in_eax(port);
+        self.eip += 2;
+    }
+
+    fn out_imm8_eax(&mut self) {
+        if !self.check_io_privilege() {
+            return;
+        }
+        let port = self.get_code8(1) as u16;
+        self.out_eax(port);
+        self.eip += 2;
+    }
+
+    /*
+     * ADD, OR, ADC, SBB, AND, SUB, XOR, CMP share one encoding scheme.
+     * In 0x00 - 0x3F the operation is opcode bits 3-5, in 0x80 - 0x83 it is the REG field.
+     *  +--+--+--+--+--+--+--+--+
+     *  | 7| 6| 5| 4| 3| 2| 1| 0|
+     *  +-----+--------+--------+
+     *  | 0  0|   op   |  form  |
+     *  +-----+--------+--------+
+     */
+    fn alu(&mut self, op: u8, v1: u32, v2: u32, bits: u32) -> u32 {
+        let v1 = v1 & mask(bits);
+        let v2 = v2 & mask(bits);
+        let carry = self.eflags.is_carry() as u64;
+
+        let result = match op {
+            ALU_ADD => {
+                let result = v1 as u64 + v2 as u64;
+                self.update_eflags_add_sized(v1, v2, result, bits);
+                result
+            },
+            ALU_OR => {
+                let result = v1 | v2;
+                self.update_eflags_logic_sized(result, bits);
+                result as u64
+            },
+            ALU_ADC => {
+                let result = v1 as u64 + v2 as u64 + carry;
+                self.update_eflags_add_sized(v1, v2, result, bits);
+                result
+            },
+            ALU_SBB => {
+                let result = (v1 as u64).wrapping_sub(v2 as u64).wrapping_sub(carry);
+                self.update_eflags_sub_sized(v1, v2, result, bits);
+                result
+            },
+            ALU_AND => {
+                let result = v1 & v2;
+                self.update_eflags_logic_sized(result, bits);
+                result as u64
+            },
+            ALU_SUB | ALU_CMP => {
+                let result = (v1 as u64).wrapping_sub(v2 as u64);
+                self.update_eflags_sub_sized(v1, v2, result, bits);
+                result
+            },
+            ALU_XOR => {
+                let result = v1 ^ v2;
+                self.update_eflags_logic_sized(result, bits);
+                result as u64
+            },
+            n => panic!("Invalid ALU operation: {}", n),
+        };
+
+        result as u32 & mask(bits)
+    }
+
+    fn alu_rm8_r8(&mut self) {
+        let op = (self.get_code8(0) & 0x38) >> 3;
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let r8 = self.get_r8(&modrm);
+        let rm8 = self.get_rm8(&modrm);
+        let result = self.alu(op, rm8 as u32, r8 as u32, 8);
+        if op != ALU_CMP {
+            self.set_rm8(&modrm, result as u8);
+        }
+    }
+
+    fn alu_rm32_r32(&mut self) {
+        let op = (self.get_code8(0) & 0x38) >> 3;
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let r32 = self.get_rv(&modrm);
+        let rm32 = self.get_rmv(&modrm);
+        let result = self.alu(op, rm32, r32, self.operand_bits());
+        if op != ALU_CMP {
+            self.set_rmv(&modrm, result);
+        }
+    }
+
+    fn alu_r8_rm8(&mut self) {
+        let op = (self.get_code8(0) & 0x38) >> 3;
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let r8 = self.get_r8(&modrm);
+        let rm8 = self.get_rm8(&modrm);
+        let result = self.alu(op, r8 as u32, rm8 as u32, 8);
+        if op != ALU_CMP {
+            self.set_r8(&modrm, result as u8);
+        }
+    }
+
+    fn alu_r32_rm32(&mut self) {
+        let op = (self.get_code8(0) & 0x38) >> 3;
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let r32 = self.get_rv(&modrm);
+        let rm32 = self.get_rmv(&modrm);
+        let result = self.alu(op, r32, rm32, self.operand_bits());
+        if op != ALU_CMP {
+            self.set_rv(&modrm, result);
+        }
+    }
+
+    fn alu_al_imm8(&mut self) {
+        let op = (self.get_code8(0) & 0x38) >> 3;
+        let value = self.get_code8(1);
+        let al = self.get_register8(AL as usize);
+        let result = self.alu(op, al as u32, value as u32, 8);
+        if op != ALU_CMP {
+            self.set_register8(AL as usize, result as u8);
+        }
+        self.eip += 2;
+    }
+
+    fn alu_eax_imm32(&mut self) {
+        let op = (self.get_code8(0) & 0x38) >> 3;
+        let value = self.get_codev(1);
+        let eax = self.get_registerv(EAX as usize);
+        let result = self.alu(op, eax, value, self.operand_bits());
+        if op != ALU_CMP {
+            self.set_registerv(EAX as usize, result);
+        }
+        self.eip += 1 + self.operand_bytes();
+    }
+
+    fn alu_rm8_imm8(&mut self, modrm: &ModRM) {
+        let op = modrm.or.unwrap();
+        let rm8 = self.get_rm8(modrm);
+        let imm8 = self.get_code8(0);
+        self.eip += 1;
+        let result = self.alu(op, rm8 as u32, imm8 as u32, 8);
+        if op != ALU_CMP {
+            self.set_rm8(modrm, result as u8);
+        }
+    }
+
+    fn alu_rm32_imm32(&mut self, modrm: &ModRM) {
+        let op = modrm.or.unwrap();
+        let rm32 = self.get_rmv(modrm);
+        let imm32 = self.get_codev(0);
+        self.eip += self.operand_bytes();
+        let result = self.alu(op, rm32, imm32, self.operand_bits());
+        if op != ALU_CMP {
+            self.set_rmv(modrm, result);
+        }
+    }
+
+    fn alu_rm32_imm8(&mut self, modrm: &ModRM) {
+        let op = modrm.or.unwrap();
+        let rm32 = self.get_rmv(modrm);
+        let imm8 = self.get_sign_code8(0) as u32;
+        self.eip += 1;
+        let result = self.alu(op, rm32, imm8, self.operand_bits());
+        if op != ALU_CMP {
+            self.set_rmv(modrm, result);
+        }
+    }
+
+    fn code_80(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        self.alu_rm8_imm8(&modrm);
+    }
+
+    fn code_81(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        self.alu_rm32_imm32(&modrm);
+    }
+
+    fn code_83(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        self.alu_rm32_imm8(&modrm);
+    }
+
+    fn test_rm8_r8(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let r8 = self.get_r8(&modrm);
+        let rm8 = self.get_rm8(&modrm);
+        self.update_eflags_logic_sized((rm8 & r8) as u32, 8);
+    }
+
+    fn test_rm32_r32(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let r32 = self.get_rv(&modrm);
+        let rm32 = self.get_rmv(&modrm);
+        self.update_eflags_logic_sized(rm32 & r32, self.operand_bits());
+    }
+
+    fn test_al_imm8(&mut self) {
+        let value = self.get_code8(1);
+        let al = self.get_register8(AL as usize);
+        self.update_eflags_logic_sized((al & value) as u32, 8);
+        self.eip += 2;
+    }
+
+    fn test_eax_imm32(&mut self) {
+        let value = self.get_codev(1);
+        let eax = self.get_registerv(EAX as usize);
+        self.update_eflags_logic_sized(eax & value, self.operand_bits());
+        self.eip += 1 + self.operand_bytes();
+    }
+
+    /// Truncating signed multiply shared by every 2 and 3 operand IMUL form.
+    fn imul_truncate(&mut self, v1: u32, v2: u32) -> u32 {
+        let bits = self.operand_bits();
+        let sign_extend = |v: u32| ((v << (32 - bits)) as i32 >> (32 - bits)) as i64;
+        let result = sign_extend(v1) * sign_extend(v2);
+        let truncated = result as u32 & mask(bits);
+        let overflow = result != sign_extend(truncated);
+        self.eflags.set_carry(overflow);
+        self.eflags.set_overflow(overflow);
+        self.update_eflags_result(truncated, bits);
+        truncated
+    }
+
+    fn imul_r32_rm32_imm32(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let rm32 = self.get_rmv(&modrm);
+        let imm32 = self.get_codev(0);
+        self.eip += self.operand_bytes();
+        let result = self.imul_truncate(rm32, imm32);
+        self.set_rv(&modrm, result);
+    }
+
+    fn imul_r32_rm32_imm8(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let rm32 = self.get_rmv(&modrm);
+        let imm8 = self.get_sign_code8(0) as u32;
+        self.eip += 1;
+        let result = self.imul_truncate(rm32, imm8);
+        self.set_rv(&modrm, result);
+    }
+
+    fn inc_r32(&mut self) {
+        let reg = self.get_code8(0) - 0x40;
+        let value = self.get_registerv(reg as usize);
+        let result = value.wrapping_add(1);
+        self.set_registerv(reg as usize, result);
+        self.update_eflags_inc_sized(value, result, self.operand_bits());
+        self.eip += 1;
+    }
+
+    fn dec_r32(&mut self) {
+        let reg = self.get_code8(0) - 0x48;
+        let value = self.get_registerv(reg as usize);
+        let result = value.wrapping_sub(1);
+        self.set_registerv(reg as usize, result);
+        self.update_eflags_dec_sized(value, result, self.operand_bits());
+        self.eip += 1;
+    }
+
+    fn inc_rm8(&mut self, modrm: &ModRM) {
+        let value = self.get_rm8(modrm);
+        let result = value.wrapping_add(1);
+        self.set_rm8(modrm, result);
+        self.update_eflags_inc_sized(value as u32, result as u32, 8);
+    }
+
+    fn dec_rm8(&mut self, modrm: &ModRM) {
+        let value = self.get_rm8(modrm);
+        let result = value.wrapping_sub(1);
+        self.set_rm8(modrm, result);
+        self.update_eflags_dec_sized(value as u32, result as u32, 8);
+    }
+
+    fn inc_rm32(&mut self, modrm: &ModRM) {
+        let value = self.get_rmv(modrm);
+        let result = value.wrapping_add(1);
+        self.set_rmv(modrm, result);
+        self.update_eflags_inc_sized(value, result, self.operand_bits());
+    }
+
+    fn dec_rm32(&mut self, modrm: &ModRM) {
+        let value = self.get_rmv(modrm);
+        let result = value.wrapping_sub(1);
+        self.set_rmv(modrm, result);
+        self.update_eflags_dec_sized(value, result, self.operand_bits());
+    }
+
+    /*
+     * ROL, ROR, RCL, RCR, SHL, SHR, SAL, SAR selected by the REG field of C0/C1/D0-D3.
+     * The count is masked to 5 bits, and a masked count of 0 leaves the flags alone.
+     * OF is only defined for 1-bit shifts, but is computed the same way for any count.
+     */
+    fn shift(&mut self, op: u8, value: u32, count: u8, bits: u32) -> u32 {
+        let count = (count & 0x1F) as u32;
+        let value = value & mask(bits);
+        if count == 0 {
+            return value;
+        }
+
+        let msb = |v: u32| (v >> (bits - 1)) & 1 != 0;
+
+        match op {
+            SHIFT_ROL => {
+                let n = count % bits;
+                let result = ((value << n) | (value >> ((bits - n) % bits))) & mask(bits);
+                let carry = result & 1 != 0;
+                self.eflags.set_carry(carry);
+                self.eflags.set_overflow(msb(result) != carry);
+                result
+            },
+            SHIFT_ROR => {
+                let n = count % bits;
+                let result = ((value >> n) | (value << ((bits - n) % bits))) & mask(bits);
+                self.eflags.set_carry(msb(result));
+                self.eflags.set_overflow(msb(result) != msb(result << 1));
+                result
+            },
+            SHIFT_RCL => {
+                let mut result = value;
+                let mut carry = self.eflags.is_carry();
+                for _ in 0..(count % (bits + 1)) {
+                    let out = msb(result);
+                    result = ((result << 1) | carry as u32) & mask(bits);
+                    carry = out;
+                }
+                self.eflags.set_carry(carry);
+                self.eflags.set_overflow(msb(result) != carry);
+                result
+            },
+            SHIFT_RCR => {
+                let mut result = value;
+                let mut carry = self.eflags.is_carry();
+                self.eflags.set_overflow(msb(value) != carry);
+                for _ in 0..(count % (bits + 1)) {
+                    let out = result & 1 != 0;
+                    result = (result >> 1) | ((carry as u32) << (bits - 1));
+                    carry = out;
+                }
+                self.eflags.set_carry(carry);
+                result
+            },
+            SHIFT_SHL | SHIFT_SAL => {
+                let wide = (value as u64) << count;
+                let result = wide as u32 & mask(bits);
+                let carry = (wide >> bits) & 1 != 0;
+                self.update_eflags_shift_sized(result, carry, msb(result) != carry, bits);
+                result
+            },
+            SHIFT_SHR => {
+                let result = ((value as u64) >> count) as u32;
+                let carry = ((value as u64) >> (count - 1)) & 1 != 0;
+                self.update_eflags_shift_sized(result, carry, msb(value), bits);
+                result
+            },
+            SHIFT_SAR => {
+                let signed = ((value << (32 - bits)) as i32 as i64) >> (32 - bits);
+                let result = (signed >> count) as u32 & mask(bits);
+                let carry = (signed >> (count - 1)) & 1 != 0;
+                self.update_eflags_shift_sized(result, carry, false, bits);
+                result
+            },
+            n => panic!("Invalid shift operation: {}", n),
+        }
+    }
+
+    fn shift_rm8(&mut self, modrm: &ModRM, count: u8) {
+        let rm8 = self.get_rm8(modrm);
+        let result = self.shift(modrm.or.unwrap(), rm8 as u32, count, 8);
+        self.set_rm8(modrm, result as u8);
+    }
+
+    fn shift_rm32(&mut self, modrm: &ModRM, count: u8) {
+        let rm32 = self.get_rmv(modrm);
+        let result = self.shift(modrm.or.unwrap(), rm32, count, self.operand_bits());
+        self.set_rmv(modrm, result);
+    }
+
+    fn code_c0(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let count = self.get_code8(0);
+        self.eip += 1;
+        self.shift_rm8(&modrm, count);
+    }
+
+    fn code_c1(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let count = self.get_code8(0);
+        self.eip += 1;
+        self.shift_rm32(&modrm, count);
+    }
+
+    fn code_d0(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        self.shift_rm8(&modrm, 1);
+    }
+
+    fn code_d1(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        self.shift_rm32(&modrm, 1);
+    }
+
+    fn code_d2(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let cl = self.get_register8(CL as usize);
+        self.shift_rm8(&modrm, cl);
+    }
+
+    fn code_d3(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let cl = self.get_register8(CL as usize);
+        self.shift_rm32(&modrm, cl);
+    }
+
+    fn code_f6(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+
+        match modrm.or.unwrap() {
+            0 | 1 => self.test_rm8_imm8(&modrm),
+            2 => self.not_rm8(&modrm),
+            3 => self.neg_rm8(&modrm),
+            4 => self.mul_rm8(&modrm),
+            5 => self.imul_rm8(&modrm),
+            6 => self.div_rm8(&modrm),
+            7 => self.idiv_rm8(&modrm),
+            n => panic!("Not implimented: F6 /{}", n),
+        }
+    }
+
+    fn code_f7(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+
+        match modrm.or.unwrap() {
+            0 | 1 => self.test_rm32_imm32(&modrm),
+            2 => self.not_rm32(&modrm),
+            3 => self.neg_rm32(&modrm),
+            4 => self.mul_rm32(&modrm),
+            5 => self.imul_rm32(&modrm),
+            6 => self.div_rm32(&modrm),
+            7 => self.idiv_rm32(&modrm),
+            n => panic!("Not implimented: F7 /{}", n),
+        }
+    }
+
+    fn test_rm8_imm8(&mut self, modrm: &ModRM) {
+        let rm8 = self.get_rm8(modrm);
+        let imm8 = self.get_code8(0);
+        self.eip += 1;
+        self.update_eflags_logic_sized((rm8 & imm8) as u32, 8);
+    }
+
+    fn test_rm32_imm32(&mut self, modrm: &ModRM) {
+        let rm32 = self.get_rmv(modrm);
+        let imm32 = self.get_codev(0);
+        self.eip += self.operand_bytes();
+        self.update_eflags_logic_sized(rm32 & imm32, self.operand_bits());
+    }
+
+    fn not_rm8(&mut self, modrm: &ModRM) {
+        let rm8 = self.get_rm8(modrm);
+        self.set_rm8(modrm, !rm8);
+    }
+
+    fn not_rm32(&mut self, modrm: &ModRM) {
+        let rm32 = self.get_rmv(modrm);
+        self.set_rmv(modrm, !rm32);
+    }
+
+    fn neg_rm8(&mut self, modrm: &ModRM) {
+        let rm8 = self.get_rm8(modrm);
+        let result = 0u64.wrapping_sub(rm8 as u64);
+        self.set_rm8(modrm, result as u8);
+        self.update_eflags_sub_sized(0, rm8 as u32, result, 8);
+    }
+
+    fn neg_rm32(&mut self, modrm: &ModRM) {
+        let rm32 = self.get_rmv(modrm);
+        let result = 0u64.wrapping_sub(rm32 as u64);
+        self.set_rmv(modrm, result as u32);
+        self.update_eflags_sub_sized(0, rm32, result, self.operand_bits());
+    }
+
+    /*
+     * MUL/IMUL set CF and OF when the upper half of the result is significant.
+     * SF, ZF, AF and PF are undefined and left alone.
+     */
+    fn mul_rm8(&mut self, modrm: &ModRM) {
+        let rm8 = self.get_rm8(modrm) as u16;
+        let al = self.get_register8(AL as usize) as u16;
+        let result = al * rm8;
+        self.set_register16(EAX as usize, result);
+
+        let upper = result >> 8 != 0;
+        self.eflags.set_carry(upper);
+        self.eflags.set_overflow(upper);
+    }
+
+    fn mul_rm32(&mut self, modrm: &ModRM) {
+        let bits = self.operand_bits();
+        let rm32 = self.get_rmv(modrm) as u64;
+        let eax = self.get_registerv(EAX as usize) as u64;
+        let result = eax * rm32;
+        self.set_registerv(EAX as usize, result as u32);
+        self.set_registerv(EDX as usize, (result >> bits) as u32);
+
+        let upper = result >> bits != 0;
+        self.eflags.set_carry(upper);
+        self.eflags.set_overflow(upper);
+    }
+
+    fn imul_rm8(&mut self, modrm: &ModRM) {
+        let rm8 = self.get_rm8(modrm) as i8 as i16;
+        let al = self.get_register8(AL as usize) as i8 as i16;
+        let result = al * rm8;
+        self.set_register16(EAX as usize, result as u16);
+
+        let upper = result != result as i8 as i16;
+        self.eflags.set_carry(upper);
+        self.eflags.set_overflow(upper);
+    }
+
+    fn imul_rm32(&mut self, modrm: &ModRM) {
+        let bits = self.operand_bits();
+        let sign_extend = |v: u32| ((v << (32 - bits)) as i32 >> (32 - bits)) as i64;
+        let rm32 = sign_extend(self.get_rmv(modrm));
+        let eax = sign_extend(self.get_registerv(EAX as usize));
+        let result = eax * rm32;
+        self.set_registerv(EAX as usize, result as u32);
+        self.set_registerv(EDX as usize, (result >> bits) as u32);
+
+        let upper = result != sign_extend(result as u32 & mask(bits));
+        self.eflags.set_carry(upper);
+        self.eflags.set_overflow(upper);
+    }
+
+    /*
+     * DIV/IDIV raise #DE both for a zero divisor and for a quotient that
+     * does not fit in the destination. Nothing is written in that case.
+     */
+    fn div_rm8(&mut self, modrm: &ModRM) {
+        let divisor = self.get_rm8(modrm) as u16;
+        let dividend = self.get_register16(EAX as usize);
+
+        match dividend.checked_div(divisor) {
+            Some(quotient) if quotient <= 0xFF => {
+                self.set_register8(AL as usize, quotient as u8);
+                self.set_register8(AH as usize, (dividend % divisor) as u8);
+            },
+            _ => self.raise(DivideError),
+        }
+    }
+
+    fn div_rm32(&mut self, modrm: &ModRM) {
+        let bits = self.operand_bits();
+        let divisor = self.get_rmv(modrm) as u64;
+        let dividend = ((self.get_registerv(EDX as usize) as u64) << bits)
+                        | self.get_registerv(EAX as usize) as u64;
+
+        match dividend.checked_div(divisor) {
+            Some(quotient) if quotient <= mask(bits) as u64 => {
+                self.set_registerv(EAX as usize, quotient as u32);
+                self.set_registerv(EDX as usize, (dividend % divisor) as u32);
+            },
+            _ => self.raise(DivideError),
+        }
+    }
+
+    fn idiv_rm8(&mut self, modrm: &ModRM) {
+        let divisor = self.get_rm8(modrm) as i8 as i16;
+        let dividend = self.get_register16(EAX as usize) as i16;
+
+        match dividend.checked_div(divisor) {
+            Some(quotient) if quotient == quotient as i8 as i16 => {
+                self.set_register8(AL as usize, quotient as u8);
+                self.set_register8(AH as usize, (dividend % divisor) as u8);
+            },
+            _ => self.raise(DivideError),
+        }
+    }
+
+    fn idiv_rm32(&mut self, modrm: &ModRM) {
+        let bits = self.operand_bits();
+        let sign_extend = |v: u32| ((v << (32 - bits)) as i32 >> (32 - bits)) as i64;
+        let divisor = sign_extend(self.get_rmv(modrm));
+        let dividend = ((((self.get_registerv(EDX as usize) as u64) << bits)
+                        | self.get_registerv(EAX as usize) as u64) << (64 - 2 * bits)) as i64
+                        >> (64 - 2 * bits);
+
+        match dividend.checked_div(divisor) {
+            Some(quotient) if quotient == sign_extend(quotient as u32 & mask(bits)) => {
+                self.set_registerv(EAX as usize, quotient as u32);
+                self.set_registerv(EDX as usize, (dividend % divisor) as u32);
+            },
+            _ => self.raise(DivideError),
+        }
+    }
+
+    fn code_fe(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+
+        match modrm.or.unwrap() {
+            0 => self.inc_rm8(&modrm),
+            1 => self.dec_rm8(&modrm),
+            _ => self.raise(InvalidOpcode),
+        }
+    }
+
+    fn code_ff(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+
+        match modrm.or.unwrap() {
+            0 => self.inc_rm32(&modrm),
+            1 => self.dec_rm32(&modrm),
+            2 => self.call_rm32(&modrm),
+            3 => self.call_far_m32(&modrm),
+            4 => self.jump_rm32(&modrm),
+            5 => self.jump_far_m32(&modrm),
+            6 => self.push_rm32(&modrm),
+            _ => self.raise(InvalidOpcode),
+        }
+    }
+
+    fn call_rm32(&mut self, modrm: &ModRM) {
+        let addr = self.get_rmv(modrm);
+        self.pushv(self.eip);
+        self.eip = addr;
+    }
+
+    fn jump_rm32(&mut self, modrm: &ModRM) {
+        self.eip = self.get_rmv(modrm);
+    }
+
+    /// An m16:16 or m16:32 operand, the offset followed by the selector.
+    fn read_far_pointer(&mut self, modrm: &ModRM) -> (u16, u32) {
+        let addr = self.modrm_address(modrm, self.operand_bytes() + 2, false);
+        let offset = self.get_memoryv(addr);
+        let selector = self.get_memory16(addr.wrapping_add(self.operand_bytes()));
+        (selector, offset)
+    }
+
+    fn call_far_m32(&mut self, modrm: &ModRM) {
+        let (selector, offset) = self.read_far_pointer(modrm);
+        self.far_call_to(selector, offset);
+    }
+
+    fn jump_far_m32(&mut self, modrm: &ModRM) {
+        let (selector, offset) = self.read_far_pointer(modrm);
+        self.far_jump_to(selector, offset);
+    }
+
+    fn push_rm32(&mut self, modrm: &ModRM) {
+        let value = self.get_rmv(modrm);
+        self.pushv(value);
+    }
+
+    fn push_r32(&mut self) {
+        let reg = self.get_code8(0) - 0x50;
+        self.pushv(self.get_registerv(reg as usize));
+        self.eip += 1;
+    }
+
+    fn push_imm32(&mut self) {
+        let value = self.get_codev(1);
+        self.pushv(value);
+        self.eip += 1 + self.operand_bytes();
+    }
+
+    fn push_imm8(&mut self) {
+        let value = self.get_sign_code8(1);
+        self.pushv(value as i32 as u32);
+        self.eip += 2;
+    }
+
+    fn pop_r32(&mut self) {
+        let reg = self.get_code8(0) - 0x58;
+        let value = self.popv();
+        self.set_registerv(reg as usize, value);
+        self.eip += 1;
+    }
+
+    /*
+     * PUSH/POP Sreg. Bits 5-3 of the opcode select the register,
+     * for the one-byte forms (06 07 0E 16 17 1E 1F) and for 0F A0 A1 A8 A9 alike.
+     */
+    fn push_sreg(&mut self) {
+        let sreg = segment_register((self.get_code8(0) >> 3) & 7).unwrap();
+        self.pushv(self.get_segment(sreg).selector as u32);
+        self.eip += 1;
+    }
+
+    fn pop_sreg(&mut self) {
+        let sreg = segment_register((self.get_code8(0) >> 3) & 7).unwrap();
+        let selector = self.popv() as u16;
+        self.load_segment(sreg, selector);
+        if sreg == SS {
+            self.interrupt_shadow = true;
+        }
+        self.eip += 1;
+    }
+
+    fn short_jump(&mut self) {
+        let diff = self.get_sign_code8(1);
+        let next = self.eip.wrapping_add(2);
+        self.jump_to(next.wrapping_add(diff as u32));
+    }
+
+    fn near_jump(&mut self) {
+        let diff = self.get_sign_codev(1);
+        let next = self.eip.wrapping_add(1 + self.operand_bytes());
+        self.jump_to(next.wrapping_add(diff as u32));
+    }
+
+    /// Jcc rel8. Falls through to the next instruction when `condition` is false.
+    fn jump_short_if(&mut self, condition: bool) {
+        let diff = if condition {
+            self.get_sign_code8(1)
+        } else {
+            0
+        };
+        let next = self.eip.wrapping_add(2);
+        self.jump_to(next.wrapping_add(diff as u32));
+    }
+
+    /*
+     * LOOP, LOOPE, LOOPNE and JCXZ count in CX or ECX depending on the address size.
+     */
+    fn get_count_register(&self) -> u32 {
+        self.get_register32(ECX as usize) & mask(self.address_bits())
+    }
+
+    fn decrement_count_register(&mut self) -> u32 {
+        let count = self.get_count_register().wrapping_sub(1);
+        match self.address_bits() {
+            16 => self.set_register16(ECX as usize, count as u16),
+            _ => self.set_register32(ECX as usize, count),
+        }
+        count & mask(self.address_bits())
+    }
+
+    fn loop_not_zero(&mut self) {
+        let count = self.decrement_count_register();
+        let condition = count != 0 && !self.eflags.is_zero();
+        self.jump_short_if(condition);
+    }
+
+    fn loop_zero(&mut self) {
+        let count = self.decrement_count_register();
+        let condition = count != 0 && self.eflags.is_zero();
+        self.jump_short_if(condition);
+    }
+
+    fn loop_rel8(&mut self) {
+        let count = self.decrement_count_register();
+        self.jump_short_if(count != 0);
+    }
+
+    fn jump_cx_zero(&mut self) {
+        let condition = self.get_count_register() == 0;
+        self.jump_short_if(condition);
+    }
+
+    fn jump_overflow(&mut self) {
+        let condition = self.eflags.is_overflow();
+        self.jump_short_if(condition);
+    }
+
+    fn jump_not_overflow(&mut self) {
+        let condition = !self.eflags.is_overflow();
+        self.jump_short_if(condition);
+    }
+
+    fn jump_carry(&mut self) {
+        let condition = self.eflags.is_carry();
+        self.jump_short_if(condition);
+    }
+
+    fn jump_not_carry(&mut self) {
+        let condition = !self.eflags.is_carry();
+        self.jump_short_if(condition);
+    }
+
+    fn jump_zero(&mut self) {
+        let condition = self.eflags.is_zero();
+        self.jump_short_if(condition);
+    }
+
+    fn jump_not_zero(&mut self) {
+        let condition = !self.eflags.is_zero();
+        self.jump_short_if(condition);
+    }
+
+    fn jump_below_or_eq(&mut self) {
+        let condition = self.eflags.is_carry() || self.eflags.is_zero();
+        self.jump_short_if(condition);
+    }
+
+    fn jump_above(&mut self) {
+        let condition = !self.eflags.is_carry() && !self.eflags.is_zero();
+        self.jump_short_if(condition);
+    }
+
+    fn jump_sign(&mut self) {
+        let condition = self.eflags.is_sign();
+        self.jump_short_if(condition);
+    }
+
+    fn jump_not_sign(&mut self) {
+        let condition = !self.eflags.is_sign();
+        self.jump_short_if(condition);
+    }
+
+    fn jump_parity(&mut self) {
+        let condition = self.eflags.is_parity();
+        self.jump_short_if(condition);
+    }
+
+    fn jump_not_parity(&mut self) {
+        let condition = !self.eflags.is_parity();
+        self.jump_short_if(condition);
+    }
+
+    fn jump_less(&mut self) {
+        let condition = self.eflags.is_sign() != self.eflags.is_overflow();
+        self.jump_short_if(condition);
+    }
+
+    fn jump_greater_or_eq(&mut self) {
+        let condition = self.eflags.is_sign() == self.eflags.is_overflow();
+        self.jump_short_if(condition);
+    }
+
+    fn jump_less_or_eq(&mut self) {
+        let condition = self.eflags.is_zero()
+                        || self.eflags.is_sign() != self.eflags.is_overflow();
+        self.jump_short_if(condition);
+    }
+
+    fn jump_greater(&mut self) {
+        let condition = !self.eflags.is_zero()
+                        && self.eflags.is_sign() == self.eflags.is_overflow();
+        self.jump_short_if(condition);
+    }
+
+    /*
+     * String instructions.
+     * The source is ESI (SI), the destination EDI (DI), stepped by the operand width
+     * backwards when DF is set. A repeat prefix runs exactly one iteration per step and
+     * points EIP back at the prefix until the count in ECX (CX) runs out,
+     * so a long REP stays interruptible between iterations.
+     */
+    fn string_op(&mut self, compares: bool, iteration: fn(&mut Emulator, u32), bytes: u32) {
+        let next = self.eip + 1;
+        let rep = match self.decode.rep {
+            Some(rep) => rep,
+            None => {
+                iteration(self, bytes);
+                self.eip = next;
+                return;
+            },
+        };
+
+        let count = self.get_string_register(ECX as usize);
+        if count == 0 {
+            self.eip = next;
+            return;
+        }
+
+        iteration(self, bytes);
+        let count = count - 1;
+        self.set_string_register(ECX as usize, count);
+
+        let done = count == 0 || (compares && match rep {
+            Rep::Rep => !self.eflags.is_zero(),
+            Rep::Repne => self.eflags.is_zero(),
+        });
+        self.eip = if done { next } else { self.decode.eip };
+    }
+
+    /// ESI, EDI and ECX as wide as the address size.
+    fn get_string_register(&self, index: usize) -> u32 {
+        self.get_register32(index) & mask(self.address_bits())
+    }
+
+    fn set_string_register(&mut self, index: usize, value: u32) {
+        match self.address_bits() {
+            16 => self.set_register16(index, value as u16),
+            _ => self.set_register32(index, value),
+        }
+    }
+
+    fn advance_string_register(&mut self, index: usize, bytes: u32) {
+        let value = self.get_string_register(index);
+        let value = if self.eflags.is_direction() {
+            value.wrapping_sub(bytes)
+        } else {
+            value.wrapping_add(bytes)
+        };
+        self.set_string_register(index, value);
+    }
+
+    /// DS:ESI, the segment can be overridden.
+    fn string_source(&mut self, bytes: u32) -> u32 {
+        let offset = self.get_string_register(ESI as usize);
+        let sreg = self.data_segment(DS);
+        self.segment_address(sreg, offset, bytes, false)
+    }
+
+    /// ES:EDI, the segment can't be overridden.
+    fn string_destination(&mut self, bytes: u32, write: bool) -> u32 {
+        let offset = self.get_string_register(EDI as usize);
+        self.segment_address(ES, offset, bytes, write)
+    }
+
+    fn read_string(&mut self, addr: u32, bytes: u32) -> u32 {
+        match bytes {
+            1 => self.get_memory8(addr) as u32,
+            2 => self.get_memory16(addr) as u32,
+            _ => self.get_memory32(addr),
+        }
+    }
+
+    fn write_string(&mut self, addr: u32, value: u32, bytes: u32) {
+        match bytes {
+            1 => self.set_memory8(addr, value),
+            2 => self.set_memory16(addr, value),
+            _ => self.set_memory32(addr, value),
+        }
+    }
+
+    fn movs_iteration(&mut self, bytes: u32) {
+        let source = self.string_source(bytes);
+        let value = self.read_string(source, bytes);
+        let destination = self.string_destination(bytes, true);
+        self.write_string(destination, value, bytes);
+        self.advance_string_register(ESI as usize, bytes);
+        self.advance_string_register(EDI as usize, bytes);
+    }
+
+    fn cmps_iteration(&mut self, bytes: u32) {
+        let source = self.string_source(bytes);
+        let v1 = self.read_string(source, bytes);
+        let destination = self.string_destination(bytes, false);
+        let v2 = self.read_string(destination, bytes);
+        let result = (v1 as u64).wrapping_sub(v2 as u64);
+        self.update_eflags_sub_sized(v1, v2, result, bytes * 8);
+        self.advance_string_register(ESI as usize, bytes);
+        self.advance_string_register(EDI as usize, bytes);
+    }
+
+    fn stos_iteration(&mut self, bytes: u32) {
+        let value = self.get_register32(EAX as usize);
+        let destination = self.string_destination(bytes, true);
+        self.write_string(destination, value, bytes);
+        self.advance_string_register(EDI as usize, bytes);
+    }
+
+    fn lods_iteration(&mut self, bytes: u32) {
+        let source = self.string_source(bytes);
+        let value = self.read_string(source, bytes);
+        match bytes {
+            1 => self.set_register8(AL as usize, value as u8),
+            2 => self.set_register16(EAX as usize, value as u16),
+            _ => self.set_register32(EAX as usize, value),
+        }
+        self.advance_string_register(ESI as usize, bytes);
+    }
+
+    /// INS: from port DX to ES:EDI.
+    fn ins_iteration(&mut self, bytes: u32) {
+        let destination = self.string_destination(bytes, true);
+        let port = self.get_register16(EDX as usize);
+        let value = self.port_in(port, bytes);
+        self.write_string(destination, value, bytes);
+        self.advance_string_register(EDI as usize, bytes);
+    }
+
+    /// OUTS: from DS:ESI to port DX.
+    fn outs_iteration(&mut self, bytes: u32) {
+        let source = self.string_source(bytes);
+        let value = self.read_string(source, bytes);
+        let port = self.get_register16(EDX as usize);
+        self.port_out(port, value, bytes);
+        self.advance_string_register(ESI as usize, bytes);
+    }
+
+    fn scas_iteration(&mut self, bytes: u32) {
+        let v1 = self.get_register32(EAX as usize) & mask(bytes * 8);
+        let destination = self.string_destination(bytes, false);
+        let v2 = self.read_string(destination, bytes);
+        let result = (v1 as u64).wrapping_sub(v2 as u64);
+        self.update_eflags_sub_sized(v1, v2, result, bytes * 8);
+        self.advance_string_register(EDI as usize, bytes);
+    }
+
+    fn movs_m8_m8(&mut self) {
+        self.string_op(false, Emulator::movs_iteration, 1);
+    }
+
+    fn movs_m32_m32(&mut self) {
+        self.string_op(false, Emulator::movs_iteration, self.operand_bytes());
+    }
+
+    fn cmps_m8_m8(&mut self) {
+        self.string_op(true, Emulator::cmps_iteration, 1);
+    }
+
+    fn cmps_m32_m32(&mut self) {
+        self.string_op(true, Emulator::cmps_iteration, self.operand_bytes());
+    }
+
+    fn stos_m8(&mut self) {
+        self.string_op(false, Emulator::stos_iteration, 1);
+    }
+
+    fn stos_m32(&mut self) {
+        self.string_op(false, Emulator::stos_iteration, self.operand_bytes());
+    }
+
+    fn lods_m8(&mut self) {
+        self.string_op(false, Emulator::lods_iteration, 1);
+    }
+
+    fn lods_m32(&mut self) {
+        self.string_op(false, Emulator::lods_iteration, self.operand_bytes());
+    }
+
+    fn ins_m8_dx(&mut self) {
+        if !self.check_io_privilege() {
+            return;
+        }
+        self.string_op(false, Emulator::ins_iteration, 1);
+    }
+
+    fn ins_m32_dx(&mut self) {
+        if !self.check_io_privilege() {
+            return;
+        }
+        self.string_op(false, Emulator::ins_iteration, self.operand_bytes());
+    }
+
+    fn outs_dx_m8(&mut self) {
+        if !self.check_io_privilege() {
+            return;
+        }
+        self.string_op(false, Emulator::outs_iteration, 1);
+    }
+
+    fn outs_dx_m32(&mut self) {
+        if !self.check_io_privilege() {
+            return;
+        }
+        self.string_op(false, Emulator::outs_iteration, self.operand_bytes());
+    }
+
+    fn scas_m8(&mut self) {
+        self.string_op(true, Emulator::scas_iteration, 1);
+    }
+
+    fn scas_m32(&mut self) {
+        self.string_op(true, Emulator::scas_iteration, self.operand_bytes());
+    }
 
-type Instruction = fn(&mut Emulator);
+    /*
+     * Condition codes of Jcc, SETcc and CMOVcc, the low 4 bits of the opcode.
+     *  0 O   1 NO   2 B   3 AE   4 E   5 NE   6 BE   7 A
+     *  8 S   9 NS   A P   B NP   C L   D GE   E LE   F G
+     */
+    fn condition(&self, cc: u8) -> bool {
+        let flags = &self.eflags;
+        let ret = match cc >> 1 {
+            0 => flags.is_overflow(),
+            1 => flags.is_carry(),
+            2 => flags.is_zero(),
+            3 => flags.is_carry() || flags.is_zero(),
+            4 => flags.is_sign(),
+            5 => flags.is_parity(),
+            6 => flags.is_sign() != flags.is_overflow(),
+            _ => flags.is_zero() || flags.is_sign() != flags.is_overflow(),
+        };
 
-impl Emulator {
-    fn mov_r32_imm32(&mut self) {
-        let reg = self.get_code8(0) - 0xB8; // 0xB8 == registers[0]
-        let value = self.get_code32(1);
-        self.registers[reg as usize] = value;
-        self.eip += 5;
+        // odd codes are the negated conditions
+        ret != (cc & 1 != 0)
     }
 
-    fn mov_rm32_imm32(&mut self) {
+    /*
+     * Two-byte opcodes. `code_0f` consumes the 0x0F escape,
+     * so these see EIP at the second opcode byte just like one-byte handlers do.
+     */
+    fn code_0f(&mut self) {
         self.eip += 1;
-        let modrm = self.parse_modrm();
-        let value = self.get_code32(0);
+        let code = self.get_code8(0);
 
-        self.eip += 4;
-        self.set_rm32(&modrm, value);
+        if let Some(inst) = instructions_0f(code) {
+            inst(self);
+        } else {
+            self.raise(InvalidOpcode);
+        }
     }
 
-    fn mov_rm32_r32(&mut self) {
+    /// UD2 exists to raise #UD.
+    fn ud2(&mut self) {
         self.eip += 1;
-        let modrm = self.parse_modrm();
-        let r32 = self.get_r32(&modrm);
+        self.raise(InvalidOpcode);
+    }
 
-        self.set_rm32(&modrm, r32);
+    /// Instructions that take a memory operand only, such as LEA and LGDT, are invalid with Mod 11.
+    fn check_memory_operand(&mut self, modrm: &ModRM) -> bool {
+        if modrm.mod_byte == 0b11 {
+            self.raise(InvalidOpcode);
+            return false;
+        }
+        true
     }
 
-    fn mov_r32_rm32(&mut self) {
+    /// The Sreg field of a ModRM byte. 6 and 7 don't name a segment register.
+    fn sreg_operand(&mut self, modrm: &ModRM) -> Option<SegmentRegister> {
+        let sreg = segment_register(modrm.or.unwrap());
+        if sreg.is_none() {
+            self.raise(InvalidOpcode);
+        }
+        sreg
+    }
+
+    /// LGDT, LIDT, MOV CRn, LMSW, HLT and friends only run at CPL 0.
+    fn check_privileged(&mut self) -> bool {
+        if self.cpl() != 0 {
+            self.raise(GeneralProtection(0));
+            return false;
+        }
+        true
+    }
+
+    /// IN, OUT, CLI and STI only run where CPL <= IOPL. There's no I/O permission bitmap to allow single ports.
+    fn check_io_privilege(&mut self) -> bool {
+        if self.is_protected_mode() && self.cpl() > self.eflags.iopl() {
+            self.raise(GeneralProtection(0));
+            return false;
+        }
+        true
+    }
+
+    /*
+     * 0F 00: the task register. There's no LDT, so SLDT, LLDT, VERR and VERW are invalid,
+     * and so is all of the group outside protected mode.
+     */
+    fn code_0f_00(&mut self) {
         self.eip += 1;
         let modrm = self.parse_modrm();
-        let rm32 = self.get_rm32(&modrm);
-        self.set_r32(&modrm, rm32);
+        if !self.is_protected_mode() {
+            self.raise(InvalidOpcode);
+            return;
+        }
+
+        match modrm.or.unwrap() {
+            1 => self.str(&modrm),
+            3 => self.ltr(&modrm),
+            _ => self.raise(InvalidOpcode),
+        }
     }
 
-    fn mov_r8_imm8(&mut self) {
-        let reg = self.get_code8(0) - 0xB0;
-        self.set_register8(reg as usize, self.get_code8(1));
-        self.eip += 2;
+    /// A register destination gets the selector zero-extended to the operand size.
+    fn str(&mut self, modrm: &ModRM) {
+        let selector = self.tr.selector;
+        if modrm.mod_byte == 0b11 {
+            self.set_registerv(modrm.rm as usize, selector as u32);
+        } else {
+            self.set_rm16(modrm, selector);
+        }
     }
 
-    fn mov_r8_rm8(&mut self) {
+    fn ltr(&mut self, modrm: &ModRM) {
+        let selector = self.get_rm16(modrm);
+        if self.check_privileged() {
+            self.load_task_register(selector);
+        }
+    }
+
+    /*
+     * 0F 01: descriptor table registers and the machine status word.
+     * The memory operand of LGDT/SGDT/LIDT/SIDT is a 16-bit limit followed by a 32-bit base,
+     * of which a 16-bit operand size only uses 24 bits.
+     */
+    fn code_0f_01(&mut self) {
         self.eip += 1;
         let modrm = self.parse_modrm();
-        let rm8 = self.get_rm8(&modrm);
-        self.set_r8(&modrm, rm8);
+        if modrm.or.unwrap() != 4 && modrm.or.unwrap() != 6 && !self.check_memory_operand(&modrm) {
+            return;
+        }
+
+        match modrm.or.unwrap() {
+            0 => self.sgdt(&modrm),
+            1 => self.sidt(&modrm),
+            2 => self.lgdt(&modrm),
+            3 => self.lidt(&modrm),
+            4 => self.smsw(&modrm),
+            6 => self.lmsw(&modrm),
+            7 => self.invlpg(&modrm),
+            _ => self.raise(InvalidOpcode),
+        }
     }
 
-    fn mov_rm8_r8(&mut self) {
+    fn read_descriptor_table(&mut self, modrm: &ModRM) -> DescriptorTable {
+        let addr = self.modrm_address(modrm, 6, false);
+        let limit = self.get_memory16(addr);
+        let base = self.get_memory32(addr.wrapping_add(2));
+        let base = if self.operand_bits() == 16 {
+            base & 0x00FFFFFF
+        } else {
+            base
+        };
+        DescriptorTable { base, limit }
+    }
+
+    fn write_descriptor_table(&mut self, modrm: &ModRM, table: DescriptorTable) {
+        let addr = self.modrm_address(modrm, 6, true);
+        let base = if self.operand_bits() == 16 {
+            table.base & 0x00FFFFFF
+        } else {
+            table.base
+        };
+        self.set_memory16(addr, table.limit as u32);
+        self.set_memory32(addr.wrapping_add(2), base);
+    }
+
+    fn sgdt(&mut self, modrm: &ModRM) {
+        let table = self.gdtr;
+        self.write_descriptor_table(modrm, table);
+    }
+
+    fn sidt(&mut self, modrm: &ModRM) {
+        let table = self.idtr;
+        self.write_descriptor_table(modrm, table);
+    }
+
+    fn lgdt(&mut self, modrm: &ModRM) {
+        if self.check_privileged() {
+            self.gdtr = self.read_descriptor_table(modrm);
+        }
+    }
+
+    fn lidt(&mut self, modrm: &ModRM) {
+        if self.check_privileged() {
+            self.idtr = self.read_descriptor_table(modrm);
+        }
+    }
+
+    /// The low 16 bits of CR0. A register destination gets CR0 zero-extended to the operand size.
+    fn smsw(&mut self, modrm: &ModRM) {
+        let cr0 = self.cr[0];
+        if modrm.mod_byte == 0b11 {
+            self.set_registerv(modrm.rm as usize, cr0 & mask(self.operand_bits()));
+        } else {
+            self.set_rm16(modrm, cr0 as u16);
+        }
+    }
+
+    /// Loads PE, MP, EM and TS. PE can be set this way but not cleared.
+    fn lmsw(&mut self, modrm: &ModRM) {
+        let msw = self.get_rm16(modrm) as u32;
+        if self.check_privileged() {
+            let bits = CR0_MP | CR0_EM | CR0_TS;
+            self.cr[0] = (self.cr[0] & !bits) | (msw & (bits | CR0_PE));
+        }
+    }
+
+    /// Drops the TLB entry of the page holding the operand's linear address.
+    fn invlpg(&mut self, modrm: &ModRM) {
+        if self.check_privileged() {
+            let linear = self.calc_memory_address(modrm);
+            self.invalidate_page(linear);
+        }
+    }
+
+    /*
+     * MOV r32, CRn and MOV CRn, r32 (0F 20 and 0F 22).
+     * The ModRM byte always names a register, whatever its Mod bits say,
+     * and REG selects the control register.
+     */
+    fn control_register_index(&mut self, modrm: &ModRM) -> Option<usize> {
+        match modrm.or.unwrap() {
+            n @ 0 | n @ 2 ..= 4 => Some(n as usize),
+            _ => {
+                self.raise(InvalidOpcode);
+                None
+            },
+        }
+    }
+
+    fn mov_r32_cr(&mut self) {
         self.eip += 1;
-        let modrm = self.parse_modrm();
-        let r8 = self.get_r8(&modrm);
-        self.set_rm8(&modrm, r8);
+        let modrm = self.parse_control_modrm();
+        let index = match self.control_register_index(&modrm) {
+            Some(index) => index,
+            None => return,
+        };
+        if self.check_privileged() {
+            self.set_register32(modrm.rm as usize, self.cr[index]);
+        }
     }
 
-    fn in_al_dx(&mut self) {
-        let addr = self.get_register32(EDX as usize) & 0xFFFF;
-        let value = io::io_in8(addr as u16);
-        self.set_register8(AL as usize, value);
+    fn mov_cr_r32(&mut self) {
         self.eip += 1;
+        let modrm = self.parse_control_modrm();
+        let index = match self.control_register_index(&modrm) {
+            Some(index) => index,
+            None => return,
+        };
+        if !self.check_privileged() {
+            return;
+        }
+
+        let value = self.get_register32(modrm.rm as usize);
+        if index == 0 && value & CR0_PG != 0 && value & CR0_PE == 0 {
+            self.raise(GeneralProtection(0));
+            return;
+        }
+        self.cr[index] = value;
+
+        // loading CR3 flushes the TLB, and so does anything that changes how it translates
+        if index != 2 {
+            self.flush_tlb();
+        }
     }
 
-    fn out_dx_al(&mut self) {
-        let addr = self.get_register32(EDX as usize) & 0xFFFF;
-        let value = self.get_register8(AL as usize);
-        io::io_out8(addr as u16, value);
+    /// Only the register form exists, so no displacement follows the ModRM byte.
+    fn parse_control_modrm(&mut self) -> ModRM {
+        let code = self.get_code8(0);
         self.eip += 1;
+        ModRM {
+            mod_byte: 0b11,
+            or: OR::RegIndex((code >> 3) & 7),
+            rm: code & 7,
+            sib: 0,
+            disp: Disp::Disp32(0),
+        }
+    }
+
+    fn jcc_rel32(&mut self) {
+        let cc = self.get_code8(0) & 0x0F;
+        let next = self.eip.wrapping_add(1 + self.operand_bytes());
+        if self.condition(cc) {
+            let diff = self.get_sign_codev(1);
+            self.jump_to(next.wrapping_add(diff as u32));
+        } else {
+            self.eip = next;
+        }
     }
 
-    fn add_rm32_r32(&mut self) {
+    fn setcc_rm8(&mut self) {
+        let cc = self.get_code8(0) & 0x0F;
         self.eip += 1;
         let modrm = self.parse_modrm();
-        let r32 = self.get_r32(&modrm);
-        let rm32 = self.get_rm32(&modrm);
-        self.set_rm32(&modrm, rm32 + r32);
+        let value = self.condition(cc) as u8;
+        self.set_rm8(&modrm, value);
     }
 
-    fn add_rm32_imm8(&mut self, modrm: &ModRM) {
-        let rm32 = self.get_rm32(&modrm);
-        let imm8 = self.get_sign_code8(0) as u32;
+    fn cmovcc_r32_rm32(&mut self) {
+        let cc = self.get_code8(0) & 0x0F;
         self.eip += 1;
-        self.set_rm32(&modrm, rm32 + imm8);
+        let modrm = self.parse_modrm();
+        let rm32 = self.get_rmv(&modrm);
+        if self.condition(cc) {
+            self.set_rv(&modrm, rm32);
+        }
     }
 
-    fn cmp_r32_rm32(&mut self) {
+    fn imul_r32_rm32(&mut self) {
         self.eip += 1;
         let modrm = self.parse_modrm();
-        let r32 = self.get_r32(&modrm);
-        let rm32 = self.get_rm32(&modrm);
-        let result: u64 = (r32 as u64).wrapping_sub(rm32 as u64);
-        self.update_eflags_sub(r32, rm32, result);
+        let r32 = self.get_rv(&modrm);
+        let rm32 = self.get_rmv(&modrm);
+        let result = self.imul_truncate(r32, rm32);
+        self.set_rv(&modrm, result);
     }
 
-    fn cmp_rm32_imm8(&mut self, modrm: &ModRM) {
-        let rm32 = self.get_rm32(&modrm);
-        let imm8 = self.get_sign_code8(0);
-        self.eip += 1;
-        let result = (rm32 as u64).wrapping_sub(imm8 as u64);
-        self.update_eflags_sub(rm32, imm8 as u32, result);
+    /*
+     * SHLD/SHRD shift rm32 and fill the vacated bits from r32.
+     * The pair is shifted as one 64-bit value so a count of 32 never happens.
+     */
+    fn double_shift(&mut self, modrm: &ModRM, left: bool, count: u8) {
+        let bits = self.operand_bits();
+        let count = (count & 0x1F) as u32;
+        if count == 0 {
+            return;
+        }
+
+        let dest = self.get_rmv(modrm) as u64;
+        let src = self.get_rv(modrm) as u64;
+        let (result, carry) = if left {
+            let wide = (dest << bits) | src;
+            ((wide << count >> bits) as u32 & mask(bits), (dest << count >> bits) & 1 != 0)
+        } else {
+            let wide = (src << bits) | dest;
+            ((wide >> count) as u32 & mask(bits), (dest >> (count - 1)) & 1 != 0)
+        };
+
+        self.set_rmv(modrm, result);
+        let overflow = ((dest as u32 ^ result) >> (bits - 1)) & 1 != 0;
+        self.update_eflags_shift_sized(result, carry, overflow, bits);
     }
 
-    fn cmp_eax_imm32(&mut self) {
-        let value = self.get_code32(1);
-        let eax = self.get_register32(EAX as usize);
-        let result = (eax as u64).wrapping_sub(value as u64);
-        self.update_eflags_sub(eax, value, result);
-        self.eip += 5;
+    fn shld_rm32_r32_imm8(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let count = self.get_code8(0);
+        self.eip += 1;
+        self.double_shift(&modrm, true, count);
     }
 
-    fn cmp_al_imm8(&mut self) {
-        let value = self.get_code8(1);
-        let al = self.get_register8(AL as usize);
-        let result = (al as u64).wrapping_sub(value as u64);
-        self.update_eflags_sub(al as u32, value as u32, result);
-        self.eip += 2;
+    fn shld_rm32_r32_cl(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let cl = self.get_register8(CL as usize);
+        self.double_shift(&modrm, true, cl);
     }
 
-    fn sub_rm32_imm8(&mut self, modrm: &ModRM) {
-        let rm32 = self.get_rm32(modrm);
-        let imm8 = self.get_sign_code8(0) as u32;
+    fn shrd_rm32_r32_imm8(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let count = self.get_code8(0);
         self.eip += 1;
-        let result = (rm32 as u64).wrapping_sub(imm8 as u64);
-        self.set_rm32(&modrm, rm32 - imm8);
-        self.update_eflags_sub(rm32, imm8, result);
+        self.double_shift(&modrm, false, count);
     }
 
-    fn code_83(&mut self) {
+    fn shrd_rm32_r32_cl(&mut self) {
         self.eip += 1;
         let modrm = self.parse_modrm();
+        let cl = self.get_register8(CL as usize);
+        self.double_shift(&modrm, false, cl);
+    }
 
-        match modrm.or.unwrap() {
-            0 => self.add_rm32_imm8(&modrm),
-            5 => self.sub_rm32_imm8(&modrm),
-            7 => self.cmp_rm32_imm8(&modrm),
-            n => panic!("Not implimented: 83 /{}", n),
-        }
+    fn movzx_r32_rm8(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let rm8 = self.get_rm8(&modrm);
+        self.set_rv(&modrm, rm8 as u32);
     }
 
-    fn inc_r32(&mut self) {
-        let reg = self.get_code8(0) - 0x40;
-        self.set_register32(reg as usize, self.get_register32(reg as usize) + 1);
+    fn movzx_r32_rm16(&mut self) {
         self.eip += 1;
+        let modrm = self.parse_modrm();
+        let rm16 = self.get_rm16(&modrm);
+        self.set_rv(&modrm, rm16 as u32);
     }
 
-    fn inc_rm32(&mut self, modrm: &ModRM) {
-        let value = self.get_rm32(&modrm);
-        self.set_rm32(&modrm, value + 1);
+    fn movsx_r32_rm8(&mut self) {
+        self.eip += 1;
+        let modrm = self.parse_modrm();
+        let rm8 = self.get_rm8(&modrm);
+        self.set_rv(&modrm, rm8 as i8 as i32 as u32);
     }
 
-    fn code_ff(&mut self) {
+    fn movsx_r32_rm16(&mut self) {
         self.eip += 1;
         let modrm = self.parse_modrm();
+        let rm16 = self.get_rm16(&modrm);
+        self.set_rv(&modrm, rm16 as i16 as i32 as u32);
+    }
 
-        match modrm.or.unwrap() {
-            0 => self.inc_rm32(&modrm),
-            n => panic!("Not implimented: FF /{}", n),
+    fn call_rel32(&mut self) {
+        let diff = self.get_sign_codev(1);
+        let next = self.eip.wrapping_add(1 + self.operand_bytes());
+        self.pushv(next);
+        self.jump_to(next.wrapping_add(diff as u32));
+    }
+
+    /// Near branch target. A 16-bit operand size truncates it to IP.
+    fn jump_to(&mut self, addr: u32) {
+        self.eip = addr & mask(self.operand_bits());
+    }
+
+    fn int(&mut self) {
+        let vector = self.get_code8(1);
+        self.eip += 2;
+        // a Linux program's system call, which goes to the emulator rather than through the IDT
+        if vector == 0x80 && self.linux.is_some() {
+            self.linux_syscall();
+            return;
         }
+        self.software_interrupt(vector);
     }
 
-    fn push_r32(&mut self) {
-        let reg = self.get_code8(0) - 0x50;
-        self.push32(self.get_register32(reg as usize));
+    fn int3(&mut self) {
         self.eip += 1;
+        self.software_interrupt(3);
     }
 
-    fn push_imm32(&mut self) {
-        let value = self.get_code32(1);
-        self.push32(value);
-        self.eip += 5;
+    /// INT 4 if OF is set.
+    fn into(&mut self) {
+        self.eip += 1;
+        if self.eflags.is_overflow() {
+            self.software_interrupt(4);
+        }
     }
 
-    fn push_imm8(&mut self) {
-        let value = self.get_code8(1);
-        self.push32(value as u32);
-        self.eip += 2;
+    fn iret(&mut self) {
+        self.interrupt_return();
     }
 
-    fn pop_r32(&mut self) {
-        let reg = self.get_code8(0) - 0x58;
-        let value = self.pop32();
-        self.set_register32(reg as usize, value);
-        self.eip += 1;
+    fn ret(&mut self) {
+        self.eip = self.popv();
     }
 
-    fn short_jump(&mut self) {
-        let diff = self.get_sign_code8(1);
-        self.eip = ((self.eip as i64) + diff as i64 + 2) as u32;
+    fn ret_imm16(&mut self) {
+        let bytes = self.get_code16(1);
+        self.eip = self.popv();
+        let sp = self.get_stack_pointer();
+        self.set_stack_pointer(sp.wrapping_add(bytes as u32));
     }
 
-    fn near_jump(&mut self) {
-        let diff = self.get_sign_code32(1);
-        self.eip = ((self.eip as i64) + diff as i64 + 5) as u32;
+    /*
+     * Far transfers load CS along with EIP.
+     * The pointer is an offset of operand size followed by a 16-bit selector,
+     * and a far call pushes CS and then the return offset, each as one operand-size slot.
+     * JMP and CALL stay at the current privilege level.
+     */
+    fn far_jump_to(&mut self, selector: u16, offset: u32) {
+        let cpl = self.cpl();
+        self.load_code_segment(selector, cpl);
+        if self.is_protected_mode() && !self.get_segment(CS).contains(offset, 1) {
+            self.raise(GeneralProtection(0));
+        }
+        self.eip = offset;
     }
 
-    fn jump_sign(&mut self) {
-        let diff = if self.eflags.is_sign() {
-            self.get_sign_code8(1)
-        } else {
-            0
-        };
-        self.eip += diff as u32 + 2;
+    fn far_call_to(&mut self, selector: u16, offset: u32) {
+        self.pushv(self.get_segment(CS).selector as u32);
+        self.pushv(self.eip);
+        self.far_jump_to(selector, offset);
     }
 
-    fn jump_not_sign(&mut self) {
-        let diff = if self.eflags.is_sign() {
-            0
-        } else {
-            self.get_sign_code8(1)
-        };
-        self.eip += diff as u32 + 2;
+    fn far_jump(&mut self) {
+        let offset = self.get_codev(1);
+        let selector = self.get_code16(1 + self.operand_bytes());
+        self.far_jump_to(selector, offset);
     }
 
-    fn jump_carry(&mut self) {
-        let diff = if self.eflags.is_carry() {
-            self.get_sign_code8(1)
-        } else {
-            0
-        };
-        self.eip += diff as u32 + 2;
+    fn far_call(&mut self) {
+        let offset = self.get_codev(1);
+        let selector = self.get_code16(1 + self.operand_bytes());
+        self.eip += 3 + self.operand_bytes();
+        self.far_call_to(selector, offset);
     }
 
-    fn jump_not_carry(&mut self) {
-        let diff = if self.eflags.is_carry() {
-            0
-        } else {
-            self.get_sign_code8(1)
-        };
-        self.eip += diff as u32 + 2;
+    /*
+     * RETF to the same level pops EIP and CS.
+     * Returning to an outer (numerically higher) level also pops ESP and SS of that level,
+     * and the immediate releases parameters on both stacks.
+     */
+    fn far_return(&mut self, bytes: u32) {
+        let offset = self.popv();
+        let selector = self.popv() as u16;
+        let sp = self.get_stack_pointer();
+        self.set_stack_pointer(sp.wrapping_add(bytes));
+
+        let rpl = (selector & 3) as u8;
+        if !self.is_protected_mode() || rpl == self.cpl() {
+            self.far_jump_to(selector, offset);
+            return;
+        }
+
+        if rpl < self.cpl() {
+            self.raise(GeneralProtection(selector & 0xFFFC));
+            return;
+        }
+
+        let esp = self.popv();
+        let ss = self.popv() as u16;
+        self.load_code_segment(selector, rpl);
+        self.load_segment(SS, ss);
+        self.set_registerv(ESP as usize, esp);
+        let sp = self.get_stack_pointer();
+        self.set_stack_pointer(sp.wrapping_add(bytes));
+        self.invalidate_data_segments();
+        self.eip = offset;
     }
 
-    fn jump_zero(&mut self) {
-        let diff = if self.eflags.is_zero() {
-            self.get_sign_code8(1)
-        } else {
-            0
-        };
-        self.eip += diff as u32 + 2;
+    fn far_ret(&mut self) {
+        self.far_return(0);
     }
-    
-    fn jump_not_zero(&mut self) {
-        let diff = if self.eflags.is_zero() {
-            0
-        } else {
-            self.get_sign_code8(1)
-        };
-        self.eip += diff as u32 + 2;
+
+    fn far_ret_imm16(&mut self) {
+        let bytes = self.get_code16(1);
+        self.far_return(bytes as u32);
     }
 
-    fn jump_overflow(&mut self) {
-        let diff = if self.eflags.is_overflow() {
-            self.get_sign_code8(1)
-        } else {
-            0
-        };
-        self.eip += diff as u32 + 2;
+    fn leave(&mut self) {
+        let ebp = self.get_register32(EBP as usize) & mask(self.stack_bits());
+        self.set_stack_pointer(ebp);
+        let top = self.popv();
+        self.set_registerv(EBP as usize, top);
+        self.eip += 1;
     }
-    
-    fn jump_not_overflow(&mut self) {
-        let diff = if self.eflags.is_overflow() {
-            0
-        } else {
-            self.get_sign_code8(1)
-        };
-        self.eip += diff as u32 + 2;
+
+    fn pushfd(&mut self) {
+        self.pushv(self.eflags.value());
+        self.eip += 1;
     }
 
-    fn jump_less(&mut self) {
-        let diff = if self.eflags.is_sign() != self.eflags.is_overflow() {
-            self.get_sign_code8(1)
-        } else {
-            0
-        };
-        self.eip += diff as u32 + 2;
+    /// POPF with a 16-bit operand size only replaces the low 16 bits.
+    fn popfd(&mut self) {
+        let value = self.popv();
+        let cpl = self.cpl();
+        self.restore_eflags(value, cpl);
+        self.eip += 1;
     }
 
-    fn jump_less_or_eq(&mut self) {
-        let diff = if self.eflags.is_zero() 
-                        || self.eflags.is_sign() != self.eflags.is_overflow() {
-            self.get_sign_code8(1)
-        } else {
-            0
-        };
-        self.eip += diff as u32 + 2;
+    fn sahf(&mut self) {
+        let mask = SIGN_FLAG | ZERO_FLAG | AUX_CARRY_FLAG | PARITY_FLAG | CARRY_FLAG;
+        let ah = self.get_register8(AH as usize) as u32;
+        let value = (self.eflags.value() & !mask) | (ah & mask);
+        self.eflags.set_value(value);
+        self.eip += 1;
     }
-    
-    fn call_rel32(&mut self) {
-        let diff = self.get_sign_code32(1);
-        self.push32(self.eip + 5);
-        self.eip += (diff + 5) as u32;
+
+    fn lahf(&mut self) {
+        let value = self.eflags.value() as u8;
+        self.set_register8(AH as usize, value);
+        self.eip += 1;
     }
 
-    fn int(&mut self) {
-        let int_index = self.get_code8(1);
-        self.eip += 2;
+    fn cmc(&mut self) {
+        let carry = self.eflags.is_carry();
+        self.eflags.set_carry(!carry);
+        self.eip += 1;
+    }
+
+    fn clc(&mut self) {
+        self.eflags.set_carry(false);
+        self.eip += 1;
+    }
+
+    fn stc(&mut self) {
+        self.eflags.set_carry(true);
+        self.eip += 1;
+    }
 
-        match int_index {
-            0x10    => self.bios_video(),
-            n       => {eprintln!("unknown interrupt: 0x{}", n)},
+    /// The CPU waits for an external interrupt, see `execute`.
+    fn hlt(&mut self) {
+        if !self.check_privileged() {
+            return;
         }
+        self.eip += 1;
+        self.halted = true;
     }
 
-    fn ret(&mut self) {
-        self.eip = self.pop32();
+    fn cli(&mut self) {
+        if !self.check_io_privilege() {
+            return;
+        }
+        self.eflags.set_interrupt(false);
+        self.eip += 1;
     }
 
-    fn leave(&mut self) {
-        let ebp = self.get_register32(EBP as usize);
-        self.set_register32(ESP as usize, ebp);
-        let top = self.pop32();
-        self.set_register32(EBP as usize, top);
+    /// Interrupts are taken after the next instruction, so STI; HLT can't miss one in between.
+    fn sti(&mut self) {
+        if !self.check_io_privilege() {
+            return;
+        }
+        if !self.eflags.is_interrupt() {
+            self.interrupt_shadow = true;
+        }
+        self.eflags.set_interrupt(true);
+        self.eip += 1;
+    }
+
+    fn cld(&mut self) {
+        self.eflags.set_direction(false);
+        self.eip += 1;
+    }
+
+    fn std(&mut self) {
+        self.eflags.set_direction(true);
         self.eip += 1;
     }
 }
 
 pub fn instructions(code: u8) -> Option<Instruction> {
     match code {
-        0x01 => Some(Emulator::add_rm32_r32),
-        0x3B => Some(Emulator::cmp_r32_rm32),
-        0x3C => Some(Emulator::cmp_al_imm8),
-        0x3D => Some(Emulator::cmp_eax_imm32),
+        0x00 => Some(Emulator::alu_rm8_r8),
+        0x01 => Some(Emulator::alu_rm32_r32),
+        0x02 => Some(Emulator::alu_r8_rm8),
+        0x03 => Some(Emulator::alu_r32_rm32),
+        0x04 => Some(Emulator::alu_al_imm8),
+        0x05 => Some(Emulator::alu_eax_imm32),
+        0x06 => Some(Emulator::push_sreg),
+        0x07 => Some(Emulator::pop_sreg),
+        0x08 => Some(Emulator::alu_rm8_r8),
+        0x09 => Some(Emulator::alu_rm32_r32),
+        0x0A => Some(Emulator::alu_r8_rm8),
+        0x0B => Some(Emulator::alu_r32_rm32),
+        0x0C => Some(Emulator::alu_al_imm8),
+        0x0D => Some(Emulator::alu_eax_imm32),
+        0x0E => Some(Emulator::push_sreg),
+        0x0F => Some(Emulator::code_0f),
+        0x10 => Some(Emulator::alu_rm8_r8),
+        0x11 => Some(Emulator::alu_rm32_r32),
+        0x12 => Some(Emulator::alu_r8_rm8),
+        0x13 => Some(Emulator::alu_r32_rm32),
+        0x14 => Some(Emulator::alu_al_imm8),
+        0x15 => Some(Emulator::alu_eax_imm32),
+        0x16 => Some(Emulator::push_sreg),
+        0x17 => Some(Emulator::pop_sreg),
+        0x18 => Some(Emulator::alu_rm8_r8),
+        0x19 => Some(Emulator::alu_rm32_r32),
+        0x1A => Some(Emulator::alu_r8_rm8),
+        0x1B => Some(Emulator::alu_r32_rm32),
+        0x1C => Some(Emulator::alu_al_imm8),
+        0x1D => Some(Emulator::alu_eax_imm32),
+        0x1E => Some(Emulator::push_sreg),
+        0x1F => Some(Emulator::pop_sreg),
+        0x20 => Some(Emulator::alu_rm8_r8),
+        0x21 => Some(Emulator::alu_rm32_r32),
+        0x22 => Some(Emulator::alu_r8_rm8),
+        0x23 => Some(Emulator::alu_r32_rm32),
+        0x24 => Some(Emulator::alu_al_imm8),
+        0x25 => Some(Emulator::alu_eax_imm32),
+        0x28 => Some(Emulator::alu_rm8_r8),
+        0x29 => Some(Emulator::alu_rm32_r32),
+        0x2A => Some(Emulator::alu_r8_rm8),
+        0x2B => Some(Emulator::alu_r32_rm32),
+        0x2C => Some(Emulator::alu_al_imm8),
+        0x2D => Some(Emulator::alu_eax_imm32),
+        0x30 => Some(Emulator::alu_rm8_r8),
+        0x31 => Some(Emulator::alu_rm32_r32),
+        0x32 => Some(Emulator::alu_r8_rm8),
+        0x33 => Some(Emulator::alu_r32_rm32),
+        0x34 => Some(Emulator::alu_al_imm8),
+        0x35 => Some(Emulator::alu_eax_imm32),
+        0x38 => Some(Emulator::alu_rm8_r8),
+        0x39 => Some(Emulator::alu_rm32_r32),
+        0x3A => Some(Emulator::alu_r8_rm8),
+        0x3B => Some(Emulator::alu_r32_rm32),
+        0x3C => Some(Emulator::alu_al_imm8),
+        0x3D => Some(Emulator::alu_eax_imm32),
         0x40 ..= 0x47 => Some(Emulator::inc_r32),
+        0x48 ..= 0x4F => Some(Emulator::dec_r32),
         0x50 ..= 0x57 => Some(Emulator::push_r32),
         0x58 ..= 0x5F => Some(Emulator::pop_r32),
         0x68 => Some(Emulator::push_imm32),
+        0x69 => Some(Emulator::imul_r32_rm32_imm32),
         0x6A => Some(Emulator::push_imm8), 
+        0x6B => Some(Emulator::imul_r32_rm32_imm8),
+        0x6C => Some(Emulator::ins_m8_dx),
+        0x6D => Some(Emulator::ins_m32_dx),
+        0x6E => Some(Emulator::outs_dx_m8),
+        0x6F => Some(Emulator::outs_dx_m32),
         0x70 => Some(Emulator::jump_overflow),
         0x71 => Some(Emulator::jump_not_overflow),
         0x72 => Some(Emulator::jump_carry),
         0x73 => Some(Emulator::jump_not_carry),
         0x74 => Some(Emulator::jump_zero),
         0x75 => Some(Emulator::jump_not_zero),
+        0x76 => Some(Emulator::jump_below_or_eq),
+        0x77 => Some(Emulator::jump_above),
         0x78 => Some(Emulator::jump_sign),
         0x79 => Some(Emulator::jump_not_sign),
+        0x7A => Some(Emulator::jump_parity),
+        0x7B => Some(Emulator::jump_not_parity),
         0x7C => Some(Emulator::jump_less),
+        0x7D => Some(Emulator::jump_greater_or_eq),
         0x7E => Some(Emulator::jump_less_or_eq),
+        0x7F => Some(Emulator::jump_greater),
+        0x80 => Some(Emulator::code_80),
+        0x81 => Some(Emulator::code_81),
+        0x82 => Some(Emulator::code_80),
         0x83 => Some(Emulator::code_83),
+        0x84 => Some(Emulator::test_rm8_r8),
+        0x85 => Some(Emulator::test_rm32_r32),
+        0x86 => Some(Emulator::xchg_rm8_r8),
+        0x87 => Some(Emulator::xchg_rm32_r32),
         0x88 => Some(Emulator::mov_rm8_r8), 
         0x89 => Some(Emulator::mov_rm32_r32),
         0x8A => Some(Emulator::mov_r8_rm8),
         0x8B => Some(Emulator::mov_r32_rm32),
+        0x8C => Some(Emulator::mov_rm16_sreg),
+        0x8D => Some(Emulator::lea_r32_m32),
+        0x8E => Some(Emulator::mov_sreg_rm16),
+        0x90 => Some(Emulator::nop),
+        0x91 ..= 0x97 => Some(Emulator::xchg_eax_r32),
+        0x98 => Some(Emulator::cwde),
+        0x99 => Some(Emulator::cdq),
+        0x9A => Some(Emulator::far_call),
+        0x9C => Some(Emulator::pushfd),
+        0x9D => Some(Emulator::popfd),
+        0x9E => Some(Emulator::sahf),
+        0x9F => Some(Emulator::lahf),
+        0xA0 => Some(Emulator::mov_al_moffs8),
+        0xA1 => Some(Emulator::mov_eax_moffs32),
+        0xA2 => Some(Emulator::mov_moffs8_al),
+        0xA3 => Some(Emulator::mov_moffs32_eax),
+        0xA4 => Some(Emulator::movs_m8_m8),
+        0xA5 => Some(Emulator::movs_m32_m32),
+        0xA6 => Some(Emulator::cmps_m8_m8),
+        0xA7 => Some(Emulator::cmps_m32_m32),
+        0xA8 => Some(Emulator::test_al_imm8),
+        0xA9 => Some(Emulator::test_eax_imm32),
+        0xAA => Some(Emulator::stos_m8),
+        0xAB => Some(Emulator::stos_m32),
+        0xAC => Some(Emulator::lods_m8),
+        0xAD => Some(Emulator::lods_m32),
+        0xAE => Some(Emulator::scas_m8),
+        0xAF => Some(Emulator::scas_m32),
         0xB0 ..= 0xB7 => Some(Emulator::mov_r8_imm8),
-        0xB8 ..= 0xBE => Some(Emulator::mov_r32_imm32),
+        0xB8 ..= 0xBF => Some(Emulator::mov_r32_imm32),
+        0xC0 => Some(Emulator::code_c0),
+        0xC1 => Some(Emulator::code_c1),
+        0xC2 => Some(Emulator::ret_imm16),
         0xC3 => Some(Emulator::ret),
+        0xC4 => Some(Emulator::les),
+        0xC5 => Some(Emulator::lds),
+        0xC6 => Some(Emulator::mov_rm8_imm8),
         0xC7 => Some(Emulator::mov_rm32_imm32),
         0xC9 => Some(Emulator::leave),
+        0xCA => Some(Emulator::far_ret_imm16),
+        0xCB => Some(Emulator::far_ret),
+        0xCC => Some(Emulator::int3),
+        0xD0 => Some(Emulator::code_d0),
+        0xD1 => Some(Emulator::code_d1),
+        0xD2 => Some(Emulator::code_d2),
+        0xD3 => Some(Emulator::code_d3),
         0xCD => Some(Emulator::int),
+        0xCE => Some(Emulator::into),
+        0xCF => Some(Emulator::iret),
+        0xE0 => Some(Emulator::loop_not_zero),
+        0xE1 => Some(Emulator::loop_zero),
+        0xE2 => Some(Emulator::loop_rel8),
+        0xE3 => Some(Emulator::jump_cx_zero),
+        0xE4 => Some(Emulator::in_al_imm8),
+        0xE5 => Some(Emulator::in_eax_imm8),
+        0xE6 => Some(Emulator::out_imm8_al),
+        0xE7 => Some(Emulator::out_imm8_eax),
         0xE8 => Some(Emulator::call_rel32),
         0xE9 => Some(Emulator::near_jump),
+        0xEA => Some(Emulator::far_jump),
         0xEC => Some(Emulator::in_al_dx),
+        0xED => Some(Emulator::in_eax_dx),
         0xEE => Some(Emulator::out_dx_al),
+        0xEF => Some(Emulator::out_dx_eax),
         0xEB => Some(Emulator::short_jump),
+        0xF4 => Some(Emulator::hlt),
+        0xF5 => Some(Emulator::cmc),
+        0xF6 => Some(Emulator::code_f6),
+        0xF7 => Some(Emulator::code_f7),
+        0xF8 => Some(Emulator::clc),
+        0xF9 => Some(Emulator::stc),
+        0xFA => Some(Emulator::cli),
+        0xFB => Some(Emulator::sti),
+        0xFC => Some(Emulator::cld),
+        0xFD => Some(Emulator::std),
+        0xFE => Some(Emulator::code_fe),
         0xFF => Some(Emulator::code_ff),
         _ => None,
     }
@@ -364,42 +2209,239 @@ pub fn instructions(code: u8) -> Option<Instruction> {
 
 pub fn instructions_with_name(code: u8) -> (Option<Instruction>, &'static str) {
     match code {
-        0x01 => (Some(Emulator::add_rm32_r32), "add_rm32_r32"),
-        0x3B => (Some(Emulator::cmp_r32_rm32), "cmp_r32_rm32"),
-        0x3C => (Some(Emulator::cmp_al_imm8), "cmp_al_imm8"),
-        0x3D => (Some(Emulator::cmp_eax_imm32), "cmp_eax_imm32"),
+        0x00 => (Some(Emulator::alu_rm8_r8), "add_rm8_r8"),
+        0x01 => (Some(Emulator::alu_rm32_r32), "add_rm32_r32"),
+        0x02 => (Some(Emulator::alu_r8_rm8), "add_r8_rm8"),
+        0x03 => (Some(Emulator::alu_r32_rm32), "add_r32_rm32"),
+        0x04 => (Some(Emulator::alu_al_imm8), "add_al_imm8"),
+        0x05 => (Some(Emulator::alu_eax_imm32), "add_eax_imm32"),
+        0x06 => (Some(Emulator::push_sreg), "push_sreg"),
+        0x07 => (Some(Emulator::pop_sreg), "pop_sreg"),
+        0x08 => (Some(Emulator::alu_rm8_r8), "or_rm8_r8"),
+        0x09 => (Some(Emulator::alu_rm32_r32), "or_rm32_r32"),
+        0x0A => (Some(Emulator::alu_r8_rm8), "or_r8_rm8"),
+        0x0B => (Some(Emulator::alu_r32_rm32), "or_r32_rm32"),
+        0x0C => (Some(Emulator::alu_al_imm8), "or_al_imm8"),
+        0x0D => (Some(Emulator::alu_eax_imm32), "or_eax_imm32"),
+        0x0E => (Some(Emulator::push_sreg), "push_sreg"),
+        0x0F => (Some(Emulator::code_0f), "code_0f"),
+        0x10 => (Some(Emulator::alu_rm8_r8), "adc_rm8_r8"),
+        0x11 => (Some(Emulator::alu_rm32_r32), "adc_rm32_r32"),
+        0x12 => (Some(Emulator::alu_r8_rm8), "adc_r8_rm8"),
+        0x13 => (Some(Emulator::alu_r32_rm32), "adc_r32_rm32"),
+        0x14 => (Some(Emulator::alu_al_imm8), "adc_al_imm8"),
+        0x15 => (Some(Emulator::alu_eax_imm32), "adc_eax_imm32"),
+        0x16 => (Some(Emulator::push_sreg), "push_sreg"),
+        0x17 => (Some(Emulator::pop_sreg), "pop_sreg"),
+        0x18 => (Some(Emulator::alu_rm8_r8), "sbb_rm8_r8"),
+        0x19 => (Some(Emulator::alu_rm32_r32), "sbb_rm32_r32"),
+        0x1A => (Some(Emulator::alu_r8_rm8), "sbb_r8_rm8"),
+        0x1B => (Some(Emulator::alu_r32_rm32), "sbb_r32_rm32"),
+        0x1C => (Some(Emulator::alu_al_imm8), "sbb_al_imm8"),
+        0x1D => (Some(Emulator::alu_eax_imm32), "sbb_eax_imm32"),
+        0x1E => (Some(Emulator::push_sreg), "push_sreg"),
+        0x1F => (Some(Emulator::pop_sreg), "pop_sreg"),
+        0x20 => (Some(Emulator::alu_rm8_r8), "and_rm8_r8"),
+        0x21 => (Some(Emulator::alu_rm32_r32), "and_rm32_r32"),
+        0x22 => (Some(Emulator::alu_r8_rm8), "and_r8_rm8"),
+        0x23 => (Some(Emulator::alu_r32_rm32), "and_r32_rm32"),
+        0x24 => (Some(Emulator::alu_al_imm8), "and_al_imm8"),
+        0x25 => (Some(Emulator::alu_eax_imm32), "and_eax_imm32"),
+        0x28 => (Some(Emulator::alu_rm8_r8), "sub_rm8_r8"),
+        0x29 => (Some(Emulator::alu_rm32_r32), "sub_rm32_r32"),
+        0x2A => (Some(Emulator::alu_r8_rm8), "sub_r8_rm8"),
+        0x2B => (Some(Emulator::alu_r32_rm32), "sub_r32_rm32"),
+        0x2C => (Some(Emulator::alu_al_imm8), "sub_al_imm8"),
+        0x2D => (Some(Emulator::alu_eax_imm32), "sub_eax_imm32"),
+        0x30 => (Some(Emulator::alu_rm8_r8), "xor_rm8_r8"),
+        0x31 => (Some(Emulator::alu_rm32_r32), "xor_rm32_r32"),
+        0x32 => (Some(Emulator::alu_r8_rm8), "xor_r8_rm8"),
+        0x33 => (Some(Emulator::alu_r32_rm32), "xor_r32_rm32"),
+        0x34 => (Some(Emulator::alu_al_imm8), "xor_al_imm8"),
+        0x35 => (Some(Emulator::alu_eax_imm32), "xor_eax_imm32"),
+        0x38 => (Some(Emulator::alu_rm8_r8), "cmp_rm8_r8"),
+        0x39 => (Some(Emulator::alu_rm32_r32), "cmp_rm32_r32"),
+        0x3A => (Some(Emulator::alu_r8_rm8), "cmp_r8_rm8"),
+        0x3B => (Some(Emulator::alu_r32_rm32), "cmp_r32_rm32"),
+        0x3C => (Some(Emulator::alu_al_imm8), "cmp_al_imm8"),
+        0x3D => (Some(Emulator::alu_eax_imm32), "cmp_eax_imm32"),
         0x40 ..= 0x47 => (Some(Emulator::inc_r32), "inc_r32"),
+        0x48 ..= 0x4F => (Some(Emulator::dec_r32), "dec_r32"),
         0x50 ..= 0x57 => (Some(Emulator::push_r32), "push_r32"),
         0x58 ..= 0x5F => (Some(Emulator::pop_r32), "pop_r32"),
         0x68 => (Some(Emulator::push_imm32), "push_imm32"),
+        0x69 => (Some(Emulator::imul_r32_rm32_imm32), "imul_r32_rm32_imm32"),
         0x6A => (Some(Emulator::push_imm8), "push_imm8"),
+        0x6B => (Some(Emulator::imul_r32_rm32_imm8), "imul_r32_rm32_imm8"),
+        0x6C => (Some(Emulator::ins_m8_dx), "ins_m8_dx"),
+        0x6D => (Some(Emulator::ins_m32_dx), "ins_m32_dx"),
+        0x6E => (Some(Emulator::outs_dx_m8), "outs_dx_m8"),
+        0x6F => (Some(Emulator::outs_dx_m32), "outs_dx_m32"),
         0x70 => (Some(Emulator::jump_overflow), "jump_overflow"),
         0x71 => (Some(Emulator::jump_not_overflow), "jump_not_overflow"),
         0x72 => (Some(Emulator::jump_carry), "jump_carry"),
         0x73 => (Some(Emulator::jump_not_carry), "jump_not_carry"),
         0x74 => (Some(Emulator::jump_zero), "jump_zero"),
         0x75 => (Some(Emulator::jump_not_zero), "jump_not_zero"),
+        0x76 => (Some(Emulator::jump_below_or_eq), "jump_below_or_eq"),
+        0x77 => (Some(Emulator::jump_above), "jump_above"),
         0x78 => (Some(Emulator::jump_sign), "jump_sign"),
         0x79 => (Some(Emulator::jump_not_sign), "jump_not_sign"),
+        0x7A => (Some(Emulator::jump_parity), "jump_parity"),
+        0x7B => (Some(Emulator::jump_not_parity), "jump_not_parity"),
         0x7C => (Some(Emulator::jump_less), "jump_less"),
+        0x7D => (Some(Emulator::jump_greater_or_eq), "jump_greater_or_eq"),
         0x7E => (Some(Emulator::jump_less_or_eq), "jump_less_or_eq"),
+        0x7F => (Some(Emulator::jump_greater), "jump_greater"),
+        0x80 => (Some(Emulator::code_80), "code_80"),
+        0x81 => (Some(Emulator::code_81), "code_81"),
+        0x82 => (Some(Emulator::code_80), "code_80"),
         0x83 => (Some(Emulator::code_83), "code_83"),
+        0x84 => (Some(Emulator::test_rm8_r8), "test_rm8_r8"),
+        0x85 => (Some(Emulator::test_rm32_r32), "test_rm32_r32"),
+        0x86 => (Some(Emulator::xchg_rm8_r8), "xchg_rm8_r8"),
+        0x87 => (Some(Emulator::xchg_rm32_r32), "xchg_rm32_r32"),
         0x88 => (Some(Emulator::mov_rm8_r8), "mov_rm8_r8"),
         0x89 => (Some(Emulator::mov_rm32_r32), "mov_rm32_r32"),
         0x8A => (Some(Emulator::mov_r8_rm8), "mov_r8_rm8"),
         0x8B => (Some(Emulator::mov_r32_rm32), "mov_r32_rm32"),
+        0x8C => (Some(Emulator::mov_rm16_sreg), "mov_rm16_sreg"),
+        0x8D => (Some(Emulator::lea_r32_m32), "lea_r32_m32"),
+        0x8E => (Some(Emulator::mov_sreg_rm16), "mov_sreg_rm16"),
+        0x90 => (Some(Emulator::nop), "nop"),
+        0x91 ..= 0x97 => (Some(Emulator::xchg_eax_r32), "xchg_eax_r32"),
+        0x98 => (Some(Emulator::cwde), "cwde"),
+        0x99 => (Some(Emulator::cdq), "cdq"),
+        0x9A => (Some(Emulator::far_call), "far_call"),
+        0x9C => (Some(Emulator::pushfd), "pushfd"),
+        0x9D => (Some(Emulator::popfd), "popfd"),
+        0x9E => (Some(Emulator::sahf), "sahf"),
+        0x9F => (Some(Emulator::lahf), "lahf"),
+        0xA0 => (Some(Emulator::mov_al_moffs8), "mov_al_moffs8"),
+        0xA1 => (Some(Emulator::mov_eax_moffs32), "mov_eax_moffs32"),
+        0xA2 => (Some(Emulator::mov_moffs8_al), "mov_moffs8_al"),
+        0xA3 => (Some(Emulator::mov_moffs32_eax), "mov_moffs32_eax"),
+        0xA4 => (Some(Emulator::movs_m8_m8), "movs_m8_m8"),
+        0xA5 => (Some(Emulator::movs_m32_m32), "movs_m32_m32"),
+        0xA6 => (Some(Emulator::cmps_m8_m8), "cmps_m8_m8"),
+        0xA7 => (Some(Emulator::cmps_m32_m32), "cmps_m32_m32"),
+        0xA8 => (Some(Emulator::test_al_imm8), "test_al_imm8"),
+        0xA9 => (Some(Emulator::test_eax_imm32), "test_eax_imm32"),
+        0xAA => (Some(Emulator::stos_m8), "stos_m8"),
+        0xAB => (Some(Emulator::stos_m32), "stos_m32"),
+        0xAC => (Some(Emulator::lods_m8), "lods_m8"),
+        0xAD => (Some(Emulator::lods_m32), "lods_m32"),
+        0xAE => (Some(Emulator::scas_m8), "scas_m8"),
+        0xAF => (Some(Emulator::scas_m32), "scas_m32"),
         0xB0 ..= 0xB7 => (Some(Emulator::mov_r8_imm8), "mov_r8_imm8"),
-        0xB8 ..= 0xBE => (Some(Emulator::mov_r32_imm32), "mov_r32_imm32"),
+        0xB8 ..= 0xBF => (Some(Emulator::mov_r32_imm32), "mov_r32_imm32"),
+        0xC0 => (Some(Emulator::code_c0), "code_c0"),
+        0xC1 => (Some(Emulator::code_c1), "code_c1"),
+        0xC2 => (Some(Emulator::ret_imm16), "ret_imm16"),
         0xC3 => (Some(Emulator::ret), "ret"),
+        0xC4 => (Some(Emulator::les), "les"),
+        0xC5 => (Some(Emulator::lds), "lds"),
+        0xC6 => (Some(Emulator::mov_rm8_imm8), "mov_rm8_imm8"),
         0xC7 => (Some(Emulator::mov_rm32_imm32), "mov_rm32_imm32"),
         0xC9 => (Some(Emulator::leave), "leave"),
+        0xCA => (Some(Emulator::far_ret_imm16), "far_ret_imm16"),
+        0xCB => (Some(Emulator::far_ret), "far_ret"),
+        0xCC => (Some(Emulator::int3), "int3"),
+        0xD0 => (Some(Emulator::code_d0), "code_d0"),
+        0xD1 => (Some(Emulator::code_d1), "code_d1"),
+        0xD2 => (Some(Emulator::code_d2), "code_d2"),
+        0xD3 => (Some(Emulator::code_d3), "code_d3"),
         0xCD => (Some(Emulator::int), "int"),
+        0xCE => (Some(Emulator::into), "into"),
+        0xCF => (Some(Emulator::iret), "iret"),
+        0xE0 => (Some(Emulator::loop_not_zero), "loop_not_zero"),
+        0xE1 => (Some(Emulator::loop_zero), "loop_zero"),
+        0xE2 => (Some(Emulator::loop_rel8), "loop_rel8"),
+        0xE3 => (Some(Emulator::jump_cx_zero), "jump_cx_zero"),
+        0xE4 => (Some(Emulator::in_al_imm8), "in_al_imm8"),
+        0xE5 => (Some(Emulator::in_eax_imm8), "in_eax_imm8"),
+        0xE6 => (Some(Emulator::out_imm8_al), "out_imm8_al"),
+        0xE7 => (Some(Emulator::out_imm8_eax), "out_imm8_eax"),
         0xE8 => (Some(Emulator::call_rel32), "call_rel32"),
         0xE9 => (Some(Emulator::near_jump), "near_jump"),
+        0xEA => (Some(Emulator::far_jump), "far_jump"),
         0xEB => (Some(Emulator::short_jump), "short_jump"),
         0xEC => (Some(Emulator::in_al_dx), "in_al_dx"),
+        0xED => (Some(Emulator::in_eax_dx), "in_eax_dx"),
         0xEE => (Some(Emulator::out_dx_al), "out_dx_al"),
+        0xEF => (Some(Emulator::out_dx_eax), "out_dx_eax"),
+        0xF4 => (Some(Emulator::hlt), "hlt"),
+        0xF5 => (Some(Emulator::cmc), "cmc"),
+        0xF6 => (Some(Emulator::code_f6), "code_f6"),
+        0xF7 => (Some(Emulator::code_f7), "code_f7"),
+        0xF8 => (Some(Emulator::clc), "clc"),
+        0xF9 => (Some(Emulator::stc), "stc"),
+        0xFA => (Some(Emulator::cli), "cli"),
+        0xFB => (Some(Emulator::sti), "sti"),
+        0xFC => (Some(Emulator::cld), "cld"),
+        0xFD => (Some(Emulator::std), "std"),
+        0xFE => (Some(Emulator::code_fe), "code_fe"),
         0xFF => (Some(Emulator::code_ff), "code_ff"),
         _ => (None, "None"),
     }
 }
+
+pub fn instructions_0f(code: u8) -> Option<Instruction> {
+    match code {
+        0x00 => Some(Emulator::code_0f_00),
+        0x01 => Some(Emulator::code_0f_01),
+        0x0B => Some(Emulator::ud2),
+        0x20 => Some(Emulator::mov_r32_cr),
+        0x22 => Some(Emulator::mov_cr_r32),
+        0x40 ..= 0x4F => Some(Emulator::cmovcc_r32_rm32),
+        0x80 ..= 0x8F => Some(Emulator::jcc_rel32),
+        0x90 ..= 0x9F => Some(Emulator::setcc_rm8),
+        0xA0 => Some(Emulator::push_sreg),
+        0xA1 => Some(Emulator::pop_sreg),
+        0xA4 => Some(Emulator::shld_rm32_r32_imm8),
+        0xA5 => Some(Emulator::shld_rm32_r32_cl),
+        0xA8 => Some(Emulator::push_sreg),
+        0xA9 => Some(Emulator::pop_sreg),
+        0xAC => Some(Emulator::shrd_rm32_r32_imm8),
+        0xAD => Some(Emulator::shrd_rm32_r32_cl),
+        0xAF => Some(Emulator::imul_r32_rm32),
+        0xB2 => Some(Emulator::lss),
+        0xB4 => Some(Emulator::lfs),
+        0xB5 => Some(Emulator::lgs),
+        0xB6 => Some(Emulator::movzx_r32_rm8),
+        0xB7 => Some(Emulator::movzx_r32_rm16),
+        0xBE => Some(Emulator::movsx_r32_rm8),
+        0xBF => Some(Emulator::movsx_r32_rm16),
+        _ => None,
+    }
+}
+
+pub fn instructions_0f_with_name(code: u8) -> (Option<Instruction>, &'static str) {
+    match code {
+        0x00 => (Some(Emulator::code_0f_00), "code_0f_00"),
+        0x01 => (Some(Emulator::code_0f_01), "code_0f_01"),
+        0x0B => (Some(Emulator::ud2), "ud2"),
+        0x20 => (Some(Emulator::mov_r32_cr), "mov_r32_cr"),
+        0x22 => (Some(Emulator::mov_cr_r32), "mov_cr_r32"),
+        0x40 ..= 0x4F => (Some(Emulator::cmovcc_r32_rm32), "cmovcc_r32_rm32"),
+        0x80 ..= 0x8F => (Some(Emulator::jcc_rel32), "jcc_rel32"),
+        0x90 ..= 0x9F => (Some(Emulator::setcc_rm8), "setcc_rm8"),
+        0xA0 => (Some(Emulator::push_sreg), "push_sreg"),
+        0xA1 => (Some(Emulator::pop_sreg), "pop_sreg"),
+        0xA4 => (Some(Emulator::shld_rm32_r32_imm8), "shld_rm32_r32_imm8"),
+        0xA5 => (Some(Emulator::shld_rm32_r32_cl), "shld_rm32_r32_cl"),
+        0xA8 => (Some(Emulator::push_sreg), "push_sreg"),
+        0xA9 => (Some(Emulator::pop_sreg), "pop_sreg"),
+        0xAC => (Some(Emulator::shrd_rm32_r32_imm8), "shrd_rm32_r32_imm8"),
+        0xAD => (Some(Emulator::shrd_rm32_r32_cl), "shrd_rm32_r32_cl"),
+        0xAF => (Some(Emulator::imul_r32_rm32), "imul_r32_rm32"),
+        0xB2 => (Some(Emulator::lss), "lss"),
+        0xB4 => (Some(Emulator::lfs), "lfs"),
+        0xB5 => (Some(Emulator::lgs), "lgs"),
+        0xB6 => (Some(Emulator::movzx_r32_rm8), "movzx_r32_rm8"),
+        0xB7 => (Some(Emulator::movzx_r32_rm16), "movzx_r32_rm16"),
+        0xBE => (Some(Emulator::movsx_r32_rm8), "movsx_r32_rm8"),
+        0xBF => (Some(Emulator::movsx_r32_rm16), "movsx_r32_rm16"),
+        _ => (None, "None"),
+    }
+}
@@ -0,0 +1,361 @@
+use super::*;
+use crate::emulator::segment::Segment;
+use crate::emulator::descriptor::DescriptorTable;
+use crate::emulator::SegmentRegister::*;
+use crate::emulator::exception::Exception;
+use crate::emulator::exception::Exception::*;
+
+/*
+ *  interrupt, trap and task gate
+ *  +----------------+--+---+--+------+-----+----------------+----------------+
+ *  |63            48|47|46 |44|43  40|39 32|31            16|15             0|
+ *  +----------------+--+---+--+------+-----+----------------+----------------+
+ *  |  offset 31-16  | P|DPL| 0| type |  0  |    selector    |  offset 15-0   |
+ *  +----------------+--+---+--+------+-----+----------------+----------------+
+ *  A task gate only uses the selector, which names a TSS.
+ */
+pub const GATE_TASK: u8        = 0x5;
+pub const GATE_INTERRUPT16: u8 = 0x6;
+pub const GATE_TRAP16: u8      = 0x7;
+pub const GATE_INTERRUPT32: u8 = 0xE;
+pub const GATE_TRAP32: u8      = 0xF;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Gate {
+    pub offset: u32,
+    pub selector: u16,
+    pub kind: u8,
+    pub dpl: u8,
+    pub present: bool,
+}
+
+impl Gate {
+    pub fn from_descriptor(raw: u64) -> Gate {
+        let low = raw as u32;
+        let high = (raw >> 32) as u32;
+        Gate {
+            offset: (high & 0xFFFF0000) | (low & 0xFFFF),
+            selector: (low >> 16) as u16,
+            kind: ((high >> 8) & 0x1F) as u8,
+            dpl: ((high >> 13) & 3) as u8,
+            present: high & (1 << 15) != 0,
+        }
+    }
+
+    /// Width of what the gate pushes: EFLAGS, CS, EIP and the error code.
+    pub fn bits(&self) -> u32 {
+        match self.kind {
+            GATE_INTERRUPT16 | GATE_TRAP16 => 16,
+            _ => 32,
+        }
+    }
+
+    /// Interrupt gates clear IF, trap gates leave it alone.
+    pub fn is_interrupt(&self) -> bool {
+        self.kind == GATE_INTERRUPT16 || self.kind == GATE_INTERRUPT32
+    }
+}
+
+/// What a faulting instruction is rolled back to: the registers before it started.
+#[derive(Debug, Clone)]
+pub struct Context {
+    registers: [u32; Register::RegistersCount as usize],
+    eflags: u32,
+    eip: u32,
+    segments: [Segment; SegmentRegister::SegmentRegistersCount as usize],
+    tr: Segment,
+}
+
+impl Emulator {
+    pub fn save_context(&self) -> Context {
+        Context {
+            registers: self.registers,
+            eflags: self.eflags.value(),
+            eip: self.eip,
+            segments: self.segments,
+            tr: self.tr,
+        }
+    }
+
+    pub fn restore_context(&mut self, context: &Context) {
+        self.registers = context.registers;
+        self.eflags.set_value(context.eflags);
+        self.eip = context.eip;
+        self.segments = context.segments;
+        self.tr = context.tr;
+    }
+
+    /*
+     * Deliver an exception raised by an instruction that has already been rolled back,
+     * so the handler returns to the faulting instruction.
+     * An exception raised by the delivery itself replaces it or becomes a double fault,
+     * and one raised while delivering the double fault shuts the CPU down.
+     */
+    pub fn deliver_exception(&mut self, exception: Exception) {
+        let mut current = exception;
+        loop {
+            let context = self.save_context();
+            self.interrupt(current.vector(), current.error_code(), false);
+            let next = match self.exception.take() {
+                Some(next) => next,
+                None => return,
+            };
+
+            self.restore_context(&context);
+            if current == DoubleFault {
+                self.shutdown = Some(exception);
+                return;
+            }
+            current = if current.is_double_fault(next) {
+                DoubleFault
+            } else {
+                next
+            };
+        }
+    }
+
+    /*
+     * INT n, INT3 and INTO.
+     * Flat binaries run without an IDT and call the BIOS services directly, as they always have.
+     * Once an IDT is loaded every vector goes through it.
+     */
+    pub fn software_interrupt(&mut self, vector: u8) {
+        if self.is_protected_mode() && self.idtr == DescriptorTable::default() && self.bios_service(vector) {
+            return;
+        }
+        self.interrupt(vector, None, true);
+    }
+
+    /*
+     * Transfer control to the handler of `vector`, returning to the current CS:EIP.
+     * `software` is set for INT n, INT3 and INTO: they're subject to the gate's DPL,
+     * while exceptions and external interrupts set the EXT bit of the error codes they cause.
+     */
+    pub fn interrupt(&mut self, vector: u8, error_code: Option<u16>, software: bool) {
+        if self.is_protected_mode() {
+            self.protected_mode_interrupt(vector, error_code, software);
+        } else {
+            self.real_mode_interrupt(vector);
+        }
+    }
+
+    /// The IVT holds a segment:offset pair per vector. Real mode exceptions push no error code.
+    fn real_mode_interrupt(&mut self, vector: u8) {
+        let entry = self.idtr.base.wrapping_add((vector as u32) << 2);
+        let offset = self.get_memory16(entry);
+        let segment = self.get_memory16(entry.wrapping_add(2));
+
+        self.push16(self.eflags.value() as u16);
+        self.push16(self.get_segment(CS).selector);
+        self.push16(self.eip as u16);
+        self.eflags.set_interrupt(false);
+        self.eflags.set_trap(false);
+        self.segments[CS as usize] = Segment::real(segment);
+        self.eip = offset as u32;
+    }
+
+    /*
+     *  IDT error code
+     *  +---------------------+--+--+
+     *  |15                  3| 1| 0|
+     *  +---------------------+--+--+
+     *  |       vector        | 1|EXT|
+     *  +---------------------+--+--+
+     */
+    fn protected_mode_interrupt(&mut self, vector: u8, error_code: Option<u16>, software: bool) {
+        let ext = if software { 0 } else { 1 };
+        let idt_error = ((vector as u16) << 3) | 2 | ext;
+        let offset = (vector as u32) << 3;
+        if offset + 7 > self.idtr.limit as u32 {
+            self.raise(GeneralProtection(idt_error));
+            return;
+        }
+
+        let addr = self.idtr.base.wrapping_add(offset);
+        let low = self.read_system32(addr) as u64;
+        let high = self.read_system32(addr.wrapping_add(4)) as u64;
+        if self.exception.is_some() {
+            return;
+        }
+        let gate = Gate::from_descriptor(low | (high << 32));
+
+        let valid = matches!(gate.kind, GATE_TASK | GATE_INTERRUPT16 | GATE_TRAP16 | GATE_INTERRUPT32 | GATE_TRAP32);
+        if !valid || (software && gate.dpl < self.cpl()) {
+            self.raise(GeneralProtection(idt_error));
+            return;
+        }
+        if !gate.present {
+            self.raise(SegmentNotPresent(idt_error));
+            return;
+        }
+
+        if gate.kind == GATE_TASK {
+            self.task_switch(gate.selector, true);
+            if let Some(code) = error_code {
+                self.push32(code as u32);
+            }
+            return;
+        }
+
+        self.interrupt_through_gate(&gate, error_code, ext);
+    }
+
+    /*
+     * An interrupt or trap gate calls its handler like a far CALL that also pushes EFLAGS.
+     * A handler in a more privileged non-conforming segment runs on that level's stack from the TSS,
+     * and the interrupted SS:ESP is pushed there first.
+     *
+     *  same level     to an inner level
+     *                 old SS
+     *                 old ESP
+     *  EFLAGS         EFLAGS
+     *  CS             CS
+     *  EIP            EIP
+     *  error code     error code   <- ESP
+     */
+    fn interrupt_through_gate(&mut self, gate: &Gate, error_code: Option<u16>, ext: u16) {
+        let selector = gate.selector;
+        let error = (selector & 0xFFFC) | ext;
+        if selector & 0xFFFC == 0 {
+            self.raise(GeneralProtection(ext));
+            return;
+        }
+
+        let raw = match self.read_descriptor(selector) {
+            Some(raw) => raw,
+            None => return,
+        };
+        let segment = Segment::from_descriptor(selector, raw);
+        let cpl = self.cpl();
+        if !segment.is_code() || segment.dpl() > cpl {
+            self.raise(GeneralProtection(error));
+            return;
+        }
+        if !segment.is_present() {
+            self.raise(SegmentNotPresent(error));
+            return;
+        }
+
+        let new_cpl = if segment.is_conforming() {
+            cpl
+        } else {
+            segment.dpl()
+        };
+        let bits = gate.bits();
+        let eflags = self.eflags.value();
+        let cs = self.get_segment(CS).selector as u32;
+        let eip = self.eip;
+
+        if new_cpl < cpl {
+            let (ss, esp) = self.inner_stack(new_cpl);
+            if self.exception.is_some() {
+                return;
+            }
+            let old_ss = self.get_segment(SS).selector as u32;
+            let old_esp = self.get_register32(ESP as usize);
+            self.load_code_segment(selector, new_cpl);
+            self.load_segment(SS, ss);
+            self.set_register32(ESP as usize, esp);
+            self.push_sized(old_ss, bits);
+            self.push_sized(old_esp, bits);
+        } else {
+            self.load_code_segment(selector, new_cpl);
+        }
+
+        self.push_sized(eflags, bits);
+        self.push_sized(cs, bits);
+        self.push_sized(eip, bits);
+        if let Some(code) = error_code {
+            self.push_sized(code as u32, bits);
+        }
+
+        let offset = gate.offset & mask(bits);
+        if !self.get_segment(CS).contains(offset, 1) {
+            self.raise(GeneralProtection(0));
+            return;
+        }
+        self.eip = offset;
+        self.eflags.set_trap(false);
+        self.eflags.set_nested_task(false);
+        if gate.is_interrupt() {
+            self.eflags.set_interrupt(false);
+        }
+    }
+
+    fn push_sized(&mut self, value: u32, bits: u32) {
+        match bits {
+            16 => self.push16(value as u16),
+            _ => self.push32(value),
+        }
+    }
+
+    /*
+     * IRET pops EIP, CS and EFLAGS, and ESP and SS too when it returns to an outer level.
+     * With NT set the current task was entered through a task gate,
+     * and IRET switches back to the task in its TSS link field instead.
+     */
+    pub fn interrupt_return(&mut self) {
+        if !self.is_protected_mode() {
+            let eip = self.popv();
+            let cs = self.popv() as u16;
+            let flags = self.popv();
+            self.segments[CS as usize] = Segment::real(cs);
+            self.eip = eip;
+            self.restore_eflags(flags, 0);
+            return;
+        }
+
+        if self.eflags.is_nested_task() {
+            self.task_return();
+            return;
+        }
+
+        let cpl = self.cpl();
+        let eip = self.popv();
+        let cs = self.popv() as u16;
+        let flags = self.popv();
+        let rpl = (cs & 3) as u8;
+        if rpl < cpl {
+            self.raise(GeneralProtection(cs & 0xFFFC));
+            return;
+        }
+
+        if rpl == cpl {
+            self.load_code_segment(cs, cpl);
+        } else {
+            let esp = self.popv();
+            let ss = self.popv() as u16;
+            self.load_code_segment(cs, rpl);
+            self.load_segment(SS, ss);
+            self.set_registerv(ESP as usize, esp);
+            self.invalidate_data_segments();
+        }
+
+        if !self.get_segment(CS).contains(eip, 1) {
+            self.raise(GeneralProtection(0));
+            return;
+        }
+        self.eip = eip;
+        self.restore_eflags(flags, cpl);
+    }
+
+    /*
+     * EFLAGS as IRET and POPF load it at privilege `cpl`: IOPL only changes at CPL 0,
+     * IF only where CPL <= IOPL, and a 16-bit one leaves the upper half alone.
+     * There's no virtual-8086 mode, so VM is never set.
+     */
+    pub fn restore_eflags(&mut self, value: u32, cpl: u8) {
+        let mut keep = VIRTUAL_8086_FLAG;
+        if self.operand_bits() == 16 {
+            keep |= 0xFFFF0000;
+        }
+        if cpl > 0 {
+            keep |= IOPL_MASK;
+        }
+        if cpl > self.eflags.iopl() {
+            keep |= INTERRUPT_FLAG;
+        }
+        let value = (self.eflags.value() & keep) | (value & !keep);
+        self.eflags.set_value(value);
+    }
+}
@@ -1,26 +1,169 @@
-use std::io::{self, Write};
+use super::*;
+use std::cell::RefCell;
+use std::ops::RangeInclusive;
+use std::rc::Rc;
+use crate::emulator::mmu::A20_GATE;
 
-pub fn io_in8(addr: u16) -> u8 {
-    match addr {
-        0x03F8 => getchar(),
-        _ => 0,
+/*
+ * The I/O port space: 64K byte-wide ports, reached only by IN, OUT, INS and OUTS.
+ * A device claims a range of ports on the bus and gets every access that starts in it.
+ * A 16 or 32-bit access is split into bytes at consecutive ports unless the device handles it whole.
+ */
+pub trait PortDevice {
+    fn in8(&mut self, port: u16) -> u8;
+    fn out8(&mut self, port: u16, value: u8);
+
+    fn in16(&mut self, port: u16) -> u16 {
+        self.in8(port) as u16 | (self.in8(port.wrapping_add(1)) as u16) << 8
+    }
+
+    fn out16(&mut self, port: u16, value: u16) {
+        self.out8(port, value as u8);
+        self.out8(port.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    fn in32(&mut self, port: u16) -> u32 {
+        self.in16(port) as u32 | (self.in16(port.wrapping_add(2)) as u32) << 16
+    }
+
+    fn out32(&mut self, port: u16, value: u32) {
+        self.out16(port, value as u16);
+        self.out16(port.wrapping_add(2), (value >> 16) as u16);
     }
 }
 
-pub fn io_out8(addr: u16, value: u8) {
-    match addr {
-        0x03F8 => putchar(value),
-        _ => (),
+/// Devices are shared, so whoever registers one keeps a handle on it, a test to inspect a mock for one.
+pub type SharedDevice = Rc<RefCell<dyn PortDevice>>;
+
+#[derive(Clone, Default)]
+pub struct IoBus {
+    devices: Vec<(RangeInclusive<u16>, SharedDevice)>,
+}
+
+impl fmt::Debug for IoBus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.devices.iter().map(|(ports, _)| ports)).finish()
     }
 }
 
-fn getchar() -> u8 {
-    let mut buf = String::new();
-    io::stdin().read_line(&mut buf).expect("Can't read line");
-    buf.as_bytes()[0]
+impl IoBus {
+    /// The latest device registered for a port wins, so a device can be replaced by registering another.
+    pub fn register(&mut self, ports: RangeInclusive<u16>, device: SharedDevice) {
+        self.devices.push((ports, device));
+    }
+
+    pub fn device(&self, port: u16) -> Option<SharedDevice> {
+        self.devices.iter().rev().find(|(ports, _)| ports.contains(&port)).map(|(_, device)| device.clone())
+    }
 }
 
-fn putchar(value: u8) {
-    print!("{}", value as char);
-    io::stdout().flush().expect("Can't flush stdout");
+/// Nothing drives the data bus for a port no device claims, it floats high.
+pub const OPEN_BUS: u8 = 0xFF;
+
+impl Emulator {
+    /// IN and OUT: registered devices first, then the chipset, the keyboard controller, the RTC, the VGA and the UARTs on their own ports.
+    pub fn port_in8(&mut self, port: u16) -> u8 {
+        if let Some(device) = self.io.device(port) {
+            return device.borrow_mut().in8(port);
+        }
+        match port {
+            0x20 | 0x21 | 0xA0 | 0xA1 => self.pic.read(port),
+            0x40 ..= 0x43 | 0x61 => self.pit.read(port),
+            0x60 | 0x64 => {
+                let value = self.keyboard.read(port);
+                self.update_keyboard_irq();
+                value
+            },
+            0x70 | 0x71 => {
+                let value = self.cmos.read(port);
+                self.update_cmos_irq();
+                value
+            },
+            0x92 => self.system_control,
+            0x3D4 | 0x3D5 | 0x3DA => self.vga.read_port(port),
+            _ => match self.serial.port(port) {
+                Some(uart) => {
+                    let value = uart.in8(port);
+                    self.update_serial_irqs();
+                    value
+                },
+                None => OPEN_BUS,
+            },
+        }
+    }
+
+    pub fn port_out8(&mut self, port: u16, value: u8) {
+        if let Some(device) = self.io.device(port) {
+            device.borrow_mut().out8(port, value);
+            return;
+        }
+        match port {
+            0x20 | 0x21 | 0xA0 | 0xA1 => self.pic.write(port, value),
+            0x40 ..= 0x43 | 0x61 => self.pit.write(port, value),
+            0x60 | 0x64 => {
+                self.keyboard.write(port, value);
+                self.update_keyboard_irq();
+            },
+            0x70 | 0x71 => {
+                self.cmos.write(port, value);
+                self.update_cmos_irq();
+            },
+            // bit 0 would reset the CPU, which there's no way to do here
+            0x92 => self.system_control = value & A20_GATE,
+            0x3D4 | 0x3D5 => self.vga.write_port(port, value),
+            _ => if let Some(uart) = self.serial.port(port) {
+                uart.out8(port, value);
+                self.update_serial_irqs();
+            },
+        }
+    }
+
+    pub fn port_in16(&mut self, port: u16) -> u16 {
+        if let Some(device) = self.io.device(port) {
+            return device.borrow_mut().in16(port);
+        }
+        self.port_in8(port) as u16 | (self.port_in8(port.wrapping_add(1)) as u16) << 8
+    }
+
+    pub fn port_out16(&mut self, port: u16, value: u16) {
+        if let Some(device) = self.io.device(port) {
+            device.borrow_mut().out16(port, value);
+            return;
+        }
+        self.port_out8(port, value as u8);
+        self.port_out8(port.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    pub fn port_in32(&mut self, port: u16) -> u32 {
+        if let Some(device) = self.io.device(port) {
+            return device.borrow_mut().in32(port);
+        }
+        self.port_in16(port) as u32 | (self.port_in16(port.wrapping_add(2)) as u32) << 16
+    }
+
+    pub fn port_out32(&mut self, port: u16, value: u32) {
+        if let Some(device) = self.io.device(port) {
+            device.borrow_mut().out32(port, value);
+            return;
+        }
+        self.port_out16(port, value as u16);
+        self.port_out16(port.wrapping_add(2), (value >> 16) as u16);
+    }
+
+    /// `bytes` wide accesses, for the instructions whose operand size picks the width.
+    pub fn port_in(&mut self, port: u16, bytes: u32) -> u32 {
+        match bytes {
+            1 => self.port_in8(port) as u32,
+            2 => self.port_in16(port) as u32,
+            _ => self.port_in32(port),
+        }
+    }
+
+    pub fn port_out(&mut self, port: u16, value: u32, bytes: u32) {
+        match bytes {
+            1 => self.port_out8(port, value as u8),
+            2 => self.port_out16(port, value as u16),
+            _ => self.port_out32(port, value),
+        }
+    }
 }
@@ -0,0 +1,314 @@
+use super::*;
+use std::collections::VecDeque;
+use crate::emulator::io::PortDevice;
+use crate::emulator::pit::PIT_FREQUENCY;
+use crate::emulator::uart::SharedBackend;
+
+/*
+ * The 8042 keyboard controller and the keyboard behind it.
+ *
+ *  port  read                  write
+ *  0x60  output buffer         data: to the keyboard, or for the last controller command
+ *  0x64  status                controller command
+ *
+ *  status
+ *  +----+----+----+--------+------+---+----+----+
+ *  | 7  | 6  | 5  |   4    |  3   | 2 | 1  | 0  |
+ *  +----+----+----+--------+------+---+----+----+
+ *  |par |time|aux |unlocked|cmd   |sys|IBF |OBF |
+ *  +----+----+----+--------+------+---+----+----+
+ *
+ * Keys come from a backend, the terminal or a script, one character at a time, and are typed
+ * as set 1 scancodes, what a PC sees with the controller translating: a make code when the key
+ * goes down, the make code with bit 7 set when it comes up, shift or ctrl around them when needed.
+ * A scancode waits in the output buffer until it is read, raising IRQ 1 meanwhile.
+ */
+const STATUS_OUTPUT_FULL: u8 = 1;
+const STATUS_SYSTEM: u8      = 1 << 2;
+const STATUS_COMMAND: u8     = 1 << 3;
+const STATUS_UNLOCKED: u8    = 1 << 4;
+
+const COMMAND_INTERRUPT: u8 = 1;
+const COMMAND_SYSTEM: u8    = 1 << 2;
+const COMMAND_DISABLED: u8  = 1 << 4;
+const DEFAULT_COMMAND: u8   = 0x45;
+
+/// Bit 1 of the output port gates A20, bit 0 low resets the CPU.
+const DEFAULT_OUTPUT_PORT: u8 = 0x03;
+
+const ACK: u8 = 0xFA;
+const SELF_TEST_PASSED: u8 = 0xAA;
+
+/// A key a millisecond at most, however fast they come.
+const KEY_TICKS: u64 = PIT_FREQUENCY / 1000;
+
+pub const SCANCODE_LEFT_SHIFT: u8 = 0x2A;
+pub const SCANCODE_CTRL: u8       = 0x1D;
+pub const SCANCODE_EXTENDED: u8   = 0xE0;
+pub const SCANCODE_RELEASE: u8    = 0x80;
+
+/// What the keys up to the space bar type in the US layout, by scancode, without and with shift.
+pub const KEYMAP: &[u8; 0x3A]         = b"\0\x1b1234567890-=\x08\tqwertyuiop[]\r\0asdfghjkl;'`\0\\zxcvbnm,./\0*\0 ";
+pub const KEYMAP_SHIFTED: &[u8; 0x3A] = b"\0\x1b!@#$%^&*()_+\x08\tQWERTYUIOP{}\r\0ASDFGHJKL:\"~\0|ZXCVBNM<>?\0*\0 ";
+
+/// The scancodes a terminal's escape sequence ESC [ x stands for: arrows, home and end.
+const ESCAPE_KEYS: [(u8, u8); 6] = [(b'A', 0x48), (b'B', 0x50), (b'C', 0x4D), (b'D', 0x4B), (b'H', 0x47), (b'F', 0x4F)];
+
+/// The scancodes that type a character, pressed and released.
+pub fn scancodes(character: u8) -> Vec<u8> {
+    let press = |scancode: u8| vec![scancode, scancode | SCANCODE_RELEASE];
+    let around = |modifier: u8, scancode: u8| vec![modifier, scancode, scancode | SCANCODE_RELEASE, modifier | SCANCODE_RELEASE];
+    let find = |keymap: &[u8; 0x3A], character: u8| keymap.iter().skip(1).position(|c| *c == character).map(|i| i as u8 + 1);
+
+    match character {
+        b'\n' => press(0x1C),
+        0x7F => press(0x0E),
+        _ => if let Some(scancode) = find(KEYMAP, character) {
+            press(scancode)
+        } else if let Some(scancode) = find(KEYMAP_SHIFTED, character) {
+            around(SCANCODE_LEFT_SHIFT, scancode)
+        } else if (0x01 ..= 0x1A).contains(&character) {
+            around(SCANCODE_CTRL, find(KEYMAP, character + 0x60).unwrap_or(0))
+        } else {
+            vec![]
+        },
+    }
+}
+
+#[derive(Clone)]
+pub struct Keyboard {
+    backend: Option<SharedBackend>,
+    scancodes: VecDeque<u8>,    // typed, waiting for the output buffer
+    replies: VecDeque<u8>,      // to commands, ahead of the scancodes
+    output: Option<u8>,
+    command: u8,
+    pending: Option<u8>,        // the controller command waiting for its data
+    keyboard_pending: Option<u8>,
+    last_write_command: bool,
+    scanning: bool,
+    pub output_port: u8,
+    ticks: u64,
+}
+
+impl fmt::Debug for Keyboard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Keyboard")
+            .field("connected", &self.backend.is_some())
+            .field("scancodes", &self.scancodes)
+            .field("output", &self.output)
+            .field("command", &self.command)
+            .field("output_port", &self.output_port)
+            .finish()
+    }
+}
+
+impl Default for Keyboard {
+    fn default() -> Keyboard {
+        Keyboard {
+            backend: None,
+            scancodes: VecDeque::new(),
+            replies: VecDeque::new(),
+            output: None,
+            command: DEFAULT_COMMAND,
+            pending: None,
+            keyboard_pending: None,
+            last_write_command: false,
+            scanning: true,
+            output_port: DEFAULT_OUTPUT_PORT,
+            ticks: 0,
+        }
+    }
+}
+
+impl Keyboard {
+    pub fn attach(&mut self, backend: SharedBackend) {
+        self.backend = Some(backend);
+    }
+
+    pub fn detach(&mut self) {
+        self.backend = None;
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.backend.is_some()
+    }
+
+    /// Whether a key may still come: one is waiting, or the backend's input hasn't ended.
+    pub fn will_type(&self) -> bool {
+        !self.scancodes.is_empty() || self.backend.as_ref().is_some_and(|backend| !backend.borrow().is_exhausted())
+    }
+
+    /// Type a character, as if it came from the backend.
+    pub fn type_character(&mut self, character: u8) {
+        self.scancodes.extend(scancodes(character));
+    }
+
+    /// Press and release keys by scancode, for the ones no character types.
+    pub fn type_scancodes(&mut self, scancodes: &[u8]) {
+        self.scancodes.extend(scancodes);
+    }
+
+    pub fn read(&mut self, port: u16) -> u8 {
+        match port {
+            0x60 => self.output.take().unwrap_or(0),
+            _ => {
+                let mut status = STATUS_UNLOCKED;
+                if self.output.is_some() {
+                    status |= STATUS_OUTPUT_FULL;
+                }
+                if self.command & COMMAND_SYSTEM != 0 {
+                    status |= STATUS_SYSTEM;
+                }
+                if self.last_write_command {
+                    status |= STATUS_COMMAND;
+                }
+                status
+            },
+        }
+    }
+
+    pub fn write(&mut self, port: u16, value: u8) {
+        self.last_write_command = port == 0x64;
+        if port == 0x64 {
+            self.controller_command(value);
+        } else if let Some(command) = self.pending.take() {
+            match command {
+                0x60 => self.command = value,
+                0xD1 => self.output_port = value,
+                // written to the output buffer as if the keyboard had sent it
+                0xD2 => self.replies.push_back(value),
+                _ => (),
+            }
+        } else {
+            self.keyboard_command(value);
+        }
+        self.fill();
+    }
+
+    fn controller_command(&mut self, command: u8) {
+        match command {
+            0x20 => self.replies.push_back(self.command),
+            0x60 | 0xD1 | 0xD2 => self.pending = Some(command),
+            0xAA => {
+                self.replies.push_back(0x55);
+                self.command |= COMMAND_SYSTEM;
+            },
+            0xAB => self.replies.push_back(0x00),
+            0xAD => self.command |= COMMAND_DISABLED,
+            0xAE => self.command &= !COMMAND_DISABLED,
+            0xD0 => self.replies.push_back(self.output_port),
+            _ => (),
+        }
+    }
+
+    /// Commands to the keyboard itself. The ones with a parameter take the next byte.
+    fn keyboard_command(&mut self, value: u8) {
+        if let Some(command) = self.keyboard_pending.take() {
+            self.replies.push_back(ACK);
+            // scancode set 0 asks which it is: 2, translated to 1
+            if command == 0xF0 && value == 0 {
+                self.replies.push_back(0x41);
+            }
+            return;
+        }
+        match value {
+            0xED | 0xF0 | 0xF3 => {
+                self.keyboard_pending = Some(value);
+                self.replies.push_back(ACK);
+            },
+            0xEE => self.replies.push_back(0xEE),
+            0xF2 => self.replies.extend(&[ACK, 0xAB, 0x83]),
+            0xF4 => {
+                self.scanning = true;
+                self.replies.push_back(ACK);
+            },
+            0xF5 => {
+                self.scanning = false;
+                self.replies.push_back(ACK);
+            },
+            0xFF => {
+                self.scancodes.clear();
+                self.scanning = true;
+                self.replies.extend(&[ACK, SELF_TEST_PASSED]);
+            },
+            _ => self.replies.push_back(ACK),
+        }
+    }
+
+    /// Move the next byte into an empty output buffer: a reply, or a scancode while the keyboard is on.
+    fn fill(&mut self) {
+        if self.output.is_some() {
+            return;
+        }
+        self.output = self.replies.pop_front();
+        if self.output.is_none() && self.scanning && self.command & COMMAND_DISABLED == 0 {
+            self.output = self.scancodes.pop_front();
+        }
+    }
+
+    /*
+     * A PIT tick. Scancodes move into the output buffer one a tick after the last was read,
+     * so each raises IRQ 1 anew, and a key is taken from the backend once the last is typed.
+     */
+    pub fn clock(&mut self) {
+        self.ticks += 1;
+        if self.scancodes.is_empty() && self.ticks >= KEY_TICKS {
+            self.ticks = 0;
+            self.receive();
+        }
+        self.fill();
+    }
+
+    /// A key from the backend, turning a terminal's escape sequences into the keys they stand for.
+    fn receive(&mut self) {
+        let backend = match self.backend.as_ref() {
+            Some(backend) => backend.clone(),
+            None => return,
+        };
+        let mut backend = backend.borrow_mut();
+        let character = match backend.receive() {
+            Some(character) => character,
+            None => return,
+        };
+        if character != 0x1B {
+            self.type_character(character);
+            return;
+        }
+        match backend.receive() {
+            Some(b'[') => {
+                let last = backend.receive().unwrap_or(0);
+                if let Some((_, scancode)) = ESCAPE_KEYS.iter().find(|(key, _)| *key == last) {
+                    self.scancodes.extend(&[SCANCODE_EXTENDED, *scancode, SCANCODE_EXTENDED, scancode | SCANCODE_RELEASE]);
+                }
+            },
+            next => {
+                self.type_character(0x1B);
+                if let Some(character) = next {
+                    self.type_character(character);
+                }
+            },
+        }
+    }
+
+    /// IRQ 1 is raised while the output buffer holds something, if the command byte lets it.
+    pub fn interrupt(&self) -> bool {
+        self.output.is_some() && self.command & COMMAND_INTERRUPT != 0
+    }
+}
+
+impl PortDevice for Keyboard {
+    fn in8(&mut self, port: u16) -> u8 {
+        self.read(port)
+    }
+
+    fn out8(&mut self, port: u16, value: u8) {
+        self.write(port, value);
+    }
+}
+
+impl Emulator {
+    pub fn update_keyboard_irq(&mut self) {
+        let high = self.keyboard.interrupt();
+        self.pic.set_irq(1, high);
+    }
+}
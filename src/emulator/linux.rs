@@ -0,0 +1,712 @@
+use super::*;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use crate::emulator::elf::Elf;
+use crate::emulator::exception::Exception;
+use crate::emulator::segment::Segment;
+use crate::emulator::Register::*;
+use crate::emulator::SegmentRegister::*;
+
+/*
+ * Static Linux i386 programs in user mode. The emulator plays the kernel: INT 0x80 is a system call,
+ * EAX the number and EBX, ECX, EDX, ESI, EDI and EBP the arguments, and it returns in EAX
+ * the result or a negative errno. The program runs at CPL 3 on the kernel's flat user segments,
+ * without paging or interrupts, in an arena of memory above its segments:
+ *
+ *  +---------+------------> <------------+-------+-----+
+ *  | program | heap                 mmap | stack | GDT |
+ *  +---------+------------> <------------+-------+-----+
+ *            brk start                   stack bottom  arena end
+ *
+ * The GDT has the user code and data segments at 0x73 and 0x7B, as Linux does,
+ * and the three TLS entries from 6 that set_thread_area fills in.
+ * The initial stack is the one the kernel builds:
+ *
+ *  ESP -> argc
+ *         argv[0] .. argv[argc - 1], 0
+ *         envp[0] .. 0
+ *         auxv: type and value pairs, up to AT_NULL
+ *         the strings they point to, at the top
+ */
+const PAGE: u32 = 0x1000;
+pub const ARENA_SIZE: u32 = 16 << 20;
+pub const STACK_SIZE: u32 = 1 << 20;
+
+pub const USER_CODE: u16 = 0x73;
+pub const USER_DATA: u16 = 0x7B;
+const TLS_ENTRIES: std::ops::RangeInclusive<u32> = 6 ..= 8;
+const GDT_ENTRIES: u32 = 16;
+
+pub const PID: u32 = 1;
+
+const AT_NULL: u32     = 0;
+const AT_PHDR: u32     = 3;
+const AT_PHENT: u32    = 4;
+const AT_PHNUM: u32    = 5;
+const AT_PAGESZ: u32   = 6;
+const AT_ENTRY: u32    = 9;
+const AT_UID: u32      = 11;
+const AT_EUID: u32     = 12;
+const AT_GID: u32      = 13;
+const AT_EGID: u32     = 14;
+const AT_PLATFORM: u32 = 15;
+const AT_CLKTCK: u32   = 17;
+const AT_SECURE: u32   = 23;
+const AT_RANDOM: u32   = 25;
+const AT_EXECFN: u32   = 31;
+const AUXV_ENTRIES: usize = 15;
+
+const PLATFORM: &[u8] = b"i686\0";
+const RANDOM_SIZE: u8 = 16;
+
+const EPERM: u32   = 1;
+const ENOENT: u32  = 2;
+const EIO: u32     = 5;
+const EBADF: u32   = 9;
+const ENOMEM: u32  = 12;
+const EACCES: u32  = 13;
+const EFAULT: u32  = 14;
+const EEXIST: u32  = 17;
+const ENODEV: u32  = 19;
+const EINVAL: u32  = 22;
+const EMFILE: u32  = 24;
+const ENOTTY: u32  = 25;
+const ESPIPE: u32  = 29;
+const ENOSYS: u32  = 38;
+const EOVERFLOW: u32 = 75;
+
+pub const SIGILL: i32  = 4;
+pub const SIGFPE: i32  = 8;
+pub const SIGSEGV: i32 = 11;
+pub const SIGXCPU: i32 = 24;
+
+const O_ACCESS: u32 = 3;
+const O_CREAT: u32  = 0x40;
+const O_EXCL: u32   = 0x80;
+const O_TRUNC: u32  = 0x200;
+const O_APPEND: u32 = 0x400;
+
+const MAP_FIXED: u32     = 0x10;
+const MAP_ANONYMOUS: u32 = 0x20;
+
+const CLOCK_REALTIME: u32      = 0;
+const CLOCK_MONOTONIC: u32     = 1;
+const CLOCK_MONOTONIC_RAW: u32 = 4;
+
+const MAX_FILES: usize = 256;
+const UIO_MAXIOV: u32 = 1024;
+const PATH_MAX: u32 = 4096;
+
+/// The signal Linux kills a process with for a fault it has no handler for.
+pub fn fault_signal(exception: Exception) -> i32 {
+    match exception {
+        Exception::DivideError => SIGFPE,
+        Exception::InvalidOpcode => SIGILL,
+        _ => SIGSEGV,
+    }
+}
+
+/// The result of a system call: what goes in EAX, or the errno whose negation does.
+type SyscallResult = Result<u32, u32>;
+
+/// What a file descriptor refers to.
+pub trait Stream {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize>;
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize>;
+    fn seek(&mut self, _position: SeekFrom) -> io::Result<u64> {
+        Err(io::Error::from_raw_os_error(ESPIPE as i32))
+    }
+}
+
+pub type SharedStream = Rc<RefCell<dyn Stream>>;
+
+fn bad_file() -> io::Error {
+    io::Error::from_raw_os_error(EBADF as i32)
+}
+
+impl Stream for File {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        Read::read(self, buffer)
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        Write::write(self, buffer)
+    }
+
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        Seek::seek(self, position)
+    }
+}
+
+/// A file in memory, for input prepared ahead or output to look at afterwards.
+impl Stream for Cursor<Vec<u8>> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        Read::read(self, buffer)
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        Write::write(self, buffer)
+    }
+
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        Seek::seek(self, position)
+    }
+}
+
+impl Stream for io::Stdin {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        Read::read(self, buffer)
+    }
+
+    fn write(&mut self, _buffer: &[u8]) -> io::Result<usize> {
+        Err(bad_file())
+    }
+}
+
+impl Stream for io::Stdout {
+    fn read(&mut self, _buffer: &mut [u8]) -> io::Result<usize> {
+        Err(bad_file())
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.write_all(buffer)?;
+        self.flush()?;
+        Ok(buffer.len())
+    }
+}
+
+impl Stream for io::Stderr {
+    fn read(&mut self, _buffer: &mut [u8]) -> io::Result<usize> {
+        Err(bad_file())
+    }
+
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.write_all(buffer)?;
+        Ok(buffer.len())
+    }
+}
+
+/// The errno for a host error, which on a Linux host is the same number.
+fn errno(error: &io::Error) -> u32 {
+    match error.raw_os_error() {
+        Some(errno) => errno as u32,
+        None => match error.kind() {
+            io::ErrorKind::NotFound => ENOENT,
+            io::ErrorKind::PermissionDenied => EACCES,
+            io::ErrorKind::AlreadyExists => EEXIST,
+            _ => EIO,
+        },
+    }
+}
+
+/// The little-endian word at `offset` in a structure read from the program.
+fn word(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+/// Rounded up to a page, if that's still an address.
+fn page_up(address: u32) -> Option<u32> {
+    address.checked_add(PAGE - 1).map(|address| address & !(PAGE - 1))
+}
+
+/// Why a program can't start.
+#[derive(Debug, PartialEq, Eq)]
+pub enum StartError {
+    NoRoom,             // the arena doesn't fit above the program
+    ArgumentsTooLong,   // the arguments and environment don't fit in the stack
+}
+
+impl fmt::Display for StartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StartError::NoRoom => write!(f, "No room for the heap and stack above the program"),
+            StartError::ArgumentsTooLong => write!(f, "The arguments and environment don't fit in the stack"),
+        }
+    }
+}
+
+/*
+ *  segment descriptor, see descriptor.rs
+ *  access: P, DPL, S and the type. `pages` sets G, every one here is 32-bit.
+ */
+fn descriptor(base: u32, limit: u32, access: u8, pages: bool) -> u64 {
+    let flags: u64 = if pages { 0xC } else { 0x4 };
+    (limit as u64 & 0xFFFF) | ((base as u64 & 0xFFFFFF) << 16) | ((access as u64) << 40)
+        | (((limit as u64 >> 16) & 0xF) << 48) | (flags << 52) | (((base >> 24) as u64) << 56)
+}
+
+/// The process: its files, where its heap and mappings end, and how it exited.
+#[derive(Clone)]
+pub struct Linux {
+    files: Vec<Option<SharedStream>>,
+    brk_start: u32,
+    brk: u32,
+    mmap_bottom: u32,       // mappings go down from the stack
+    stack_bottom: u32,
+    gdt: u32,
+    pub exit_status: Option<i32>,
+}
+
+impl fmt::Debug for Linux {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Linux")
+            .field("files", &self.files.iter().filter(|file| file.is_some()).count())
+            .field("brk", &self.brk)
+            .field("mmap_bottom", &self.mmap_bottom)
+            .field("exit_status", &self.exit_status)
+            .finish()
+    }
+}
+
+impl Linux {
+    /// Standard input, output and error are the host's.
+    pub fn new(brk_start: u32, stack_bottom: u32, gdt: u32) -> Linux {
+        let stdin: SharedStream = Rc::new(RefCell::new(io::stdin()));
+        let stdout: SharedStream = Rc::new(RefCell::new(io::stdout()));
+        let stderr: SharedStream = Rc::new(RefCell::new(io::stderr()));
+        Linux {
+            files: vec![Some(stdin), Some(stdout), Some(stderr)],
+            brk_start,
+            brk: brk_start,
+            mmap_bottom: stack_bottom,
+            stack_bottom,
+            gdt,
+            exit_status: None,
+        }
+    }
+
+    pub fn brk(&self) -> u32 {
+        self.brk
+    }
+
+    pub fn file(&self, fd: u32) -> Option<SharedStream> {
+        self.files.get(fd as usize).cloned().flatten()
+    }
+
+    /// Put a stream behind a file descriptor, in place of whatever was there.
+    pub fn set_file(&mut self, fd: u32, stream: SharedStream) {
+        let fd = fd as usize;
+        if self.files.len() <= fd {
+            self.files.resize(fd + 1, None);
+        }
+        self.files[fd] = Some(stream);
+    }
+
+    /// The lowest free descriptor, as open returns.
+    fn add_file(&mut self, stream: SharedStream) -> Option<u32> {
+        let fd = self.files.iter().position(|file| file.is_none()).unwrap_or(self.files.len());
+        if fd >= MAX_FILES {
+            return None;
+        }
+        self.set_file(fd as u32, stream);
+        Some(fd as u32)
+    }
+}
+
+impl Emulator {
+    fn linux(&mut self) -> &mut Linux {
+        self.linux.as_mut().expect("not running a Linux program")
+    }
+
+    /*
+     * Get a loaded ELF program ready to run as a Linux process: the arena, the GDT,
+     * the user segments and the initial stack with `args`, `env` and the auxiliary vector.
+     */
+    pub fn start_linux(&mut self, elf: &Elf, args: &[String], env: &[String]) -> Result<(), StartError> {
+        let mut end = 0;
+        for segment in elf.segments.iter() {
+            end = end.max(segment.address.checked_add(segment.size).ok_or(StartError::NoRoom)?);
+        }
+        let brk_start = page_up(end).ok_or(StartError::NoRoom)?;
+        let arena_end = brk_start.checked_add(ARENA_SIZE).ok_or(StartError::NoRoom)?;
+        // the strings, the words up to the auxiliary vector's end, and the alignment
+        let strings: usize = args.iter().chain(env.iter()).map(|s| s.len() + 1).sum::<usize>() + PLATFORM.len() + RANDOM_SIZE as usize;
+        let words = 3 + args.len() + env.len() + 2 * AUXV_ENTRIES;
+        if strings + words * 4 + 16 > STACK_SIZE as usize {
+            return Err(StartError::ArgumentsTooLong);
+        }
+        if self.memory.len() < arena_end as usize {
+            self.memory.resize(arena_end as usize, 0);
+        }
+        let gdt = arena_end - PAGE;
+        let stack_top = gdt;
+        self.linux = Some(Linux::new(brk_start, stack_top - STACK_SIZE, gdt));
+
+        for entry in 0..GDT_ENTRIES {
+            self.write_physical32(gdt + entry * 8, 0);
+            self.write_physical32(gdt + entry * 8 + 4, 0);
+        }
+        for (selector, access) in [(USER_CODE, 0xFA), (USER_DATA, 0xF2)].iter() {
+            let raw = descriptor(0, 0xFFFFF, *access, true);
+            self.write_physical32(gdt + (*selector & !7) as u32, raw as u32);
+            self.write_physical32(gdt + (*selector & !7) as u32 + 4, (raw >> 32) as u32);
+        }
+        self.gdtr = DescriptorTable { base: gdt, limit: (GDT_ENTRIES * 8 - 1) as u16 };
+        self.cr[0] |= CR0_PE;
+        self.segments[CS as usize] = Segment::from_descriptor(USER_CODE, descriptor(0, 0xFFFFF, 0xFB, true));
+        for sreg in [ES, SS, DS].iter() {
+            self.load_segment(*sreg, USER_DATA);
+        }
+        for sreg in [FS, GS].iter() {
+            self.load_segment(*sreg, 0);
+        }
+
+        // the strings, at the top
+        let mut sp = stack_top;
+        let random = self.push_user_bytes(&mut sp, &(0x10 .. 0x10 + RANDOM_SIZE).collect::<Vec<u8>>());
+        let platform = self.push_user_bytes(&mut sp, PLATFORM);
+        let mut string = |emu: &mut Emulator, s: &String| emu.push_user_bytes(&mut sp, format!("{}\0", s).as_bytes());
+        let env: Vec<u32> = env.iter().map(|s| string(self, s)).collect();
+        let args: Vec<u32> = args.iter().map(|s| string(self, s)).collect();
+
+        let auxv: [(u32, u32); AUXV_ENTRIES] = [
+            (AT_PHDR, elf.program_headers), (AT_PHENT, 32), (AT_PHNUM, elf.program_header_count as u32),
+            (AT_PAGESZ, PAGE), (AT_ENTRY, elf.entry), (AT_UID, 0), (AT_EUID, 0), (AT_GID, 0), (AT_EGID, 0),
+            (AT_PLATFORM, platform), (AT_CLKTCK, 100), (AT_SECURE, 0), (AT_RANDOM, random),
+            (AT_EXECFN, args.first().copied().unwrap_or(0)), (AT_NULL, 0),
+        ];
+        let mut words = vec![args.len() as u32];
+        words.extend(&args);
+        words.push(0);
+        words.extend(&env);
+        words.push(0);
+        auxv.iter().for_each(|(kind, value)| words.extend(&[*kind, *value]));
+
+        let sp = (sp - words.len() as u32 * 4) & !0xF;
+        for (i, word) in words.iter().enumerate() {
+            self.write_physical32(sp + i as u32 * 4, *word);
+        }
+
+        self.registers = [0; Register::RegistersCount as usize];
+        self.registers[ESP as usize] = sp;
+        self.eflags = Eflags::new();
+        self.eip = elf.entry;
+        Ok(())
+    }
+
+    /// Put bytes below `sp`, returning where they start.
+    fn push_user_bytes(&mut self, sp: &mut u32, bytes: &[u8]) -> u32 {
+        *sp -= bytes.len() as u32;
+        for (i, byte) in bytes.iter().enumerate() {
+            self.write_physical8(*sp + i as u32, *byte);
+        }
+        *sp
+    }
+
+    /// The program's memory, what a system call may read or write, is the memory there is.
+    fn check_user(&self, address: u32, size: u32) -> Result<(), u32> {
+        match address.checked_add(size) {
+            Some(end) if end as usize <= self.memory.len() => Ok(()),
+            _ => Err(EFAULT),
+        }
+    }
+
+    fn read_user(&self, address: u32, size: u32) -> Result<Vec<u8>, u32> {
+        self.check_user(address, size)?;
+        Ok((0..size).map(|i| self.read_physical8(address + i)).collect())
+    }
+
+    fn write_user(&mut self, address: u32, bytes: &[u8]) -> Result<(), u32> {
+        self.check_user(address, bytes.len() as u32)?;
+        for (i, byte) in bytes.iter().enumerate() {
+            self.write_physical8(address + i as u32, *byte);
+        }
+        Ok(())
+    }
+
+    fn write_user32(&mut self, address: u32, value: u32) -> Result<(), u32> {
+        self.write_user(address, &value.to_le_bytes())
+    }
+
+    /// Two words, as struct timeval and struct timespec are.
+    fn write_user_pair(&mut self, address: u32, first: u32, second: u32) -> Result<(), u32> {
+        let mut bytes = first.to_le_bytes().to_vec();
+        bytes.extend(&second.to_le_bytes());
+        self.write_user(address, &bytes)
+    }
+
+    fn read_user_string(&self, address: u32) -> Result<String, u32> {
+        let mut bytes = vec![];
+        for i in 0..PATH_MAX {
+            let address = address.checked_add(i).ok_or(EFAULT)?;
+            self.check_user(address, 1)?;
+            match self.read_physical8(address) {
+                0 => return Ok(String::from_utf8_lossy(&bytes).into_owned()),
+                byte => bytes.push(byte),
+            }
+        }
+        Err(EINVAL)
+    }
+
+    fn zero_user(&mut self, address: u32, size: u32) {
+        for i in 0..size {
+            self.write_physical8(address + i, 0);
+        }
+    }
+
+    fn linux_file(&self, fd: u32) -> Result<SharedStream, u32> {
+        self.linux.as_ref().and_then(|linux| linux.file(fd)).ok_or(EBADF)
+    }
+
+    /// INT 0x80.
+    pub fn linux_syscall(&mut self) {
+        let number = self.registers[EAX as usize];
+        let [a, b, c, d, e, f] = [EBX, ECX, EDX, ESI, EDI, EBP].map(|register| self.registers[register as usize]);
+        let result = match number {
+            1 | 252 => self.sys_exit(a),                    // exit, exit_group
+            3 => self.sys_read(a, b, c),
+            4 => self.sys_write(a, b, c),
+            5 => self.sys_open(a, b, c),
+            6 => self.sys_close(a),
+            13 => self.sys_time(a),
+            19 => self.sys_lseek(a, b, c),
+            20 | 224 => Ok(PID),                            // getpid, gettid
+            45 => self.sys_brk(a),
+            54 => self.linux_file(a).and(Err(ENOTTY)),      // ioctl: nothing is a terminal
+            78 => self.sys_gettimeofday(a),
+            91 => self.sys_munmap(a, b),
+            122 => self.sys_uname(a),
+            146 => self.sys_writev(a, b, c),
+            174 | 175 => Ok(0),                             // rt_sigaction, rt_sigprocmask: no signals come
+            192 => self.sys_mmap2(a, b, c, d, e, f),
+            199 ..= 202 => Ok(0),                           // getuid32, getgid32, geteuid32, getegid32: root
+            243 => self.sys_set_thread_area(a),
+            258 => Ok(PID),                                 // set_tid_address
+            265 => self.sys_clock_gettime(a, b),
+            n => {
+                eprintln!("not implemented Linux syscall {}", n);
+                Err(ENOSYS)
+            },
+        };
+        self.registers[EAX as usize] = match result {
+            Ok(value) => value,
+            Err(errno) => errno.wrapping_neg(),
+        };
+    }
+
+    /// The process is over: the CPU stops for good, and the low byte of the status is the exit code.
+    fn sys_exit(&mut self, status: u32) -> SyscallResult {
+        self.linux().exit_status = Some((status & 0xFF) as i32);
+        self.eflags.set_interrupt(false);
+        self.halted = true;
+        Ok(0)
+    }
+
+    fn sys_read(&mut self, fd: u32, buffer: u32, count: u32) -> SyscallResult {
+        let stream = self.linux_file(fd)?;
+        self.check_user(buffer, count)?;
+        let mut bytes = vec![0; count as usize];
+        let read = stream.borrow_mut().read(&mut bytes).map_err(|e| errno(&e))?;
+        self.write_user(buffer, &bytes[.. read])?;
+        Ok(read as u32)
+    }
+
+    fn sys_write(&mut self, fd: u32, buffer: u32, count: u32) -> SyscallResult {
+        let stream = self.linux_file(fd)?;
+        let bytes = self.read_user(buffer, count)?;
+        let written = stream.borrow_mut().write(&bytes).map_err(|e| errno(&e))?;
+        Ok(written as u32)
+    }
+
+    /// Each of the `count` buffers in turn, stopping at a short write. All of them have to add up to an i32.
+    fn sys_writev(&mut self, fd: u32, iov: u32, count: u32) -> SyscallResult {
+        if count > UIO_MAXIOV {
+            return Err(EINVAL);
+        }
+        let vectors: Vec<(u32, u32)> = self.read_user(iov, count * 8)?.chunks_exact(8).map(|vector| (word(vector, 0), word(vector, 4))).collect();
+        let length = vectors.iter().try_fold(0u32, |length, (_, size)| length.checked_add(*size));
+        if length.is_none_or(|length| length > i32::MAX as u32) {
+            return Err(EINVAL);
+        }
+
+        let mut total = 0;
+        for (base, size) in vectors {
+            match self.sys_write(fd, base, size) {
+                Ok(written) => {
+                    total += written;
+                    if written < size {
+                        break;
+                    }
+                },
+                Err(errno) if total == 0 => return Err(errno),
+                Err(_) => break,
+            }
+        }
+        Ok(total)
+    }
+
+    fn sys_open(&mut self, path: u32, flags: u32, mode: u32) -> SyscallResult {
+        let path = self.read_user_string(path)?;
+        let access = flags & O_ACCESS;
+        let file = OpenOptions::new()
+            .read(access != 1)
+            .write(access != 0)
+            .append(flags & O_APPEND != 0)
+            .truncate(flags & O_TRUNC != 0)
+            .create(flags & O_CREAT != 0 && flags & O_EXCL == 0)
+            .create_new(flags & O_CREAT != 0 && flags & O_EXCL != 0)
+            .mode(mode)
+            .open(&path)
+            .map_err(|e| errno(&e))?;
+        self.linux().add_file(Rc::new(RefCell::new(file))).ok_or(EMFILE)
+    }
+
+    fn sys_close(&mut self, fd: u32) -> SyscallResult {
+        let file = self.linux().files.get_mut(fd as usize).ok_or(EBADF)?;
+        file.take().map(|_| 0).ok_or(EBADF)
+    }
+
+    fn sys_lseek(&mut self, fd: u32, offset: u32, whence: u32) -> SyscallResult {
+        let stream = self.linux_file(fd)?;
+        let offset = offset as i32 as i64;
+        let position = match whence {
+            0 if offset >= 0 => SeekFrom::Start(offset as u64),
+            1 => SeekFrom::Current(offset),
+            2 => SeekFrom::End(offset),
+            _ => return Err(EINVAL),
+        };
+        let position = stream.borrow_mut().seek(position).map_err(|e| errno(&e))?;
+        if position > i32::MAX as u64 {
+            return Err(EOVERFLOW);
+        }
+        Ok(position as u32)
+    }
+
+    /// Move the end of the heap, if it stays between its start and the mappings. Returns where it is.
+    fn sys_brk(&mut self, address: u32) -> SyscallResult {
+        let (start, old, limit) = {
+            let linux = self.linux();
+            (linux.brk_start, linux.brk, linux.mmap_bottom)
+        };
+        if address < start || address > limit {
+            return Ok(old);
+        }
+        if address > old {
+            self.zero_user(old, address - old);
+        }
+        self.linux().brk = address;
+        Ok(address)
+    }
+
+    /// Anonymous mappings only, a fixed one anywhere in the arena, the others below the last.
+    fn sys_mmap2(&mut self, address: u32, length: u32, _prot: u32, flags: u32, _fd: u32, _offset: u32) -> SyscallResult {
+        if length == 0 {
+            return Err(EINVAL);
+        }
+        if flags & MAP_ANONYMOUS == 0 {
+            return Err(ENODEV);
+        }
+        let size = page_up(length).ok_or(ENOMEM)?;
+        if flags & MAP_FIXED != 0 {
+            if !address.is_multiple_of(PAGE) {
+                return Err(EINVAL);
+            }
+            self.check_user(address, size).map_err(|_| ENOMEM)?;
+            self.zero_user(address, size);
+            return Ok(address);
+        }
+
+        let linux = self.linux();
+        let bottom = linux.mmap_bottom.checked_sub(size).filter(|bottom| *bottom >= linux.brk).ok_or(ENOMEM)?;
+        linux.mmap_bottom = bottom;
+        self.zero_user(bottom, size);
+        Ok(bottom)
+    }
+
+    /// Only the lowest mapping goes back to being free, the others stay where they are.
+    fn sys_munmap(&mut self, address: u32, length: u32) -> SyscallResult {
+        if !address.is_multiple_of(PAGE) || length == 0 {
+            return Err(EINVAL);
+        }
+        let end = page_up(length).and_then(|size| address.checked_add(size)).ok_or(EINVAL)?;
+        let linux = self.linux();
+        if address == linux.mmap_bottom {
+            linux.mmap_bottom = end.min(linux.stack_bottom);
+        }
+        Ok(0)
+    }
+
+    /// struct utsname: six strings of 65 bytes.
+    fn sys_uname(&mut self, buffer: u32) -> SyscallResult {
+        let fields = ["Linux", "aria", "4.19.0-aria", "#1", "i686", "(none)"];
+        let mut bytes = [0; 6 * 65];
+        for (i, field) in fields.iter().enumerate() {
+            bytes[i * 65 .. i * 65 + field.len()].copy_from_slice(field.as_bytes());
+        }
+        self.write_user(buffer, &bytes)?;
+        Ok(0)
+    }
+
+    /// The time comes from the RTC, so a pinned one pins this too.
+    fn sys_time(&mut self, address: u32) -> SyscallResult {
+        let seconds = self.cmos.unix_time().0 as u32;
+        if address != 0 {
+            self.write_user32(address, seconds)?;
+        }
+        Ok(seconds)
+    }
+
+    fn sys_gettimeofday(&mut self, address: u32) -> SyscallResult {
+        let (seconds, nanoseconds) = self.cmos.unix_time();
+        if address != 0 {
+            self.write_user_pair(address, seconds as u32, nanoseconds / 1000)?;
+        }
+        Ok(0)
+    }
+
+    /// The real time from the RTC, the monotonic one the virtual time since reset.
+    fn sys_clock_gettime(&mut self, clock: u32, address: u32) -> SyscallResult {
+        let (seconds, nanoseconds) = match clock {
+            CLOCK_REALTIME => self.cmos.unix_time(),
+            CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW => {
+                let time = self.clock.nanoseconds();
+                (time / 1_000_000_000, (time % 1_000_000_000) as u32)
+            },
+            _ => return Err(EINVAL),
+        };
+        self.write_user_pair(address, seconds as u32, nanoseconds)?;
+        Ok(0)
+    }
+
+    /*
+     * A TLS segment in the GDT, for the program to load into GS.
+     *  struct user_desc
+     *  0x00  entry number, -1 for the first free one, which is written back
+     *  0x04  base
+     *  0x08  limit
+     *  0x0C  bit 0: 32-bit, 1-2: contents, 3: read/exec only, 4: limit in pages, 5: not present
+     */
+    fn sys_set_thread_area(&mut self, info: u32) -> SyscallResult {
+        let fields = self.read_user(info, 16)?;
+        let (entry, base, limit, flags) = (word(&fields, 0), word(&fields, 4), word(&fields, 8), word(&fields, 12));
+        let gdt = self.linux().gdt;
+        let used = |emu: &Emulator, entry: u32| emu.read_physical32(gdt + entry * 8 + 4) != 0;
+
+        let entry = if entry == u32::MAX {
+            let free = TLS_ENTRIES.clone().find(|entry| !used(self, *entry)).ok_or(EPERM)?;
+            self.write_user32(info, free)?;
+            free
+        } else if TLS_ENTRIES.contains(&entry) {
+            entry
+        } else {
+            return Err(EINVAL);
+        };
+
+        let code = flags >> 1 & 3 == 2;
+        let read_only = flags & 8 != 0;
+        let mut access: u8 = 0xF0 | if code { 0x08 | if read_only { 0 } else { 0x02 } } else if read_only { 0 } else { 0x02 };
+        if flags & 0x20 != 0 {
+            access &= !0x80;
+        }
+        let raw = if base == 0 && limit == 0 && read_only && flags & 0x20 != 0 {
+            0
+        } else {
+            let raw = descriptor(base, limit, access, flags & 0x10 != 0);
+            if flags & 1 != 0 { raw } else { raw & !(1 << 54) }
+        };
+        self.write_physical32(gdt + entry * 8, raw as u32);
+        self.write_physical32(gdt + entry * 8 + 4, (raw >> 32) as u32);
+        Ok(0)
+    }
+}
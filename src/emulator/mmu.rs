@@ -0,0 +1,315 @@
+use super::*;
+use std::collections::HashMap;
+use crate::emulator::io::OPEN_BUS;
+use crate::emulator::exception::Exception::*;
+
+/*
+ *  page directory and page table entry
+ *  +----------------------------+-----+--+--+--+--+--+--+--+--+--+
+ *  |31                        12|11  9| 8| 7| 6| 5| 4| 3| 2| 1| 0|
+ *  +----------------------------+-----+--+--+--+--+--+--+--+--+--+
+ *  |      page frame address    | AVL | G|PS| D| A|CD|WT|US|RW| P|
+ *  +----------------------------+-----+--+--+--+--+--+--+--+--+--+
+ *  PS (directory entries only): a 4MiB page when CR4.PSE is set, the frame is bits 31-22.
+ */
+pub const PAGE_PRESENT: u32  = 1;
+pub const PAGE_WRITABLE: u32 = 1 << 1;
+pub const PAGE_USER: u32     = 1 << 2;
+pub const PAGE_ACCESSED: u32 = 1 << 5;
+pub const PAGE_DIRTY: u32    = 1 << 6;
+pub const PAGE_SIZE: u32     = 1 << 7;
+
+/*
+ *  #PF error code
+ *  P: the page was present, so a protection check failed
+ *  W: the access was a write
+ *  U: the access was made at CPL 3
+ */
+pub const PF_PROTECTION: u16 = 1;
+pub const PF_WRITE: u16      = 1 << 1;
+pub const PF_USER: u16       = 1 << 2;
+
+/*
+ * The A20 gate. Closed, it holds address line 20 low, so the 64K above 1 MB wrap around to 0
+ * the way they do on an 8086. It is open if either of the places it can be opened says so:
+ * bit 1 of the keyboard controller's output port, or bit 1 of port 0x92, the fast gate.
+ */
+pub const A20_GATE: u8 = 1 << 1;
+const A20_LINE: u32 = 1 << 20;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TlbEntry {
+    pub frame: u32,     // physical address of the 4KiB frame
+    pub writable: bool, // RW of the directory and the table entry combined
+    pub user: bool,     // US of the directory and the table entry combined
+    pub dirty: bool,    // D is already set, so a write doesn't have to walk the tables
+}
+
+/*
+ * Translations cached per 4KiB linear page, 4MiB pages included.
+ * Like a real TLB it isn't kept coherent with the page tables:
+ * software flushes it with INVLPG or by loading CR3.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Tlb {
+    entries: HashMap<u32, TlbEntry>,
+}
+
+impl Tlb {
+    pub fn lookup(&self, linear: u32) -> Option<TlbEntry> {
+        self.entries.get(&(linear >> 12)).copied()
+    }
+
+    pub fn insert(&mut self, linear: u32, entry: TlbEntry) {
+        self.entries.insert(linear >> 12, entry);
+    }
+
+    pub fn invalidate(&mut self, linear: u32) {
+        self.entries.remove(&(linear >> 12));
+    }
+
+    pub fn flush(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl Emulator {
+    pub fn is_paging(&self) -> bool {
+        self.cr[0] & CR0_PG != 0
+    }
+
+    /*
+     * Linear to physical address for an access at the given privilege.
+     * Returns None after raising #PF, with the faulting address in CR2.
+     */
+    pub fn translate(&mut self, linear: u32, write: bool, user: bool) -> Option<u32> {
+        if !self.is_paging() {
+            return Some(linear);
+        }
+
+        if let Some(entry) = self.tlb.lookup(linear) {
+            if self.page_allowed(entry.writable, entry.user, write, user) && (entry.dirty || !write) {
+                return Some(entry.frame | (linear & 0xFFF));
+            }
+        }
+
+        self.walk_page_tables(linear, write, user)
+    }
+
+    /// Supervisor writes ignore RW unless CR0.WP is set.
+    fn page_allowed(&self, writable: bool, page_user: bool, write: bool, user: bool) -> bool {
+        if user {
+            page_user && (writable || !write)
+        } else {
+            writable || !write || self.cr[0] & CR0_WP == 0
+        }
+    }
+
+    /*
+     * CR3 -> page directory (bits 31-22) -> page table (bits 21-12) -> page (bits 11-0).
+     * A successful access sets A in every entry it went through and D in the last one on a write.
+     */
+    fn walk_page_tables(&mut self, linear: u32, write: bool, user: bool) -> Option<u32> {
+        let pde_addr = (self.cr[3] & 0xFFFFF000) + ((linear >> 22) << 2);
+        let pde = self.read_physical32(pde_addr);
+        if pde & PAGE_PRESENT == 0 {
+            return self.page_fault(linear, 0, write, user);
+        }
+
+        if pde & PAGE_SIZE != 0 && self.cr[4] & CR4_PSE != 0 {
+            let writable = pde & PAGE_WRITABLE != 0;
+            let page_user = pde & PAGE_USER != 0;
+            if !self.page_allowed(writable, page_user, write, user) {
+                return self.page_fault(linear, PF_PROTECTION, write, user);
+            }
+
+            let pde = self.update_page_entry(pde_addr, pde, write);
+            let frame = (pde & 0xFFC00000) | (linear & 0x003FF000);
+            self.tlb.insert(linear, TlbEntry { frame, writable, user: page_user, dirty: pde & PAGE_DIRTY != 0 });
+            return Some(frame | (linear & 0xFFF));
+        }
+
+        let pte_addr = (pde & 0xFFFFF000) + (((linear >> 12) & 0x3FF) << 2);
+        let pte = self.read_physical32(pte_addr);
+        if pte & PAGE_PRESENT == 0 {
+            return self.page_fault(linear, 0, write, user);
+        }
+
+        let writable = pde & pte & PAGE_WRITABLE != 0;
+        let page_user = pde & pte & PAGE_USER != 0;
+        if !self.page_allowed(writable, page_user, write, user) {
+            return self.page_fault(linear, PF_PROTECTION, write, user);
+        }
+
+        self.update_page_entry(pde_addr, pde, false);
+        let pte = self.update_page_entry(pte_addr, pte, write);
+        let frame = pte & 0xFFFFF000;
+        self.tlb.insert(linear, TlbEntry { frame, writable, user: page_user, dirty: pte & PAGE_DIRTY != 0 });
+        Some(frame | (linear & 0xFFF))
+    }
+
+    /// Set A, and D for a write, writing the entry back only if it changed.
+    fn update_page_entry(&mut self, addr: u32, entry: u32, write: bool) -> u32 {
+        let mut updated = entry | PAGE_ACCESSED;
+        if write {
+            updated |= PAGE_DIRTY;
+        }
+        if updated != entry {
+            self.write_physical32(addr, updated);
+        }
+        updated
+    }
+
+    fn page_fault(&mut self, linear: u32, code: u16, write: bool, user: bool) -> Option<u32> {
+        let mut code = code;
+        if write {
+            code |= PF_WRITE;
+        }
+        if user {
+            code |= PF_USER;
+        }
+
+        if self.exception.is_none() {
+            self.cr[2] = linear;
+            self.raise(PageFault(code));
+        }
+        None
+    }
+
+    /// Translation without side effects for tracing: no A/D bits, no TLB fill, no #PF.
+    pub fn peek_translate(&self, linear: u32) -> Option<u32> {
+        if !self.is_paging() {
+            return Some(linear);
+        }
+
+        let pde = self.read_physical32((self.cr[3] & 0xFFFFF000) + ((linear >> 22) << 2));
+        if pde & PAGE_PRESENT == 0 {
+            return None;
+        }
+        if pde & PAGE_SIZE != 0 && self.cr[4] & CR4_PSE != 0 {
+            return Some((pde & 0xFFC00000) | (linear & 0x003FFFFF));
+        }
+
+        let pte = self.read_physical32((pde & 0xFFFFF000) + (((linear >> 12) & 0x3FF) << 2));
+        if pte & PAGE_PRESENT == 0 {
+            return None;
+        }
+        Some((pte & 0xFFFFF000) | (linear & 0xFFF))
+    }
+
+    pub fn invalidate_page(&mut self, linear: u32) {
+        self.tlb.invalidate(linear);
+    }
+
+    pub fn flush_tlb(&mut self) {
+        self.tlb.flush();
+    }
+
+    /*
+     * Linear accesses. A read that faults returns 0, a write that faults is dropped,
+     * and so is any write while an exception is pending.
+     */
+    pub fn read_linear8(&mut self, addr: u32, user: bool) -> u8 {
+        match self.translate(addr, false, user) {
+            Some(physical) => self.read_physical8(physical),
+            None => 0,
+        }
+    }
+
+    pub fn write_linear8(&mut self, addr: u32, value: u8, user: bool) {
+        if self.exception.is_some() {
+            return;
+        }
+        if let Some(physical) = self.translate(addr, true, user) {
+            self.write_physical8(physical, value);
+        }
+    }
+
+    /// The CPU's own accesses to descriptor tables are supervisor accesses whatever the CPL.
+    pub fn read_system32(&mut self, addr: u32) -> u32 {
+        let mut ret: u32 = 0;
+        for i in 0..4 {
+            ret |= (self.read_linear8(addr.wrapping_add(i), false) as u32) << (i * 8);
+        }
+
+        ret
+    }
+
+    pub fn write_system8(&mut self, addr: u32, value: u8) {
+        self.write_linear8(addr, value, false);
+    }
+
+    pub fn write_system32(&mut self, addr: u32, value: u32) {
+        for i in 0..4 {
+            self.write_system8(addr.wrapping_add(i), (value >> (i * 8)) as u8);
+        }
+    }
+
+    pub fn a20_enabled(&self) -> bool {
+        (self.keyboard.output_port | self.system_control) & A20_GATE != 0
+    }
+
+    /// Open or close the gate at both places, as the BIOS does.
+    pub fn set_a20(&mut self, enabled: bool) {
+        if enabled {
+            self.keyboard.output_port |= A20_GATE;
+            self.system_control |= A20_GATE;
+        } else {
+            self.keyboard.output_port &= !A20_GATE;
+            self.system_control &= !A20_GATE;
+        }
+    }
+
+    /// Physical memory is RAM, except for the VGA text window. Past the end of RAM nothing
+    /// answers, so reads see the open bus and writes are lost.
+    pub fn read_physical8(&self, addr: u32) -> u8 {
+        let addr = if self.a20_enabled() { addr } else { addr & !A20_LINE };
+        if Vga::contains(addr) {
+            return self.vga.read8(addr);
+        }
+        self.memory.get(addr as usize).copied().unwrap_or(OPEN_BUS)
+    }
+
+    pub fn write_physical8(&mut self, addr: u32, value: u8) {
+        let addr = if self.a20_enabled() { addr } else { addr & !A20_LINE };
+        if Vga::contains(addr) {
+            self.vga.write8(addr, value);
+            return;
+        }
+        if let Some(byte) = self.memory.get_mut(addr as usize) {
+            *byte = value;
+        }
+    }
+
+    pub fn read_physical16(&self, addr: u32) -> u16 {
+        self.read_physical8(addr) as u16 | (self.read_physical8(addr.wrapping_add(1)) as u16) << 8
+    }
+
+    pub fn write_physical16(&mut self, addr: u32, value: u16) {
+        self.write_physical8(addr, value as u8);
+        self.write_physical8(addr.wrapping_add(1), (value >> 8) as u8);
+    }
+
+    pub fn read_physical32(&self, addr: u32) -> u32 {
+        let mut ret: u32 = 0;
+        for i in 0..4 {
+            ret |= (self.read_physical8(addr.wrapping_add(i)) as u32) << (i * 8);
+        }
+
+        ret
+    }
+
+    pub fn write_physical32(&mut self, addr: u32, value: u32) {
+        for i in 0..4 {
+            self.write_physical8(addr.wrapping_add(i), (value >> (i * 8)) as u8);
+        }
+    }
+}
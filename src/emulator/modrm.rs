@@ -1,4 +1,5 @@
 use super::*;
+use crate::emulator::exception::Exception::*;
 
 #[derive(Debug, Copy, Clone)]
 pub enum OR {
@@ -20,6 +21,7 @@ impl OR {
 #[derive(Debug, Copy, Clone)]
 pub enum Disp {
     Disp8(i8),
+    Disp16(u16),
     Disp32(u32),
 }
 
@@ -34,6 +36,14 @@ impl Disp {
         }
     }
 
+    pub fn word(self) -> u16 {
+        if let Disp16(ret) = self {
+            ret
+        } else {
+            panic!("{:?} is not 2byte.", self);
+        }
+    }
+
     pub fn dword(self) -> u32 {
         if let Disp32(ret) = self {
             ret
@@ -49,7 +59,7 @@ pub struct ModRM {
     pub or: OR,     // u8
     pub rm: u8,
     pub sib: u8,
-    pub disp: Disp, // u8 or u32
+    pub disp: Disp, // u8, u16 or u32
 }
 
 impl Emulator {
@@ -103,13 +113,19 @@ impl Emulator {
 
         self.eip += 1;
 
+        if self.address_bits() == 16 {
+            self.parse_disp16(&mut ret);
+            return ret;
+        }
+
         if ret.mod_byte != 0b11 
             && ret.rm == 0b100 {
             ret.sib = self.get_code8(0);
             self.eip += 1;
         }
 
-        if (ret.mod_byte == 0b00 && ret.rm == 0b0101) 
+        if (ret.mod_byte == 0b00 && ret.rm == 0b0101)
+            || (ret.mod_byte == 0b00 && ret.rm == 0b100 && ret.sib & 0x07 == 0b101)
             || ret.mod_byte == 0b0010 {
             ret.disp = Disp32(self.get_sign_code32(0) as u32);
             self.eip += 4;
@@ -121,20 +137,40 @@ impl Emulator {
         ret
     }
 
+    /// 16-bit addressing has no SIB byte, and Mod 00 R/M 110 is a bare disp16.
+    fn parse_disp16(&mut self, modrm: &mut ModRM) {
+        if (modrm.mod_byte == 0b00 && modrm.rm == 0b110) || modrm.mod_byte == 0b10 {
+            modrm.disp = Disp16(self.get_code16(0));
+            self.eip += 2;
+        } else if modrm.mod_byte == 0b01 {
+            modrm.disp = Disp8(self.get_sign_code8(0));
+            self.eip += 1;
+        }
+    }
+
     pub fn get_rm8(&mut self, modrm: &ModRM) -> u8 {
         if modrm.mod_byte == 3 {
-            self.get_register8(modrm.or.unwrap() as usize)
+            self.get_register8(modrm.rm as usize)
         } else {
-            let addr = self.calc_memory_address(&modrm);
+            let addr = self.modrm_address(modrm, 1, false);
             self.get_memory8(addr)
         }
     }
 
+    pub fn get_rm16(&mut self, modrm: &ModRM) -> u16 {
+        if modrm.mod_byte == 0b11 {
+            self.get_register16(modrm.rm as usize)
+        } else {
+            let addr = self.modrm_address(modrm, 2, false);
+            self.get_memory16(addr)
+        }
+    }
+
     pub fn get_rm32(&mut self, modrm: &ModRM) -> u32 {
         if modrm.mod_byte == 0b11 {
             self.get_register32(modrm.rm as usize)
         } else {
-            let addr = self.calc_memory_address(&modrm);
+            let addr = self.modrm_address(modrm, 4, false);
             self.get_memory32(addr)
         }
     }
@@ -155,16 +191,25 @@ impl Emulator {
         if modrm.mod_byte == 3 {
             self.set_register8(modrm.rm as usize, value);
         } else {
-            let addr = self.calc_memory_address(&modrm);
+            let addr = self.modrm_address(modrm, 1, true);
             self.set_memory8(addr, value as u32);
         }
     }
 
+    pub fn set_rm16(&mut self, modrm: &ModRM, value: u16) {
+        if modrm.mod_byte == 0b11 {
+            self.set_register16(modrm.rm as usize, value);
+        } else {
+            let addr = self.modrm_address(modrm, 2, true);
+            self.set_memory16(addr, value as u32);
+        }
+    }
+
     pub fn set_rm32(&mut self, modrm: &ModRM, value: u32) {
         if modrm.mod_byte == 0b11 {
             self.set_register32(modrm.rm as usize, value);
         } else {
-            let addr = self.calc_memory_address(modrm);
+            let addr = self.modrm_address(modrm, 4, true);
             self.set_memory32(addr, value);
         }
     }
@@ -173,33 +218,173 @@ impl Emulator {
         self.set_register32(modrm.or.unwrap() as usize, value);
     }
     
+    pub fn get_rmv(&mut self, modrm: &ModRM) -> u32 {
+        if modrm.mod_byte == 0b11 {
+            self.get_registerv(modrm.rm as usize)
+        } else {
+            let addr = self.modrm_address(modrm, self.operand_bytes(), false);
+            self.get_memoryv(addr)
+        }
+    }
+
+    pub fn set_rmv(&mut self, modrm: &ModRM, value: u32) {
+        if modrm.mod_byte == 0b11 {
+            self.set_registerv(modrm.rm as usize, value);
+        } else {
+            let addr = self.modrm_address(modrm, self.operand_bytes(), true);
+            self.set_memoryv(addr, value);
+        }
+    }
+
+    pub fn get_rv(&mut self, modrm: &ModRM) -> u32 {
+        self.get_registerv(modrm.or.unwrap() as usize)
+    }
+
+    pub fn set_rv(&mut self, modrm: &ModRM, value: u32) {
+        self.set_registerv(modrm.or.unwrap() as usize, value);
+    }
+
+    /*
+     * Checked linear address of a `bytes` wide memory operand, see `segment_address`.
+     * Mod 11 names a register, so an instruction that needs memory there is invalid.
+     */
+    pub fn modrm_address(&mut self, modrm: &ModRM, bytes: u32, write: bool) -> u32 {
+        if modrm.mod_byte == 0b11 {
+            self.raise(InvalidOpcode);
+            return 0;
+        }
+        let sreg = self.data_segment(self.modrm_default_segment(modrm));
+        let offset = self.calc_effective_address(modrm);
+        self.segment_address(sreg, offset, bytes, write)
+    }
+
+    /// Linear address of the memory operand: segment base + effective address.
     pub fn calc_memory_address(&self, modrm: &ModRM) -> u32 {
+        let sreg = self.data_segment(self.modrm_default_segment(modrm));
+        self.linear_address(sreg, self.calc_effective_address(modrm))
+    }
+
+    /// The offset within the segment, which is what LEA loads.
+    pub fn calc_effective_address(&self, modrm: &ModRM) -> u32 {
+        if self.address_bits() == 16 {
+            return self.calc_effective_address16(modrm);
+        }
+
         match modrm.mod_byte {
             0 => {
                 match modrm.rm {
-                    4 => modrm_not_impl(*modrm),
+                    4 => self.calc_sib_address(modrm),
                     5 => modrm.disp.dword(),
                     _ => self.get_register32(modrm.rm as usize),
                 }
             },
             1 => {
-                if modrm.rm == 4 {
-                    modrm_not_impl(*modrm)
+                let base = if modrm.rm == 4 {
+                    self.calc_sib_address(modrm)
                 } else {
-                    self.get_register32(modrm.rm as usize) 
-                        + modrm.disp.byte() as u32
-                }
+                    self.get_register32(modrm.rm as usize)
+                };
+                base.wrapping_add(modrm.disp.byte() as u32)
             },
             2 => {
-                if modrm.rm == 4 {
-                    modrm_not_impl(*modrm)
+                let base = if modrm.rm == 4 {
+                    self.calc_sib_address(modrm)
                 } else {
-                    self.get_register32(modrm.rm as usize) + modrm.disp.dword()
-                }
+                    self.get_register32(modrm.rm as usize)
+                };
+                base.wrapping_add(modrm.disp.dword())
             },
             _ => modrm_not_impl(*modrm),
         }
     }
+
+    /*
+     *  R/M  16-bit address     R/M  16-bit address
+     *  000  [BX + SI]          100  [SI]
+     *  001  [BX + DI]          101  [DI]
+     *  010  [BP + SI]          110  [BP] (Mod 00: [disp16])
+     *  011  [BP + DI]          111  [BX]
+     *
+     *  Mod 01 adds a sign-extended disp8, Mod 10 a disp16. The sum wraps at 64KiB.
+     */
+    fn calc_effective_address16(&self, modrm: &ModRM) -> u32 {
+        let bx = self.get_register16(EBX as usize);
+        let bp = self.get_register16(EBP as usize);
+        let si = self.get_register16(ESI as usize);
+        let di = self.get_register16(EDI as usize);
+
+        let base = match modrm.rm {
+            0 => bx.wrapping_add(si),
+            1 => bx.wrapping_add(di),
+            2 => bp.wrapping_add(si),
+            3 => bp.wrapping_add(di),
+            4 => si,
+            5 => di,
+            6 if modrm.mod_byte == 0b00 => 0,
+            6 => bp,
+            _ => bx,
+        };
+
+        let disp = match modrm.mod_byte {
+            0 if modrm.rm == 0b110 => modrm.disp.word(),
+            1 => modrm.disp.byte() as u16,
+            2 => modrm.disp.word(),
+            0 => 0,
+            _ => modrm_not_impl(*modrm),
+        };
+
+        base.wrapping_add(disp) as u32
+    }
+
+    /// Addresses based on EBP/ESP (BP in 16-bit code) are in the stack segment.
+    fn modrm_default_segment(&self, modrm: &ModRM) -> SegmentRegister {
+        let stack = if self.address_bits() == 16 {
+            modrm.rm == 0b010 || modrm.rm == 0b011 || (modrm.rm == 0b110 && modrm.mod_byte != 0b00)
+        } else if modrm.rm == 0b100 {
+            let base = modrm.sib & 0x07;
+            base == 0b100 || (base == 0b101 && modrm.mod_byte != 0b00)
+        } else {
+            modrm.rm == 0b101 && modrm.mod_byte != 0b00
+        };
+
+        if stack {
+            SegmentRegister::SS
+        } else {
+            SegmentRegister::DS
+        }
+    }
+
+    /*  sib
+     *  +--+--+--+--+--+--+--+--+
+     *  | 7| 6| 5| 4| 3| 2| 1| 0|
+     *  +-----+--------+--------+
+     *  |Scale| Index  |  Base  |
+     *  +-----+--------+--------+
+     *
+     *  address = base + index * (1 << scale)
+     *  Index 100 (ESP) means no index.
+     *  Base 101 (EBP) with Mod 00 means no base, disp32 instead.
+     *  The displacement of Mod 01/10 is added by the caller.
+     */
+    fn calc_sib_address(&self, modrm: &ModRM) -> u32 {
+        let scale = (modrm.sib & 0xC0) >> 6;
+        let index = (modrm.sib & 0x38) >> 3;
+        let base = modrm.sib & 0x07;
+
+        let base = if modrm.mod_byte == 0b00 && base == 0b101 {
+            modrm.disp.dword()
+        } else {
+            self.get_register32(base as usize)
+        };
+
+        let index = if index == 0b100 {
+            0
+        } else {
+            self.get_register32(index as usize) << scale
+        };
+
+        base.wrapping_add(index)
+    }
 }
 
 fn modrm_not_impl(modrm: ModRM) -> ! {
@@ -0,0 +1,309 @@
+use super::*;
+
+/*
+ * 8259A programmable interrupt controller.
+ * Two of them: the master on ports 0x20/0x21 takes IRQ 0-7,
+ * the slave on 0xA0/0xA1 takes IRQ 8-15 and is cascaded into IRQ 2 of the master.
+ *
+ *  IRR: requests waiting to be serviced
+ *  ISR: requests being serviced, until the handler sends an EOI
+ *  IMR: masked lines, which stay in IRR until they're unmasked
+ *
+ * Initialization: ICW1 on the command port, then ICW2 (vector base), ICW3 (cascade)
+ * and ICW4 (mode) on the data port. Afterwards the data port is OCW1, the IMR,
+ * and the command port takes OCW2 (EOI, priority rotation) and OCW3 (which register reads return).
+ */
+const ICW1: u8          = 1 << 4;
+const ICW1_ICW4: u8     = 1;
+const ICW1_SINGLE: u8   = 1 << 1;
+const ICW1_LEVEL: u8    = 1 << 3;
+const ICW4_AUTO_EOI: u8 = 1 << 1;
+const OCW3: u8          = 1 << 3;
+
+/// The slave's INT output is wired to this master input.
+const CASCADE_IRQ: u8 = 2;
+
+/// Which initialization command word the data port expects next.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+enum Init {
+    #[default]
+    Ready,
+    Icw2,
+    Icw3,
+    Icw4,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Pic8259 {
+    pub irr: u8,
+    pub isr: u8,
+    pub imr: u8,
+    pub vector_base: u8,
+    lines: u8,          // the current level of the IR inputs
+    priority: u8,       // the IRQ with the highest priority, it rotates
+    level: bool,        // level triggered instead of edge triggered
+    single: bool,       // no cascade, no ICW3
+    needs_icw4: bool,
+    auto_eoi: bool,
+    read_isr: bool,     // a command port read returns ISR instead of IRR
+    init: Init,
+    rotate_on_auto_eoi: bool,
+    pub icw3: u8,
+}
+
+impl Pic8259 {
+    fn with_base(vector_base: u8) -> Pic8259 {
+        Pic8259 {
+            vector_base,
+            ..Pic8259::default()
+        }
+    }
+
+    /// An edge triggered input requests an interrupt on its rising edge, a level triggered one while high.
+    pub fn set_line(&mut self, irq: u8, high: bool) {
+        let bit = 1 << irq;
+        if high {
+            if self.level || self.lines & bit == 0 {
+                self.irr |= bit;
+            }
+            self.lines |= bit;
+        } else {
+            if self.level {
+                self.irr &= !bit;
+            }
+            self.lines &= !bit;
+        }
+    }
+
+    /*
+     * The highest priority request that isn't masked, if nothing of higher or equal priority is in service.
+     * `extra` adds requests from outside IRR, the cascaded slave.
+     */
+    fn highest_request(&self, extra: u8) -> Option<u8> {
+        let requests = (self.irr | extra) & !self.imr;
+        for i in 0..8 {
+            let irq = (self.priority + i) & 7;
+            if self.isr & (1 << irq) != 0 {
+                return None;
+            }
+            if requests & (1 << irq) != 0 {
+                return Some(irq);
+            }
+        }
+        None
+    }
+
+    /// INTA: the request moves from IRR to ISR, unless the controller ends it automatically.
+    fn acknowledge(&mut self, irq: u8) {
+        let bit = 1 << irq;
+        if !self.level {
+            self.irr &= !bit;
+        }
+        if self.auto_eoi {
+            if self.rotate_on_auto_eoi {
+                self.priority = (irq + 1) & 7;
+            }
+        } else {
+            self.isr |= bit;
+        }
+    }
+
+    /// The in-service IRQ a non-specific EOI ends.
+    fn highest_in_service(&self) -> Option<u8> {
+        (0..8).map(|i| (self.priority + i) & 7).find(|irq| self.isr & (1 << irq) != 0)
+    }
+
+    pub fn read(&self, data: bool) -> u8 {
+        if data {
+            self.imr
+        } else if self.read_isr {
+            self.isr
+        } else {
+            self.irr
+        }
+    }
+
+    pub fn write(&mut self, data: bool, value: u8) {
+        if !data && value & ICW1 != 0 {
+            *self = Pic8259 {
+                level: value & ICW1_LEVEL != 0,
+                single: value & ICW1_SINGLE != 0,
+                needs_icw4: value & ICW1_ICW4 != 0,
+                init: Init::Icw2,
+                lines: self.lines,
+                ..Pic8259::default()
+            };
+            return;
+        }
+
+        if data {
+            match self.init {
+                Init::Ready => self.imr = value,
+                Init::Icw2 => {
+                    self.vector_base = value & 0xF8;
+                    self.init = if !self.single {
+                        Init::Icw3
+                    } else if self.needs_icw4 {
+                        Init::Icw4
+                    } else {
+                        Init::Ready
+                    };
+                },
+                Init::Icw3 => {
+                    self.icw3 = value;
+                    self.init = if self.needs_icw4 { Init::Icw4 } else { Init::Ready };
+                },
+                Init::Icw4 => {
+                    self.auto_eoi = value & ICW4_AUTO_EOI != 0;
+                    self.init = Init::Ready;
+                },
+            }
+        } else if value & OCW3 != 0 {
+            // RR set: RIS picks the register
+            if value & 0x02 != 0 {
+                self.read_isr = value & 0x01 != 0;
+            }
+        } else {
+            self.command(value);
+        }
+    }
+
+    /*
+     *  OCW2
+     *  +---+--+---+-----+
+     *  | 7 | 6| 5 |2   0|
+     *  +---+--+---+-----+
+     *  | R |SL|EOI| IRQ |
+     *  +---+--+---+-----+
+     *  R: rotate, the ended IRQ gets the lowest priority. SL: the command names the IRQ.
+     */
+    fn command(&mut self, value: u8) {
+        let rotate = value & 0x80 != 0;
+        let specific = value & 0x40 != 0;
+        let eoi = value & 0x20 != 0;
+        let named = value & 7;
+
+        if eoi {
+            let irq = if specific {
+                Some(named)
+            } else {
+                self.highest_in_service()
+            };
+            if let Some(irq) = irq {
+                self.isr &= !(1 << irq);
+                if rotate {
+                    self.priority = (irq + 1) & 7;
+                }
+            }
+        } else if specific {
+            if rotate {
+                self.priority = (named + 1) & 7;
+            }
+        } else {
+            self.rotate_on_auto_eoi = rotate;
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Pic {
+    pub master: Pic8259,
+    pub slave: Pic8259,
+}
+
+impl Default for Pic {
+    /// As the BIOS leaves them: IRQ 0-7 at vectors 0x08-0x0F, IRQ 8-15 at 0x70-0x77, nothing masked.
+    fn default() -> Pic {
+        Pic {
+            master: Pic8259::with_base(0x08),
+            slave: Pic8259::with_base(0x70),
+        }
+    }
+}
+
+impl Pic {
+    pub fn set_irq(&mut self, irq: u8, high: bool) {
+        if irq < 8 {
+            self.master.set_line(irq, high);
+        } else {
+            self.slave.set_line(irq - 8, high);
+        }
+    }
+
+    fn slave_request(&self) -> u8 {
+        if self.slave.highest_request(0).is_some() {
+            1 << CASCADE_IRQ
+        } else {
+            0
+        }
+    }
+
+    /// Whether the master's INT output is raised.
+    pub fn has_interrupt(&self) -> bool {
+        self.master.highest_request(self.slave_request()).is_some()
+    }
+
+    /// The interrupt acknowledge cycle: the vector of the request being serviced.
+    pub fn acknowledge(&mut self) -> Option<u8> {
+        let slave = self.slave_request();
+        let irq = self.master.highest_request(slave)?;
+        self.master.acknowledge(irq);
+        if irq == CASCADE_IRQ && slave != 0 {
+            let irq = self.slave.highest_request(0)?;
+            self.slave.acknowledge(irq);
+            return Some(self.slave.vector_base + irq);
+        }
+        Some(self.master.vector_base + irq)
+    }
+
+    pub fn read(&self, port: u16) -> u8 {
+        match port {
+            0x20 | 0x21 => self.master.read(port & 1 != 0),
+            _ => self.slave.read(port & 1 != 0),
+        }
+    }
+
+    pub fn write(&mut self, port: u16, value: u8) {
+        match port {
+            0x20 | 0x21 => self.master.write(port & 1 != 0, value),
+            _ => self.slave.write(port & 1 != 0, value),
+        }
+    }
+}
+
+impl Emulator {
+    /// Raise or lower an IRQ line, 0-15.
+    pub fn set_irq(&mut self, irq: u8, high: bool) {
+        self.pic.set_irq(irq, high);
+    }
+
+    /// An edge on an IRQ line, which is how most devices request an interrupt.
+    pub fn pulse_irq(&mut self, irq: u8) {
+        self.pic.set_irq(irq, true);
+        self.pic.set_irq(irq, false);
+    }
+
+    /// Whether the CPU takes an external interrupt before the next instruction.
+    pub fn interrupt_pending(&self) -> bool {
+        self.eflags.is_interrupt() && self.pic.has_interrupt()
+    }
+
+    /*
+     * Deliver the PIC's highest priority request, waking the CPU up from HLT.
+     * External interrupts aren't subject to gate DPLs and set EXT in the error codes they cause.
+     */
+    pub fn external_interrupt(&mut self) {
+        let vector = match self.pic.acknowledge() {
+            Some(vector) => vector,
+            None => return,
+        };
+        self.halted = false;
+
+        let context = self.save_context();
+        self.interrupt(vector, None, false);
+        if let Some(exception) = self.exception.take() {
+            self.restore_context(&context);
+            self.deliver_exception(exception);
+        }
+    }
+}
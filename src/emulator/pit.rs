@@ -0,0 +1,414 @@
+/*
+ * 8253/8254 programmable interval timer: three 16-bit down counters clocked at 1.193182 MHz.
+ * Channel 0 drives IRQ 0, channel 1 used to refresh the DRAM,
+ * and channel 2 feeds the speaker, its gate being bit 0 of port 0x61.
+ *
+ *  control word, port 0x43
+ *  +-----+-----+-------+---+
+ *  |7   6|5   4|3     1| 0 |
+ *  +-----+-----+-------+---+
+ *  | SC  | RW  | mode  |BCD|
+ *  +-----+-----+-------+---+
+ *  SC: the channel, 3 is the 8254 read-back command
+ *  RW: 0 latches the count, 1 low byte only, 2 high byte only, 3 low byte then high byte
+ *
+ *  mode 0: interrupt on terminal count      mode 3: square wave generator
+ *  mode 1: hardware retriggerable one-shot  mode 4: software triggered strobe
+ *  mode 2: rate generator                   mode 5: hardware triggered strobe
+ */
+pub const PIT_FREQUENCY: u64 = 1_193_182;
+
+const ACCESS_LOW: u8  = 1;
+const ACCESS_HIGH: u8 = 2;
+const ACCESS_WORD: u8 = 3;
+
+#[derive(Debug, Clone, Default)]
+pub struct PitChannel {
+    pub mode: u8,
+    pub access: u8,
+    pub bcd: bool,
+    pub count: u32,             // the count register, 0 standing for 0x10000 (10000 in BCD)
+    pub counter: u32,           // the counting element
+    pub output: bool,
+    pub gate: bool,
+    counting: bool,
+    load: bool,                 // the count register goes into the counter on the next clock
+    trigger: bool,              // a gate rising edge, acted on at the next clock
+    fired: bool,                // a one-shot or strobe already ended
+    null_count: bool,           // a count was written and hasn't been loaded yet
+    write_low: Option<u8>,      // the low byte of a word being written
+    read_high: bool,            // the next read of a word returns the high byte
+    latch: Option<u16>,
+    status: Option<u8>,
+}
+
+fn to_bcd(value: u32) -> u16 {
+    let mut bcd = 0;
+    for i in 0..4 {
+        bcd |= ((value / 10u32.pow(i)) % 10) << (i * 4);
+    }
+    bcd as u16
+}
+
+fn from_bcd(value: u16) -> u32 {
+    (0..4).map(|i| ((value as u32 >> (i * 4)) & 0xF) * 10u32.pow(i)).sum()
+}
+
+impl PitChannel {
+    /// The counters wrap at 0x10000, or at 10000 when counting in BCD.
+    fn modulus(&self) -> u32 {
+        if self.bcd {
+            10000
+        } else {
+            0x10000
+        }
+    }
+
+    fn decrement(&mut self, n: u32) {
+        let modulus = self.modulus();
+        self.counter = (self.counter + modulus - n) % modulus;
+    }
+
+    /// A control word selecting the mode resets the channel until a count is written.
+    fn set_control(&mut self, access: u8, mode: u8, bcd: bool) {
+        // modes 6 and 7 are 2 and 3
+        let mode = if mode > 5 { mode & 3 } else { mode };
+        *self = PitChannel {
+            mode,
+            access,
+            bcd,
+            output: mode != 0,
+            gate: self.gate,
+            null_count: true,
+            ..PitChannel::default()
+        };
+    }
+
+    fn latch_count(&mut self) {
+        if self.latch.is_none() {
+            self.latch = Some(self.read_counter());
+        }
+    }
+
+    /*
+     *  status byte of the read-back command
+     *  +---+----+-----+-------+---+
+     *  | 7 | 6  |5   4|3     1| 0 |
+     *  +---+----+-----+-------+---+
+     *  |OUT|NULL| RW  | mode  |BCD|
+     *  +---+----+-----+-------+---+
+     */
+    fn latch_status(&mut self) {
+        if self.status.is_none() {
+            self.status = Some(
+                ((self.output as u8) << 7)
+                | ((self.null_count as u8) << 6)
+                | (self.access << 4)
+                | (self.mode << 1)
+                | self.bcd as u8
+            );
+        }
+    }
+
+    fn read_counter(&self) -> u16 {
+        if self.bcd {
+            to_bcd(self.counter)
+        } else {
+            self.counter as u16
+        }
+    }
+
+    /// A latched status comes first, then a latched count, otherwise the live counter.
+    pub fn read(&mut self) -> u8 {
+        if let Some(status) = self.status.take() {
+            return status;
+        }
+
+        let value = self.latch.unwrap_or_else(|| self.read_counter());
+        match self.access {
+            ACCESS_LOW => {
+                self.latch = None;
+                value as u8
+            },
+            ACCESS_HIGH => {
+                self.latch = None;
+                (value >> 8) as u8
+            },
+            _ if !self.read_high => {
+                self.read_high = true;
+                value as u8
+            },
+            _ => {
+                self.read_high = false;
+                self.latch = None;
+                (value >> 8) as u8
+            },
+        }
+    }
+
+    pub fn write(&mut self, value: u8) {
+        match self.access {
+            ACCESS_LOW => self.set_count(value as u16),
+            ACCESS_HIGH => self.set_count((value as u16) << 8),
+            _ => match self.write_low.take() {
+                Some(low) => self.set_count(((value as u16) << 8) | low as u16),
+                None => {
+                    self.write_low = Some(value);
+                    // mode 0 stops counting while a new count is half written
+                    if self.mode == 0 {
+                        self.counting = false;
+                        self.output = false;
+                    }
+                },
+            },
+        }
+    }
+
+    /*
+     * Modes 0 and 4 start counting the new count right away.
+     * Modes 2 and 3 keep the current period if they're already counting, and use it for the next one.
+     * Modes 1 and 5 wait for a gate trigger.
+     */
+    fn set_count(&mut self, raw: u16) {
+        self.count = if self.bcd {
+            from_bcd(raw)
+        } else {
+            raw as u32
+        };
+        self.null_count = true;
+
+        match self.mode {
+            0 => {
+                self.output = false;
+                self.load = true;
+            },
+            4 => self.load = true,
+            2 | 3 if !self.counting => self.load = true,
+            _ => (),
+        }
+    }
+
+    /// Modes 1 and 5 start on a rising edge of the gate, modes 2 and 3 start over.
+    pub fn set_gate(&mut self, gate: bool) {
+        if gate && !self.gate {
+            self.trigger = true;
+        }
+        if !gate && (self.mode == 2 || self.mode == 3) {
+            self.output = true;
+        }
+        self.gate = gate;
+    }
+
+    fn reload(&mut self) {
+        self.counter = self.count;
+        self.null_count = false;
+        self.counting = true;
+        self.fired = false;
+    }
+
+    /*
+     * Mode 3 counts down by 2 and toggles the output every half period.
+     * An odd count keeps the output high for (N + 1) / 2 clocks and low for (N - 1) / 2.
+     */
+    fn reload_square(&mut self) {
+        self.reload();
+        let count = if self.count == 0 { self.modulus() } else { self.count };
+        let half = if count & 1 == 0 {
+            count
+        } else if self.output {
+            count + 1
+        } else {
+            count - 1
+        };
+        self.counter = half.max(2) % self.modulus();
+    }
+
+    /// One input clock.
+    pub fn clock(&mut self) {
+        let trigger = self.trigger;
+        self.trigger = false;
+
+        match self.mode {
+            0 | 4 => {
+                if self.load {
+                    self.load = false;
+                    self.reload();
+                    return;
+                }
+                if !self.counting || !self.gate {
+                    if self.mode == 4 && !self.output {
+                        self.output = true;
+                    }
+                    return;
+                }
+                self.decrement(1);
+                if self.mode == 0 {
+                    if self.counter == 0 {
+                        self.output = true;
+                    }
+                } else {
+                    self.strobe();
+                }
+            },
+            1 | 5 => {
+                if trigger {
+                    self.reload();
+                    if self.mode == 1 {
+                        self.output = false;
+                    }
+                    return;
+                }
+                if !self.counting {
+                    return;
+                }
+                self.decrement(1);
+                if self.mode == 1 {
+                    if self.counter == 0 && !self.fired {
+                        self.output = true;
+                        self.fired = true;
+                    }
+                } else {
+                    self.strobe();
+                }
+            },
+            2 => {
+                if self.load || trigger {
+                    self.load = false;
+                    self.reload();
+                    self.output = true;
+                    return;
+                }
+                if !self.counting || !self.gate {
+                    return;
+                }
+                self.decrement(1);
+                if self.counter == 1 {
+                    self.output = false;
+                } else if self.counter == 0 {
+                    self.reload();
+                    self.output = true;
+                }
+            },
+            _ => {
+                if self.load || trigger {
+                    self.load = false;
+                    self.output = true;
+                    self.reload_square();
+                    return;
+                }
+                if !self.counting || !self.gate {
+                    return;
+                }
+                self.decrement(2);
+                if self.counter == 0 {
+                    self.output = !self.output;
+                    self.reload_square();
+                }
+            },
+        }
+    }
+
+    /// Modes 4 and 5 pull the output low for one clock at terminal count, once.
+    fn strobe(&mut self) {
+        if !self.output {
+            self.output = true;
+        } else if self.counter == 0 && !self.fired {
+            self.output = false;
+            self.fired = true;
+        }
+    }
+
+    /// Whether the output will rise again, which is what raises an IRQ.
+    pub fn will_fire(&self) -> bool {
+        let started = self.counting || self.load;
+        match self.mode {
+            0 => started && !self.output,
+            1 | 5 => self.counting && !self.fired || self.mode == 5 && !self.output,
+            4 => started && (!self.fired || !self.output),
+            _ => started && self.gate,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Pit {
+    pub channels: [PitChannel; 3],
+    pub speaker: u8,    // bits 0 and 1 of port 0x61: channel 2 gate, speaker data
+    ticks: u64,
+}
+
+impl Default for Pit {
+    /// As the BIOS leaves it: channel 0 in mode 3 with count 0, IRQ 0 at 18.2 Hz.
+    fn default() -> Pit {
+        let mut pit = Pit {
+            channels: Default::default(),
+            speaker: 0,
+            ticks: 0,
+        };
+        pit.channels[0].gate = true;
+        pit.channels[1].gate = true;
+        pit.channels[0].set_control(ACCESS_WORD, 3, false);
+        pit.channels[0].set_count(0);
+        pit.channels[0].clock();
+        pit
+    }
+}
+
+/// Port 0x61 bit 4 toggles with every DRAM refresh, which BIOS delay loops count.
+const REFRESH_TICKS: u64 = 18;
+
+impl Pit {
+    pub fn read(&mut self, port: u16) -> u8 {
+        match port {
+            0x40 ..= 0x42 => self.channels[(port - 0x40) as usize].read(),
+            0x61 => {
+                let refresh = (self.ticks / REFRESH_TICKS) & 1 == 1;
+                self.speaker | ((refresh as u8) << 4) | ((self.channels[2].output as u8) << 5)
+            },
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, port: u16, value: u8) {
+        match port {
+            0x40 ..= 0x42 => self.channels[(port - 0x40) as usize].write(value),
+            0x43 => self.control(value),
+            0x61 => {
+                self.speaker = value & 3;
+                self.channels[2].set_gate(value & 1 != 0);
+            },
+            _ => (),
+        }
+    }
+
+    /// Read-back: bit 5 clear latches the counts, bit 4 clear the status, of the channels in bits 1-3.
+    fn control(&mut self, value: u8) {
+        let channel = (value >> 6) as usize;
+        let access = (value >> 4) & 3;
+
+        if channel == 3 {
+            for (i, channel) in self.channels.iter_mut().enumerate() {
+                if value & (2 << i) == 0 {
+                    continue;
+                }
+                if value & 0x20 == 0 {
+                    channel.latch_count();
+                }
+                if value & 0x10 == 0 {
+                    channel.latch_status();
+                }
+            }
+        } else if access == 0 {
+            self.channels[channel].latch_count();
+        } else {
+            self.channels[channel].set_control(access, (value >> 1) & 7, value & 1 != 0);
+        }
+    }
+
+    pub fn clock(&mut self) {
+        self.ticks += 1;
+        for channel in self.channels.iter_mut() {
+            channel.clock();
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use super::*;
+use crate::emulator::SegmentRegister::*;
+use crate::emulator::exception::Exception::*;
+
+/*
+ * A segment register: the visible selector and the hidden part the CPU caches
+ * when the selector is loaded. Every memory access adds `base` to its offset.
+ * The cache is what counts, so a segment keeps working as it was loaded
+ * until it's reloaded, even across a switch between real and protected mode.
+ *
+ *  access
+ *  +--+--+--+--+--+--+--+--+
+ *  | 7| 6| 5| 4| 3| 2| 1| 0|
+ *  +--+-----+--+--+--+--+--+
+ *  | P| DPL | S| E|DC|RW| A|
+ *  +--+-----+--+--+--+--+--+
+ *  E: code segment, DC: expand-down (data) or conforming (code),
+ *  RW: writable (data) or readable (code)
+ */
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Segment {
+    pub selector: u16,
+    pub base: u32,
+    pub limit: u32,
+    pub access: u8,
+    pub big: bool,  // D/B bit. 32-bit operands and addresses for CS, ESP instead of SP for SS.
+}
+
+/// Present, DPL 0, read/write data, accessed.
+const ACCESS_DATA: u8 = 0x93;
+
+impl Default for Segment {
+    /// Flat 4GiB 32-bit segment, the environment the emulator runs flat binaries in.
+    fn default() -> Segment {
+        Segment {
+            selector: 0,
+            base: 0,
+            limit: 0xFFFFFFFF,
+            access: ACCESS_DATA,
+            big: true,
+        }
+    }
+}
+
+impl Segment {
+    /// Real mode: base is selector * 16 and the segment is 64KiB of 16-bit code or data.
+    pub fn real(selector: u16) -> Segment {
+        Segment {
+            selector,
+            base: (selector as u32) << 4,
+            limit: 0xFFFF,
+            access: ACCESS_DATA,
+            big: false,
+        }
+    }
+
+    /// What a null selector leaves in DS, ES, FS or GS: any access through it faults.
+    pub fn unusable(selector: u16) -> Segment {
+        Segment {
+            selector,
+            base: 0,
+            limit: 0,
+            access: 0,
+            big: false,
+        }
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.access & 0x80 != 0
+    }
+
+    pub fn dpl(&self) -> u8 {
+        (self.access >> 5) & 3
+    }
+
+    /// S clear: a system descriptor (TSS, LDT, gate) rather than code or data.
+    pub fn is_system(&self) -> bool {
+        self.access & 0x10 == 0
+    }
+
+    /// The type field of a system descriptor, see `task` for the TSS types.
+    pub fn system_type(&self) -> u8 {
+        self.access & 0x0F
+    }
+
+    pub fn is_code(&self) -> bool {
+        !self.is_system() && self.access & 0x08 != 0
+    }
+
+    pub fn is_data(&self) -> bool {
+        !self.is_system() && self.access & 0x08 == 0
+    }
+
+    pub fn is_conforming(&self) -> bool {
+        self.is_code() && self.access & 0x04 != 0
+    }
+
+    pub fn is_expand_down(&self) -> bool {
+        self.is_data() && self.access & 0x04 != 0
+    }
+
+    pub fn is_readable(&self) -> bool {
+        self.is_data() || (self.is_code() && self.access & 0x02 != 0)
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.is_data() && self.access & 0x02 != 0
+    }
+
+    /// Whether `bytes` bytes at `offset` lie inside the segment.
+    pub fn contains(&self, offset: u32, bytes: u32) -> bool {
+        let last = offset as u64 + bytes as u64 - 1;
+        if self.is_expand_down() {
+            let upper = if self.big { 0xFFFFFFFF } else { 0xFFFF };
+            offset > self.limit && last <= upper
+        } else {
+            last <= self.limit as u64
+        }
+    }
+}
+
+/// The Sreg field of ModRM (8C/8E) and of the PUSH/POP Sreg opcodes.
+pub fn segment_register(index: u8) -> Option<SegmentRegister> {
+    match index {
+        0 => Some(ES),
+        1 => Some(CS),
+        2 => Some(SS),
+        3 => Some(DS),
+        4 => Some(FS),
+        5 => Some(GS),
+        _ => None,
+    }
+}
+
+impl Emulator {
+    /// Reset the segment registers to 0000 and run 16-bit code, as the CPU does after power on.
+    pub fn enter_real_mode(&mut self) {
+        self.cr[0] &= !(CR0_PE | CR0_PG);
+        self.segments = [Segment::real(0); SegmentRegistersCount as usize];
+    }
+
+    pub fn is_protected_mode(&self) -> bool {
+        self.cr[0] & CR0_PE != 0
+    }
+
+    /// Current privilege level, the RPL of CS. Always 0 in real mode.
+    pub fn cpl(&self) -> u8 {
+        if self.is_protected_mode() {
+            (self.get_segment(CS).selector & 3) as u8
+        } else {
+            0
+        }
+    }
+
+    pub fn get_segment(&self, sreg: SegmentRegister) -> Segment {
+        self.segments[sreg as usize]
+    }
+
+    /*
+     * MOV Sreg, POP Sreg, LDS and friends.
+     * Real mode derives the segment from the selector,
+     * protected mode looks the selector up in the GDT and checks the descriptor.
+     * CS is loaded by far transfers through `load_code_segment` instead.
+     */
+    pub fn load_segment(&mut self, sreg: SegmentRegister, selector: u16) {
+        if self.is_protected_mode() {
+            self.load_protected_segment(sreg, selector);
+        } else {
+            self.segments[sreg as usize] = Segment::real(selector);
+        }
+    }
+
+    pub fn linear_address(&self, sreg: SegmentRegister, offset: u32) -> u32 {
+        self.segments[sreg as usize].base.wrapping_add(offset)
+    }
+
+    /*
+     * Linear address of a checked access to `bytes` bytes at sreg:offset.
+     * In protected mode the access has to fit the segment's limit and type,
+     * otherwise it raises #GP(0), or #SS(0) for the stack segment.
+     */
+    pub fn segment_address(&mut self, sreg: SegmentRegister, offset: u32, bytes: u32, write: bool) -> u32 {
+        if self.is_protected_mode() {
+            let segment = self.get_segment(sreg);
+            let allowed = segment.is_present()
+                && segment.contains(offset, bytes)
+                && if write { segment.is_writable() } else { segment.is_readable() };
+
+            if !allowed {
+                if sreg == SS {
+                    self.raise(StackFault(0));
+                } else {
+                    self.raise(GeneralProtection(0));
+                }
+            }
+        }
+
+        self.linear_address(sreg, offset)
+    }
+
+    /// The segment an access uses: a segment override prefix wins over the default.
+    pub fn data_segment(&self, default: SegmentRegister) -> SegmentRegister {
+        self.decode.segment.unwrap_or(default)
+    }
+
+    /// Width of the stack pointer, SP or ESP.
+    pub fn stack_bits(&self) -> u32 {
+        if self.segments[SS as usize].big {
+            32
+        } else {
+            16
+        }
+    }
+
+    pub fn get_stack_pointer(&self) -> u32 {
+        self.get_register32(ESP as usize) & mask(self.stack_bits())
+    }
+
+    pub fn set_stack_pointer(&mut self, value: u32) {
+        match self.stack_bits() {
+            16 => self.set_register16(ESP as usize, value as u16),
+            _ => self.set_register32(ESP as usize, value),
+        }
+    }
+}
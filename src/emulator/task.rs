@@ -0,0 +1,179 @@
+use super::*;
+use crate::emulator::segment::Segment;
+use crate::emulator::descriptor::selector_error_code;
+use crate::emulator::SegmentRegister::*;
+use crate::emulator::exception::Exception::*;
+
+/*
+ *  32-bit TSS
+ *  +------+--------+------+--------+------+--------+------+--------+
+ *  | 0x00 | link   | 0x04 | ESP0   | 0x08 | SS0    | 0x0C | ESP1   |
+ *  | 0x10 | SS1    | 0x14 | ESP2   | 0x18 | SS2    | 0x1C | CR3    |
+ *  | 0x20 | EIP    | 0x24 | EFLAGS | 0x28 | EAX, ECX, EDX, EBX,    |
+ *  |      |        |      |        |      | ESP, EBP, ESI, EDI     |
+ *  | 0x48 | ES, CS, SS, DS, FS, GS                | 0x60 | LDT    |
+ *  +------+---------------------------------------+------+--------+
+ *  The segment registers are in the order of `SegmentRegister`, the registers in that of `Register`.
+ */
+const TSS_LINK: u32      = 0x00;
+const TSS_STACKS: u32    = 0x04;
+const TSS_CR3: u32       = 0x1C;
+const TSS_EIP: u32       = 0x20;
+const TSS_EFLAGS: u32    = 0x24;
+const TSS_REGISTERS: u32 = 0x28;
+const TSS_SEGMENTS: u32  = 0x48;
+const TSS_MIN_LIMIT: u32 = 0x67;
+
+/// Type field of a 32-bit TSS descriptor. The busy bit keeps a task from being entered twice.
+pub const TSS_AVAILABLE: u8 = 0x9;
+pub const TSS_BUSY: u8      = 0xB;
+
+impl Emulator {
+    /// LTR: the TSS must be available, and is busy from then on.
+    pub fn load_task_register(&mut self, selector: u16) {
+        let error = selector_error_code(selector);
+        if error == 0 {
+            self.raise(GeneralProtection(0));
+            return;
+        }
+
+        let raw = match self.read_descriptor(selector) {
+            Some(raw) => raw,
+            None => return,
+        };
+        let tss = Segment::from_descriptor(selector, raw);
+        if !tss.is_system() || tss.system_type() != TSS_AVAILABLE {
+            self.raise(GeneralProtection(error));
+            return;
+        }
+        if !tss.is_present() {
+            self.raise(SegmentNotPresent(error));
+            return;
+        }
+
+        self.set_task_busy(selector, true);
+        self.tr = Segment {
+            access: tss.access | 2,
+            ..tss
+        };
+    }
+
+    fn set_task_busy(&mut self, selector: u16, busy: bool) {
+        let addr = self.gdtr.base.wrapping_add((selector & 0xFFF8) as u32 + 5);
+        let access = self.read_linear8(addr, false);
+        let access = if busy { access | 2 } else { access & !2 };
+        self.write_system8(addr, access);
+    }
+
+    /// SS:ESP of privilege level `cpl`, from the current TSS. #TS if there's no TSS to read them from.
+    pub fn inner_stack(&mut self, cpl: u8) -> (u16, u32) {
+        let offset = TSS_STACKS + cpl as u32 * 8;
+        if self.tr.selector & 0xFFFC == 0 || offset + 5 > self.tr.limit {
+            self.raise(InvalidTss(self.tr.selector & 0xFFFC));
+            return (0, 0);
+        }
+
+        let esp = self.read_system32(self.tr.base.wrapping_add(offset));
+        let ss = self.read_system32(self.tr.base.wrapping_add(offset + 4)) as u16;
+        (ss, esp)
+    }
+
+    /*
+     * Switch to the task whose TSS `selector` names.
+     * The current task's registers are saved in its own TSS first, EIP being where it resumes.
+     * A nested switch, through a task gate, links the new task back to the current one and sets NT,
+     * so the new task's IRET comes back here. The switch back is `task_return`.
+     */
+    pub fn task_switch(&mut self, selector: u16, nested: bool) {
+        let error = selector_error_code(selector);
+        if error == 0 {
+            self.raise(GeneralProtection(0));
+            return;
+        }
+
+        let raw = match self.read_descriptor(selector) {
+            Some(raw) => raw,
+            None => return,
+        };
+        let tss = Segment::from_descriptor(selector, raw);
+        let expected = if nested { TSS_AVAILABLE } else { TSS_BUSY };
+        if !tss.is_system() || tss.system_type() != expected {
+            self.raise(GeneralProtection(error));
+            return;
+        }
+        if !tss.is_present() {
+            self.raise(SegmentNotPresent(error));
+            return;
+        }
+        if tss.limit < TSS_MIN_LIMIT || self.tr.selector & 0xFFFC == 0 {
+            self.raise(InvalidTss(error));
+            return;
+        }
+
+        let old = self.tr.selector;
+        if !nested {
+            self.eflags.set_nested_task(false);
+        }
+        self.save_task_state();
+
+        if nested {
+            self.write_system32(tss.base.wrapping_add(TSS_LINK), old as u32);
+            self.set_task_busy(selector, true);
+        } else {
+            self.set_task_busy(old, false);
+        }
+        self.tr = Segment {
+            access: tss.access | 2,
+            ..tss
+        };
+        self.load_task_state();
+        if nested {
+            self.eflags.set_nested_task(true);
+        }
+    }
+
+    /// IRET with NT set: back to the task in the link field, which is still busy.
+    pub fn task_return(&mut self) {
+        let link = self.read_system32(self.tr.base.wrapping_add(TSS_LINK)) as u16;
+        self.task_switch(link, false);
+    }
+
+    fn save_task_state(&mut self) {
+        let base = self.tr.base;
+        self.write_system32(base.wrapping_add(TSS_EIP), self.eip);
+        self.write_system32(base.wrapping_add(TSS_EFLAGS), self.eflags.value());
+        for i in 0..Register::RegistersCount as u32 {
+            self.write_system32(base.wrapping_add(TSS_REGISTERS + i * 4), self.registers[i as usize]);
+        }
+        for i in 0..SegmentRegister::SegmentRegistersCount as u32 {
+            let selector = self.segments[i as usize].selector as u32;
+            self.write_system32(base.wrapping_add(TSS_SEGMENTS + i * 4), selector);
+        }
+    }
+
+    /// CS is loaded first, the new CPL being its RPL, and the other segments are checked against it.
+    fn load_task_state(&mut self) {
+        let base = self.tr.base;
+        if self.is_paging() {
+            self.cr[3] = self.read_system32(base.wrapping_add(TSS_CR3));
+            self.flush_tlb();
+        }
+
+        self.eip = self.read_system32(base.wrapping_add(TSS_EIP));
+        let eflags = self.read_system32(base.wrapping_add(TSS_EFLAGS));
+        self.eflags.set_value(eflags);
+        for i in 0..Register::RegistersCount as u32 {
+            self.registers[i as usize] = self.read_system32(base.wrapping_add(TSS_REGISTERS + i * 4));
+        }
+
+        let mut selectors = [0u16; SegmentRegister::SegmentRegistersCount as usize];
+        for (i, selector) in selectors.iter_mut().enumerate() {
+            *selector = self.read_system32(base.wrapping_add(TSS_SEGMENTS + i as u32 * 4)) as u16;
+        }
+        let cs = selectors[CS as usize];
+        self.load_code_segment(cs, (cs & 3) as u8);
+        for sreg in [SS, ES, DS, FS, GS].iter() {
+            self.load_segment(*sreg, selectors[*sreg as usize]);
+        }
+    }
+}
@@ -0,0 +1,532 @@
+use super::*;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::rc::Rc;
+use crate::emulator::io::PortDevice;
+use crate::emulator::pit::PIT_FREQUENCY;
+
+/*
+ * 16550A UART, on COM1-COM4.
+ *
+ *  offset  DLAB=0 read  DLAB=0 write  DLAB=1
+ *  +0      RBR          THR           DLL, divisor latch low
+ *  +1      IER          IER           DLM, divisor latch high
+ *  +2      IIR          FCR
+ *  +3      LCR, bit 7 is DLAB
+ *  +4      MCR: DTR, RTS, OUT1, OUT2, loopback. OUT2 gates the IRQ on a PC
+ *  +5      LSR: data ready, overrun, parity, framing, break, THR empty, transmitter empty
+ *  +6      MSR: deltas, CTS, DSR, RI, DCD
+ *  +7      scratch
+ *
+ * The line runs at 115200 / divisor baud, 10 bits a character.
+ * Received characters arrive from the backend one per character time into a 16 byte FIFO,
+ * transmitted ones leave for the backend as soon as they're written, so THR is always empty.
+ * A driver polling LSR gets a character as soon as the backend has one.
+ */
+pub const COM_PORTS: [(u16, u8); 4] = [(0x3F8, 4), (0x2F8, 3), (0x3E8, 4), (0x2E8, 3)];
+
+const IER_RX_DATA: u8   = 1;
+const IER_THR_EMPTY: u8 = 1 << 1;
+const IER_LINE: u8      = 1 << 2;
+const IER_MODEM: u8     = 1 << 3;
+
+const IIR_NONE: u8      = 0x01;
+const IIR_MODEM: u8     = 0x00;
+const IIR_THR_EMPTY: u8 = 0x02;
+const IIR_RX_DATA: u8   = 0x04;
+const IIR_LINE: u8      = 0x06;
+const IIR_TIMEOUT: u8   = 0x0C;
+const IIR_FIFO: u8      = 0xC0;
+
+const FCR_ENABLE: u8   = 1;
+const FCR_CLEAR_RX: u8 = 1 << 1;
+
+const LCR_DLAB: u8 = 1 << 7;
+
+const MCR_OUT2: u8     = 1 << 3;
+const MCR_LOOPBACK: u8 = 1 << 4;
+
+const LSR_DATA_READY: u8 = 1;
+const LSR_OVERRUN: u8    = 1 << 1;
+const LSR_ERRORS: u8     = 0x1E;
+const LSR_THR_EMPTY: u8  = 1 << 5;
+const LSR_TX_EMPTY: u8   = 1 << 6;
+
+/// CTS, DSR and DCD: something is connected and ready.
+const MSR_CONNECTED: u8 = 0xB0;
+
+const FIFO_SIZE: usize = 16;
+const FIFO_TRIGGERS: [usize; 4] = [1, 4, 8, 14];
+const BASE_BAUD: u64 = 115_200;
+const CHARACTER_BITS: u64 = 10;
+
+/// Where a serial line goes. Neither direction may block.
+pub trait SerialBackend {
+    fn receive(&mut self) -> Option<u8>;
+    fn transmit(&mut self, value: u8);
+
+    /// Whether the input has ended, so `receive` will never have anything again.
+    fn is_exhausted(&self) -> bool {
+        false
+    }
+}
+
+pub type SharedBackend = Rc<RefCell<dyn SerialBackend>>;
+
+/*
+ * The terminal. Stdin goes into raw mode the first time it's read from, if it's a terminal:
+ * no line editing, no echo, no CR translation. Signals still work, so ^C quits.
+ */
+#[derive(Default)]
+pub struct Stdio {
+    saved: Option<libc::termios>,
+    eof: bool,
+}
+
+impl Stdio {
+    fn enter_raw_mode(&mut self) {
+        if self.saved.is_some() || unsafe { libc::isatty(libc::STDIN_FILENO) } == 0 {
+            return;
+        }
+        unsafe {
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+                return;
+            }
+            self.saved = Some(termios);
+            termios.c_lflag &= !(libc::ICANON | libc::ECHO);
+            termios.c_iflag &= !(libc::ICRNL | libc::IXON);
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+        }
+    }
+}
+
+impl Drop for Stdio {
+    fn drop(&mut self) {
+        if let Some(termios) = self.saved {
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios);
+            }
+        }
+    }
+}
+
+impl SerialBackend for Stdio {
+    fn receive(&mut self) -> Option<u8> {
+        self.enter_raw_mode();
+        let mut fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let mut byte = 0u8;
+        unsafe {
+            if libc::poll(&mut fd, 1, 0) <= 0 || fd.revents & libc::POLLIN == 0 {
+                return None;
+            }
+            match libc::read(libc::STDIN_FILENO, &mut byte as *mut u8 as *mut libc::c_void, 1) {
+                1 => {},
+                0 => {
+                    self.eof = true;
+                    return None;
+                },
+                _ => return None,
+            }
+        }
+        Some(byte)
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.eof
+    }
+
+    fn transmit(&mut self, value: u8) {
+        let mut stdout = io::stdout();
+        stdout.write_all(&[value]).expect("Can't write stdout");
+        stdout.flush().expect("Can't flush stdout");
+    }
+}
+
+/// Input read from one file until it runs out, output written to another. Without one, there's no input or output is dropped.
+pub struct FileBackend {
+    input: Option<File>,
+    output: Option<File>,
+}
+
+impl FileBackend {
+    pub fn new(input: Option<File>, output: Option<File>) -> FileBackend {
+        FileBackend { input, output }
+    }
+}
+
+impl SerialBackend for FileBackend {
+    /// The input is closed at the end of the file, so a file that grows later isn't followed.
+    fn receive(&mut self) -> Option<u8> {
+        let mut byte = [0u8];
+        match self.input.as_mut()?.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            Ok(_) => {
+                self.input = None;
+                None
+            },
+            Err(_) => None,
+        }
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.input.is_none()
+    }
+
+    fn transmit(&mut self, value: u8) {
+        if let Some(output) = self.output.as_mut() {
+            output.write_all(&[value]).expect("Can't write serial output");
+        }
+    }
+}
+
+/// A connection to a Unix socket, with a terminal program or a debugger on the other end.
+pub struct SocketBackend {
+    stream: UnixStream,
+    closed: bool,
+}
+
+impl SocketBackend {
+    pub fn connect(path: &str) -> io::Result<SocketBackend> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_nonblocking(true)?;
+        Ok(SocketBackend { stream, closed: false })
+    }
+}
+
+impl SerialBackend for SocketBackend {
+    fn receive(&mut self) -> Option<u8> {
+        let mut byte = [0u8];
+        match self.stream.read(&mut byte) {
+            Ok(1) => Some(byte[0]),
+            Ok(_) => {
+                self.closed = true;
+                None
+            },
+            Err(_) => None,
+        }
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.closed
+    }
+
+    /// A character the peer isn't reading fast enough for is dropped, as on a real line.
+    fn transmit(&mut self, value: u8) {
+        let _ = self.stream.write(&[value]);
+    }
+}
+
+/// In memory, for tests: `input` is what the guest will receive, `output` what it sent.
+#[derive(Debug, Default)]
+pub struct Buffer {
+    pub input: VecDeque<u8>,
+    pub output: Vec<u8>,
+}
+
+impl SerialBackend for Buffer {
+    fn receive(&mut self) -> Option<u8> {
+        self.input.pop_front()
+    }
+
+    fn transmit(&mut self, value: u8) {
+        self.output.push(value);
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Uart {
+    pub base: u16,
+    pub irq: u8,
+    backend: Option<SharedBackend>,
+    rx: VecDeque<u8>,
+    divisor: u16,
+    ier: u8,
+    lcr: u8,
+    mcr: u8,
+    lsr: u8,            // the error bits, the others are computed
+    msr_delta: u8,
+    scratch: u8,
+    fifo: bool,
+    trigger: usize,
+    thr_empty: bool,    // the THR empty interrupt is pending
+    timeout: bool,      // characters wait in the FIFO below the trigger level
+    ticks: u64,         // PIT ticks into the current character time
+}
+
+impl fmt::Debug for Uart {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Uart")
+            .field("base", &self.base)
+            .field("connected", &self.backend.is_some())
+            .field("rx", &self.rx)
+            .field("divisor", &self.divisor)
+            .field("ier", &self.ier)
+            .field("lcr", &self.lcr)
+            .field("mcr", &self.mcr)
+            .finish()
+    }
+}
+
+impl Uart {
+    pub fn new(base: u16, irq: u8) -> Uart {
+        Uart {
+            base,
+            irq,
+            divisor: 12,
+            trigger: 1,
+            ..Uart::default()
+        }
+    }
+
+    pub fn attach(&mut self, backend: SharedBackend) {
+        self.backend = Some(backend);
+    }
+
+    pub fn detach(&mut self) {
+        self.backend = None;
+    }
+
+    /// A character straight to the backend, for the BIOS console: the registers a driver sees stay as they are.
+    pub fn send(&mut self, value: u8) {
+        if let Some(backend) = &self.backend {
+            backend.borrow_mut().transmit(value);
+        }
+    }
+
+    fn loopback(&self) -> bool {
+        self.mcr & MCR_LOOPBACK != 0
+    }
+
+    fn capacity(&self) -> usize {
+        if self.fifo {
+            FIFO_SIZE
+        } else {
+            1
+        }
+    }
+
+    fn push(&mut self, value: u8) {
+        if self.rx.len() >= self.capacity() {
+            self.lsr |= LSR_OVERRUN;
+        } else {
+            self.rx.push_back(value);
+        }
+    }
+
+    /// One character from the backend, if it has one. Returns whether one came.
+    fn receive(&mut self) -> bool {
+        if self.loopback() || self.rx.len() >= self.capacity() {
+            return false;
+        }
+        let value = match &self.backend {
+            Some(backend) => backend.borrow_mut().receive(),
+            None => None,
+        };
+        match value {
+            Some(value) => {
+                self.push(value);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn modem_status(&self) -> u8 {
+        if self.loopback() {
+            let mcr = self.mcr;
+            // CTS = RTS, DSR = DTR, RI = OUT1, DCD = OUT2
+            ((mcr & 2) << 3) | ((mcr & 1) << 5) | ((mcr & 4) << 4) | ((mcr & 8) << 4)
+        } else if self.backend.is_some() {
+            MSR_CONNECTED
+        } else {
+            0
+        }
+    }
+
+    /// The pending interrupt of highest priority, as IIR identifies it.
+    fn pending(&self) -> Option<u8> {
+        if self.ier & IER_LINE != 0 && self.lsr & LSR_ERRORS != 0 {
+            Some(IIR_LINE)
+        } else if self.ier & IER_RX_DATA != 0 && self.rx.len() >= self.trigger {
+            Some(IIR_RX_DATA)
+        } else if self.ier & IER_RX_DATA != 0 && self.timeout && !self.rx.is_empty() {
+            Some(IIR_TIMEOUT)
+        } else if self.ier & IER_THR_EMPTY != 0 && self.thr_empty {
+            Some(IIR_THR_EMPTY)
+        } else if self.ier & IER_MODEM != 0 && self.msr_delta != 0 {
+            Some(IIR_MODEM)
+        } else {
+            None
+        }
+    }
+
+    /// The IRQ line, which OUT2 connects to the PIC.
+    pub fn interrupt(&self) -> bool {
+        self.mcr & MCR_OUT2 != 0 && self.pending().is_some()
+    }
+
+    fn character_ticks(&self) -> u64 {
+        let divisor = if self.divisor == 0 { 0x10000 } else { self.divisor as u64 };
+        (PIT_FREQUENCY * CHARACTER_BITS * divisor / BASE_BAUD).max(1)
+    }
+
+    /*
+     * One PIT tick. Every character time a character may arrive, or the FIFO times out.
+     * Only for a driver that wants receive interrupts, one that polls LSR is served as it reads it.
+     */
+    pub fn clock(&mut self) {
+        self.ticks += 1;
+        if self.ticks < self.character_ticks() {
+            return;
+        }
+        self.ticks = 0;
+        if self.ier & IER_RX_DATA == 0 {
+            return;
+        }
+        if !self.receive() && !self.rx.is_empty() {
+            self.timeout = true;
+        }
+    }
+
+    pub fn read(&mut self, offset: u16) -> u8 {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        match offset {
+            0 if dlab => self.divisor as u8,
+            0 => {
+                if self.rx.is_empty() {
+                    self.receive();
+                }
+                self.timeout = false;
+                self.rx.pop_front().unwrap_or(0)
+            },
+            1 if dlab => (self.divisor >> 8) as u8,
+            1 => self.ier,
+            2 => {
+                let fifo = if self.fifo { IIR_FIFO } else { 0 };
+                match self.pending() {
+                    Some(id) => {
+                        if id == IIR_THR_EMPTY {
+                            self.thr_empty = false;
+                        }
+                        id | fifo
+                    },
+                    None => IIR_NONE | fifo,
+                }
+            },
+            3 => self.lcr,
+            4 => self.mcr,
+            5 => {
+                if self.rx.is_empty() {
+                    self.receive();
+                }
+                let ready = if self.rx.is_empty() { 0 } else { LSR_DATA_READY };
+                let lsr = self.lsr | LSR_THR_EMPTY | LSR_TX_EMPTY | ready;
+                self.lsr &= !LSR_ERRORS;
+                lsr
+            },
+            6 => {
+                let msr = self.modem_status() | self.msr_delta;
+                self.msr_delta = 0;
+                msr
+            },
+            _ => self.scratch,
+        }
+    }
+
+    pub fn write(&mut self, offset: u16, value: u8) {
+        let dlab = self.lcr & LCR_DLAB != 0;
+        match offset {
+            0 if dlab => self.divisor = (self.divisor & 0xFF00) | value as u16,
+            0 => {
+                if self.loopback() {
+                    self.push(value);
+                } else if let Some(backend) = &self.backend {
+                    backend.borrow_mut().transmit(value);
+                }
+                self.thr_empty = true;
+            },
+            1 if dlab => self.divisor = (self.divisor & 0x00FF) | (value as u16) << 8,
+            1 => {
+                if value & IER_THR_EMPTY != 0 && self.ier & IER_THR_EMPTY == 0 {
+                    self.thr_empty = true;
+                }
+                self.ier = value & 0x0F;
+            },
+            2 => {
+                let fifo = value & FCR_ENABLE != 0;
+                if fifo != self.fifo || value & FCR_CLEAR_RX != 0 {
+                    self.rx.clear();
+                    self.timeout = false;
+                }
+                self.fifo = fifo;
+                self.trigger = if fifo { FIFO_TRIGGERS[(value >> 6) as usize] } else { 1 };
+            },
+            3 => self.lcr = value,
+            4 => {
+                let before = self.modem_status();
+                self.mcr = value & 0x1F;
+                // the deltas: CTS, DSR, DCD changed, RI went from on to off
+                let after = self.modem_status();
+                let changed = (before ^ after) >> 4;
+                self.msr_delta |= (changed & 0xB) | (changed & before >> 4 & 4);
+            },
+            5 | 6 => (),
+            _ => self.scratch = value,
+        }
+    }
+}
+
+impl PortDevice for Uart {
+    fn in8(&mut self, port: u16) -> u8 {
+        self.read(port.wrapping_sub(self.base) & 7)
+    }
+
+    fn out8(&mut self, port: u16, value: u8) {
+        self.write(port.wrapping_sub(self.base) & 7, value);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Serial {
+    pub ports: [Uart; 4],
+}
+
+impl Default for Serial {
+    /// COM1-COM4 with nothing connected.
+    fn default() -> Serial {
+        let uart = |i: usize| Uart::new(COM_PORTS[i].0, COM_PORTS[i].1);
+        Serial {
+            ports: [uart(0), uart(1), uart(2), uart(3)],
+        }
+    }
+}
+
+impl Serial {
+    pub fn port(&mut self, port: u16) -> Option<&mut Uart> {
+        self.ports.iter_mut().find(|uart| (uart.base ..= uart.base + 7).contains(&port))
+    }
+
+    pub fn clock(&mut self) {
+        for uart in self.ports.iter_mut() {
+            uart.clock();
+        }
+    }
+}
+
+impl Emulator {
+    /// COM1 and COM3 share IRQ 4, COM2 and COM4 IRQ 3.
+    pub fn update_serial_irqs(&mut self) {
+        for irq in [3, 4].iter() {
+            let high = self.serial.ports.iter().any(|uart| uart.irq == *irq && uart.interrupt());
+            self.pic.set_irq(*irq, high);
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use super::*;
+use crate::emulator::pit::PIT_FREQUENCY;
+use std::io::{self, Write};
+
+/*
+ * VGA in colour text mode: 80x25 cells of two bytes, the character and its attribute,
+ * in a 32K window at 0xB8000 that holds 8 pages. The CRTC, through the index and data
+ * ports 0x3D4 and 0x3D5, says which page is displayed and where the cursor is.
+ *
+ *  attribute
+ *  +-----+-------+------+-------+
+ *  |  7  | 6   4 |  3   | 2   0 |
+ *  +-----+-------+------+-------+
+ *  |blink| back  |bright| fore  |
+ *  +-----+-------+------+-------+
+ */
+pub const VGA_TEXT_BASE: u32 = 0xB8000;
+pub const VGA_TEXT_SIZE: u32 = 0x8000;
+pub const TEXT_COLUMNS: usize = 80;
+pub const TEXT_ROWS: usize = 25;
+pub const PAGE_SIZE: u32 = 0x1000;
+pub const DEFAULT_ATTRIBUTE: u8 = 0x07;
+
+/// The live display is redrawn at most 60 times a second of virtual time.
+pub const FRAME_TICKS: u64 = PIT_FREQUENCY / 60;
+
+const CRTC_CURSOR_START: usize = 0x0A;
+const CRTC_START_HIGH: usize   = 0x0C;
+const CRTC_START_LOW: usize    = 0x0D;
+const CRTC_CURSOR_HIGH: usize  = 0x0E;
+const CRTC_CURSOR_LOW: usize   = 0x0F;
+const CRTC_REGISTERS: usize    = 0x19;
+const CURSOR_DISABLE: u8       = 1 << 5;
+
+/// Input status 1: display disabled and vertical retrace, which wait loops poll for.
+const STATUS_RETRACE: u8 = 0x09;
+
+/// VGA colour numbers in ANSI order: black, blue, green, cyan, red, magenta, brown, grey.
+const ANSI_COLORS: [u8; 8] = [0, 4, 2, 6, 1, 5, 3, 7];
+
+/// The glyphs of code page 437 for 0x00-0x1F and 0x7F-0xFF.
+const CP437_LOW: &str = " ☺☻♥♦♣♠•◘○◙♂♀♪♫☼►◄↕‼¶§▬↨↑↓→←∟↔▲▼";
+const CP437_HIGH: &str = "⌂ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+pub fn cp437(byte: u8) -> char {
+    match byte {
+        0x00 ..= 0x1F => CP437_LOW.chars().nth(byte as usize).unwrap_or(' '),
+        0x20 ..= 0x7E => byte as char,
+        _ => CP437_HIGH.chars().nth(byte as usize - 0x7F).unwrap_or(' '),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Vga {
+    pub memory: Vec<u8>,
+    crtc: [u8; CRTC_REGISTERS],
+    crtc_index: u8,
+    retrace: bool,
+    dirty: bool,        // the screen changed since it was last rendered
+}
+
+impl Default for Vga {
+    /// A blank screen, grey on black, the cursor at the top left.
+    fn default() -> Vga {
+        let mut memory = vec![0; VGA_TEXT_SIZE as usize];
+        for cell in memory.chunks_mut(2) {
+            cell[0] = b' ';
+            cell[1] = DEFAULT_ATTRIBUTE;
+        }
+        let mut crtc = [0; CRTC_REGISTERS];
+        crtc[CRTC_CURSOR_START] = 0x0D;
+        crtc[CRTC_CURSOR_START + 1] = 0x0E;
+        Vga {
+            memory,
+            crtc,
+            crtc_index: 0,
+            retrace: false,
+            dirty: true,
+        }
+    }
+}
+
+impl Vga {
+    pub fn contains(addr: u32) -> bool {
+        (VGA_TEXT_BASE .. VGA_TEXT_BASE + VGA_TEXT_SIZE).contains(&addr)
+    }
+
+    pub fn read8(&self, addr: u32) -> u8 {
+        self.memory[(addr - VGA_TEXT_BASE) as usize]
+    }
+
+    pub fn write8(&mut self, addr: u32, value: u8) {
+        self.memory[(addr - VGA_TEXT_BASE) as usize] = value;
+        self.dirty = true;
+    }
+
+    pub fn read_port(&mut self, port: u16) -> u8 {
+        match port {
+            0x3D4 => self.crtc_index,
+            0x3D5 => self.crtc.get(self.crtc_index as usize).copied().unwrap_or(0),
+            0x3DA => {
+                self.retrace = !self.retrace;
+                if self.retrace { STATUS_RETRACE } else { 0 }
+            },
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write_port(&mut self, port: u16, value: u8) {
+        match port {
+            0x3D4 => self.crtc_index = value,
+            0x3D5 => if let Some(register) = self.crtc.get_mut(self.crtc_index as usize) {
+                *register = value;
+                self.dirty = true;
+            },
+            _ => (),
+        }
+    }
+
+    fn crtc_word(&self, high: usize, low: usize) -> u16 {
+        (self.crtc[high] as u16) << 8 | self.crtc[low] as u16
+    }
+
+    /// The displayed page, as a cell offset into the window.
+    pub fn start_address(&self) -> u16 {
+        self.crtc_word(CRTC_START_HIGH, CRTC_START_LOW)
+    }
+
+    pub fn set_start_address(&mut self, cell: u16) {
+        self.crtc[CRTC_START_HIGH] = (cell >> 8) as u8;
+        self.crtc[CRTC_START_LOW] = cell as u8;
+        self.dirty = true;
+    }
+
+    /// The cursor as a cell offset into the window.
+    pub fn cursor_address(&self) -> u16 {
+        self.crtc_word(CRTC_CURSOR_HIGH, CRTC_CURSOR_LOW)
+    }
+
+    pub fn set_cursor_address(&mut self, cell: u16) {
+        self.crtc[CRTC_CURSOR_HIGH] = (cell >> 8) as u8;
+        self.crtc[CRTC_CURSOR_LOW] = cell as u8;
+        self.dirty = true;
+    }
+
+    /// The first and last scan lines of the cursor, 0x20 in the first one hiding it.
+    pub fn cursor_shape(&self) -> (u8, u8) {
+        (self.crtc[CRTC_CURSOR_START], self.crtc[CRTC_CURSOR_START + 1])
+    }
+
+    pub fn set_cursor_shape(&mut self, start: u8, end: u8) {
+        self.crtc[CRTC_CURSOR_START] = start;
+        self.crtc[CRTC_CURSOR_START + 1] = end;
+        self.dirty = true;
+    }
+
+    /// Where a cell is in the window. A position off the page is still in the window, as it is on a VGA.
+    fn cell(page: u8, row: usize, column: usize) -> usize {
+        ((page as u32 % 8 * PAGE_SIZE) as usize + (row * TEXT_COLUMNS + column) * 2) % VGA_TEXT_SIZE as usize
+    }
+
+    /// The character and attribute at a position of a page.
+    pub fn get(&self, page: u8, row: usize, column: usize) -> (u8, u8) {
+        let cell = Vga::cell(page, row, column);
+        (self.memory[cell], self.memory[cell + 1])
+    }
+
+    pub fn put(&mut self, page: u8, row: usize, column: usize, character: u8, attribute: u8) {
+        let cell = Vga::cell(page, row, column);
+        self.memory[cell] = character;
+        self.memory[cell + 1] = attribute;
+        self.dirty = true;
+    }
+
+    /// Move the lines of a window of a page up, blanking the ones uncovered at the bottom with `attribute`.
+    pub fn scroll_up(&mut self, page: u8, window: Window, lines: usize, attribute: u8) {
+        self.scroll(page, window, lines, attribute, true);
+    }
+
+    pub fn scroll_down(&mut self, page: u8, window: Window, lines: usize, attribute: u8) {
+        self.scroll(page, window, lines, attribute, false);
+    }
+
+    fn scroll(&mut self, page: u8, window: Window, lines: usize, attribute: u8, up: bool) {
+        let Window { top, left, bottom, right } = window.clip();
+        if top > bottom || left > right {
+            return;
+        }
+        let lines = lines.min(bottom - top + 1);
+        for i in 0 ..= bottom - top {
+            let (row, from) = if up {
+                (top + i, top + i + lines)
+            } else {
+                (bottom - i, (bottom - i).wrapping_sub(lines))
+            };
+            for column in left ..= right {
+                let (character, attribute) = if (top ..= bottom).contains(&from) {
+                    self.get(page, from, column)
+                } else {
+                    (b' ', attribute)
+                };
+                self.put(page, row, column, character, attribute);
+            }
+        }
+    }
+
+    /// Blank every page.
+    pub fn clear(&mut self, attribute: u8) {
+        for page in 0..8 {
+            self.scroll_up(page, Window::SCREEN, TEXT_ROWS, attribute);
+        }
+    }
+
+    /// Whether the screen changed since the last call.
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::replace(&mut self.dirty, false)
+    }
+
+    /// The displayed page as it looks right now.
+    pub fn screen(&self) -> Screen {
+        let start = self.start_address() as usize;
+        let cells = TEXT_COLUMNS * TEXT_ROWS;
+        let cell = |i: usize| ((start + i) * 2) % self.memory.len();
+
+        let cursor = self.cursor_address() as usize;
+        let visible = self.crtc[CRTC_CURSOR_START] & CURSOR_DISABLE == 0;
+        let cursor = if visible && cursor >= start && cursor < start + cells {
+            Some(((cursor - start) / TEXT_COLUMNS, (cursor - start) % TEXT_COLUMNS))
+        } else {
+            None
+        };
+
+        Screen {
+            characters: (0..cells).map(|i| self.memory[cell(i)]).collect(),
+            attributes: (0..cells).map(|i| self.memory[cell(i) + 1]).collect(),
+            cursor,
+        }
+    }
+
+    /// Draw the screen on an ANSI terminal, over whatever was drawn before.
+    pub fn render(&self, out: &mut dyn Write) -> io::Result<()> {
+        let screen = self.screen();
+        write!(out, "\x1b[?25l\x1b[H")?;
+        for row in 0..TEXT_ROWS {
+            let mut last = None;
+            for column in 0..TEXT_COLUMNS {
+                let attribute = screen.attribute(row, column);
+                if last != Some(attribute) {
+                    let fore = ANSI_COLORS[(attribute & 7) as usize] + if attribute & 8 != 0 { 90 } else { 30 };
+                    let back = ANSI_COLORS[((attribute >> 4) & 7) as usize] + 40;
+                    write!(out, "\x1b[{};{}m", fore, back)?;
+                    last = Some(attribute);
+                }
+                write!(out, "{}", cp437(screen.character(row, column)))?;
+            }
+            write!(out, "\x1b[0m\r\n")?;
+        }
+        if let Some((row, column)) = screen.cursor {
+            write!(out, "\x1b[{};{}H\x1b[?25h", row + 1, column + 1)?;
+        }
+        out.flush()
+    }
+}
+
+/// A rectangle of cells, the corners included.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Window {
+    pub top: usize,
+    pub left: usize,
+    pub bottom: usize,
+    pub right: usize,
+}
+
+impl Window {
+    pub const SCREEN: Window = Window { top: 0, left: 0, bottom: TEXT_ROWS - 1, right: TEXT_COLUMNS - 1 };
+
+    /// The part of the window on the screen.
+    fn clip(self) -> Window {
+        Window {
+            bottom: self.bottom.min(TEXT_ROWS - 1),
+            right: self.right.min(TEXT_COLUMNS - 1),
+            ..self
+        }
+    }
+}
+
+/// A capture of the text screen, row by row, for assertions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Screen {
+    pub characters: Vec<u8>,
+    pub attributes: Vec<u8>,
+    pub cursor: Option<(usize, usize)>,     // row and column, None while hidden
+}
+
+impl Screen {
+    pub fn character(&self, row: usize, column: usize) -> u8 {
+        self.characters[row * TEXT_COLUMNS + column]
+    }
+
+    pub fn attribute(&self, row: usize, column: usize) -> u8 {
+        self.attributes[row * TEXT_COLUMNS + column]
+    }
+
+    /// A row as text, without the trailing blanks.
+    pub fn row(&self, row: usize) -> String {
+        let cells = &self.characters[row * TEXT_COLUMNS .. (row + 1) * TEXT_COLUMNS];
+        let text: String = cells.iter().map(|c| cp437(*c)).collect();
+        text.trim_end().to_string()
+    }
+
+    /// The whole screen as text, one line a row, without the blank rows at the bottom.
+    pub fn text(&self) -> String {
+        let rows: Vec<String> = (0..TEXT_ROWS).map(|row| self.row(row)).collect();
+        rows.join("\n").trim_end().to_string()
+    }
+}
+
+impl fmt::Display for Screen {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text())
+    }
+}
@@ -1,11 +1,15 @@
-mod emulator;
-
 #[macro_use]
 extern crate clap;
+extern crate aria;
 
+use std::cell::RefCell;
 use std::fs::File;
-use std::env;
-use emulator::*;
+use std::rc::Rc;
+use aria::emulator::*;
+use aria::emulator::uart::*;
+use aria::emulator::disk::Disk;
+use aria::emulator::cmos::Cmos;
+use aria::emulator::elf;
 
 const MEMORY_SIZE: usize = 1024 * 1024;
 const ORG: u32 = 0x7C00;
@@ -18,21 +22,146 @@ fn main() {
                     (@arg verbose: -v --verbose "Run verbose. dump verbose. information will flood.")
                     (@arg with_name: -w --with_name "Run with print each instruction name.")
                     (@arg quiet: -q --quiet "Shut up and explode")
-                    (@arg file: +required "x86 binary file")
+                    (@arg limit: -l --limit +takes_value "Stop after this many steps: instructions, BIOS services and cycles spent halted.")
+                    (@arg cycles: -c --cycles +takes_value "Virtual CPU cycles each instruction takes, 4 by default.")
+                    (@arg serial: -s --serial +takes_value "Where COM1 goes: stdio (default), none, file:PATH or unix:PATH.")
+                    (@arg vga: --vga "Draw the VGA text screen on the terminal instead of printing COM1 there.")
+                    (@arg fda: --fda +takes_value "Raw floppy image for drive 0x00.")
+                    (@arg hda: --hda +takes_value "Raw hard disk image for drive 0x80.")
+                    (@arg keyboard: -k --keyboard +takes_value "Where keys come from: stdio (default with --vga), none (default) or file:PATH.")
+                    (@arg epoch: --epoch +takes_value "Start the RTC this many seconds after 1970 instead of at the host's time, the same on every run.")
+                    (@arg real: -r --real "Start in 16-bit real mode with all segments at 0000.")
+                    (@arg boot: -b --boot +takes_value possible_values(&["a", "c"]) conflicts_with[file] "Boot from the floppy (a) or the hard disk (c) given with --fda or --hda.")
+                    (@arg linux: --linux requires[file] "Run the ELF file as a Linux i386 program, serving its INT 0x80 system calls.")
+                    (@arg env: -e --env +takes_value +multiple number_of_values(1) requires[linux] "VAR=VALUE in the Linux program's environment.")
+                    (@arg file: required_unless[boot] "x86 binary file: flat, loaded at 0x7C00, or ELF32")
+                    (@arg args: +multiple requires[linux] "Arguments for the Linux program.")
                 ).get_matches();
 
-    if let Some(path) = matches.value_of("file") {
-        if let Ok(mut file) = File::open(path) {
-            let mut emu = Emulator::new(MEMORY_SIZE, ORG, ORG);
-            emu.load(&mut file);
-            let flag = RunFlags {
-                verbose:    matches.is_present("verbose"),
-                with_name:  matches.is_present("with_name"),
-                quiet:      matches.is_present("quiet")
-            };
-            emu.run(flag);
-        } else {
-            eprintln!("Can't open {}.", path);
+    let mut emu = Emulator::new(MEMORY_SIZE, ORG, ORG);
+    match matches.value_of("file") {
+        Some(path) => match std::fs::read(path) {
+            Ok(bytes) if elf::is_elf(&bytes) => {
+                emu.memory.resize(MEMORY_SIZE, 0);
+                match emu.load_elf(&bytes) {
+                    Ok(elf) if matches.is_present("linux") => {
+                        let values = |arg| matches.values_of(arg).into_iter().flatten();
+                        let args: Vec<String> = std::iter::once(path).chain(values("args")).map(String::from).collect();
+                        let env: Vec<String> = values("env").map(String::from).collect();
+                        if let Err(e) = emu.start_linux(&elf, &args, &env) {
+                            eprintln!("Can't run {}: {}", path, e);
+                            return;
+                        }
+                    },
+                    Ok(_) => (),
+                    Err(e) => {
+                        eprintln!("Can't load {}: {}", path, e);
+                        return;
+                    },
+                }
+            },
+            Ok(_) if matches.is_present("linux") => {
+                eprintln!("Can't run {} as a Linux program: not an ELF file", path);
+                return;
+            },
+            Ok(bytes) => emu.load(&mut bytes.as_slice()),
+            Err(_) => {
+                eprintln!("Can't open {}.", path);
+                return;
+            },
+        },
+        None => emu.memory.resize(MEMORY_SIZE, 0),
+    }
+    for (arg, drive) in [("fda", 0x00), ("hda", 0x80)].iter() {
+        if let Some(image) = matches.value_of(arg) {
+            match Disk::open(image, *drive == 0x00) {
+                Ok(disk) => emu.disks.attach(*drive, disk),
+                Err(e) => {
+                    eprintln!("Can't open {}: {}", image, e);
+                    return;
+                },
+            }
+        }
+    }
+    if let Some(epoch) = number(&matches, "epoch") {
+        emu.cmos = Cmos::at(epoch);
+    }
+    if let Some(boot) = matches.value_of("boot") {
+        let drive = if boot == "c" { 0x80 } else { 0x00 };
+        if let Err(e) = emu.boot(drive) {
+            eprintln!("{}", e);
+            return;
+        }
+    } else if matches.is_present("real") {
+        emu.enter_real_mode();
+        emu.install_bios();
+    }
+    if let Some(cycles) = number(&matches, "cycles") {
+        emu.clock.cycles_per_instruction = cycles;
+    }
+    if matches.is_present("vga") && !matches.is_present("serial") {
+        emu.serial.ports[0].detach();
+    }
+    let keyboard = matches.value_of("keyboard").unwrap_or(if matches.is_present("vga") { "stdio" } else { "none" });
+    match keyboard_backend(keyboard) {
+        Ok(Some(backend)) => emu.keyboard.attach(backend),
+        Ok(None) => (),
+        Err(e) => {
+            eprintln!("Can't open {}: {}", keyboard, e);
+            return;
+        },
+    }
+    if let Some(serial) = matches.value_of("serial") {
+        match serial_backend(serial) {
+            Ok(Some(backend)) => emu.serial.ports[0].attach(backend),
+            Ok(None) => emu.serial.ports[0].detach(),
+            Err(e) => {
+                eprintln!("Can't open {}: {}", serial, e);
+                return;
+            },
         }
     }
+    let flag = RunFlags {
+        verbose:    matches.is_present("verbose"),
+        with_name:  matches.is_present("with_name"),
+        quiet:      matches.is_present("quiet"),
+        limit:      number(&matches, "limit"),
+        display:    matches.is_present("vga"),
+    };
+    if let Some(status) = emu.run(flag) {
+        std::process::exit(status);
+    }
+}
+
+/// The value of a numeric option, if it was given. One that doesn't parse ends the program with clap's error.
+fn number<T: std::str::FromStr>(matches: &clap::ArgMatches, name: &str) -> Option<T> {
+    if !matches.is_present(name) {
+        return None;
+    }
+    Some(value_t!(matches, name, T).unwrap_or_else(|e| e.exit()))
+}
+
+fn unknown_backend(choices: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("not one of {}", choices))
+}
+
+fn serial_backend(spec: &str) -> std::io::Result<Option<SharedBackend>> {
+    let backend: SharedBackend = match spec.split_once(':') {
+        Some(("file", path)) => Rc::new(RefCell::new(FileBackend::new(None, Some(File::create(path)?)))),
+        Some(("unix", path)) => Rc::new(RefCell::new(SocketBackend::connect(path)?)),
+        _ if spec == "none" => return Ok(None),
+        _ if spec == "stdio" => Rc::new(RefCell::new(Stdio::default())),
+        _ => return Err(unknown_backend("stdio, none, file:PATH, unix:PATH")),
+    };
+    Ok(Some(backend))
+}
+
+fn keyboard_backend(spec: &str) -> std::io::Result<Option<SharedBackend>> {
+    let backend: SharedBackend = match spec.split_once(':') {
+        Some(("file", path)) => Rc::new(RefCell::new(FileBackend::new(Some(File::open(path)?), None))),
+        _ if spec == "none" => return Ok(None),
+        _ if spec == "stdio" => Rc::new(RefCell::new(Stdio::default())),
+        _ => return Err(unknown_backend("stdio, none, file:PATH")),
+    };
+    Ok(Some(backend))
 }
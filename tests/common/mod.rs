@@ -0,0 +1,38 @@
+// Each test file that uses these compiles its own copy, and none of them uses them all.
+#![allow(dead_code)]
+
+use aria::emulator::Emulator;
+
+/// `size` bytes of memory, in real mode with every segment at 0000, `code` at 0000:0100 and the
+/// stack at 0000:8000. With a `handler` vector, an IRQ handler at 0000:0600 counts in BL.
+pub fn real_mode(size: usize, code: &[u8], handler: Option<u8>) -> Emulator {
+    let mut emu = Emulator {
+        memory: vec![0; size],
+        eip: 0x100,
+        ..Default::default()
+    };
+    emu.enter_real_mode();
+    emu.registers[4] = 0x8000;
+    emu.memory[0x100 .. 0x100 + code.len()].copy_from_slice(code);
+    if let Some(vector) = handler {
+        // inc bl; mov al, 0x20; out 0x20, al; iret
+        emu.set_memory32(vector as u32 * 4, 0x0000_0600);
+        emu.memory[0x600 .. 0x607].copy_from_slice(&[0xFE, 0xC3, 0xB0, 0x20, 0xE6, 0x20, 0xCF]);
+    }
+    emu
+}
+
+/// The machine as the BIOS leaves it before booting, for the tests of its services.
+pub fn with_bios(size: usize) -> Emulator {
+    let mut emu = real_mode(size, &[], None);
+    emu.install_bios();
+    emu
+}
+
+/// Call a BIOS service with EAX, ECX, EDX and EBX, as many as are given, the way INT would reach it.
+pub fn bios_call(emu: &mut Emulator, service: fn(&mut Emulator), registers: &[u32]) {
+    for (i, value) in registers.iter().enumerate() {
+        emu.registers[i] = *value;
+    }
+    service(emu);
+}
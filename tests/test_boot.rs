@@ -0,0 +1,78 @@
+extern crate aria;
+
+mod common;
+
+#[cfg(test)]
+mod boot {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use aria::emulator::{
+        *,
+        bios::boot::*,
+        disk::*,
+    };
+    use crate::common::*;
+
+    fn emulator() -> Emulator {
+        real_mode(0x100000, &[], None)
+    }
+
+    /// A disk whose boot sector holds `code` and whose sector 1 is filled with 0xA5.
+    fn image(sectors: usize, code: &[u8], signature: bool) -> Rc<RefCell<Vec<u8>>> {
+        let mut bytes = vec![0; sectors * SECTOR_SIZE];
+        bytes[.. code.len()].copy_from_slice(code);
+        if signature {
+            bytes[510] = 0x55;
+            bytes[511] = 0xAA;
+        }
+        bytes[SECTOR_SIZE .. SECTOR_SIZE * 2].iter_mut().for_each(|byte| *byte = 0xA5);
+        Rc::new(RefCell::new(bytes))
+    }
+
+    #[test]
+    fn boot_registers() {
+        let mut emu = emulator();
+        emu.registers = [0xFFFF_FFFF; 8];
+        emu.disks.attach(0x80, Disk::hard_disk(image(16 * 63, &[0xF4], true)));
+        emu.boot(0x80).unwrap();
+
+        assert!(!emu.is_protected_mode());
+        assert_eq!(emu.eip, 0x7C00);
+        assert_eq!(emu.memory[0x7C00], 0xF4);
+        assert_eq!(emu.get_memory16(0x7DFE), 0xAA55);
+        // the rest of the disk isn't loaded
+        assert_eq!(emu.memory[0x7E00], 0);
+        assert_eq!(emu.registers, [0xAA55, 0, 0x80, 0, 0x7C00, 0, 0, 0]);
+        assert_eq!(emu.get_segment(SegmentRegister::CS).selector, 0);
+        assert_eq!(emu.get_segment(SegmentRegister::SS).selector, 0);
+        assert!(emu.eflags.is_interrupt());
+        // the BIOS is set up
+        assert_eq!(emu.read_physical8(0x475), 1);
+    }
+
+    #[test]
+    fn boot_reads_the_disk() {
+        let mut emu = emulator();
+        // mov ax, 0x0201; mov cx, 2; mov dh, 0; mov bx, 0x7E00; int 0x13; hlt
+        let code = [0xB8, 0x01, 0x02, 0xB9, 0x02, 0x00, 0xB6, 0x00, 0xBB, 0x00, 0x7E, 0xCD, 0x13, 0xF4];
+        emu.disks.attach(0x00, Disk::floppy(image(2880, &code, true)));
+        emu.boot(0x00).unwrap();
+        assert_eq!(emu.get_register8(2), 0x00);
+        while !emu.halted {
+            emu.execute().unwrap();
+        }
+        assert_eq!(emu.eip, 0x7C0E);
+        assert!(!emu.eflags.is_carry());
+        assert_eq!(emu.memory[0x7E00], 0xA5);
+        assert_eq!(emu.memory[0x7FFF], 0xA5);
+    }
+
+    #[test]
+    fn boot_failures() {
+        let mut emu = emulator();
+        assert!(matches!(emu.boot(0x00), Err(BootError::NoDrive(0x00))));
+        emu.disks.attach(0x00, Disk::floppy(image(2880, &[0xF4], false)));
+        assert!(matches!(emu.boot(0x00), Err(BootError::NotBootable)));
+        assert_eq!(emu.memory[0x7C00], 0);
+    }
+}
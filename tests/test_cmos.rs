@@ -0,0 +1,140 @@
+extern crate aria;
+
+mod common;
+
+#[cfg(test)]
+mod cmos {
+    use aria::emulator::{
+        *,
+        cmos::*,
+        pit::PIT_FREQUENCY,
+    };
+    use crate::common::*;
+
+    /// 2024-02-29 12:34:56, a Thursday.
+    const LEAP_DAY: u64 = 1709210096;
+
+    fn emulator() -> Emulator {
+        let mut emu = real_mode(0x10000, &[], None);
+        emu.cmos = Cmos::at(LEAP_DAY);
+        emu.install_bios();
+        emu
+    }
+
+    fn read(emu: &mut Emulator, index: u8) -> u8 {
+        emu.port_out8(0x70, index);
+        emu.port_in8(0x71)
+    }
+
+    fn write(emu: &mut Emulator, index: u8, value: u8) {
+        emu.port_out8(0x70, index);
+        emu.port_out8(0x71, value);
+    }
+
+    /// INT 1Ah with AX, CX and DX, returning CX, DX and CF.
+    fn int1a(emu: &mut Emulator, ax: u16, cx: u16, dx: u16) -> (u16, u16, bool) {
+        bios_call(emu, Emulator::bios_time, &[ax as u32, cx as u32, dx as u32]);
+        (emu.get_register16(1), emu.get_register16(2), emu.eflags.is_carry())
+    }
+
+    #[test]
+    fn cmos_date_time() {
+        let time = DateTime::from_seconds(LEAP_DAY);
+        assert_eq!(time, DateTime { year: 2024, month: 2, day: 29, hour: 12, minute: 34, second: 56 });
+        assert_eq!(time.to_seconds(), LEAP_DAY);
+        assert_eq!(time.weekday(), 5);
+        assert_eq!(DateTime::from_seconds(0).weekday(), 5);
+        assert_eq!(DateTime::from_seconds(951868800), DateTime { year: 2000, month: 3, day: 1, hour: 0, minute: 0, second: 0 });
+        // the 31st of February is in March
+        assert_eq!(DateTime { year: 2023, month: 2, day: 31, hour: 0, minute: 0, second: 0 }.to_seconds(), 1677801600);
+    }
+
+    #[test]
+    fn cmos_registers() {
+        let mut emu = emulator();
+        assert_eq!(read(&mut emu, 0x00), 0x56);
+        assert_eq!(read(&mut emu, 0x04), 0x12);
+        assert_eq!(read(&mut emu, 0x06), 0x05);
+        assert_eq!(read(&mut emu, 0x08), 0x02);
+        assert_eq!(read(&mut emu, 0x09), 0x24);
+        assert_eq!(read(&mut emu, 0x32), 0x20);
+        assert_eq!(read(&mut emu, 0x0D), 0x80);
+
+        // binary, 12-hour
+        write(&mut emu, 0x0B, 0x04);
+        assert_eq!(read(&mut emu, 0x00), 56);
+        assert_eq!(read(&mut emu, 0x04), 0x80 | 12);
+        write(&mut emu, 0x04, 0x80 | 3);
+        write(&mut emu, 0x0B, 0x06);
+        assert_eq!(read(&mut emu, 0x04), 15);
+        write(&mut emu, 0x07, 1);
+        write(&mut emu, 0x08, 3);
+        assert_eq!(emu.cmos.date_time(), DateTime { year: 2024, month: 3, day: 1, hour: 15, minute: 34, second: 56 });
+
+        // the RAM keeps what is written to it
+        write(&mut emu, 0x40, 0xA5);
+        assert_eq!(read(&mut emu, 0x40), 0xA5);
+    }
+
+    #[test]
+    fn cmos_update_interrupt() {
+        let mut emu = emulator();
+        emu.pic.write(0x21, 0xFB);
+        emu.pic.write(0xA1, 0xFE);
+        write(&mut emu, 0x0B, 0x12);
+        emu.eflags.set_interrupt(true);
+        assert!(emu.can_wake());
+
+        emu.advance_clock((PIT_FREQUENCY - 1) * 4);
+        assert!(!emu.pic.has_interrupt());
+        assert_eq!(read(&mut emu, 0x0A) & 0x80, 0x80);
+        emu.advance_clock(4);
+        assert!(emu.pic.has_interrupt());
+        assert_eq!(read(&mut emu, 0x00), 0x57);
+        assert_eq!(read(&mut emu, 0x0A) & 0x80, 0);
+        // the periodic flag is set too, without its interrupt
+        assert_eq!(read(&mut emu, 0x0C), 0xD0);
+        assert_eq!(read(&mut emu, 0x0C) & 0x80, 0);
+
+        // SET holds the time
+        write(&mut emu, 0x0B, 0x82);
+        emu.advance_clock(PIT_FREQUENCY * 4);
+        assert_eq!(read(&mut emu, 0x00), 0x57);
+        assert!(!emu.cmos.will_interrupt());
+    }
+
+    #[test]
+    fn cmos_periodic_interrupt() {
+        let mut emu = emulator();
+        write(&mut emu, 0x0B, 0x42);
+        let mut interrupts = 0;
+        for _ in 0..PIT_FREQUENCY {
+            emu.advance_clock(4);
+            if read(&mut emu, 0x0C) & 0xC0 == 0xC0 {
+                interrupts += 1;
+            }
+        }
+        assert_eq!(interrupts, 1024);
+    }
+
+    #[test]
+    fn cmos_int1a() {
+        let mut emu = emulator();
+        // 12:34:56 is 45296 seconds into the day
+        assert_eq!(emu.get_memory32(0x46C), (45296u64 * 0x1800B0 / 86400) as u32);
+        assert_eq!(int1a(&mut emu, 0x0100, 0x0001, 0x0002), (0x0001, 0x0002, false));
+        assert_eq!(int1a(&mut emu, 0x0000, 0, 0), (0x0001, 0x0002, false));
+
+        assert_eq!(int1a(&mut emu, 0x0200, 0, 0), (0x1234, 0x5600, false));
+        assert_eq!(int1a(&mut emu, 0x0400, 0, 0), (0x2024, 0x0229, false));
+        int1a(&mut emu, 0x0300, 0x2359, 0x5901);
+        int1a(&mut emu, 0x0500, 0x1999, 0x1231);
+        assert_eq!(emu.cmos.date_time(), DateTime { year: 1999, month: 12, day: 31, hour: 23, minute: 59, second: 59 });
+        assert_eq!(int1a(&mut emu, 0x0200, 0, 0), (0x2359, 0x5901, false));
+
+        // binary mode doesn't change what the BIOS returns
+        write(&mut emu, 0x0B, 0x07);
+        assert_eq!(int1a(&mut emu, 0x0400, 0, 0), (0x1999, 0x1231, false));
+        assert!(int1a(&mut emu, 0x0900, 0, 0).2);
+    }
+}
@@ -0,0 +1,166 @@
+extern crate aria;
+
+#[cfg(test)]
+mod decode {
+    use aria::emulator::{
+        *,
+        decode::*
+    };
+
+    #[test]
+    fn decode_prefix() {
+        let mut emu = Emulator {
+            registers: [0, 0, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0xF0, 0x26, 0x66, 0x67, 0xF3, 0x90],
+            eip: 0,
+            ..Default::default()
+        };
+
+        emu.decode_prefix();
+        assert_eq!(emu.eip, 5);
+        assert_eq!(emu.decode.eip, 0);
+        assert!(emu.decode.lock);
+        assert!(emu.decode.operand_size);
+        assert!(emu.decode.address_size);
+        assert_eq!(emu.decode.segment, Some(SegmentRegister::ES));
+        assert_eq!(emu.decode.rep, Some(Rep::Rep));
+        assert_eq!(emu.operand_bits(), 16);
+        assert_eq!(emu.address_bits(), 16);
+    }
+
+    #[test]
+    fn decode_reset_per_instruction() {
+        let mut emu = Emulator {
+            registers: [0, 0, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x66, 0x90, 0xF2, 0x90],
+            eip: 0,
+            ..Default::default()
+        };
+
+        assert_eq!(emu.step(), Ok("nop"));
+        assert!(emu.decode.operand_size);
+        assert_eq!(emu.step(), Ok("nop"));
+        assert!(!emu.decode.operand_size);
+        assert_eq!(emu.decode.rep, Some(Rep::Repne));
+        assert_eq!(emu.decode.eip, 2);
+        assert_eq!(emu.eip, 4);
+    }
+
+    #[test]
+    fn decode_operand_size_mov() {
+        let mut emu = Emulator {
+            registers: [0x12345678, 0, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x66, 0xB8, 0xCD, 0xAB, 0x66, 0x05, 0x01, 0x00],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // mov ax, 0xABCD ; add ax, 1
+        emu.step().unwrap();
+        assert_eq!(emu.registers[0], 0x1234ABCD);
+        assert_eq!(emu.eip, 4);
+        emu.step().unwrap();
+        assert_eq!(emu.registers[0], 0x1234ABCE);
+        assert_eq!(emu.eip, 8);
+    }
+
+    #[test]
+    fn decode_operand_size_flags() {
+        let mut emu = Emulator {
+            registers: [0x0000FFFF, 0, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x66, 0x40],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // inc ax wraps at 16 bits
+        emu.step().unwrap();
+        assert_eq!(emu.registers[0], 0);
+        assert!(emu.eflags.is_zero());
+    }
+
+    #[test]
+    fn decode_operand_size_stack() {
+        let mut emu = Emulator {
+            registers: [0xAAAA5555, 0, 0, 0, 8, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x66, 0x50, 0x66, 0x5B, 0, 0, 0, 0],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // push ax ; pop bx
+        emu.step().unwrap();
+        assert_eq!(emu.registers[Register::ESP as usize], 6);
+        assert_eq!(emu.get_memory16(6), 0x5555);
+        emu.step().unwrap();
+        assert_eq!(emu.registers[Register::ESP as usize], 8);
+        assert_eq!(emu.registers[Register::EBX as usize], 0x5555);
+    }
+
+    #[test]
+    fn decode_not_implemented() {
+        let mut emu = Emulator {
+            registers: [0, 0, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x66, 0xF1],
+            eip: 0,
+            ..Default::default()
+        };
+
+        assert_eq!(emu.step(), Err(0xF1));
+    }
+
+    #[test]
+    fn decode_too_long() {
+        let mut prefixes = vec![0x66; 15];
+        prefixes.push(0x90);
+        let mut emu = Emulator {
+            memory: prefixes,
+            ..Default::default()
+        };
+
+        emu.decode_prefix();
+        assert_eq!(emu.exception, Some(exception::Exception::GeneralProtection(0)));
+
+        // 14 prefixes and the opcode are still an instruction
+        let mut emu = Emulator {
+            memory: emu.memory[1 ..].to_vec(),
+            ..Default::default()
+        };
+        emu.step().unwrap();
+        assert!(emu.exception.is_none());
+        assert_eq!(emu.eip, 15);
+
+        // 14 prefixes and mov eax, imm32 are 19 bytes
+        let mut code = vec![0x3E; 14];
+        code.extend(&[0xB8, 0x78, 0x56, 0x34, 0x12]);
+        let mut emu = Emulator {
+            memory: code,
+            ..Default::default()
+        };
+        emu.step().unwrap();
+        assert_eq!(emu.exception, Some(exception::Exception::GeneralProtection(0)));
+    }
+
+    #[test]
+    fn decode_push_imm8_sign_extended() {
+        let mut emu = Emulator {
+            registers: [0, 0, 0, 0, 0x10, 0, 0, 0],
+            memory: vec![0x6A, 0xFF, 0x66, 0x6A, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+            ..Default::default()
+        };
+
+        // push -1 ; push word -128
+        emu.step().unwrap();
+        assert_eq!(emu.registers[Register::ESP as usize], 0x0C);
+        assert_eq!(emu.get_memory32(0x0C), 0xFFFFFFFF);
+        emu.step().unwrap();
+        assert_eq!(emu.registers[Register::ESP as usize], 0x0A);
+        assert_eq!(emu.get_memory16(0x0A), 0xFF80);
+    }
+}
@@ -0,0 +1,257 @@
+extern crate aria;
+
+#[cfg(test)]
+mod descriptor {
+    use aria::emulator::{
+        *,
+        descriptor::DescriptorTable,
+        exception::Exception::*,
+        segment::Segment,
+        SegmentRegister::*,
+    };
+
+    const GDT: [u64; 6] = [
+        0,
+        0x00CF9A000000FFFF, // 0x08 ring 0 code, flat
+        0x00CF92000000FFFF, // 0x10 ring 0 data, flat
+        0x00009201000000FF, // 0x18 ring 0 data, 0x10000 - 0x100FF
+        0x00CFFA000000FFFF, // 0x20 ring 3 code, flat
+        0x00CFF2000000FFFF, // 0x28 ring 3 data, flat
+    ];
+
+    fn write_gdt(emu: &mut Emulator, base: u32) {
+        for (i, descriptor) in GDT.iter().enumerate() {
+            let addr = base + i as u32 * 8;
+            emu.set_memory32(addr, *descriptor as u32);
+            emu.set_memory32(addr + 4, (*descriptor >> 32) as u32);
+        }
+    }
+
+    /// Ring 0 flat protected mode with the GDT above at 0x100 and `code` at 0x1000.
+    fn protected_mode(code: &[u8]) -> Emulator {
+        let mut emu = Emulator {
+            memory: vec![0; 0x20000],
+            eip: 0x1000,
+            cr: [CR0_PE | CR0_ET, 0, 0, 0, 0],
+            gdtr: DescriptorTable { base: 0x100, limit: 0x2F },
+            ..Default::default()
+        };
+        write_gdt(&mut emu, 0x100);
+        emu.load_code_segment(0x08, 0);
+        emu.load_segment(SS, 0x10);
+        emu.load_segment(DS, 0x10);
+        emu.registers[4] = 0x8000;
+        emu.memory[0x1000 .. 0x1000 + code.len()].copy_from_slice(code);
+        emu
+    }
+
+    #[test]
+    fn descriptor_decode() {
+        let segment = Segment::from_descriptor(0x08, 0x00CF9A000000FFFF);
+        assert_eq!(segment.base, 0);
+        assert_eq!(segment.limit, 0xFFFFFFFF);
+        assert!(segment.big);
+        assert!(segment.is_code());
+        assert!(segment.is_readable());
+        assert!(!segment.is_writable());
+
+        let segment = Segment::from_descriptor(0x18, 0x00009201000000FF);
+        assert_eq!(segment.base, 0x10000);
+        assert_eq!(segment.limit, 0xFF);
+        assert!(!segment.big);
+        assert!(segment.is_writable());
+        assert_eq!(segment.dpl(), 0);
+    }
+
+    #[test]
+    fn descriptor_enter_protected_mode() {
+        let mut emu = Emulator {
+            memory: vec![0; 0x10000],
+            eip: 0x7C00,
+            ..Default::default()
+        };
+        emu.enter_real_mode();
+        write_gdt(&mut emu, 0x7E00);
+        emu.set_memory16(0x7DF0, 0x2F);
+        emu.set_memory32(0x7DF2, 0x7E00);
+
+        let code = [
+            0x0F, 0x01, 0x16, 0xF0, 0x7D,                   // lgdt [0x7DF0]
+            0x0F, 0x20, 0xC0,                               // mov eax, cr0
+            0x66, 0x83, 0xC8, 0x01,                         // or eax, 1
+            0x0F, 0x22, 0xC0,                               // mov cr0, eax
+            0x66, 0xEA, 0x17, 0x7C, 0x00, 0x00, 0x08, 0x00, // jmp dword 0x08:0x7C17
+            0x66, 0xB8, 0x10, 0x00,                         // mov ax, 0x10
+            0x8E, 0xD8,                                     // mov ds, ax
+            0xF4,                                           // hlt
+        ];
+        emu.memory[0x7C00 .. 0x7C00 + code.len()].copy_from_slice(&code);
+
+        while !emu.halted {
+            emu.step().unwrap();
+            assert_eq!(emu.exception, None);
+        }
+        assert_eq!(emu.gdtr, DescriptorTable { base: 0x7E00, limit: 0x2F });
+        assert!(emu.is_protected_mode());
+        assert_eq!(emu.get_segment(CS).selector, 0x08);
+        assert!(emu.get_segment(CS).big);
+        assert_eq!(emu.get_segment(DS).limit, 0xFFFFFFFF);
+        // the descriptors were marked accessed
+        assert_eq!(emu.memory[0x7E08 + 5], 0x9B);
+        assert_eq!(emu.memory[0x7E10 + 5], 0x93);
+    }
+
+    #[test]
+    fn descriptor_limit_violation() {
+        // mov ds, 0x18; mov al, [0xFF]; mov [0xFF], ax
+        let mut emu = protected_mode(&[
+            0x66, 0xB8, 0x18, 0x00, 0x8E, 0xD8,
+            0x8A, 0x05, 0xFF, 0x00, 0x00, 0x00,
+            0x66, 0x89, 0x05, 0xFF, 0x00, 0x00, 0x00,
+        ]);
+        emu.memory[0x100FF] = 0x42;
+
+        for _ in 0..3 {
+            emu.step().unwrap();
+        }
+        assert_eq!(emu.get_segment(DS).base, 0x10000);
+        assert_eq!(emu.registers[0] & 0xFF, 0x42);
+        assert_eq!(emu.exception, None);
+
+        emu.step().unwrap();
+        assert_eq!(emu.exception, Some(GeneralProtection(0)));
+        assert_eq!(emu.memory[0x100FF], 0x42);
+    }
+
+    #[test]
+    fn descriptor_null_selector() {
+        let mut emu = protected_mode(&[0x8A, 0x05, 0x00, 0x00, 0x00, 0x00]);
+
+        emu.load_segment(DS, 0);
+        assert_eq!(emu.exception, None);
+        emu.step().unwrap();
+        assert_eq!(emu.exception, Some(GeneralProtection(0)));
+
+        emu.exception = None;
+        emu.load_segment(SS, 0);
+        assert_eq!(emu.exception, Some(GeneralProtection(0)));
+    }
+
+    #[test]
+    fn descriptor_outside_gdt() {
+        let mut emu = protected_mode(&[]);
+
+        emu.load_segment(ES, 0x33);
+        assert_eq!(emu.exception, Some(GeneralProtection(0x30)));
+
+        // no LDT
+        emu.exception = None;
+        emu.load_segment(ES, 0x14);
+        assert_eq!(emu.exception, Some(GeneralProtection(0x14)));
+    }
+
+    #[test]
+    fn descriptor_code_not_writable() {
+        // mov cs:[0x2000], al
+        let mut emu = protected_mode(&[0x2E, 0x88, 0x05, 0x00, 0x20, 0x00, 0x00]);
+
+        emu.step().unwrap();
+        assert_eq!(emu.exception, Some(GeneralProtection(0)));
+        assert_eq!(emu.memory[0x2000], 0);
+    }
+
+    #[test]
+    fn descriptor_return_to_ring3() {
+        // retf to 0x23:0x2000 with the stack 0x2B:0x9000
+        let mut emu = protected_mode(&[0xCB]);
+        emu.push32(0x2B);
+        emu.push32(0x9000);
+        emu.push32(0x23);
+        emu.push32(0x2000);
+        // mov ax, 0x10; mov ds, ax
+        emu.memory[0x2000 .. 0x2006].copy_from_slice(&[0x66, 0xB8, 0x10, 0x00, 0x8E, 0xD8]);
+        // mov eax, cr0
+        emu.memory[0x2006 .. 0x2009].copy_from_slice(&[0x0F, 0x20, 0xC0]);
+
+        emu.step().unwrap();
+        assert_eq!(emu.exception, None);
+        assert_eq!(emu.cpl(), 3);
+        assert_eq!(emu.get_segment(CS).selector, 0x23);
+        assert_eq!(emu.get_segment(SS).selector, 0x2B);
+        assert_eq!(emu.registers[4], 0x9000);
+        assert_eq!(emu.eip, 0x2000);
+        // the ring 0 DS isn't usable from ring 3
+        assert!(!emu.get_segment(DS).is_present());
+
+        emu.step().unwrap();
+        emu.step().unwrap();
+        assert_eq!(emu.exception, Some(GeneralProtection(0x10)));
+
+        emu.exception = None;
+        emu.eip = 0x2006;
+        emu.step().unwrap();
+        assert_eq!(emu.exception, Some(GeneralProtection(0)));
+    }
+
+    #[test]
+    fn descriptor_sidt() {
+        // sidt [0x3000]
+        let mut emu = protected_mode(&[0x0F, 0x01, 0x0D, 0x00, 0x30, 0x00, 0x00]);
+        emu.idtr = DescriptorTable { base: 0x12345678, limit: 0x7FF };
+
+        emu.step().unwrap();
+        assert_eq!(emu.get_memory16(0x3000), 0x7FF);
+        assert_eq!(emu.get_memory32(0x3002), 0x12345678);
+    }
+
+    /// At CPL 3 with IOPL 0: HLT, CLI, STI, IN and OUT fault, and POPF keeps IOPL and IF.
+    #[test]
+    fn descriptor_ring3_privilege() {
+        let mut emu = protected_mode(&[]);
+        emu.segments[CS as usize] = Segment::from_descriptor(0x23, GDT[4]);
+        emu.load_segment(SS, 0x2B);
+        emu.eflags.set_interrupt(true);
+        assert_eq!(emu.cpl(), 3);
+
+        // hlt; cli; sti; in al, 0x60; out dx, al; ins byte; outs byte
+        for code in [&[0xF4][..], &[0xFA], &[0xFB], &[0xE4, 0x60], &[0xEE], &[0x6C], &[0x6E]].iter() {
+            emu.memory[0x1000 .. 0x1000 + code.len()].copy_from_slice(code);
+            emu.eip = 0x1000;
+            emu.step().unwrap();
+            assert_eq!(emu.exception.take(), Some(GeneralProtection(0)));
+        }
+        assert!(!emu.halted);
+        assert!(emu.eflags.is_interrupt());
+
+        // popf with IOPL 3 and IF clear
+        emu.memory[0x1000] = 0x9D;
+        emu.eip = 0x1000;
+        emu.push32(0x3002);
+        emu.step().unwrap();
+        assert_eq!(emu.exception, None);
+        assert_eq!(emu.eflags.iopl(), 0);
+        assert!(emu.eflags.is_interrupt());
+
+        // with IOPL 3, CLI is allowed
+        emu.eflags.set_iopl(3);
+        emu.memory[0x1000] = 0xFA;
+        emu.eip = 0x1000;
+        emu.step().unwrap();
+        assert_eq!(emu.exception, None);
+        assert!(!emu.eflags.is_interrupt());
+    }
+
+    #[test]
+    fn descriptor_code_limit() {
+        // nop x8, in a code segment that ends at 0x1005
+        let mut emu = protected_mode(&[0x90; 8]);
+        emu.segments[CS as usize] = Segment::from_descriptor(0x08, 0x00409A0000001005);
+
+        for _ in 0..6 {
+            emu.step().unwrap();
+            assert_eq!(emu.exception, None);
+        }
+        emu.step().unwrap();
+        assert_eq!(emu.exception, Some(GeneralProtection(0)));
+    }
+}
@@ -0,0 +1,181 @@
+extern crate aria;
+
+mod common;
+
+#[cfg(test)]
+mod disk {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use aria::emulator::{
+        *,
+        disk::*,
+    };
+    use crate::common::*;
+
+    /// An image whose sector n is filled with n.
+    fn image(sectors: usize) -> Rc<RefCell<Vec<u8>>> {
+        let bytes = (0..sectors * SECTOR_SIZE).map(|i| (i / SECTOR_SIZE) as u8).collect();
+        Rc::new(RefCell::new(bytes))
+    }
+
+    fn emulator() -> Emulator {
+        real_mode(0x10000, &[], None)
+    }
+
+    /// INT 13h with AX, BX, CX and DX, returning AX and CF.
+    fn int13(emu: &mut Emulator, ax: u16, bx: u16, cx: u16, dx: u16) -> (u16, bool) {
+        bios_call(emu, Emulator::bios_disk, &[ax as u32, cx as u32, dx as u32, bx as u32]);
+        (emu.get_register16(0), emu.eflags.is_carry())
+    }
+
+    #[test]
+    fn disk_geometry() {
+        let floppy = Disk::floppy(image(2880));
+        assert_eq!(floppy.geometry, Geometry { cylinders: 80, heads: 2, sectors: 18 });
+        assert_eq!(floppy.floppy_type, 4);
+        assert_eq!(floppy.lba(1, 1, 1), Some(54));
+        assert_eq!(floppy.lba(0, 0, 19), None);
+        assert_eq!(Disk::floppy(image(720)).geometry.sectors, 9);
+
+        let disk = Disk::hard_disk(image(16 * 63 * 3 + 1));
+        assert_eq!(disk.geometry, Geometry { cylinders: 4, heads: 16, sectors: 63 });
+        assert_eq!(disk.sectors(), 16 * 63 * 3 + 1);
+    }
+
+    #[test]
+    fn disk_boot_second_stage() {
+        let floppy = image(2880);
+        // mov ax, 0x1234; hlt
+        floppy.borrow_mut()[SECTOR_SIZE .. SECTOR_SIZE + 4].copy_from_slice(&[0xB8, 0x34, 0x12, 0xF4]);
+        let mut emu = emulator();
+        emu.disks.attach(0x00, Disk::floppy(floppy));
+        emu.install_bios();
+        emu.eip = 0x7C00;
+        emu.registers[4] = 0x7C00;
+        // mov ax, 0x0201; mov cx, 2; mov dx, 0; mov bx, 0x7E00; int 0x13; jc +3; jmp 0x7E00; hlt
+        emu.memory[0x7C00 .. 0x7C14].copy_from_slice(&[
+            0xB8, 0x01, 0x02, 0xB9, 0x02, 0x00, 0xBA, 0x00, 0x00, 0xBB, 0x00, 0x7E,
+            0xCD, 0x13, 0x72, 0x03, 0xE9, 0xED, 0x01, 0xF4,
+        ]);
+        for _ in 0..20 {
+            if emu.halted {
+                break;
+            }
+            emu.execute().unwrap();
+        }
+        assert!(emu.halted);
+        assert_eq!(emu.registers[0], 0x1234);
+        assert_eq!(emu.eip, 0x7E04);
+    }
+
+    #[test]
+    fn disk_chs() {
+        let mut emu = emulator();
+        emu.disks.attach(0x80, Disk::hard_disk(image(16 * 63 * 2)));
+        emu.install_bios();
+
+        // two sectors from cylinder 1, head 1, sector 1 to 0x1000
+        assert_eq!(int13(&mut emu, 0x0202, 0x1000, 0x0101, 0x0180), (0x0002, false));
+        assert_eq!(emu.memory[0x1000], (63 * 17) as u8);
+        assert_eq!(emu.memory[0x1200], (63 * 17 + 1) as u8);
+
+        // written back one sector on
+        emu.memory[0x1000 .. 0x1200].iter_mut().for_each(|byte| *byte = 0xAA);
+        assert_eq!(int13(&mut emu, 0x0301, 0x1000, 5, 0x0080), (0x0001, false));
+        assert_eq!(int13(&mut emu, 0x0201, 0x2000, 5, 0x0080), (0x0001, false));
+        assert_eq!(emu.memory[0x21FF], 0xAA);
+
+        // a sector the geometry doesn't have, and the status stays
+        assert_eq!(int13(&mut emu, 0x0201, 0x2000, 64, 0x0080), (0x0400, true));
+        assert_eq!(int13(&mut emu, 0x0100, 0, 0, 0x0080), (0x0400, true));
+        assert_eq!(emu.read_physical8(0x474), 0x04);
+        assert_eq!(int13(&mut emu, 0x0000, 0, 0, 0x0080), (0x0000, false));
+
+        // past the last cylinder: what's there is read, and AL says how much
+        assert_eq!(int13(&mut emu, 0x0203, 0x2000, 0x013E, 0x0F80), (0x0402, true));
+
+        // no count, no drive, no function
+        assert_eq!(int13(&mut emu, 0x0200, 0x2000, 1, 0x0080), (0x0100, true));
+        assert_eq!(int13(&mut emu, 0x0201, 0x2000, 1, 0x0081), (0x8000, true));
+        assert_eq!(int13(&mut emu, 0x0201, 0x2000, 1, 0x0001), (0x8000, true));
+        assert_eq!(int13(&mut emu, 0x0500, 0, 0, 0x0080), (0x0100, true));
+    }
+
+    #[test]
+    fn disk_write_protected() {
+        let mut emu = emulator();
+        let floppy = Disk { read_only: true, ..Disk::floppy(image(2880)) };
+        emu.disks.attach(0x00, floppy);
+        assert_eq!(int13(&mut emu, 0x0301, 0x1000, 1, 0x0000), (0x0300, true));
+        assert_eq!(emu.read_physical8(0x441), 0x03);
+        assert_eq!(int13(&mut emu, 0x0201, 0x1000, 1, 0x0000), (0x0001, false));
+        assert_eq!(emu.read_physical8(0x441), 0x00);
+    }
+
+    #[test]
+    fn disk_parameters() {
+        let mut emu = emulator();
+        emu.disks.attach(0x00, Disk::floppy(image(2880)));
+        let geometry = Geometry { cylinders: 1000, heads: 16, sectors: 63 };
+        emu.disks.attach(0x80, Disk { geometry, ..Disk::hard_disk(image(1)) });
+        emu.disks.attach(0x81, Disk::hard_disk(image(1)));
+        emu.install_bios();
+        assert_eq!(emu.read_physical8(0x475), 2);
+
+        emu.write_physical32(0x1E * 4, 0xF000_EFC7);
+        assert_eq!(int13(&mut emu, 0x0800, 0, 0, 0x0000), (0x0000, false));
+        assert_eq!(emu.get_register16(1), 0x4F12);
+        assert_eq!(emu.get_register16(2), 0x0101);
+        assert_eq!(emu.get_register8(3), 4);
+        assert_eq!(emu.get_segment(SegmentRegister::ES).selector, 0xF000);
+        assert_eq!(emu.get_register16(7), 0xEFC7);
+
+        // cylinder 999 is 0x3E7: 0xE7 in CH, 3 in bits 6-7 of CL
+        assert_eq!(int13(&mut emu, 0x0800, 0, 0, 0x0080), (0x0000, false));
+        assert_eq!(emu.get_register16(1), 0xE7FF);
+        assert_eq!(emu.get_register16(2), 0x0F02);
+
+        assert!(int13(&mut emu, 0x0800, 0, 0, 0x0001).1);
+    }
+
+    #[test]
+    fn disk_extensions() {
+        let mut emu = emulator();
+        emu.disks.attach(0x80, Disk::hard_disk(image(100)));
+        assert_eq!(int13(&mut emu, 0x4100, 0x55AA, 0, 0x0080), (0x3000, false));
+        assert_eq!(emu.get_register16(3), 0xAA55);
+        assert_eq!(emu.get_register16(1), 0x0001);
+        assert_eq!(int13(&mut emu, 0x4100, 0x1234, 0, 0x0080), (0x0100, true));
+        assert_eq!(int13(&mut emu, 0x4100, 0x55AA, 0, 0x0081), (0x8000, true));
+
+        // 3 sectors from LBA 97 to 0100:0000, through the packet at 0x500
+        emu.memory[0x500 .. 0x510].copy_from_slice(&[0x10, 0, 3, 0, 0x00, 0x00, 0x00, 0x01, 97, 0, 0, 0, 0, 0, 0, 0]);
+        emu.set_register16(6, 0x500);
+        assert_eq!(int13(&mut emu, 0x4200, 0, 0, 0x0080), (0x0000, false));
+        assert_eq!(emu.memory[0x1000], 97);
+        assert_eq!(emu.memory[0x15FF], 99);
+
+        // past the end nothing is transferred
+        emu.memory[0x508] = 98;
+        assert_eq!(int13(&mut emu, 0x4300, 0, 0, 0x0080), (0x0400, true));
+        assert_eq!(emu.get_memory16(0x502), 0);
+
+        emu.memory[0x502] = 1;
+        emu.memory[0x1000] = 0x55;
+        assert_eq!(int13(&mut emu, 0x4300, 0, 0, 0x0080), (0x0000, false));
+        let mut sector = [0; SECTOR_SIZE];
+        emu.disks.get(0x80).unwrap().read(98, &mut sector).unwrap();
+        assert_eq!(sector[0], 0x55);
+
+        // drive parameters at 0x600
+        emu.memory[0x600] = 0x1E;
+        emu.set_register16(6, 0x600);
+        assert_eq!(int13(&mut emu, 0x4800, 0, 0, 0x0080), (0x0000, false));
+        assert_eq!(emu.get_memory16(0x600), 0x1A);
+        assert_eq!(emu.get_memory32(0x604), 1);
+        assert_eq!(emu.get_memory32(0x608), 16);
+        assert_eq!(emu.get_memory32(0x60C), 63);
+        assert_eq!(emu.get_memory32(0x610), 100);
+        assert_eq!(emu.get_memory16(0x618), 512);
+    }
+}
@@ -0,0 +1,146 @@
+extern crate aria;
+
+#[cfg(test)]
+mod elf {
+    use aria::emulator::{
+        *,
+        elf::*,
+    };
+
+    fn put16(bytes: &mut [u8], offset: usize, value: u16) {
+        bytes[offset .. offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put32(bytes: &mut [u8], offset: usize, value: u32) {
+        bytes[offset .. offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    /*
+     * An executable with its code at 0x200000, 2 bytes of data and a BSS at 0x201000,
+     * symbols for both, and a .bss section that has nothing in the file.
+     *  0x000  ELF header          0x0A0  .symtab
+     *  0x034  program headers     0x0D0  .strtab
+     *  0x080  code                0x100  section headers
+     *  0x090  data
+     */
+    fn executable(code: &[u8]) -> Vec<u8> {
+        let mut elf = vec![0; 0x1A0];
+        elf[.. 7].copy_from_slice(b"\x7FELF\x01\x01\x01");
+        put16(&mut elf, 0x10, 2);
+        put16(&mut elf, 0x12, 3);
+        put32(&mut elf, 0x14, 1);
+        put32(&mut elf, 0x18, 0x200000);
+        put32(&mut elf, 0x1C, 0x34);
+        put32(&mut elf, 0x20, 0x100);
+        put16(&mut elf, 0x28, 0x34);
+        put16(&mut elf, 0x2A, 32);
+        put16(&mut elf, 0x2C, 2);
+        put16(&mut elf, 0x2E, 40);
+        put16(&mut elf, 0x30, 4);
+
+        for (i, (offset, address, file_size, size)) in [(0x80, 0x200000, code.len() as u32, code.len() as u32), (0x90, 0x201000, 2, 0x100)].iter().enumerate() {
+            let header = 0x34 + i * 32;
+            put32(&mut elf, header, 1);
+            put32(&mut elf, header + 0x04, *offset);
+            put32(&mut elf, header + 0x08, *address);
+            put32(&mut elf, header + 0x10, *file_size);
+            put32(&mut elf, header + 0x14, *size);
+        }
+        elf[0x80 .. 0x80 + code.len()].copy_from_slice(code);
+        elf[0x90 .. 0x92].copy_from_slice(b"AB");
+
+        // _start, a function, and buffer, an object
+        for (i, (name, value, size, info)) in [(1, 0x200000, code.len() as u32, 0x12), (8, 0x201000, 0x100, 0x11)].iter().enumerate() {
+            let symbol = 0xA0 + (i + 1) * 16;
+            put32(&mut elf, symbol, *name);
+            put32(&mut elf, symbol + 4, *value);
+            put32(&mut elf, symbol + 8, *size);
+            elf[symbol + 12] = *info;
+            put16(&mut elf, symbol + 14, 1);
+        }
+        elf[0xD0 .. 0xDF].copy_from_slice(b"\0_start\0buffer\0");
+
+        for (i, (kind, offset, size, link)) in [(2, 0xA0, 0x30, 2), (3, 0xD0, 0x0F, 0), (8, 0x8000, 0x100, 0)].iter().enumerate() {
+            let header = 0x100 + (i + 1) * 40;
+            put32(&mut elf, header + 0x04, *kind);
+            put32(&mut elf, header + 0x10, *offset);
+            put32(&mut elf, header + 0x14, *size);
+            put32(&mut elf, header + 0x18, *link);
+        }
+        elf
+    }
+
+    #[test]
+    fn elf_parse() {
+        // mov eax, [0x201000]; hlt
+        let elf = Elf::parse(&executable(&[0xA1, 0x00, 0x10, 0x20, 0x00, 0xF4])).unwrap();
+        assert_eq!(elf.entry, 0x200000);
+        assert_eq!(elf.segments.len(), 2);
+        assert_eq!(elf.segments[1], LoadSegment { address: 0x201000, data: b"AB".to_vec(), size: 0x100 });
+        assert_eq!(elf.symbols.get("buffer").unwrap().address, 0x201000);
+        assert_eq!(elf.symbols.describe(0x200000).unwrap(), "_start");
+        assert_eq!(elf.symbols.describe(0x200005).unwrap(), "_start+0x5");
+        assert_eq!(elf.symbols.describe(0x2010FF).unwrap(), "buffer+0xff");
+        assert!(elf.symbols.describe(0x200006).is_none());
+        assert!(elf.symbols.describe(0x1000).is_none());
+    }
+
+    #[test]
+    fn elf_load_and_run() {
+        let mut emu = Emulator {
+            memory: vec![0xFF; 0x10000],
+            ..Default::default()
+        };
+        emu.load_elf(&executable(&[0xA1, 0x00, 0x10, 0x20, 0x00, 0xF4])).unwrap();
+        assert_eq!(emu.memory.len(), 0x201100);
+        assert_eq!(emu.eip, 0x200000);
+        assert_eq!(emu.symbolize(0x200002), "0x200002 <_start+0x2>");
+        while !emu.halted {
+            emu.execute().unwrap();
+        }
+        // the BSS right after the data is zeroed
+        assert_eq!(emu.registers[0], 0x4241);
+        assert_eq!(emu.eip, 0x200006);
+    }
+
+    #[test]
+    fn elf_errors() {
+        let elf = executable(&[0xF4]);
+        assert_eq!(Elf::parse(b"MZ").unwrap_err(), ElfError::NotElf);
+        assert!(!is_elf(b"\x7FEL"));
+
+        let mut wide = elf.clone();
+        wide[4] = 2;
+        assert_eq!(Elf::parse(&wide).unwrap_err(), ElfError::Unsupported("not 32-bit"));
+        let mut arm = elf.clone();
+        arm[0x12] = 0x28;
+        assert_eq!(Elf::parse(&arm).unwrap_err(), ElfError::Unsupported("not for i386"));
+        assert_eq!(Elf::parse(&elf[.. 0x40]).unwrap_err(), ElfError::Truncated);
+    }
+
+    #[test]
+    fn elf_hostile_segments() {
+        let elf = executable(&[0xF4]);
+        let mut emu = Emulator {
+            memory: vec![0; 0x1000],
+            ..Default::default()
+        };
+
+        // a segment whose end in the file overflows
+        let mut wrapped = elf.clone();
+        put32(&mut wrapped, 0x34 + 0x04, 0xFFFFFFF0);
+        put32(&mut wrapped, 0x34 + 0x10, 0x20);
+        put32(&mut wrapped, 0x34 + 0x14, 0x20);
+        assert_eq!(Elf::parse(&wrapped).unwrap_err(), ElfError::Truncated);
+
+        // one that wraps around memory, and one far past it
+        let mut wrapped = elf.clone();
+        put32(&mut wrapped, 0x34 + 0x08, 0xFFFFF000);
+        put32(&mut wrapped, 0x34 + 0x14, 0x2000);
+        assert_eq!(emu.load_elf(&wrapped).unwrap_err(), ElfError::TooLarge(0xFFFFF000));
+        let mut huge = elf.clone();
+        put32(&mut huge, 0x34 + 0x14, 0x40000000);
+        assert_eq!(emu.load_elf(&huge).unwrap_err(), ElfError::TooLarge(0x200000));
+        assert_eq!(emu.memory.len(), 0x1000);
+    }
+}
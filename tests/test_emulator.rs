@@ -36,7 +36,7 @@ mod emulator {
         let emu = Emulator::new(memsiz, 0x0000, 0x7C00);
         assert_eq!(emu.registers.iter().sum::<u32>(), 0x7c00);
         assert_eq!(emu.registers[Register::ESP as usize], 0x7c00);
-        assert_eq!(emu.eflags.raw, 0);
+        assert_eq!(emu.eflags.value(), 0x2);
         assert_eq!(emu.memory.capacity(), memsiz + 0x7c00);
         assert_eq!(emu.eip, 0);
     }
@@ -56,6 +56,7 @@ mod emulator {
             eflags: Eflags{ raw: 0 },
             memory: vec![0x00, 0x56, 0x34, 0x12],
             eip: 0,
+            ..Default::default()
         };
     
         emu.set_memory8(0x00, 0xFF78);
@@ -89,11 +90,12 @@ mod emulator {
 
     #[test]
     fn emulator_get_code8() {
-        let emu = Emulator {
+        let mut emu = Emulator {
             registers: [0, 0, 0, 0, 0, 0, 0, 0],
             eflags: Eflags{ raw: 0 },
             memory: vec![0xB8],
             eip: 0,
+            ..Default::default()
         };
     
         assert_eq!(0xB8, emu.get_code8(0));
@@ -101,11 +103,12 @@ mod emulator {
     
     #[test]
     fn emulator_get_sign_code8() {
-        let emu = Emulator {
+        let mut emu = Emulator {
             registers: [0, 0, 0, 0, 0, 0, 0, 0],
             eflags: Eflags{ raw: 0 },
             memory: vec![0xFF, 0xFE],
             eip: 0,
+            ..Default::default()
         };
     
         assert_eq!(-2, emu.get_sign_code8(1));
@@ -113,11 +116,12 @@ mod emulator {
     
     #[test]
     fn emulator_get_code32() {
-        let emu = Emulator {
+        let mut emu = Emulator {
             registers: [0, 0, 0, 0, 0, 0, 0, 0],
             eflags: Eflags{ raw: 0 },
             memory: vec![0x78, 0x56, 0x34, 0x12],
             eip: 0,
+            ..Default::default()
         };
     
         assert_eq!(0x12345678, emu.get_code32(0));
@@ -137,6 +141,7 @@ mod emulator {
             eflags: Eflags{ raw: 0 },
             memory: vec![0x00, 0x56, 0x34, 0x12],
             eip: 0,
+            ..Default::default()
         };
         emu.set_register32(EAX as usize, 0x61) ;
         assert_eq!(emu.registers[EAX as usize], emu.get_register32(EAX as usize));
@@ -150,4 +155,40 @@ mod emulator {
         emu.push32(0x12345678);
         assert_eq!(0x12345678, emu.pop32());
    }
+
+    #[test]
+    fn emulator_eflags_reserved() {
+        let mut eflags = Eflags::new();
+        assert_eq!(eflags.value(), 0x2);
+        eflags.set_value(0xFFFFFFFF);
+        assert_eq!(eflags.value(), 0x003F7FD7);
+        eflags.set_value(0);
+        assert_eq!(eflags.value(), 0x2);
+
+        eflags.set_iopl(3);
+        assert_eq!(eflags.iopl(), 3);
+        assert_eq!(eflags.value(), 0x3002);
+        eflags.set_direction(true);
+        eflags.set_interrupt(true);
+        eflags.set_trap(true);
+        eflags.set_nested_task(true);
+        assert!(eflags.is_direction() && eflags.is_interrupt());
+        assert!(eflags.is_trap() && eflags.is_nested_task());
+    }
+
+    #[test]
+    fn emulator_limit_while_halted() {
+        // halted, waiting for a key that never comes
+        let mut emu = Emulator {
+            memory: vec![0; 0x1000],
+            halted: true,
+            ..Default::default()
+        };
+        emu.eflags.set_interrupt(true);
+        emu.keyboard.attach(std::rc::Rc::new(std::cell::RefCell::new(uart::Buffer::default())));
+        assert!(emu.can_wake());
+
+        let flag = RunFlags { verbose: false, with_name: false, quiet: true, limit: Some(1000), display: false };
+        assert_eq!(emu.run(flag), None);
+    }
 }
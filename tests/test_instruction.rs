@@ -24,6 +24,10 @@ mod instruction {
         }
         assert_eq!(instructions_with_name(0x68).1, "push_imm32");
         assert_eq!(instructions_with_name(0x6A).1, "push_imm8");
+        assert_eq!(instructions_with_name(0x6C).1, "ins_m8_dx");
+        assert_eq!(instructions_with_name(0x6D).1, "ins_m32_dx");
+        assert_eq!(instructions_with_name(0x6E).1, "outs_dx_m8");
+        assert_eq!(instructions_with_name(0x6F).1, "outs_dx_m32");
         assert_eq!(instructions_with_name(0x70).1, "jump_overflow");
         assert_eq!(instructions_with_name(0x71).1, "jump_not_overflow");
         assert_eq!(instructions_with_name(0x72).1, "jump_carry");
@@ -34,6 +38,12 @@ mod instruction {
         assert_eq!(instructions_with_name(0x79).1, "jump_not_sign");
         assert_eq!(instructions_with_name(0x7C).1, "jump_less");
         assert_eq!(instructions_with_name(0x7E).1, "jump_less_or_eq");
+        assert_eq!(instructions_with_name(0x76).1, "jump_below_or_eq");
+        assert_eq!(instructions_with_name(0x77).1, "jump_above");
+        assert_eq!(instructions_with_name(0x7A).1, "jump_parity");
+        assert_eq!(instructions_with_name(0x7B).1, "jump_not_parity");
+        assert_eq!(instructions_with_name(0x7D).1, "jump_greater_or_eq");
+        assert_eq!(instructions_with_name(0x7F).1, "jump_greater");
         assert_eq!(instructions_with_name(0x83).1, "code_83");
         assert_eq!(instructions_with_name(0x88).1, "mov_rm8_r8");
         assert_eq!(instructions_with_name(0x89).1, "mov_rm32_r32");
@@ -53,7 +63,9 @@ mod instruction {
         assert_eq!(instructions_with_name(0xE9).1, "near_jump");
         assert_eq!(instructions_with_name(0xEB).1, "short_jump");
         assert_eq!(instructions_with_name(0xEC).1, "in_al_dx");
+        assert_eq!(instructions_with_name(0xED).1, "in_eax_dx");
         assert_eq!(instructions_with_name(0xEE).1, "out_dx_al");
+        assert_eq!(instructions_with_name(0xEF).1, "out_dx_eax");
         assert_eq!(instructions_with_name(0xFF).1, "code_ff");
     }
     
@@ -64,11 +76,12 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0xB8, 0x00, 0x00, 0x00, 0x00],
             eip: 0,
+            ..Default::default()
         };
         
         emu.set_memory32(1, 0x01234567);
         instructions(emu.get_code8(0)).unwrap()(&mut emu);
-        assert_eq!(emu.registers[0] as u32, 0x01234567 as u32);
+        assert_eq!(emu.registers[0], 0x01234567);
     }
 
     #[test]
@@ -78,6 +91,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0xC7, 0xC0, 0x00, 0x00, 0x00, 0x00],
             eip: 0,
+            ..Default::default()
         };
 
         emu.set_memory32(2, 0x01234567);
@@ -92,10 +106,12 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0x89, 0x00, 0x00, 0x00, 0x00, 0x00],
             eip: 0,
+            ..Default::default()
         };
 
         instructions(emu.get_code8(0)).unwrap()(&mut emu);
-        assert_eq!(emu.registers[0], emu.get_memory32(2));
+        let value = emu.get_memory32(2);
+        assert_eq!(emu.registers[0], value);
     }
 
     #[test]
@@ -105,11 +121,13 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0x8B, 0x11, 0x00, 0x00, 0x00, 0x00],
             eip: 0,
+            ..Default::default()
         };
 
         emu.set_memory32(2, 0x12345678);
         instructions(emu.get_code8(0)).unwrap()(&mut emu);
-        assert_eq!(emu.registers[2], emu.get_memory32(2));
+        let value = emu.get_memory32(2);
+        assert_eq!(emu.registers[2], value);
     }
 
     #[test]
@@ -119,6 +137,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0xB0, 0xFF],
             eip: 0,
+            ..Default::default()
         };
 
         instructions(emu.get_code8(0)).unwrap()(&mut emu);
@@ -132,6 +151,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0x8A, 0b00000001, 0xFF],
             eip: 0,
+            ..Default::default()
         };
 
         instructions(emu.get_code8(0)).unwrap()(&mut emu);
@@ -145,6 +165,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0x88, 0b00001010, 0x00],
             eip: 0,
+            ..Default::default()
         };
 
         instructions(emu.get_code8(0)).unwrap()(&mut emu);
@@ -158,6 +179,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0x01, 0b11010001, 0x00],
             eip: 0,
+            ..Default::default()
         };
 
         instructions(emu.get_code8(0)).unwrap()(&mut emu);
@@ -171,12 +193,170 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0x83, 0b11000001, 0x0F],
             eip: 0,
+            ..Default::default()
         };
 
         instructions(emu.get_code8(0)).unwrap()(&mut emu);
         assert_eq!(emu.registers[1], 0xFF);
     }
 
+    #[test]
+    fn instructions_alu_name() {
+        let ops = ["add", "or", "adc", "sbb", "and", "sub", "xor", "cmp"];
+        let forms = ["rm8_r8", "rm32_r32", "r8_rm8", "r32_rm32", "al_imm8", "eax_imm32"];
+        for (i, op) in ops.iter().enumerate() {
+            for (j, form) in forms.iter().enumerate() {
+                let code = (i * 8 + j) as u8;
+                assert_eq!(instructions_with_name(code).1, format!("{}_{}", op, form));
+            }
+        }
+        assert_eq!(instructions_with_name(0x80).1, "code_80");
+        assert_eq!(instructions_with_name(0x81).1, "code_81");
+    }
+
+    #[test]
+    fn instruction_xor_r32_rm32() {
+        let mut emu = Emulator {
+            registers: [0x12345678, 0, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x33, 0b11000000],
+            eip: 0,
+            ..Default::default()
+        };
+
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[0], 0);
+        assert!(emu.eflags.is_zero());
+        assert!(!emu.eflags.is_carry());
+        assert_eq!(emu.eip, 2);
+    }
+
+    #[test]
+    fn instruction_and_rm8_r8() {
+        let mut emu = Emulator {
+            registers: [0, 0, 0, 0x0F, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x20, 0b00011110, 0x12],
+            eip: 0,
+            ..Default::default()
+        };
+
+        emu.set_register32(Register::ESI as usize, 2);
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.memory[2], 0x02);
+    }
+
+    #[test]
+    fn instruction_or_r8_rm8() {
+        let mut emu = Emulator {
+            registers: [0x80, 0, 0, 0x01, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x0A, 0b11000011],
+            eip: 0,
+            ..Default::default()
+        };
+
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.get_register8(0), 0x81);
+        assert!(emu.eflags.is_sign());
+    }
+
+    #[test]
+    fn instruction_adc_sbb() {
+        let mut emu = Emulator {
+            registers: [0xFFFFFFFF, 0, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x05, 0x01, 0x00, 0x00, 0x00, 0x15, 0x00, 0x00, 0x00, 0x00,
+                         0x2D, 0x01, 0x00, 0x00, 0x00, 0x1D, 0x00, 0x00, 0x00, 0x00],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // add eax, 1 ; adc eax, 0
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[0], 0);
+        assert!(emu.eflags.is_carry());
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[0], 1);
+        assert!(!emu.eflags.is_carry());
+
+        // sub eax, 1 ; sbb eax, 0
+        emu.set_register32(0, 0);
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[0], 0xFFFFFFFF);
+        assert!(emu.eflags.is_carry());
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[0], 0xFFFFFFFE);
+        assert!(!emu.eflags.is_carry());
+    }
+
+    #[test]
+    fn instruction_cmp_al_imm8() {
+        let mut emu = Emulator {
+            registers: [0x7F, 0, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x3C, 0xFF],
+            eip: 0,
+            ..Default::default()
+        };
+
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[0], 0x7F);
+        assert!(emu.eflags.is_carry());
+        assert!(emu.eflags.is_overflow());
+        assert!(emu.eflags.is_sign());
+        assert!(!emu.eflags.is_zero());
+    }
+
+    #[test]
+    fn instruction_code_80() {
+        let mut emu = Emulator {
+            registers: [0, 0x00F0, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x80, 0b11110001, 0xFF],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // xor cl, 0xFF
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[1], 0x000F);
+        assert_eq!(emu.eip, 3);
+    }
+
+    #[test]
+    fn instruction_code_81() {
+        let mut emu = Emulator {
+            registers: [0, 0, 0xFFFF0000, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x81, 0b11100010, 0x34, 0x12, 0xFF, 0xFF],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // and edx, 0xFFFF1234
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[2], 0xFFFF0000);
+        assert_eq!(emu.eip, 6);
+    }
+
+    #[test]
+    fn instruction_cmp_rm32_imm8() {
+        let mut emu = Emulator {
+            registers: [1, 0, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x83, 0b11111000, 0xFF],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // cmp eax, -1
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[0], 1);
+        assert!(emu.eflags.is_carry());
+        assert!(!emu.eflags.is_zero());
+    }
+
     #[test]
     fn instruction_update_eflags_sub() {
         let mut emu = Emulator {
@@ -184,6 +364,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0x0],
             eip: 0,
+            ..Default::default()
         };
 
         emu.update_eflags_sub(0x10, 0x01, (2u64).wrapping_sub(1u64));
@@ -211,6 +392,103 @@ mod instruction {
         assert!(emu.eflags.is_overflow());
     }
 
+    #[test]
+    fn instruction_update_eflags_parity_aux() {
+        let mut emu = Emulator::new(0, 0, 0);
+
+        emu.update_eflags_add(0x0F, 0x01, 0x10);
+        assert!(emu.eflags.is_aux_carry());
+        assert!(!emu.eflags.is_parity());
+
+        emu.update_eflags_logic(0x03);
+        assert!(!emu.eflags.is_aux_carry());
+        assert!(emu.eflags.is_parity());
+
+        emu.eflags.set_carry(true);
+        emu.update_eflags_inc_sized(0x7F, 0x80, 8);
+        assert!(emu.eflags.is_carry());
+        assert!(emu.eflags.is_overflow());
+        assert!(emu.eflags.is_sign());
+
+        emu.update_eflags_dec_sized(0x80000000, 0x7FFFFFFF, 32);
+        assert!(emu.eflags.is_overflow());
+        assert!(!emu.eflags.is_sign());
+    }
+
+    #[test]
+    fn instruction_jump_conditions() {
+        // (opcode, eflags, taken)
+        let cases = [
+            (0x76, 0x001, true), (0x76, 0x040, true), (0x76, 0x000, false),
+            (0x77, 0x000, true), (0x77, 0x001, false),
+            (0x7A, 0x004, true), (0x7A, 0x000, false),
+            (0x7B, 0x000, true), (0x7B, 0x004, false),
+            (0x7D, 0x880, true), (0x7D, 0x080, false),
+            (0x7F, 0x000, true), (0x7F, 0x040, false), (0x7F, 0x800, false),
+        ];
+        for (code, flags, taken) in cases.iter() {
+            let mut emu = Emulator {
+                registers: [0, 0, 0, 0, 0, 0, 0, 0],
+                eflags: Eflags { raw: *flags },
+                memory: vec![*code, 0x10],
+                eip: 0,
+                ..Default::default()
+            };
+
+            instructions(emu.get_code8(0)).unwrap()(&mut emu);
+            assert_eq!(emu.eip, if *taken { 0x12 } else { 2 }, "opcode 0x{:X}", code);
+        }
+    }
+
+    #[test]
+    fn instruction_jump_backward() {
+        let mut emu = Emulator {
+            registers: [0, 0, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0, 0, 0x75, 0xFC],
+            eip: 2,
+            ..Default::default()
+        };
+
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.eip, 0);
+    }
+
+    #[test]
+    fn instruction_dec_r32() {
+        let mut emu = Emulator {
+            registers: [0, 1, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x49],
+            eip: 0,
+            ..Default::default()
+        };
+
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[1], 0);
+        assert!(emu.eflags.is_zero());
+    }
+
+    #[test]
+    fn instruction_pushfd_popfd() {
+        let mut emu = Emulator {
+            registers: [0, 0, 0, 0, 8, 0, 0, 0],
+            eflags: Eflags::new(),
+            memory: vec![0x9C, 0x9D, 0, 0, 0, 0, 0, 0],
+            eip: 0,
+            ..Default::default()
+        };
+
+        emu.eflags.set_carry(true);
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.get_memory32(4), 0x3);
+
+        // every writable bit but VM, which POPF leaves alone
+        emu.set_memory32(4, 0xFFFFFFFF);
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.eflags.value(), 0x003D7FD7);
+    }
+
     #[test]
     fn instruction_sub_rm32_imm8() {
         let mut emu = Emulator {
@@ -218,6 +496,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0x83, 0xec, 0x10],
             eip: 0,
+            ..Default::default()
         };
         
         instructions(emu.get_code8(0)).unwrap()(&mut emu);
@@ -231,6 +510,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0x41],
             eip: 0,
+            ..Default::default()
         };
         
         instructions(emu.get_code8(0)).unwrap()(&mut emu);
@@ -244,6 +524,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0xFF, 0b11000111],
             eip: 0,
+            ..Default::default()
         };
 
         instructions(emu.get_code8(0)).unwrap()(&mut emu);
@@ -257,6 +538,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0x51, 0x00, 0x00, 0x00, 0x00, 0x00],
             eip: 0,
+            ..Default::default()
         };
 
         instructions(emu.get_code8(0)).unwrap()(&mut emu);
@@ -270,6 +552,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0x68, 0, 0, 0, 0, 0, 0, 0, 0],
             eip: 0,
+            ..Default::default()
         };
 
         emu.set_memory32(1, 0x12345678);
@@ -284,11 +567,13 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0x6A, 0, 0, 0, 0, 0],
             eip: 0,
+            ..Default::default()
         };
 
         emu.set_memory8(1, 0xFF);
         instructions(emu.get_code8(0)).unwrap()(&mut emu);
-        assert_eq!(emu.get_memory32(2), 0xFF);
+        // push -1: the immediate is sign-extended
+        assert_eq!(emu.get_memory32(2), 0xFFFFFFFF);
     }
 
     #[test]
@@ -298,6 +583,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0x58, 0, 0, 0, 0],
             eip: 0,
+            ..Default::default()
         };
 
         emu.set_memory32(1, 0x12345678);
@@ -312,6 +598,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0xEB, 0xFF],
             eip: 0,
+            ..Default::default()
         };
 
         instructions(emu.get_code8(0)).unwrap()(&mut emu);
@@ -325,6 +612,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0xE9, 0, 0, 0, 0],
             eip: 0,
+            ..Default::default()
         };
 
         emu.set_memory32(1, 0x12345673);
@@ -339,6 +627,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0xE8, 0, 0, 0, 0],
             eip: 0,
+            ..Default::default()
         };
 
         emu.set_memory32(1, 0x12345673);
@@ -354,6 +643,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0xC3, 0, 0, 0, 0],
             eip: 0,
+            ..Default::default()
         };
 
         emu.set_memory32(1, 0x12345678);
@@ -368,6 +658,7 @@ mod instruction {
             eflags: Eflags { raw: 0 },
             memory: vec![0xC9, 0, 0, 0, 0],
             eip: 0,
+            ..Default::default()
         };
 
         emu.set_register32(Register::EBP as usize, 1);
@@ -375,4 +666,458 @@ mod instruction {
         instructions(emu.get_code8(0)).unwrap()(&mut emu);
         assert_eq!(emu.registers[Register::EBP as usize], 0x12345678);
     }
+
+    #[test]
+    fn instructions_0f_name() {
+        assert_eq!(instructions_with_name(0x0F).1, "code_0f");
+        for i in 0x80 ..= 0x8F {
+            assert_eq!(instructions_0f_with_name(i).1, "jcc_rel32");
+        }
+        for i in 0x90 ..= 0x9F {
+            assert_eq!(instructions_0f_with_name(i).1, "setcc_rm8");
+        }
+        assert_eq!(instructions_0f_with_name(0xAF).1, "imul_r32_rm32");
+        assert_eq!(instructions_0f_with_name(0xB6).1, "movzx_r32_rm8");
+        assert_eq!(instructions_0f_with_name(0xB7).1, "movzx_r32_rm16");
+        assert_eq!(instructions_0f_with_name(0xBE).1, "movsx_r32_rm8");
+        assert_eq!(instructions_0f_with_name(0xBF).1, "movsx_r32_rm16");
+    }
+
+    #[test]
+    fn instruction_jcc_rel32() {
+        let mut emu = Emulator {
+            registers: [0, 0, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x0F, 0x84, 0x00, 0x01, 0x00, 0x00],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // je not taken
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.eip, 6);
+
+        // je taken
+        emu.eip = 0;
+        emu.eflags.set_zero(true);
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.eip, 0x106);
+
+        // jg backwards
+        emu.memory = vec![0x0F, 0x8F, 0xFA, 0xFF, 0xFF, 0xFF];
+        emu.eip = 0;
+        emu.eflags.set_zero(false);
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.eip, 0);
+    }
+
+    #[test]
+    fn instruction_setcc_rm8() {
+        let mut emu = Emulator {
+            registers: [0xFFFFFFFF, 0, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x0F, 0x92, 0xC0, 0x0F, 0x93, 0xC0],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // setb al ; setae al
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[0], 0xFFFFFF00);
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[0], 0xFFFFFF01);
+        assert_eq!(emu.eip, 6);
+    }
+
+    #[test]
+    fn instruction_movzx_movsx() {
+        let mut emu = Emulator {
+            registers: [0, 0x80, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x0F, 0xB6, 0xC1, 0x0F, 0xBE, 0xD1, 0x0F, 0xBF, 0x1D, 0x0D, 0, 0, 0, 0xFE, 0xFF],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // movzx eax, cl ; movsx edx, cl ; movsx ebx, word [0x0D]
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[0], 0x80);
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[2], 0xFFFFFF80);
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[3], 0xFFFFFFFE);
+    }
+
+    #[test]
+    fn instruction_imul() {
+        let mut emu = Emulator {
+            registers: [0xFFFFFFFE, 3, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x0F, 0xAF, 0xC1, 0x6B, 0xD0, 0x10, 0x69, 0xD9, 0x00, 0x00, 0x00, 0x80],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // imul eax, ecx ; imul edx, eax, 16 ; imul ebx, ecx, 0x80000000
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[0] as i32, -6);
+        assert!(!emu.eflags.is_overflow());
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[2] as i32, -96);
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[3], 0x80000000);
+        assert!(emu.eflags.is_overflow());
+        assert!(emu.eflags.is_carry());
+    }
+
+    #[test]
+    fn instruction_lea_r32_m32() {
+        let mut emu = Emulator {
+            registers: [0, 0, 0, 0, 0, 0x100, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x8D, 0x45, 0xF8],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // lea eax, [ebp-8]
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[0], 0xF8);
+    }
+
+    #[test]
+    fn instruction_test_and_cdq() {
+        let mut emu = Emulator {
+            registers: [0x80000000, 0, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x85, 0xC0, 0x99, 0xA8, 0xFF],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // test eax, eax ; cdq ; test al, 0xFF
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert!(emu.eflags.is_sign());
+        assert!(!emu.eflags.is_zero());
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[2], 0xFFFFFFFF);
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert!(emu.eflags.is_zero());
+    }
+
+    #[test]
+    fn instruction_call_rm32() {
+        let mut emu = Emulator {
+            registers: [0x1234, 0, 0, 0, 8, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0xFF, 0xD0, 0, 0, 0, 0, 0, 0],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // call eax
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.eip, 0x1234);
+        assert_eq!(emu.get_memory32(4), 2);
+    }
+
+    #[test]
+    fn instruction_mul_div_rm32() {
+        let mut emu = Emulator {
+            registers: [0x80000000, 0x10, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0xF7, 0xE1, 0xF7, 0xF1],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // mul ecx ; div ecx
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[0], 0);
+        assert_eq!(emu.registers[2], 0x8);
+        assert!(emu.eflags.is_carry());
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[0], 0x80000000);
+        assert_eq!(emu.registers[2], 0);
+        assert!(emu.exception.is_none());
+    }
+
+    #[test]
+    fn instruction_imul_idiv_rm8() {
+        let mut emu = Emulator {
+            registers: [0xFD, 0x07, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0xF6, 0xE9, 0xF6, 0xF9],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // imul cl (-3 * 7) ; idiv cl
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.get_register16(0) as i16, -21);
+        assert!(!emu.eflags.is_overflow());
+        emu.set_register16(0, (-22i16) as u16);
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.get_register8(0) as i8, -3);
+        assert_eq!(emu.get_register8(4) as i8, -1);
+    }
+
+    #[test]
+    fn instruction_div_error() {
+        let mut emu = Emulator {
+            registers: [1, 0, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0xF7, 0xF1],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // div ecx with ecx = 0
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.exception, Some(exception::Exception::DivideError));
+        assert_eq!(emu.registers[0], 1);
+
+        // idiv ecx with EDX:EAX = INT64_MIN / -1 style overflow
+        emu.exception = None;
+        emu.eip = 0;
+        emu.registers = [0, 0xFFFFFFFF, 0x80000000, 0, 0, 0, 0, 0];
+        emu.memory = vec![0xF7, 0xF9];
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.exception, Some(exception::Exception::DivideError));
+
+        // div cl with a quotient above 0xFF
+        emu.exception = None;
+        emu.eip = 0;
+        emu.registers = [0x1000, 0x2, 0, 0, 0, 0, 0, 0];
+        emu.memory = vec![0xF6, 0xF1];
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.exception, Some(exception::Exception::DivideError));
+    }
+
+    #[test]
+    fn instruction_not_neg_test() {
+        let mut emu = Emulator {
+            registers: [0x0F, 1, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0xF7, 0xD0, 0xF7, 0xD9, 0xF6, 0xC1, 0x01],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // not eax ; neg ecx ; test cl, 1
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[0], 0xFFFFFFF0);
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[1], 0xFFFFFFFF);
+        assert!(emu.eflags.is_carry());
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert!(!emu.eflags.is_zero());
+        assert!(!emu.eflags.is_carry());
+        assert_eq!(emu.eip, 7);
+    }
+
+    fn run_shift(code: Vec<u8>, eax: u32, ecx: u32, carry: bool) -> Emulator {
+        let mut emu = Emulator {
+            registers: [eax, ecx, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags { raw: 0 },
+            memory: code,
+            eip: 0,
+            ..Default::default()
+        };
+
+        emu.eflags.set_carry(carry);
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        emu
+    }
+
+    #[test]
+    fn instruction_shift_rm32() {
+        // shl eax, 4
+        let emu = run_shift(vec![0xC1, 0xE0, 0x04], 0x1000000F, 0, false);
+        assert_eq!(emu.registers[0], 0x000000F0);
+        assert!(emu.eflags.is_carry());
+        assert_eq!(emu.eip, 3);
+
+        // shr eax, 1
+        let emu = run_shift(vec![0xD1, 0xE8], 0x80000001, 0, false);
+        assert_eq!(emu.registers[0], 0x40000000);
+        assert!(emu.eflags.is_carry());
+        assert!(emu.eflags.is_overflow());
+
+        // sar eax, cl
+        let emu = run_shift(vec![0xD3, 0xF8], 0x80000000, 4, false);
+        assert_eq!(emu.registers[0], 0xF8000000);
+        assert!(!emu.eflags.is_carry());
+        assert!(emu.eflags.is_sign());
+
+        // shl eax, cl with cl = 33 is masked to 1
+        let emu = run_shift(vec![0xD3, 0xE0], 0x1, 33, false);
+        assert_eq!(emu.registers[0], 0x2);
+
+        // shl eax, cl with cl = 32 is masked to 0 and leaves the flags alone
+        let emu = run_shift(vec![0xD3, 0xE0], 0x1, 32, true);
+        assert_eq!(emu.registers[0], 0x1);
+        assert!(emu.eflags.is_carry());
+    }
+
+    #[test]
+    fn instruction_rotate() {
+        // rol eax, 8
+        let emu = run_shift(vec![0xC1, 0xC0, 0x08], 0x12345678, 0, false);
+        assert_eq!(emu.registers[0], 0x34567812);
+
+        // ror al, 1
+        let emu = run_shift(vec![0xD0, 0xC8], 0x01, 0, false);
+        assert_eq!(emu.registers[0], 0x80);
+        assert!(emu.eflags.is_carry());
+        assert!(emu.eflags.is_overflow());
+
+        // rcl eax, 1
+        let emu = run_shift(vec![0xD1, 0xD0], 0x80000000, 0, true);
+        assert_eq!(emu.registers[0], 0x1);
+        assert!(emu.eflags.is_carry());
+
+        // rcr al, cl with cl = 9 is a full rotation through CF
+        let emu = run_shift(vec![0xD2, 0xD8], 0x5A, 9, true);
+        assert_eq!(emu.registers[0], 0x5A);
+        assert!(emu.eflags.is_carry());
+
+        // rcr al, 1
+        let emu = run_shift(vec![0xD0, 0xD8], 0x01, 0, false);
+        assert_eq!(emu.registers[0], 0x00);
+        assert!(emu.eflags.is_carry());
+    }
+
+    #[test]
+    fn instruction_shld_shrd() {
+        // shld eax, ecx, 8
+        let emu = run_shift(vec![0x0F, 0xA4, 0xC8, 0x08], 0x12345678, 0xABCDEF01, false);
+        assert_eq!(emu.registers[0], 0x345678AB);
+        assert_eq!(emu.eip, 4);
+
+        // shrd eax, ecx, cl with cl = 32 is masked to 0
+        let mut emu = run_shift(vec![0x0F, 0xAD, 0xC8], 0x12345678, 0x00000020, false);
+        assert_eq!(emu.registers[0], 0x12345678);
+        // shrd eax, ecx, cl with cl = 4
+        emu.eip = 0;
+        emu.set_register32(1, 0x04);
+        emu.set_register32(0, 0x1234567F);
+        instructions(emu.get_code8(0)).unwrap()(&mut emu);
+        assert_eq!(emu.registers[0], 0x41234567);
+        assert!(emu.eflags.is_carry());
+    }
+
+    fn run_until(emu: &mut Emulator, eip: u32) {
+        while emu.eip != eip {
+            emu.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn instruction_rep_movsb() {
+        let mut emu = Emulator {
+            registers: [0, 4, 0, 0, 0, 0, 0x10, 0x20],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0; 0x30],
+            eip: 0,
+            ..Default::default()
+        };
+
+        emu.memory[0] = 0xF3;
+        emu.memory[1] = 0xA4;
+        emu.memory[0x10 .. 0x15].copy_from_slice(b"hello");
+
+        // every iteration is a step of its own
+        emu.step().unwrap();
+        assert_eq!(emu.eip, 0);
+        assert_eq!(emu.registers[1], 3);
+        run_until(&mut emu, 2);
+        assert_eq!(emu.instruction_count, 4);
+        assert_eq!(&emu.memory[0x20 .. 0x25], b"hell\0");
+        assert_eq!(emu.registers[1], 0);
+        assert_eq!(emu.registers[6], 0x14);
+        assert_eq!(emu.registers[7], 0x24);
+
+        // ECX == 0 does nothing
+        emu.eip = 0;
+        emu.step().unwrap();
+        assert_eq!(emu.eip, 2);
+        assert_eq!(emu.registers[6], 0x14);
+    }
+
+    #[test]
+    fn instruction_rep_stosd_backwards() {
+        let mut emu = Emulator {
+            registers: [0xDEADBEEF, 2, 0, 0, 0, 0, 0, 0x1C],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0; 0x20],
+            eip: 0,
+            ..Default::default()
+        };
+
+        emu.memory[0] = 0xF3;
+        emu.memory[1] = 0xAB;
+        emu.eflags.set_direction(true);
+        run_until(&mut emu, 2);
+        assert_eq!(emu.get_memory32(0x1C), 0xDEADBEEF);
+        assert_eq!(emu.get_memory32(0x18), 0xDEADBEEF);
+        assert_eq!(emu.get_memory32(0x14), 0);
+        assert_eq!(emu.registers[7], 0x14);
+    }
+
+    #[test]
+    fn instruction_repne_scasb() {
+        let mut emu = Emulator {
+            registers: [0, 0xFFFFFFFF, 0, 0, 0, 0, 0, 0x10],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0; 0x20],
+            eip: 0,
+            ..Default::default()
+        };
+
+        // strlen
+        emu.memory[0] = 0xF2;
+        emu.memory[1] = 0xAE;
+        emu.memory[0x10 .. 0x15].copy_from_slice(b"abcd\0");
+        run_until(&mut emu, 2);
+        assert!(emu.eflags.is_zero());
+        assert_eq!(!emu.registers[1] - 1, 4);
+        assert_eq!(emu.registers[7], 0x15);
+    }
+
+    #[test]
+    fn instruction_repe_cmpsb() {
+        let mut emu = Emulator {
+            registers: [0, 5, 0, 0, 0, 0, 0x10, 0x18],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0; 0x20],
+            eip: 0,
+            ..Default::default()
+        };
+
+        emu.memory[0] = 0xF3;
+        emu.memory[1] = 0xA6;
+        emu.memory[0x10 .. 0x15].copy_from_slice(b"abcde");
+        emu.memory[0x18 .. 0x1D].copy_from_slice(b"abXde");
+        run_until(&mut emu, 2);
+        assert!(!emu.eflags.is_zero());
+        assert_eq!(emu.registers[1], 2);
+        assert_eq!(emu.registers[6], 0x13);
+    }
+
+    #[test]
+    fn instruction_lodsw() {
+        let mut emu = Emulator {
+            registers: [0xFFFFFFFF, 0, 0, 0, 0, 0, 0x4, 0],
+            eflags: Eflags { raw: 0 },
+            memory: vec![0x66, 0xAD, 0, 0, 0x34, 0x12],
+            eip: 0,
+            ..Default::default()
+        };
+
+        emu.step().unwrap();
+        assert_eq!(emu.registers[0], 0xFFFF1234);
+        assert_eq!(emu.registers[6], 0x6);
+        assert_eq!(emu.eip, 2);
+    }
 }
@@ -0,0 +1,301 @@
+extern crate aria;
+
+mod common;
+
+#[cfg(test)]
+mod interrupt {
+    use aria::emulator::{
+        *,
+        bios::BIOS_SEGMENT,
+        descriptor::DescriptorTable,
+        exception::Exception::*,
+        interrupt::*,
+        SegmentRegister::*,
+    };
+    use crate::common::*;
+
+    const GDT: [u64; 7] = [
+        0,
+        0x00CF9A000000FFFF, // 0x08 ring 0 code, flat
+        0x00CF92000000FFFF, // 0x10 ring 0 data, flat
+        0x00CFFA000000FFFF, // 0x18 ring 3 code, flat
+        0x00CFF2000000FFFF, // 0x20 ring 3 data, flat
+        0x0000890030000067, // 0x28 TSS at 0x3000
+        0x0000890031000067, // 0x30 TSS at 0x3100
+    ];
+    const IDT: u32 = 0x800;
+
+    /// Ring 0 flat protected mode with the GDT above at 0x100, an empty IDT at 0x800 and `code` at 0x1000.
+    fn protected_mode(code: &[u8]) -> Emulator {
+        let mut emu = Emulator {
+            memory: vec![0; 0x10000],
+            eip: 0x1000,
+            cr: [CR0_PE | CR0_ET, 0, 0, 0, 0],
+            gdtr: DescriptorTable { base: 0x100, limit: 0x37 },
+            idtr: DescriptorTable { base: IDT, limit: 0x7FF },
+            ..Default::default()
+        };
+        for (i, descriptor) in GDT.iter().enumerate() {
+            let addr = 0x100 + i as u32 * 8;
+            emu.set_memory32(addr, *descriptor as u32);
+            emu.set_memory32(addr + 4, (*descriptor >> 32) as u32);
+        }
+        emu.load_code_segment(0x08, 0);
+        emu.load_segment(SS, 0x10);
+        emu.load_segment(DS, 0x10);
+        emu.registers[4] = 0x8000;
+        emu.memory[0x1000 .. 0x1000 + code.len()].copy_from_slice(code);
+        emu
+    }
+
+    fn set_gate(emu: &mut Emulator, vector: u8, selector: u16, offset: u32, kind: u8, dpl: u8) {
+        let addr = IDT + vector as u32 * 8;
+        emu.set_memory32(addr, ((selector as u32) << 16) | (offset & 0xFFFF));
+        emu.set_memory32(addr + 4, (offset & 0xFFFF0000) | 0x8000 | ((dpl as u32) << 13) | ((kind as u32) << 8));
+    }
+
+    fn enter_ring3(emu: &mut Emulator) {
+        emu.load_task_register(0x28);
+        emu.set_memory32(0x3004, 0x7000);  // ESP0
+        emu.set_memory32(0x3008, 0x10);    // SS0
+        emu.load_code_segment(0x1B, 3);
+        emu.load_segment(SS, 0x23);
+        emu.load_segment(DS, 0x23);
+        assert_eq!(emu.exception, None);
+    }
+
+    #[test]
+    fn interrupt_real_mode() {
+        // int 0x21; handler at 0060:0000: mov al, 0x55; iret
+        let mut emu = real_mode(0x10000, &[0xCD, 0x21], None);
+        emu.set_memory32(0x21 * 4, 0x0060_0000);
+        emu.memory[0x600 .. 0x603].copy_from_slice(&[0xB0, 0x55, 0xCF]);
+        emu.eflags.set_interrupt(true);
+
+        emu.execute().unwrap();
+        assert_eq!(emu.get_segment(CS).selector, 0x60);
+        assert_eq!(emu.eip, 0);
+        assert!(!emu.eflags.is_interrupt());
+        assert_eq!(emu.registers[4], 0x8000 - 6);
+        assert_eq!(emu.get_memory16(0x7FFA), 0x102);
+        assert_eq!(emu.get_memory16(0x7FFC), 0);
+
+        emu.execute().unwrap();
+        emu.execute().unwrap();
+        assert_eq!(emu.get_segment(CS).selector, 0);
+        assert_eq!(emu.eip, 0x102);
+        assert_eq!(emu.registers[0] & 0xFF, 0x55);
+        assert_eq!(emu.registers[4], 0x8000);
+        assert!(emu.eflags.is_interrupt());
+    }
+
+    #[test]
+    fn interrupt_divide_error_restarts() {
+        // div bl with BL = 0; the #DE handler sets BL = 1 and returns to the DIV
+        let mut emu = real_mode(0x10000, &[0xF6, 0xF3], None);
+        emu.set_memory32(0, 0x0000_0600);
+        emu.memory[0x600 .. 0x603].copy_from_slice(&[0xB3, 0x01, 0xCF]);
+        emu.registers[0] = 10;
+
+        emu.execute().unwrap();
+        assert_eq!(emu.exception, None);
+        assert_eq!(emu.eip, 0x600);
+        assert_eq!(emu.get_memory16(0x7FFA), 0x100);
+
+        for _ in 0..3 {
+            emu.execute().unwrap();
+        }
+        assert_eq!(emu.eip, 0x102);
+        assert_eq!(emu.registers[0] & 0xFFFF, 10);
+    }
+
+    #[test]
+    fn interrupt_int3_into() {
+        // into; int3
+        let mut emu = real_mode(0x10000, &[0xCE, 0xCC], None);
+        emu.set_memory32(3 * 4, 0x0000_0600);
+        emu.set_memory32(4 * 4, 0x0000_0700);
+
+        emu.execute().unwrap();
+        assert_eq!(emu.eip, 0x101);
+        emu.execute().unwrap();
+        assert_eq!(emu.eip, 0x600);
+        assert_eq!(emu.get_memory16(0x7FFA), 0x102);
+
+        emu.eip = 0x100;
+        emu.eflags.set_overflow(true);
+        emu.execute().unwrap();
+        assert_eq!(emu.eip, 0x700);
+    }
+
+    #[test]
+    fn interrupt_bios_entry() {
+        // int 0x99, which the BIOS doesn't serve, returns to the caller. The IVT covers 0x100.
+        let mut emu = real_mode(0x10000, &[], None);
+        emu.install_ivt();
+        emu.memory[0x500 .. 0x502].copy_from_slice(&[0xCD, 0x99]);
+        emu.eip = 0x500;
+        assert_eq!(emu.get_memory32(0x99 * 4), ((BIOS_SEGMENT as u32) << 16) | 0x99);
+
+        emu.execute().unwrap();
+        assert_eq!(emu.get_segment(CS).selector, BIOS_SEGMENT);
+        assert_eq!(emu.eip, 0x99);
+        assert_eq!(emu.execute(), Ok("bios"));
+        assert_eq!(emu.get_segment(CS).selector, 0);
+        assert_eq!(emu.eip, 0x502);
+        assert_eq!(emu.registers[4], 0x8000);
+    }
+
+    #[test]
+    fn interrupt_exception_frame() {
+        // mov eax, cr5
+        let mut emu = protected_mode(&[0x0F, 0x20, 0xE8]);
+        set_gate(&mut emu, 0x06, 0x08, 0x2000, GATE_INTERRUPT32, 0);
+        emu.eflags.set_interrupt(true);
+
+        emu.execute().unwrap();
+        assert_eq!(emu.exception, None);
+        assert_eq!(emu.eip, 0x2000);
+        assert!(!emu.eflags.is_interrupt());
+        // no error code for #UD
+        assert_eq!(emu.registers[4], 0x8000 - 12);
+        assert_eq!(emu.get_memory32(0x8000 - 12), 0x1000);
+        assert_eq!(emu.get_memory32(0x8000 - 8), 0x08);
+
+        // mov ds, [0x3000] with a selector outside the GDT, through a trap gate
+        let mut emu = protected_mode(&[0x8E, 0x1D, 0x00, 0x30, 0x00, 0x00]);
+        set_gate(&mut emu, 0x0D, 0x08, 0x2000, GATE_TRAP32, 0);
+        emu.set_memory16(0x3000, 0x40);
+        emu.eflags.set_interrupt(true);
+
+        emu.execute().unwrap();
+        assert_eq!(emu.eip, 0x2000);
+        assert!(emu.eflags.is_interrupt());
+        assert_eq!(emu.get_memory32(0x8000 - 16), 0x40);
+        assert_eq!(emu.get_memory32(0x8000 - 12), 0x1000);
+        assert_eq!(emu.get_segment(DS).selector, 0x10);
+    }
+
+    #[test]
+    fn interrupt_privilege_change() {
+        // int 0x80 from ring 3; the handler is iret
+        let mut emu = protected_mode(&[0xCD, 0x80]);
+        set_gate(&mut emu, 0x80, 0x08, 0x2000, GATE_TRAP32, 3);
+        emu.memory[0x2000] = 0xCF;
+        enter_ring3(&mut emu);
+
+        emu.execute().unwrap();
+        assert_eq!(emu.exception, None);
+        assert_eq!(emu.cpl(), 0);
+        assert_eq!(emu.get_segment(SS).selector, 0x10);
+        assert_eq!(emu.registers[4], 0x7000 - 20);
+        assert_eq!(emu.get_memory32(0x7000 - 20), 0x1002);
+        assert_eq!(emu.get_memory32(0x7000 - 16), 0x1B);
+        assert_eq!(emu.get_memory32(0x7000 - 8), 0x8000);
+        assert_eq!(emu.get_memory32(0x7000 - 4), 0x23);
+
+        emu.execute().unwrap();
+        assert_eq!(emu.exception, None);
+        assert_eq!(emu.cpl(), 3);
+        assert_eq!(emu.get_segment(SS).selector, 0x23);
+        assert_eq!(emu.registers[4], 0x8000);
+        assert_eq!(emu.eip, 0x1002);
+    }
+
+    #[test]
+    fn interrupt_gate_dpl() {
+        // int 0x81 from ring 3 through a DPL 0 gate raises #GP(0x81 * 8 + 2)
+        let mut emu = protected_mode(&[0xCD, 0x81]);
+        set_gate(&mut emu, 0x81, 0x08, 0x2000, GATE_INTERRUPT32, 0);
+        set_gate(&mut emu, 0x0D, 0x08, 0x2100, GATE_INTERRUPT32, 0);
+        enter_ring3(&mut emu);
+
+        emu.execute().unwrap();
+        assert_eq!(emu.eip, 0x2100);
+        assert_eq!(emu.cpl(), 0);
+        assert_eq!(emu.get_memory32(0x7000 - 24), 0x81 * 8 + 2);
+        assert_eq!(emu.get_memory32(0x7000 - 20), 0x1000);
+    }
+
+    #[test]
+    fn interrupt_task_gate() {
+        // int 0x40 through a task gate to the TSS at 0x3100, whose task does iret
+        let mut emu = protected_mode(&[0xCD, 0x40]);
+        set_gate(&mut emu, 0x40, 0x30, 0, GATE_TASK, 0);
+        emu.load_task_register(0x28);
+        emu.set_memory32(0x3100 + 0x20, 0x2200);        // EIP
+        emu.set_memory32(0x3100 + 0x24, 0x2);           // EFLAGS
+        emu.set_memory32(0x3100 + 0x38, 0x6000);        // ESP
+        emu.set_memory32(0x3100 + 0x4C, 0x08);          // CS
+        for sreg in [0x48, 0x50, 0x54, 0x58, 0x5C].iter() {
+            emu.set_memory32(0x3100 + sreg, 0x10);
+        }
+        emu.memory[0x2200] = 0xCF;
+        emu.registers[3] = 0x1234;
+
+        emu.execute().unwrap();
+        assert_eq!(emu.exception, None);
+        assert_eq!(emu.tr.selector, 0x30);
+        assert_eq!(emu.eip, 0x2200);
+        assert_eq!(emu.registers[4], 0x6000);
+        assert_eq!(emu.registers[3], 0);
+        assert!(emu.eflags.is_nested_task());
+        assert_eq!(emu.get_memory16(0x3100), 0x28);
+        assert_eq!(emu.get_memory32(0x3000 + 0x20), 0x1002);
+        assert_eq!(emu.get_memory32(0x3000 + 0x34), 0x1234);
+        assert_eq!(emu.memory[0x100 + 0x28 + 5], 0x8B);
+        assert_eq!(emu.memory[0x100 + 0x30 + 5], 0x8B);
+
+        emu.execute().unwrap();
+        assert_eq!(emu.exception, None);
+        assert_eq!(emu.tr.selector, 0x28);
+        assert_eq!(emu.eip, 0x1002);
+        assert_eq!(emu.registers[3], 0x1234);
+        assert_eq!(emu.registers[4], 0x8000);
+        assert!(!emu.eflags.is_nested_task());
+        assert_eq!(emu.memory[0x100 + 0x30 + 5], 0x89);
+    }
+
+    #[test]
+    fn interrupt_double_fault() {
+        // ud2 with neither a #UD nor a #NP handler present
+        let mut emu = protected_mode(&[0x0F, 0x0B]);
+        set_gate(&mut emu, 0x08, 0x08, 0x2300, GATE_INTERRUPT32, 0);
+
+        emu.execute().unwrap();
+        assert_eq!(emu.shutdown, None);
+        assert_eq!(emu.eip, 0x2300);
+        assert_eq!(emu.get_memory32(0x8000 - 16), 0);
+        assert_eq!(emu.get_memory32(0x8000 - 12), 0x1000);
+    }
+
+    #[test]
+    fn interrupt_triple_fault() {
+        // mov eax, 1; div ebx without an IDT
+        let mut emu = protected_mode(&[0xB8, 0x01, 0x00, 0x00, 0x00, 0xF7, 0xF3]);
+        emu.idtr = DescriptorTable::default();
+
+        emu.execute().unwrap();
+        emu.execute().unwrap();
+        assert_eq!(emu.shutdown, Some(DivideError));
+        assert_eq!(emu.eip, 0x1005);
+        assert_eq!(emu.registers[0], 1);
+        assert_eq!(emu.registers[4], 0x8000);
+    }
+
+    #[test]
+    fn interrupt_invalid_opcode() {
+        for code in [
+            vec![0xFF, 0xF8],       // FF /7
+            vec![0x8E, 0xC8],       // mov cs, ax
+            vec![0x8C, 0xF0],       // mov ax, sreg 6
+            vec![0x8D, 0xC0],       // lea eax, eax
+            vec![0x0F, 0x0B],       // ud2
+            vec![0x0F, 0x01, 0xE8], // 0F 01 /5
+        ].iter() {
+            let mut emu = protected_mode(code);
+            emu.step().unwrap();
+            assert_eq!(emu.exception, Some(InvalidOpcode), "{:02X?}", code);
+        }
+    }
+}
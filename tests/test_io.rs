@@ -0,0 +1,122 @@
+extern crate aria;
+
+#[cfg(test)]
+mod io {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use aria::emulator::{
+        *,
+        io::*,
+    };
+
+    /// Records every write, and answers reads with the low byte of the port plus a counter.
+    #[derive(Default)]
+    struct Mock {
+        writes: Vec<(u16, u32, u32)>,
+        reads: u8,
+    }
+
+    impl PortDevice for Mock {
+        fn in8(&mut self, port: u16) -> u8 {
+            self.reads += 1;
+            (port as u8).wrapping_add(self.reads)
+        }
+
+        fn out8(&mut self, port: u16, value: u8) {
+            self.writes.push((port, value as u32, 1));
+        }
+
+        fn out32(&mut self, port: u16, value: u32) {
+            self.writes.push((port, value, 4));
+        }
+    }
+
+    fn emulator(code: &[u8]) -> (Emulator, Rc<RefCell<Mock>>) {
+        let mut emu = Emulator {
+            memory: vec![0; 0x1000],
+            ..Default::default()
+        };
+        emu.memory[.. code.len()].copy_from_slice(code);
+        let mock = Rc::new(RefCell::new(Mock::default()));
+        emu.io.register(0x300 ..= 0x30F, mock.clone());
+        (emu, mock)
+    }
+
+    fn run(emu: &mut Emulator, steps: usize) {
+        for _ in 0..steps {
+            emu.execute().unwrap();
+        }
+    }
+
+    #[test]
+    fn io_in_out() {
+        // out dx, al; out dx, eax; out 0x42, al (PIT); in al, dx; in eax, dx; out dx, ax
+        let (mut emu, mock) = emulator(&[0xEE, 0xEF, 0xE6, 0x42, 0xEC, 0xED, 0x66, 0xEF]);
+        emu.registers[0] = 0x12345678;
+        emu.registers[2] = 0x301;
+        run(&mut emu, 2);
+        assert_eq!(mock.borrow().writes, [(0x301, 0x78, 1), (0x301, 0x12345678, 4)]);
+
+        run(&mut emu, 1);
+        assert_eq!(mock.borrow().writes.len(), 2);
+
+        run(&mut emu, 2);
+        assert_eq!(emu.registers[0], 0x09070503);
+
+        // a word the device doesn't handle whole is split into bytes
+        run(&mut emu, 1);
+        assert_eq!(mock.borrow().writes[2 ..], [(0x301, 0x03, 1), (0x302, 0x05, 1)]);
+        assert_eq!(emu.eip, 8);
+    }
+
+    #[test]
+    fn io_open_bus() {
+        // in al, 0x80; in eax, 0x80
+        let (mut emu, _) = emulator(&[0xE4, 0x80, 0xE5, 0x80]);
+        run(&mut emu, 2);
+        assert_eq!(emu.registers[0], 0xFFFFFFFF);
+    }
+
+    #[test]
+    fn io_string() {
+        // rep outsb; std; rep insw
+        let (mut emu, mock) = emulator(&[0xF3, 0x6E, 0xFD, 0x66, 0xF3, 0x6D]);
+        emu.memory[0x100 .. 0x103].copy_from_slice(b"abc");
+        emu.registers[1] = 3;
+        emu.registers[2] = 0x300;
+        emu.registers[6] = 0x100;
+        emu.registers[7] = 0x202;
+        run(&mut emu, 3);
+        let bytes: Vec<u32> = mock.borrow().writes.iter().map(|write| write.1).collect();
+        assert_eq!(bytes, [0x61, 0x62, 0x63]);
+        assert_eq!(emu.registers[6], 0x103);
+        assert_eq!(emu.registers[1], 0);
+
+        emu.registers[1] = 2;
+        run(&mut emu, 3);
+        assert_eq!(emu.eip, 6);
+        assert_eq!(emu.get_memory16(0x202), 0x0301);
+        assert_eq!(emu.get_memory16(0x200), 0x0503);
+        assert_eq!(emu.registers[7], 0x1FE);
+    }
+
+    #[test]
+    fn io_replace_device() {
+        let (mut emu, _) = emulator(&[]);
+        // a later device takes over the ports it overlaps
+        let console = Rc::new(RefCell::new(Mock::default()));
+        emu.io.register(0x3F8 ..= 0x3F8, console.clone());
+        emu.port_out8(0x3F8, b'!');
+        assert_eq!(console.borrow().writes, [(0x3F8, 0x21, 1)]);
+
+        let other = Rc::new(RefCell::new(Mock::default()));
+        emu.io.register(0x308 ..= 0x308, other.clone());
+        emu.port_in8(0x308);
+        emu.port_in8(0x307);
+        assert_eq!(other.borrow().reads, 1);
+
+        // the chipset is still there when nothing claims its ports
+        emu.port_out8(0x21, 0xFE);
+        assert_eq!(emu.port_in8(0x21), 0xFE);
+    }
+}
@@ -0,0 +1,190 @@
+extern crate aria;
+
+mod common;
+
+#[cfg(test)]
+mod keyboard {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use aria::emulator::{
+        *,
+        keyboard::*,
+        uart::{Buffer, FileBackend},
+    };
+    use crate::common::*;
+
+    fn emulator() -> Emulator {
+        let mut emu = with_bios(0x10000);
+        emu.registers[4] = 0x8000;
+        emu
+    }
+
+    /// Clock the keyboard until a scancode is in the output buffer, then let the BIOS take it.
+    fn deliver(emu: &mut Emulator, scancodes: &[u8]) {
+        emu.keyboard.type_scancodes(scancodes);
+        for _ in scancodes {
+            emu.keyboard.clock();
+            emu.bios_keyboard_irq();
+        }
+    }
+
+    /// INT 16h with AX and CX, returning AX and ZF.
+    fn int16(emu: &mut Emulator, ax: u16, cx: u16) -> (u16, bool) {
+        bios_call(emu, Emulator::bios_keyboard, &[ax as u32, cx as u32]);
+        (emu.get_register16(0), emu.eflags.is_zero())
+    }
+
+    #[test]
+    fn keyboard_scancodes() {
+        assert_eq!(scancodes(b'a'), [0x1E, 0x9E]);
+        assert_eq!(scancodes(b'A'), [0x2A, 0x1E, 0x9E, 0xAA]);
+        assert_eq!(scancodes(b'\n'), [0x1C, 0x9C]);
+        assert_eq!(scancodes(b'\r'), [0x1C, 0x9C]);
+        assert_eq!(scancodes(b'?'), [0x2A, 0x35, 0xB5, 0xAA]);
+        // ^C
+        assert_eq!(scancodes(0x03), [0x1D, 0x2E, 0xAE, 0x9D]);
+        assert!(scancodes(0xFF).is_empty());
+    }
+
+    #[test]
+    fn keyboard_controller() {
+        let mut keyboard = Keyboard::default();
+        assert_eq!(keyboard.read(0x64) & 0x01, 0);
+
+        keyboard.write(0x64, 0xAA);
+        assert_eq!(keyboard.read(0x64) & 0x0D, 0x0D);
+        assert_eq!(keyboard.read(0x60), 0x55);
+
+        keyboard.write(0x64, 0x20);
+        assert_eq!(keyboard.read(0x60), 0x45);
+        keyboard.write(0x64, 0x60);
+        keyboard.write(0x60, 0x44);
+        keyboard.write(0x64, 0x20);
+        assert_eq!(keyboard.read(0x60), 0x44);
+
+        // A20 is bit 1 of the output port
+        keyboard.write(0x64, 0xD1);
+        keyboard.write(0x60, 0xDD);
+        assert_eq!(keyboard.output_port, 0xDD);
+        keyboard.write(0x64, 0xD0);
+        assert_eq!(keyboard.read(0x60), 0xDD);
+
+        // the keyboard: reset, identify, echo, LEDs
+        keyboard.write(0x60, 0xFF);
+        assert_eq!(keyboard.read(0x60), 0xFA);
+        keyboard.clock();
+        assert_eq!(keyboard.read(0x60), 0xAA);
+        keyboard.write(0x60, 0xEE);
+        assert_eq!(keyboard.read(0x60), 0xEE);
+        keyboard.write(0x60, 0xED);
+        assert_eq!(keyboard.read(0x60), 0xFA);
+        keyboard.write(0x60, 0x07);
+        assert_eq!(keyboard.read(0x60), 0xFA);
+    }
+
+    #[test]
+    fn keyboard_scanning() {
+        let mut keyboard = Keyboard::default();
+        keyboard.type_character(b'k');
+        keyboard.clock();
+        assert!(keyboard.interrupt());
+        assert_eq!(keyboard.read(0x60), 0x25);
+        assert!(!keyboard.interrupt());
+        keyboard.clock();
+        assert_eq!(keyboard.read(0x60), 0xA5);
+
+        // nothing while the keyboard is disabled, or without IRQ 1 in the command byte
+        keyboard.type_character(b'k');
+        keyboard.write(0x64, 0xAD);
+        keyboard.clock();
+        assert_eq!(keyboard.read(0x64) & 0x01, 0);
+        keyboard.write(0x64, 0xAE);
+        keyboard.write(0x64, 0x60);
+        keyboard.write(0x60, 0x44);
+        keyboard.clock();
+        assert_eq!(keyboard.read(0x64) & 0x01, 0x01);
+        assert!(!keyboard.interrupt());
+    }
+
+    #[test]
+    fn keyboard_int16_wait() {
+        let mut emu = emulator();
+        emu.eip = 0x500;
+        emu.registers[3] = 0x700;
+        // mov ah, 0; int 0x16; mov [bx], ax; inc bx; inc bx; jmp -10
+        emu.memory[0x500 .. 0x50A].copy_from_slice(&[0xB4, 0x00, 0xCD, 0x16, 0x89, 0x07, 0x43, 0x43, 0xEB, 0xF6]);
+
+        let buffer = Rc::new(RefCell::new(Buffer::default()));
+        buffer.borrow_mut().input.extend(b"Hi\x1b[A");
+        emu.keyboard.attach(buffer.clone());
+        for _ in 0..20000 {
+            emu.execute().unwrap();
+        }
+        assert_eq!(emu.get_memory16(0x700), 0x2348);
+        assert_eq!(emu.get_memory16(0x702), 0x1769);
+        // the up arrow, the gray one
+        assert_eq!(emu.get_memory16(0x704), 0x4800);
+        assert_eq!(emu.registers[3], 0x706);
+        // waiting in the BIOS for the next one
+        assert!(emu.halted);
+        assert!(emu.can_wake());
+    }
+
+    #[test]
+    fn keyboard_file_runs_out() {
+        let path = std::env::temp_dir().join(format!("aria-keyboard-{}", std::process::id()));
+        std::fs::write(&path, b"Hi").unwrap();
+        let file = std::fs::File::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut emu = emulator();
+        emu.eip = 0x500;
+        emu.registers[3] = 0x700;
+        // mov ah, 0; int 0x16; mov [bx], ax; inc bx; inc bx; jmp -10
+        emu.memory[0x500 .. 0x50A].copy_from_slice(&[0xB4, 0x00, 0xCD, 0x16, 0x89, 0x07, 0x43, 0x43, 0xEB, 0xF6]);
+        emu.keyboard.attach(Rc::new(RefCell::new(FileBackend::new(Some(file), None))));
+        // only the keyboard can wake it
+        emu.pic.master.imr |= 0x01;
+
+        for _ in 0..20000 {
+            emu.execute().unwrap();
+        }
+        assert_eq!(emu.get_memory16(0x700), 0x2348);
+        assert_eq!(emu.get_memory16(0x702), 0x1769);
+        assert!(emu.halted);
+        assert!(!emu.can_wake());
+    }
+
+    #[test]
+    fn keyboard_int16_shift_state() {
+        let mut emu = emulator();
+        assert!(int16(&mut emu, 0x0100, 0).1);
+
+        // shift held, then caps lock: shift undoes it for letters only
+        deliver(&mut emu, &[0x2A, 0x1E, 0x9E, 0x02, 0x82]);
+        assert_eq!(int16(&mut emu, 0x0200, 0).0 & 0xFF, 0x02);
+        deliver(&mut emu, &[0xAA, 0x3A, 0xBA, 0x1E, 0x9E]);
+        assert_eq!(int16(&mut emu, 0x0200, 0).0 & 0xFF, 0x40);
+        deliver(&mut emu, &[0x2A, 0x1E, 0x9E, 0xAA]);
+        // ctrl-c, F1, and the gray left arrow, which AH=10 reports as such
+        deliver(&mut emu, &[0x1D, 0x2E, 0xAE, 0x9D, 0x3B, 0xBB, 0xE0, 0x4B, 0xE0, 0xCB]);
+
+        assert_eq!(int16(&mut emu, 0x0100, 0), (0x1E41, false));
+        assert_eq!(int16(&mut emu, 0x0000, 0).0, 0x1E41);
+        assert_eq!(int16(&mut emu, 0x0000, 0).0, 0x0221);
+        assert_eq!(int16(&mut emu, 0x0000, 0).0, 0x1E41);
+        assert_eq!(int16(&mut emu, 0x0000, 0).0, 0x1E61);
+        assert_eq!(int16(&mut emu, 0x0000, 0).0, 0x2E03);
+        assert_eq!(int16(&mut emu, 0x0000, 0).0, 0x3B00);
+        assert_eq!(int16(&mut emu, 0x1000, 0).0, 0x4BE0);
+        assert!(int16(&mut emu, 0x1100, 0).1);
+
+        // a key stuffed into the buffer, until it's full
+        assert_eq!(int16(&mut emu, 0x0500, 0x1C0D).0 & 0xFF, 0);
+        assert_eq!(int16(&mut emu, 0x1000, 0).0, 0x1C0D);
+        for _ in 0..15 {
+            assert_eq!(int16(&mut emu, 0x0500, 0x3920).0 & 0xFF, 0);
+        }
+        assert_eq!(int16(&mut emu, 0x0500, 0x3920).0 & 0xFF, 1);
+    }
+}
@@ -0,0 +1,200 @@
+extern crate aria;
+
+#[cfg(test)]
+mod linux {
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+    use aria::emulator::{
+        *,
+        elf::*,
+        linux::*,
+    };
+
+    const CODE: u32 = 0x200000;
+
+    /// A program with `code` at 0x200000, started as a Linux process.
+    fn process(code: &[u8], args: &[&str], env: &[&str]) -> Emulator {
+        let elf = Elf {
+            entry: CODE,
+            segments: vec![LoadSegment { address: CODE, data: code.to_vec(), size: 0x1000 }],
+            symbols: Symbols::default(),
+            program_headers: 0,
+            program_header_count: 0,
+        };
+        let mut emu = Emulator {
+            memory: vec![0; 0x201000],
+            ..Default::default()
+        };
+        emu.memory[CODE as usize .. CODE as usize + code.len()].copy_from_slice(code);
+        let strings = |strings: &[&str]| strings.iter().map(|s| s.to_string()).collect::<Vec<String>>();
+        emu.start_linux(&elf, &strings(args), &strings(env)).unwrap();
+        emu
+    }
+
+    fn syscall(emu: &mut Emulator, number: u32, args: &[u32]) -> u32 {
+        emu.registers[0] = number;
+        for (register, arg) in [3, 1, 2, 6, 7, 5].iter().zip(args) {
+            emu.registers[*register] = *arg;
+        }
+        emu.linux_syscall();
+        emu.registers[0]
+    }
+
+    fn string(emu: &Emulator, address: u32) -> String {
+        let bytes = &emu.memory[address as usize ..];
+        String::from_utf8(bytes[.. bytes.iter().position(|b| *b == 0).unwrap()].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn linux_initial_stack() {
+        let emu = process(&[], &["prog", "-x"], &["HOME=/"]);
+        let sp = emu.registers[4];
+        let word = |i: u32| emu.read_physical32(sp + i * 4);
+        assert_eq!(sp % 16, 0);
+        assert_eq!(emu.get_segment(SegmentRegister::CS).selector, USER_CODE);
+        assert_eq!(emu.get_segment(SegmentRegister::SS).selector, USER_DATA);
+        assert_eq!(emu.cpl(), 3);
+        assert_eq!(emu.eip, CODE);
+
+        assert_eq!(word(0), 2);
+        assert_eq!(string(&emu, word(1)), "prog");
+        assert_eq!(string(&emu, word(2)), "-x");
+        assert_eq!(word(3), 0);
+        assert_eq!(string(&emu, word(4)), "HOME=/");
+        assert_eq!(word(5), 0);
+
+        let auxv: Vec<(u32, u32)> = (0..).map(|i| (word(6 + i * 2), word(7 + i * 2))).take_while(|(kind, _)| *kind != 0).collect();
+        assert!(auxv.contains(&(6, 0x1000)));
+        assert!(auxv.contains(&(9, CODE)));
+        let platform = auxv.iter().find(|(kind, _)| *kind == 15).unwrap().1;
+        assert_eq!(string(&emu, platform), "i686");
+    }
+
+    #[test]
+    fn linux_write_and_exit() {
+        // mov eax, 4; mov ebx, 1; mov ecx, message; mov edx, 5; int 0x80; mov ebx, eax; mov eax, 1; int 0x80
+        let mut code = vec![
+            0xB8, 0x04, 0x00, 0x00, 0x00, 0xBB, 0x01, 0x00, 0x00, 0x00, 0xB9, 0x00, 0x01, 0x20, 0x00,
+            0xBA, 0x05, 0x00, 0x00, 0x00, 0xCD, 0x80, 0x89, 0xC3, 0xB8, 0x01, 0x00, 0x00, 0x00, 0xCD, 0x80,
+        ];
+        code.resize(0x100, 0);
+        code.extend(b"hello");
+        let mut emu = process(&code, &["hello"], &[]);
+        let output = Rc::new(RefCell::new(Cursor::new(vec![])));
+        emu.linux.as_mut().unwrap().set_file(1, output.clone());
+
+        while !emu.halted {
+            emu.execute().unwrap();
+        }
+        assert_eq!(output.borrow().get_ref(), b"hello");
+        assert_eq!(emu.linux.as_ref().unwrap().exit_status, Some(5));
+        assert!(!emu.can_wake());
+    }
+
+    #[test]
+    fn linux_memory() {
+        let mut emu = process(&[], &["memory"], &[]);
+        let start = syscall(&mut emu, 45, &[0]);
+        assert_eq!(start, 0x201000);
+        assert_eq!(syscall(&mut emu, 45, &[start + 0x2000]), start + 0x2000);
+        assert_eq!(syscall(&mut emu, 45, &[start - 1]), start + 0x2000);
+
+        // mmap2(0, 0x1800, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0)
+        let first = syscall(&mut emu, 192, &[0, 0x1800, 3, 0x22, u32::MAX, 0]);
+        let second = syscall(&mut emu, 192, &[0, 0x1000, 3, 0x22, u32::MAX, 0]);
+        assert_eq!(first % 0x1000, 0);
+        assert_eq!(second, first - 0x1000);
+        assert_eq!(syscall(&mut emu, 91, &[second, 0x1000]), 0);
+        assert_eq!(syscall(&mut emu, 192, &[0, 0x1000, 3, 0x22, u32::MAX, 0]), second);
+        // a file mapping
+        assert_eq!(syscall(&mut emu, 192, &[0, 0x1000, 1, 0x02, 3, 0]) as i32, -19);
+
+        syscall(&mut emu, 122, &[start]);
+        assert_eq!(string(&emu, start), "Linux");
+        assert_eq!(string(&emu, start + 65 * 4), "i686");
+    }
+
+    #[test]
+    fn linux_errors() {
+        let mut emu = process(&[], &["errors"], &[]);
+        assert_eq!(syscall(&mut emu, 6, &[9]) as i32, -9);
+        assert_eq!(syscall(&mut emu, 4, &[1, 0xFFFF_FF00, 0x200]) as i32, -14);
+        assert_eq!(syscall(&mut emu, 5, &[CODE, 0, 0]) as i32, -2);
+        assert_eq!(syscall(&mut emu, 0x3FF, &[]) as i32, -38);
+        assert_eq!(syscall(&mut emu, 20, &[]), PID);
+    }
+
+    /// Sizes and pointers that overflow are errors, not a crash.
+    #[test]
+    fn linux_hostile_arguments() {
+        let mut emu = process(&[], &["hostile"], &[]);
+        let errno = |emu: &mut Emulator, number: u32, args: &[u32]| -(syscall(emu, number, args) as i32);
+        assert_eq!(errno(&mut emu, 192, &[0, u32::MAX, 3, 0x22, u32::MAX, 0]), 12);
+        assert_eq!(errno(&mut emu, 192, &[0xFFFFF000, 0x2000, 3, 0x32, u32::MAX, 0]), 12);
+        assert_eq!(errno(&mut emu, 91, &[0xFFFFF000, 0x2000]), 22);
+        assert_eq!(errno(&mut emu, 91, &[0, u32::MAX]), 22);
+        assert_eq!(errno(&mut emu, 146, &[1, 0xFFFFFFF8, 0x20000000]), 22);
+        assert_eq!(errno(&mut emu, 146, &[1, 0xFFFFFFFC, 1]), 14);
+        assert_eq!(errno(&mut emu, 5, &[u32::MAX, 0, 0]), 14);
+        assert_eq!(errno(&mut emu, 122, &[0xFFFFFF00]), 14);
+        assert_eq!(errno(&mut emu, 78, &[0xFFFFFFFC, 0]), 14);
+        assert_eq!(errno(&mut emu, 265, &[0, 0xFFFFFFFC]), 14);
+        assert_eq!(errno(&mut emu, 243, &[0xFFFFFFF8]), 14);
+
+        let elf = Elf {
+            entry: 0xFFFFF000,
+            segments: vec![LoadSegment { address: 0xFFFFF000, data: vec![], size: 0x1000 }],
+            symbols: Symbols::default(),
+            program_headers: 0,
+            program_header_count: 0,
+        };
+        assert_eq!(emu.start_linux(&elf, &[], &[]), Err(StartError::NoRoom));
+        let elf = Elf { entry: CODE, segments: vec![], ..elf };
+        let long = vec!["x".repeat(1 << 20)];
+        assert_eq!(emu.start_linux(&elf, &long, &[]), Err(StartError::ArgumentsTooLong));
+    }
+
+    #[test]
+    fn linux_thread_area() {
+        // mov ax, 0x33; mov gs, ax; mov ebx, gs:[0]; mov eax, 1; int 0x80
+        let code = [
+            0x66, 0xB8, 0x33, 0x00, 0x8E, 0xE8, 0x65, 0x8B, 0x1D, 0x00, 0x00, 0x00, 0x00,
+            0xB8, 0x01, 0x00, 0x00, 0x00, 0xCD, 0x80,
+        ];
+        let mut emu = process(&code, &["tls"], &[]);
+        emu.write_physical8(0x300000, 42);
+
+        // struct user_desc: entry -1, base, limit 0xFFFFF in pages, 32-bit
+        let info = 0x201000;
+        for (i, word) in [u32::MAX, 0x300000, 0xFFFFF, 0x51].iter().enumerate() {
+            emu.write_physical32(info + i as u32 * 4, *word);
+        }
+        assert_eq!(syscall(&mut emu, 243, &[info]), 0);
+        assert_eq!(emu.read_physical32(info), 6);
+
+        while !emu.halted {
+            emu.execute().unwrap();
+        }
+        assert_eq!(emu.linux.as_ref().unwrap().exit_status, Some(42));
+    }
+
+    /// A program that dies instead of exiting gets a status that says so, like a killed process.
+    #[test]
+    fn linux_killed() {
+        let run = |code: &[u8], limit: Option<u64>| {
+            let emu = process(code, &["killed"], &[]);
+            emu.run(RunFlags { verbose: false, with_name: false, quiet: true, limit, display: false })
+        };
+        // ud2
+        assert_eq!(run(&[0x0F, 0x0B], None), Some(128 + SIGILL));
+        // mov [0xFFFFFFF0], eax, past the end of memory
+        assert_eq!(run(&[0xA3, 0xF0, 0xFF, 0xFF, 0xFF], None), Some(128 + SIGSEGV));
+        // xor ecx, ecx; div ecx
+        assert_eq!(run(&[0x31, 0xC9, 0xF7, 0xF1], None), Some(128 + SIGFPE));
+        // jmp $
+        assert_eq!(run(&[0xEB, 0xFE], Some(100)), Some(128 + SIGXCPU));
+        // mov eax, 1; mov ebx, 3; int 0x80
+        assert_eq!(run(&[0xB8, 0x01, 0x00, 0x00, 0x00, 0xBB, 0x03, 0x00, 0x00, 0x00, 0xCD, 0x80], None), Some(3));
+    }
+}
@@ -0,0 +1,183 @@
+extern crate aria;
+
+#[cfg(test)]
+mod mmu {
+    use aria::emulator::{
+        *,
+        exception::Exception::*,
+        mmu::*,
+        SegmentRegister::*,
+    };
+
+    const DIRECTORY: u32 = 0x1000;
+    const TABLE0: u32 = 0x2000;     // 0x00000000 - 0x003FFFFF
+    const TABLE1: u32 = 0x3000;     // 0x00400000 - 0x007FFFFF
+
+    /*
+     * The first 16 pages are identity mapped for the supervisor,
+     * 0x400000 is a user page at 0x5000 and 0x401000 a read-only user page at 0x6000.
+     */
+    fn paging() -> Emulator {
+        let mut emu = Emulator {
+            memory: vec![0; 0x10000],
+            cr: [CR0_PE | CR0_PG, 0, 0, DIRECTORY, 0],
+            ..Default::default()
+        };
+        let rw = PAGE_PRESENT | PAGE_WRITABLE;
+        emu.write_physical32(DIRECTORY, TABLE0 | rw);
+        emu.write_physical32(DIRECTORY + 4, TABLE1 | rw | PAGE_USER);
+        for i in 0..16 {
+            emu.write_physical32(TABLE0 + i * 4, (i << 12) | rw);
+        }
+        emu.write_physical32(TABLE1, 0x5000 | rw | PAGE_USER);
+        emu.write_physical32(TABLE1 + 4, 0x6000 | PAGE_PRESENT | PAGE_USER);
+        emu
+    }
+
+    fn enter_user_mode(emu: &mut Emulator) {
+        emu.segments[CS as usize].selector = 0x1B;
+    }
+
+    #[test]
+    fn mmu_translate_4k() {
+        let mut emu = paging();
+
+        emu.set_memory32(0x400010, 0x12345678);
+        assert_eq!(emu.read_physical32(0x5010), 0x12345678);
+        assert_eq!(emu.get_memory32(0x400010), 0x12345678);
+        assert_eq!(emu.exception, None);
+
+        let pde = emu.read_physical32(DIRECTORY + 4);
+        let pte = emu.read_physical32(TABLE1);
+        assert_ne!(pde & PAGE_ACCESSED, 0);
+        assert_ne!(pte & PAGE_ACCESSED, 0);
+        assert_ne!(pte & PAGE_DIRTY, 0);
+
+        // a read only sets A
+        emu.get_memory8(0x401000);
+        let pte = emu.read_physical32(TABLE1 + 4);
+        assert_ne!(pte & PAGE_ACCESSED, 0);
+        assert_eq!(pte & PAGE_DIRTY, 0);
+    }
+
+    #[test]
+    fn mmu_not_present() {
+        let mut emu = paging();
+
+        assert_eq!(emu.get_memory8(0x800123), 0);
+        assert_eq!(emu.exception, Some(PageFault(0)));
+        assert_eq!(emu.cr[2], 0x800123);
+
+        emu.exception = None;
+        emu.set_memory8(0x402000, 1);
+        assert_eq!(emu.exception, Some(PageFault(PF_WRITE)));
+        assert_eq!(emu.cr[2], 0x402000);
+    }
+
+    #[test]
+    fn mmu_protection() {
+        let mut emu = paging();
+
+        // the supervisor may write a read-only page unless CR0.WP is set
+        emu.set_memory8(0x401000, 1);
+        assert_eq!(emu.exception, None);
+        emu.cr[0] |= CR0_WP;
+        emu.flush_tlb();
+        emu.set_memory8(0x401001, 1);
+        assert_eq!(emu.exception, Some(PageFault(PF_PROTECTION | PF_WRITE)));
+
+        emu.exception = None;
+        enter_user_mode(&mut emu);
+        assert_eq!(emu.get_memory8(0x401000), 1);
+        emu.set_memory8(0x401000, 2);
+        assert_eq!(emu.exception, Some(PageFault(PF_PROTECTION | PF_WRITE | PF_USER)));
+        assert_eq!(emu.read_physical8(0x6000), 1);
+
+        emu.exception = None;
+        emu.get_memory8(0x100);
+        assert_eq!(emu.exception, Some(PageFault(PF_PROTECTION | PF_USER)));
+        assert_eq!(emu.cr[2], 0x100);
+    }
+
+    #[test]
+    fn mmu_large_page() {
+        let mut emu = paging();
+        emu.cr[4] |= CR4_PSE;
+        // 0x80000000 - 0x803FFFFF is physical 0x00000000
+        emu.write_physical32(DIRECTORY + 0x200 * 4, PAGE_PRESENT | PAGE_WRITABLE | PAGE_SIZE);
+
+        emu.set_memory8(0x80001234, 0x42);
+        assert_eq!(emu.exception, None);
+        assert_eq!(emu.read_physical8(0x1234), 0x42);
+        let pde = emu.read_physical32(DIRECTORY + 0x200 * 4);
+        assert_ne!(pde & PAGE_DIRTY, 0);
+
+        // without PSE the same entry points at a page table at 0, whose entry 1 is empty
+        emu.cr[4] = 0;
+        emu.flush_tlb();
+        assert_eq!(emu.peek_translate(0x80001234), None);
+        emu.get_memory8(0x80001234);
+        assert_eq!(emu.exception, Some(PageFault(0)));
+    }
+
+    #[test]
+    fn mmu_tlb() {
+        // invlpg [0x400000]
+        let mut emu = paging();
+        emu.memory[0x100 .. 0x107].copy_from_slice(&[0x0F, 0x01, 0x3D, 0x00, 0x00, 0x40, 0x00]);
+        emu.eip = 0x100;
+        emu.write_physical32(0x5000, 0x11111111);
+        emu.write_physical32(0x7000, 0x22222222);
+
+        assert_eq!(emu.get_memory32(0x400000), 0x11111111);
+        assert!(!emu.tlb.is_empty());
+
+        // the cached translation survives a change of the page table
+        emu.write_physical32(TABLE1, 0x7000 | PAGE_PRESENT | PAGE_WRITABLE | PAGE_USER);
+        assert_eq!(emu.get_memory32(0x400000), 0x11111111);
+
+        emu.step().unwrap();
+        assert_eq!(emu.get_memory32(0x400000), 0x22222222);
+
+        // and so does loading CR3: mov cr3, eax
+        emu.write_physical32(TABLE1, 0x5000 | PAGE_PRESENT | PAGE_WRITABLE | PAGE_USER);
+        assert_eq!(emu.get_memory32(0x400000), 0x22222222);
+        emu.memory[0x107 .. 0x10A].copy_from_slice(&[0x0F, 0x22, 0xD8]);
+        emu.registers[0] = DIRECTORY;
+        emu.step().unwrap();
+        assert!(emu.tlb.is_empty());
+        assert_eq!(emu.get_memory32(0x400000), 0x11111111);
+    }
+
+    #[test]
+    fn mmu_code_fetch() {
+        // mov eax, [0x400100] at linear 0x400000
+        let mut emu = paging();
+        emu.memory[0x5000 .. 0x5005].copy_from_slice(&[0xA1, 0x00, 0x01, 0x40, 0x00]);
+        emu.write_physical32(0x5100, 0xCAFEBABE);
+        emu.eip = 0x400000;
+
+        assert_eq!(emu.peek_code8(), Some(0xA1));
+        emu.step().unwrap();
+        assert_eq!(emu.registers[0], 0xCAFEBABE);
+        assert_eq!(emu.eip, 0x400005);
+    }
+
+    #[test]
+    fn mmu_beyond_memory() {
+        let mut emu = Emulator {
+            memory: vec![0; 0x10000],
+            ..Default::default()
+        };
+        emu.set_a20(true);
+        emu.write_physical8(0x10000, 0x12);
+        assert_eq!(emu.read_physical8(0x10000), 0xFF);
+        assert_eq!(emu.read_physical32(0xFFFE), 0xFFFF0000);
+
+        // wraps around the top of the address space to the start of RAM
+        emu.set_memory32(0xFFFFFFFE, 0x12345678);
+        assert_eq!(emu.read_physical16(0), 0x1234);
+        assert_eq!(emu.get_memory32(0xFFFFFFFE), 0x1234FFFF);
+        assert_eq!(emu.get_memory16(0xFFFFFFFF), 0x34FF);
+    }
+}
@@ -16,6 +16,7 @@ mod modrm {
             eflags: Eflags{ raw: 0 },
             memory: Vec::new(),
             eip: 0,
+            ..Default::default()
         };
     
         let modrm = ModRM {
@@ -29,4 +30,103 @@ mod modrm {
         emu.set_rm32(&modrm, 10);
         assert_eq!(emu.get_rm32(&modrm), 10);
     }
+
+    #[test]
+    fn modrm_sib_base_index_scale() {
+        // [ebx + esi*4 + 0x10]
+        let mut emu = Emulator {
+            registers: [0, 0, 0, 0x100, 0, 0, 0x8, 0],
+            eflags: Eflags{ raw: 0 },
+            memory: vec![0b01000100, 0b10110011, 0x10],
+            eip: 0,
+            ..Default::default()
+        };
+
+        let modrm = emu.parse_modrm();
+        assert_eq!(emu.eip, 3);
+        assert_eq!(modrm.sib, 0b10110011);
+        assert_eq!(emu.calc_memory_address(&modrm), 0x130);
+    }
+
+    #[test]
+    fn modrm_sib_esp_negative_disp() {
+        // [esp - 4], no index
+        let mut emu = Emulator {
+            registers: [0, 0, 0, 0, 0x7C00, 0, 0, 0],
+            eflags: Eflags{ raw: 0 },
+            memory: vec![0b01000100, 0b00100100, 0xFC],
+            eip: 0,
+            ..Default::default()
+        };
+
+        let modrm = emu.parse_modrm();
+        assert_eq!(emu.calc_memory_address(&modrm), 0x7BFC);
+    }
+
+    #[test]
+    fn modrm_sib_no_base_disp32() {
+        // [ecx*8 + 0x1000]
+        let mut emu = Emulator {
+            registers: [0, 0x2, 0, 0, 0, 0, 0, 0],
+            eflags: Eflags{ raw: 0 },
+            memory: vec![0b00000100, 0b11001101, 0x00, 0x10, 0x00, 0x00],
+            eip: 0,
+            ..Default::default()
+        };
+
+        let modrm = emu.parse_modrm();
+        assert_eq!(emu.eip, 6);
+        assert_eq!(emu.calc_memory_address(&modrm), 0x1010);
+    }
+
+    #[test]
+    fn modrm_sib_disp32() {
+        // [ebp + eax*2 + 0x100]
+        let mut emu = Emulator {
+            registers: [0x10, 0, 0, 0, 0, 0x2000, 0, 0],
+            eflags: Eflags{ raw: 0 },
+            memory: vec![0b10000100, 0b01000101, 0x00, 0x01, 0x00, 0x00],
+            eip: 0,
+            ..Default::default()
+        };
+
+        let modrm = emu.parse_modrm();
+        assert_eq!(emu.calc_memory_address(&modrm), 0x2120);
+    }
+
+    #[test]
+    fn modrm16_disp16() {
+        // [0x1234], 16-bit addressing via 0x67
+        let mut emu = Emulator {
+            registers: [0, 0, 0, 0x100, 0, 0, 0, 0],
+            memory: vec![0b00000110, 0x34, 0x12],
+            eip: 0,
+            ..Default::default()
+        };
+        emu.decode.address_size = true;
+
+        let modrm = emu.parse_modrm();
+        assert_eq!(emu.eip, 3);
+        assert_eq!(modrm.disp.word(), 0x1234);
+        assert_eq!(emu.calc_memory_address(&modrm), 0x1234);
+    }
+
+    #[test]
+    fn modrm16_wraps_in_segment() {
+        // [bp + si + 0x10] in a real-mode SS, the offset wraps at 64KiB
+        let mut emu = Emulator {
+            memory: vec![0b01000010, 0x10],
+            eip: 0,
+            ..Default::default()
+        };
+        emu.enter_real_mode();
+        emu.load_segment(SegmentRegister::SS, 0x1000);
+        emu.registers[5] = 0xFFF0;
+        emu.registers[6] = 0x8;
+
+        let modrm = emu.parse_modrm();
+        assert_eq!(emu.eip, 2);
+        assert_eq!(emu.calc_effective_address(&modrm), 0x8);
+        assert_eq!(emu.calc_memory_address(&modrm), 0x10008);
+    }
 }
@@ -0,0 +1,201 @@
+extern crate aria;
+
+mod common;
+
+#[cfg(test)]
+mod pic {
+    use aria::emulator::{
+        pic::*,
+        SegmentRegister::*,
+    };
+    use crate::common::*;
+
+    /// The usual remap: IRQ 0-7 at 0x20, IRQ 8-15 at 0x28, cascade on IRQ 2, 8086 mode.
+    fn remap(pic: &mut Pic) {
+        for (port, value) in [
+            (0x20, 0x11), (0xA0, 0x11),
+            (0x21, 0x20), (0xA1, 0x28),
+            (0x21, 0x04), (0xA1, 0x02),
+            (0x21, 0x01), (0xA1, 0x01),
+        ].iter() {
+            pic.write(*port, *value);
+        }
+    }
+
+    fn eoi(pic: &mut Pic) {
+        pic.write(0x20, 0x20);
+    }
+
+    #[test]
+    fn pic_initialize() {
+        let mut pic = Pic::default();
+        remap(&mut pic);
+        assert_eq!(pic.master.vector_base, 0x20);
+        assert_eq!(pic.slave.vector_base, 0x28);
+        assert_eq!(pic.master.icw3, 0x04);
+
+        pic.write(0x21, 0xFB);
+        assert_eq!(pic.read(0x21), 0xFB);
+        assert_eq!(pic.read(0xA1), 0);
+
+        pic.set_irq(1, true);
+        assert_eq!(pic.read(0x20), 0x02);
+        assert!(!pic.has_interrupt());
+        pic.write(0x21, 0x00);
+        assert!(pic.has_interrupt());
+        assert_eq!(pic.acknowledge(), Some(0x21));
+        assert_eq!(pic.read(0x20), 0);
+
+        // OCW3: read ISR
+        pic.write(0x20, 0x0B);
+        assert_eq!(pic.read(0x20), 0x02);
+        eoi(&mut pic);
+        assert_eq!(pic.read(0x20), 0);
+    }
+
+    #[test]
+    fn pic_priority() {
+        let mut pic = Pic::default();
+        pic.set_irq(3, true);
+        pic.set_irq(1, true);
+
+        assert_eq!(pic.acknowledge(), Some(0x09));
+        // IRQ 3 waits for the EOI of IRQ 1, IRQ 0 doesn't
+        assert!(!pic.has_interrupt());
+        pic.set_irq(0, true);
+        assert_eq!(pic.acknowledge(), Some(0x08));
+        eoi(&mut pic);
+        assert!(!pic.has_interrupt());
+        eoi(&mut pic);
+        assert_eq!(pic.acknowledge(), Some(0x0B));
+
+        // specific EOI
+        pic.write(0x20, 0x63);
+        assert!(!pic.has_interrupt());
+        assert_eq!(pic.master.isr, 0);
+    }
+
+    #[test]
+    fn pic_edge_and_level() {
+        let mut pic = Pic::default();
+        pic.set_irq(4, true);
+        assert_eq!(pic.acknowledge(), Some(0x0C));
+        eoi(&mut pic);
+        // the line is still high, but there's no new edge
+        pic.set_irq(4, true);
+        assert!(!pic.has_interrupt());
+
+        // level triggered: the request lasts as long as the line is high
+        pic.write(0x20, 0x19);
+        pic.write(0x21, 0x08);
+        pic.write(0x21, 0x01);
+        pic.set_irq(4, true);
+        assert_eq!(pic.acknowledge(), Some(0x0C));
+        eoi(&mut pic);
+        assert!(pic.has_interrupt());
+        pic.set_irq(4, false);
+        assert!(!pic.has_interrupt());
+    }
+
+    #[test]
+    fn pic_cascade() {
+        let mut pic = Pic::default();
+        remap(&mut pic);
+        pic.set_irq(12, true);
+        assert!(pic.has_interrupt());
+
+        // masking the slave's line on the master holds it back
+        pic.write(0x21, 0x04);
+        assert!(!pic.has_interrupt());
+        pic.write(0x21, 0x00);
+
+        assert_eq!(pic.acknowledge(), Some(0x2C));
+        assert_eq!(pic.master.isr, 0x04);
+        assert_eq!(pic.slave.isr, 0x10);
+        pic.write(0xA0, 0x20);
+        pic.write(0x20, 0x20);
+        assert_eq!(pic.master.isr, 0);
+        assert_eq!(pic.slave.isr, 0);
+    }
+
+    #[test]
+    fn pic_rotate() {
+        let mut pic = Pic::default();
+        pic.set_irq(1, true);
+        pic.set_irq(5, true);
+        assert_eq!(pic.acknowledge(), Some(0x09));
+        // rotate on non-specific EOI: IRQ 1 becomes the lowest priority
+        pic.write(0x20, 0xA0);
+        pic.set_irq(1, false);
+        pic.set_irq(1, true);
+        assert_eq!(pic.acknowledge(), Some(0x0D));
+    }
+
+    #[test]
+    fn pic_auto_eoi() {
+        let mut pic = Pic::default();
+        pic.write(0x20, 0x13);
+        pic.write(0x21, 0x08);
+        pic.write(0x21, 0x03);
+
+        pic.set_irq(6, true);
+        assert_eq!(pic.acknowledge(), Some(0x0E));
+        assert_eq!(pic.master.isr, 0);
+    }
+
+    #[test]
+    fn pic_deliver_irq() {
+        // nop; nop
+        let mut emu = real_mode(0x10000, &[0x90, 0x90], Some(0x09));
+        emu.pulse_irq(1);
+
+        // IF is clear
+        emu.execute().unwrap();
+        assert_eq!(emu.eip, 0x101);
+
+        emu.eflags.set_interrupt(true);
+        emu.execute().unwrap();
+        assert_eq!(emu.get_segment(CS).selector, 0);
+        assert_eq!(emu.eip, 0x602);
+        assert_eq!(emu.get_memory16(0x7FFA), 0x101);
+        assert_eq!(emu.pic.master.isr, 0x02);
+
+        for _ in 0..3 {
+            emu.execute().unwrap();
+        }
+        assert_eq!(emu.eip, 0x101);
+        assert_eq!(emu.registers[3] & 0xFF, 1);
+        assert_eq!(emu.pic.master.isr, 0);
+        assert!(emu.eflags.is_interrupt());
+    }
+
+    #[test]
+    fn pic_sti_hlt() {
+        // sti; hlt; nop
+        let mut emu = real_mode(0x10000, &[0xFB, 0xF4, 0x90], Some(0x09));
+        emu.pulse_irq(1);
+
+        // the interrupt waits for the instruction after STI
+        emu.execute().unwrap();
+        assert_eq!(emu.eip, 0x101);
+        emu.execute().unwrap();
+        assert!(emu.halted);
+        assert_eq!(emu.eip, 0x102);
+
+        // a halted CPU wakes up for the interrupt and returns after HLT
+        emu.execute().unwrap();
+        assert!(!emu.halted);
+        assert_eq!(emu.eip, 0x602);
+        for _ in 0..3 {
+            emu.execute().unwrap();
+        }
+        assert_eq!(emu.eip, 0x102);
+
+        // with nothing pending it stays halted
+        emu.eip = 0x101;
+        emu.execute().unwrap();
+        assert_eq!(emu.execute(), Ok("hlt"));
+        assert!(emu.halted);
+        assert!(!emu.interrupt_pending());
+    }
+}
@@ -0,0 +1,188 @@
+extern crate aria;
+
+mod common;
+
+#[cfg(test)]
+mod pit {
+    use aria::emulator::{
+        pit::*,
+        clock::*,
+    };
+    use crate::common::*;
+
+    /// The output of channel `channel` after each of `clocks` clocks.
+    fn outputs(pit: &mut Pit, channel: usize, clocks: usize) -> Vec<bool> {
+        (0..clocks).map(|_| {
+            pit.clock();
+            pit.channels[channel].output
+        }).collect()
+    }
+
+    fn program(pit: &mut Pit, control: u8, port: u16, count: u16) {
+        pit.write(0x43, control);
+        pit.write(port, count as u8);
+        pit.write(port, (count >> 8) as u8);
+    }
+
+    #[test]
+    fn pit_interrupt_on_terminal_count() {
+        let mut pit = Pit::default();
+        pit.write(0x43, 0x30);
+        assert!(!pit.channels[0].output);
+        pit.write(0x40, 5);
+        pit.write(0x40, 0);
+
+        // the count is loaded on the first clock, the output rises N clocks later
+        assert_eq!(outputs(&mut pit, 0, 6), [false, false, false, false, false, true]);
+        assert!(outputs(&mut pit, 0, 100).iter().all(|out| *out));
+    }
+
+    #[test]
+    fn pit_rate_generator() {
+        let mut pit = Pit::default();
+        program(&mut pit, 0x34, 0x40, 4);
+        assert_eq!(outputs(&mut pit, 0, 8), [true, true, true, false, true, true, true, false]);
+
+        // a new count takes over at the end of the current period
+        pit.write(0x40, 2);
+        pit.write(0x40, 0);
+        assert_eq!(outputs(&mut pit, 0, 6), [true, false, true, false, true, false]);
+    }
+
+    #[test]
+    fn pit_square_wave() {
+        let mut pit = Pit::default();
+        program(&mut pit, 0x36, 0x40, 4);
+        assert_eq!(outputs(&mut pit, 0, 8), [true, true, false, false, true, true, false, false]);
+
+        // an odd count is high one clock longer than it's low
+        program(&mut pit, 0x36, 0x40, 5);
+        assert_eq!(outputs(&mut pit, 0, 10), [true, true, true, false, false, true, true, true, false, false]);
+    }
+
+    #[test]
+    fn pit_strobe() {
+        let mut pit = Pit::default();
+        program(&mut pit, 0x38, 0x40, 2);
+        assert_eq!(outputs(&mut pit, 0, 5), [true, true, false, true, true]);
+        assert!(!pit.channels[0].will_fire());
+    }
+
+    #[test]
+    fn pit_latch() {
+        let mut pit = Pit::default();
+        program(&mut pit, 0x34, 0x40, 100);
+        for _ in 0..11 {
+            pit.clock();
+        }
+
+        pit.write(0x43, 0x00);
+        for _ in 0..5 {
+            pit.clock();
+        }
+        assert_eq!(pit.read(0x40), 90);
+        assert_eq!(pit.read(0x40), 0);
+        // unlatched, reads follow the counter
+        assert_eq!(pit.read(0x40), 85);
+        assert_eq!(pit.read(0x40), 0);
+    }
+
+    #[test]
+    fn pit_read_back() {
+        let mut pit = Pit::default();
+        program(&mut pit, 0x34, 0x40, 0x1234);
+        // OUT, NULL count, RW 3, mode 2
+        pit.write(0x43, 0xE2);
+        assert_eq!(pit.read(0x40), 0xF4);
+
+        pit.clock();
+        pit.clock();
+        pit.write(0x43, 0xC2);
+        pit.clock();
+        assert_eq!(pit.read(0x40), 0xB4);
+        assert_eq!(pit.read(0x40), 0x33);
+        assert_eq!(pit.read(0x40), 0x12);
+    }
+
+    #[test]
+    fn pit_low_byte_bcd() {
+        let mut pit = Pit::default();
+        // channel 1, low byte only, mode 0, BCD
+        pit.write(0x43, 0x51);
+        pit.write(0x41, 0x25);
+        for _ in 0..11 {
+            pit.clock();
+        }
+        assert_eq!(pit.read(0x41), 0x15);
+        assert_eq!(pit.read(0x41), 0x15);
+    }
+
+    #[test]
+    fn pit_gate() {
+        let mut pit = Pit::default();
+        // channel 2, one-shot: nothing happens until the gate rises
+        program(&mut pit, 0xB2, 0x42, 3);
+        assert_eq!(outputs(&mut pit, 2, 3), [true, true, true]);
+        assert_eq!(pit.read(0x61) & 0x21, 0x20);
+
+        pit.write(0x61, 0x01);
+        assert_eq!(outputs(&mut pit, 2, 5), [false, false, false, true, true]);
+        assert_eq!(pit.read(0x61) & 0x21, 0x21);
+    }
+
+    #[test]
+    fn pit_clock() {
+        let clock = Clock {
+            cycles: CPU_FREQUENCY,
+            ..Default::default()
+        };
+        assert_eq!(clock.pit_ticks(), PIT_FREQUENCY);
+        assert_eq!(clock.nanoseconds(), 1_000_000_000);
+    }
+
+    /// Rate generator on channel 0 with a count of 100, then spin with interrupts enabled.
+    const TIMER_LOOP: [u8; 16] = [
+        0xB0, 0x34, 0xE6, 0x43,     // mov al, 0x34; out 0x43, al
+        0xB0, 0x64, 0xE6, 0x40,     // mov al, 100; out 0x40, al
+        0xB0, 0x00, 0xE6, 0x40,     // mov al, 0; out 0x40, al
+        0xFB, 0xEB, 0xFE, 0x90,     // sti; jmp $
+    ];
+
+    fn timer_interrupts(cycles_per_instruction: u64) -> u32 {
+        let mut emu = real_mode(0x10000, &TIMER_LOOP, Some(0x08));
+        emu.clock.cycles_per_instruction = cycles_per_instruction;
+        for _ in 0..1000 {
+            emu.execute().unwrap();
+        }
+        emu.registers[3] & 0xFF
+    }
+
+    #[test]
+    fn pit_irq0() {
+        // one PIT tick per instruction: IRQ 0 every 100 instructions, give or take the handler's
+        let interrupts = timer_interrupts(4);
+        assert_eq!(interrupts, 9);
+        assert_eq!(timer_interrupts(4), interrupts);
+        assert!(timer_interrupts(8) > interrupts * 2 - 2);
+    }
+
+    #[test]
+    fn pit_hlt_waits_for_the_timer() {
+        // sti; hlt; jmp hlt
+        let mut emu = real_mode(0x10000, &[], None);
+        emu.install_ivt();
+        // the IVT covers 0x100, the code goes past it
+        emu.memory[0x500 .. 0x504].copy_from_slice(&[0xFB, 0xF4, 0xEB, 0xFD]);
+        emu.eip = 0x500;
+        // BIOS default: IRQ 0 every 0x10000 ticks, counted by the BIOS handler
+        for _ in 0..0x10000 * 3 + 100 {
+            emu.execute().unwrap();
+            assert!(!emu.halted || emu.can_wake());
+        }
+        assert_eq!(emu.read_physical32(0x46C), 3);
+        assert!(emu.instruction_count < 100);
+
+        emu.eflags.set_interrupt(false);
+        assert!(!emu.can_wake());
+    }
+}
@@ -0,0 +1,164 @@
+extern crate aria;
+
+mod common;
+
+#[cfg(test)]
+mod segment {
+    use aria::emulator::{
+        *,
+        segment::Segment,
+        SegmentRegister::*,
+    };
+    use crate::common::*;
+
+    #[test]
+    fn segment_default_flat() {
+        let emu = Emulator::default();
+        assert_eq!(emu.get_segment(CS), Segment::default());
+        assert_eq!(emu.get_segment(CS).limit, 0xFFFFFFFF);
+        assert_eq!(emu.operand_bits(), 32);
+        assert_eq!(emu.address_bits(), 32);
+    }
+
+    #[test]
+    fn segment_real_operand_size() {
+        // mov ax, 0x1234; mov eax, 0x12345678
+        let mut emu = real_mode(0x30000, &[0xB8, 0x34, 0x12, 0x66, 0xB8, 0x78, 0x56, 0x34, 0x12], None);
+        emu.registers[0] = 0xFFFF0000;
+
+        emu.step().unwrap();
+        assert_eq!(emu.registers[0], 0xFFFF1234);
+        assert_eq!(emu.eip, 0x103);
+        emu.step().unwrap();
+        assert_eq!(emu.registers[0], 0x12345678);
+        assert_eq!(emu.eip, 0x109);
+    }
+
+    #[test]
+    fn segment_real_data_segment() {
+        // mov ax, 0x1000; mov ds, ax; mov al, [0x0010]
+        let mut emu = real_mode(0x30000, &[0xB8, 0x00, 0x10, 0x8E, 0xD8, 0xA0, 0x10, 0x00], None);
+        emu.memory[0x10010] = 0x42;
+
+        for _ in 0..3 {
+            emu.step().unwrap();
+        }
+        assert_eq!(emu.get_segment(DS), Segment::real(0x1000));
+        assert_eq!(emu.get_segment(DS).base, 0x10000);
+        assert_eq!(emu.registers[0] & 0xFF, 0x42);
+    }
+
+    #[test]
+    fn segment_real_modrm16() {
+        // mov al, [bx+si+4]; mov cx, [bp+di] (SS); mov dl, es:[bx]
+        let mut emu = real_mode(0x30000, &[0x8A, 0x40, 0x04, 0x8B, 0x0B, 0x26, 0x8A, 0x17], None);
+        emu.load_segment(SS, 0x2000);
+        emu.load_segment(ES, 0x1000);
+        emu.registers[3] = 0x100;   // BX
+        emu.registers[5] = 0x10;    // BP
+        emu.registers[6] = 0x20;    // SI
+        emu.registers[7] = 0x2;     // DI
+        emu.memory[0x124] = 0x11;
+        emu.memory[0x20012] = 0x34;
+        emu.memory[0x20013] = 0x12;
+        emu.memory[0x10100] = 0x56;
+
+        for _ in 0..3 {
+            emu.step().unwrap();
+        }
+        assert_eq!(emu.registers[0] & 0xFF, 0x11);
+        assert_eq!(emu.registers[1], 0x1234);
+        assert_eq!(emu.registers[2], 0x56);
+        assert_eq!(emu.eip, 0x108);
+    }
+
+    #[test]
+    fn segment_real_stack_wraps() {
+        // push cs; pop ds
+        let mut emu = real_mode(0x30000, &[0x0E, 0x1F], None);
+        emu.load_segment(CS, 0);
+        emu.load_segment(SS, 0x2000);
+        emu.registers[4] = 0;
+
+        emu.step().unwrap();
+        assert_eq!(emu.registers[4], 0xFFFE);
+        assert_eq!(emu.get_memory16(0x2FFFE), 0);
+        emu.step().unwrap();
+        assert_eq!(emu.registers[4], 0);
+        assert_eq!(emu.get_segment(DS).selector, 0);
+    }
+
+    #[test]
+    fn segment_far_jump() {
+        // jmp 0x2000:0x0100
+        let mut emu = real_mode(0x30000, &[0xEA, 0x00, 0x01, 0x00, 0x20], None);
+        emu.memory[0x20100] = 0x90;
+
+        emu.step().unwrap();
+        assert_eq!(emu.get_segment(CS).base, 0x20000);
+        assert_eq!(emu.eip, 0x100);
+        assert_eq!(emu.get_code8(0), 0x90);
+    }
+
+    #[test]
+    fn segment_jump_to_offset_0() {
+        // jmp 0x0800:0000, the way a boot sector hands over to what it loaded
+        let mut emu = real_mode(0x30000, &[0xEA, 0x00, 0x00, 0x00, 0x08], None);
+        assert!(!emu.is_end_of_program());
+
+        emu.step().unwrap();
+        assert_eq!(emu.eip, 0);
+        assert!(!emu.is_end_of_program());
+
+        emu.segments[CS as usize] = Segment::real(0);
+        assert!(emu.is_end_of_program());
+    }
+
+    #[test]
+    fn segment_far_call_ret() {
+        // call 0x1000:0x0000, which is retf 2
+        let mut emu = real_mode(0x30000, &[0x9A, 0x00, 0x00, 0x00, 0x10], None);
+        emu.memory[0x10000] = 0xCA;
+        emu.memory[0x10001] = 0x02;
+
+        emu.step().unwrap();
+        assert_eq!(emu.get_segment(CS).selector, 0x1000);
+        assert_eq!(emu.eip, 0);
+        assert_eq!(emu.registers[4], 0x7FFC);
+        assert_eq!(emu.get_memory16(0x7FFC), 0x105);
+        assert_eq!(emu.get_memory16(0x7FFE), 0x0000);
+
+        emu.step().unwrap();
+        assert_eq!(emu.get_segment(CS).selector, 0);
+        assert_eq!(emu.eip, 0x105);
+        assert_eq!(emu.registers[4], 0x8002);
+    }
+
+    #[test]
+    fn segment_lds() {
+        // lds si, [0x0200]
+        let mut emu = real_mode(0x30000, &[0xC5, 0x36, 0x00, 0x02], None);
+        emu.memory[0x200 .. 0x204].copy_from_slice(&[0x34, 0x12, 0x00, 0x20]);
+
+        emu.step().unwrap();
+        assert_eq!(emu.registers[6], 0x1234);
+        assert_eq!(emu.get_segment(DS).base, 0x20000);
+    }
+
+    #[test]
+    fn segment_real_lodsb_loop() {
+        // mov cx, 3; lodsb; add bl, al; loop -5; hlt
+        let mut emu = real_mode(0x30000, &[0xB9, 0x03, 0x00, 0xAC, 0x00, 0xC3, 0xE2, 0xFB, 0xF4], None);
+        emu.load_segment(DS, 0x1000);
+        emu.registers[6] = 0x10;
+        emu.memory[0x10010 .. 0x10013].copy_from_slice(&[1, 2, 3]);
+
+        while !emu.halted {
+            emu.step().unwrap();
+        }
+        assert_eq!(emu.registers[3], 6);
+        assert_eq!(emu.registers[1], 0);
+        assert_eq!(emu.registers[6], 0x13);
+        assert_eq!(emu.eip, 0x109);
+    }
+}
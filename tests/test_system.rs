@@ -0,0 +1,111 @@
+extern crate aria;
+
+mod common;
+
+#[cfg(test)]
+mod system {
+    use aria::emulator::{
+        *,
+        bios::system::*,
+    };
+    use crate::common::*;
+
+    /// INT 15h with EAX, EBX, ECX and EDX, returning EAX and CF.
+    fn int15(emu: &mut Emulator, eax: u32, ebx: u32, ecx: u32, edx: u32) -> (u32, bool) {
+        bios_call(emu, Emulator::bios_system, &[eax, ecx, edx, ebx]);
+        (emu.registers[0], emu.eflags.is_carry())
+    }
+
+    #[test]
+    fn system_memory_size() {
+        let mut emu = with_bios(0x10000);
+        emu.bios_memory_size();
+        assert_eq!(emu.get_register16(0), 64);
+
+        let mut emu = with_bios(0x1000000 + 0x30000);
+        emu.bios_memory_size();
+        assert_eq!(emu.get_register16(0), 640);
+        assert_eq!(int15(&mut emu, 0x8800, 0, 0, 0), (15 * 1024 + 192, false));
+        assert_eq!(int15(&mut emu, 0xE801, 0, 0, 0), (15 * 1024, false));
+        assert_eq!(emu.get_register16(1), 15 * 1024);
+        assert_eq!(emu.get_register16(3), 3);
+        assert_eq!(emu.get_register16(2), 3);
+
+        // nothing above 1 MB
+        let mut emu = with_bios(0x10000);
+        assert_eq!(int15(&mut emu, 0x8800, 0, 0, 0), (0, false));
+        assert_eq!(int15(&mut emu, 0xC000, 0, 0, 0), (0x8600, true));
+    }
+
+    #[test]
+    fn system_equipment() {
+        let mut emu = with_bios(0x10000);
+        emu.bios_equipment();
+        assert_eq!(emu.get_register16(0), 0x0820);
+        assert_eq!(emu.read_physical16(0x400), 0x3F8);
+        assert_eq!(emu.read_physical16(0x406), 0x2E8);
+
+        let mut emu = Emulator {
+            memory: vec![0; 0x10000],
+            ..Default::default()
+        };
+        emu.disks.attach(0x00, disk::Disk::floppy(std::rc::Rc::new(std::cell::RefCell::new(vec![0; 1474560]))));
+        emu.disks.attach(0x01, disk::Disk::floppy(std::rc::Rc::new(std::cell::RefCell::new(vec![0; 1474560]))));
+        emu.install_bios();
+        emu.bios_equipment();
+        assert_eq!(emu.get_register16(0), 0x0861);
+    }
+
+    #[test]
+    fn system_e820() {
+        let mut emu = with_bios(0x200000);
+        assert_eq!(emu.memory_map(), [(0, 0xA0000, E820_USABLE), (0xF0000, 0x10000, E820_RESERVED), (0x100000, 0x100000, E820_USABLE)]);
+
+        let mut entries = vec![];
+        let mut next = 0;
+        loop {
+            emu.set_register16(7, 0x500);
+            assert_eq!(int15(&mut emu, 0xE820, next, 24, 0x534D4150), (0x534D4150, false));
+            assert_eq!(emu.registers[1], 20);
+            entries.push((emu.get_memory32(0x500), emu.get_memory32(0x508), emu.get_memory32(0x510)));
+            next = emu.registers[3];
+            if next == 0 {
+                break;
+            }
+        }
+        assert_eq!(entries, [(0, 0xA0000, 1), (0xF0000, 0x10000, 2), (0x100000, 0x100000, 1)]);
+
+        // no signature, too small a buffer, past the last entry
+        assert!(int15(&mut emu, 0xE820, 0, 20, 0).1);
+        assert!(int15(&mut emu, 0xE820, 0, 16, 0x534D4150).1);
+        assert_eq!(int15(&mut emu, 0xE820, 3, 20, 0x534D4150).0 >> 8 & 0xFF, 0x86);
+    }
+
+    #[test]
+    fn system_a20() {
+        let mut emu = with_bios(0x110000);
+        assert!(emu.a20_enabled());
+        emu.write_physical8(0x100500, 0xAA);
+        assert_eq!(emu.read_physical8(0x500), 0);
+
+        // closed, FFFF:0510 is 0000:0500
+        assert_eq!(int15(&mut emu, 0x2400, 0, 0, 0), (0x0000, false));
+        assert_eq!(int15(&mut emu, 0x2402, 0, 0, 0), (0x0000, false));
+        assert_eq!(emu.read_physical8(0x100500), 0);
+        emu.write_physical8(0x100500, 0x55);
+        assert_eq!(emu.read_physical8(0x500), 0x55);
+        assert_eq!(int15(&mut emu, 0x2403, 0, 0, 0), (0x0003, false));
+        assert_eq!(emu.registers[3], 3);
+
+        // opened by the fast gate, then by the keyboard controller
+        emu.port_out8(0x92, 0x02);
+        assert_eq!(emu.read_physical8(0x100500), 0xAA);
+        assert_eq!(emu.port_in8(0x92), 0x02);
+        emu.port_out8(0x92, 0x00);
+        assert!(!emu.a20_enabled());
+        emu.port_out8(0x64, 0xD1);
+        emu.port_out8(0x60, 0xDF);
+        assert!(emu.a20_enabled());
+        assert_eq!(int15(&mut emu, 0x2402, 0, 0, 0), (0x0001, false));
+    }
+}
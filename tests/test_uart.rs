@@ -0,0 +1,175 @@
+extern crate aria;
+
+#[cfg(test)]
+mod uart {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use aria::emulator::{
+        *,
+        uart::*,
+    };
+
+    fn com1() -> (Uart, Rc<RefCell<Buffer>>) {
+        let mut uart = Uart::new(0x3F8, 4);
+        let buffer = Rc::new(RefCell::new(Buffer::default()));
+        uart.attach(buffer.clone());
+        (uart, buffer)
+    }
+
+    /// Clock through `characters` character times at the default 9600 baud.
+    fn wait(uart: &mut Uart, characters: usize) {
+        for _ in 0..characters * 1243 {
+            uart.clock();
+        }
+    }
+
+    #[test]
+    fn uart_registers() {
+        let (mut uart, _) = com1();
+        // 115200 baud, 8N1
+        uart.write(3, 0x80);
+        uart.write(0, 0x01);
+        uart.write(1, 0x00);
+        assert_eq!(uart.read(0), 0x01);
+        assert_eq!(uart.read(1), 0x00);
+        uart.write(3, 0x03);
+        assert_eq!(uart.read(3), 0x03);
+        assert_eq!(uart.read(1), 0x00);
+
+        uart.write(7, 0x5A);
+        assert_eq!(uart.read(7), 0x5A);
+        assert_eq!(uart.read(2), 0x01);
+        uart.write(2, 0xC7);
+        assert_eq!(uart.read(2), 0xC1);
+        // connected: CTS, DSR, DCD
+        assert_eq!(uart.read(6), 0xB0);
+    }
+
+    #[test]
+    fn uart_polled() {
+        let (mut uart, buffer) = com1();
+        uart.write(0, b'o');
+        uart.write(0, b'k');
+        assert_eq!(buffer.borrow().output, b"ok");
+        assert_eq!(uart.read(5), 0x60);
+
+        buffer.borrow_mut().input.extend(b"hi");
+        assert_eq!(uart.read(5), 0x61);
+        assert_eq!(uart.read(0), b'h');
+        assert_eq!(uart.read(5), 0x61);
+        assert_eq!(uart.read(0), b'i');
+        assert_eq!(uart.read(5), 0x60);
+    }
+
+    #[test]
+    fn uart_fifo_overrun() {
+        let (mut uart, buffer) = com1();
+        // loopback: what's sent comes back, not to the backend
+        uart.write(4, 0x10);
+        uart.write(0, 1);
+        uart.write(0, 2);
+        assert!(buffer.borrow().output.is_empty());
+        assert_eq!(uart.read(5), 0x63);
+        assert_eq!(uart.read(5), 0x61);
+        assert_eq!(uart.read(0), 1);
+
+        uart.write(2, 0x01);
+        for i in 0..17 {
+            uart.write(0, i);
+        }
+        assert_eq!(uart.read(5) & 0x03, 0x03);
+        for i in 0..16 {
+            assert_eq!(uart.read(0), i);
+        }
+        assert_eq!(uart.read(5), 0x60);
+    }
+
+    #[test]
+    fn uart_interrupts() {
+        let (mut uart, buffer) = com1();
+        uart.write(4, 0x08);
+        uart.write(1, 0x01);
+        assert!(!uart.interrupt());
+
+        buffer.borrow_mut().input.push_back(b'a');
+        wait(&mut uart, 1);
+        assert!(uart.interrupt());
+        assert_eq!(uart.read(2), 0x04);
+        assert_eq!(uart.read(0), b'a');
+        assert!(!uart.interrupt());
+
+        // THR empty: reading IIR acknowledges it, writing THR arms it again
+        uart.write(1, 0x02);
+        assert_eq!(uart.read(2), 0x02);
+        assert_eq!(uart.read(2), 0x01);
+        uart.write(0, b'!');
+        assert!(uart.interrupt());
+        assert_eq!(uart.read(2), 0x02);
+
+        // OUT2 is what connects the interrupt to the PIC
+        uart.write(1, 0x02);
+        uart.write(0, b'!');
+        uart.write(4, 0x00);
+        assert!(!uart.interrupt());
+    }
+
+    #[test]
+    fn uart_timeout() {
+        let (mut uart, buffer) = com1();
+        uart.write(4, 0x08);
+        uart.write(2, 0xC1);
+        uart.write(1, 0x01);
+        buffer.borrow_mut().input.extend(b"ab");
+
+        // below the trigger level of 14: nothing until a character time passes without one
+        wait(&mut uart, 2);
+        assert_eq!(uart.read(2), 0xC1);
+        wait(&mut uart, 1);
+        assert_eq!(uart.read(2), 0xCC);
+        assert_eq!(uart.read(0), b'a');
+        assert_eq!(uart.read(2), 0xC1);
+    }
+
+    #[test]
+    fn uart_modem_status() {
+        let (mut uart, _) = com1();
+        uart.read(6);
+        uart.write(4, 0x10);
+        assert_eq!(uart.read(6), 0x0B);
+        uart.write(4, 0x1F);
+        assert_eq!(uart.read(6), 0xFB);
+        assert_eq!(uart.read(6), 0xF0);
+        uart.write(4, 0x1B);
+        assert_eq!(uart.read(6), 0xB4);
+    }
+
+    #[test]
+    fn uart_irq4() {
+        let mut emu = Emulator {
+            memory: vec![0; 0x10000],
+            eip: 0x100,
+            ..Default::default()
+        };
+        emu.enter_real_mode();
+        emu.registers[2] = 0x3F8;
+        emu.registers[3] = 0x700;
+        emu.registers[4] = 0x8000;
+        // sti; jmp $
+        emu.memory[0x100 .. 0x103].copy_from_slice(&[0xFB, 0xEB, 0xFE]);
+        // in al, dx; mov [bx], al; inc bx; mov al, 0x20; out 0x20, al; iret
+        emu.set_memory32(0x0C * 4, 0x0000_0600);
+        emu.memory[0x600 .. 0x60A].copy_from_slice(&[0xEC, 0x88, 0x07, 0x43, 0xB0, 0x20, 0xE6, 0x20, 0xCF, 0x90]);
+
+        let buffer = Rc::new(RefCell::new(Buffer::default()));
+        buffer.borrow_mut().input.extend(b"ok");
+        emu.serial.ports[0].attach(buffer.clone());
+        emu.port_out8(0x3F9, 0x01);
+        emu.port_out8(0x3FC, 0x08);
+
+        for _ in 0..3000 {
+            emu.execute().unwrap();
+        }
+        assert_eq!(&emu.memory[0x700 .. 0x702], b"ok");
+        assert_eq!(emu.registers[3] & 0xFFFF, 0x702);
+    }
+}
@@ -0,0 +1,152 @@
+extern crate aria;
+
+#[cfg(test)]
+mod vga {
+    use aria::emulator::{
+        *,
+        vga::*,
+    };
+
+    /// Real mode with the BIOS, running a loop that prints the string at 0x600 with INT 10h teletype.
+    fn teletype(text: &[u8]) -> Emulator {
+        let mut emu = Emulator {
+            memory: vec![0; 0x10000],
+            eip: 0x500,
+            ..Default::default()
+        };
+        emu.enter_real_mode();
+        emu.install_ivt();
+        emu.registers[4] = 0x8000;
+        // mov si, 0x600; lodsb; test al, al; jz +6; mov ah, 0x0E; int 0x10; jmp -11; hlt
+        emu.memory[0x500 .. 0x50F].copy_from_slice(&[
+            0xBE, 0x00, 0x06, 0xAC, 0x84, 0xC0, 0x74, 0x06, 0xB4, 0x0E, 0xCD, 0x10, 0xEB, 0xF5, 0xF4,
+        ]);
+        emu.memory[0x600 .. 0x600 + text.len()].copy_from_slice(text);
+        emu
+    }
+
+    fn run(emu: &mut Emulator) {
+        for _ in 0..10000 {
+            if emu.halted {
+                return;
+            }
+            emu.execute().unwrap();
+        }
+        panic!("still running");
+    }
+
+    #[test]
+    fn vga_text_memory() {
+        let mut emu = Emulator {
+            memory: vec![0; 0x1000],
+            ..Default::default()
+        };
+        assert_eq!(emu.vga.screen().text(), "");
+        assert_eq!(emu.vga.screen().cursor, Some((0, 0)));
+
+        emu.write_physical32(VGA_TEXT_BASE, 0x1E691E48);
+        emu.write_physical8(VGA_TEXT_BASE + 160 * 2 + 4, b'!');
+        let screen = emu.vga.screen();
+        assert_eq!(screen.text(), "Hi\n\n  !");
+        assert_eq!(screen.attribute(0, 1), 0x1E);
+        assert_eq!(screen.attribute(2, 2), DEFAULT_ATTRIBUTE);
+        assert_eq!(emu.read_physical8(VGA_TEXT_BASE + 2), b'i');
+
+        // the displayed page follows the start address
+        emu.write_physical8(VGA_TEXT_BASE + PAGE_SIZE, b'2');
+        emu.vga.set_start_address((PAGE_SIZE / 2) as u16);
+        assert_eq!(emu.vga.screen().text(), "2");
+        assert_eq!(emu.vga.screen().cursor, None);
+    }
+
+    #[test]
+    fn vga_crtc_ports() {
+        let mut emu = Emulator {
+            memory: vec![0; 0x1000],
+            ..Default::default()
+        };
+        emu.port_out8(0x3D4, 0x0E);
+        emu.port_out8(0x3D5, 0x00);
+        emu.port_out8(0x3D4, 0x0F);
+        emu.port_out8(0x3D5, 83);
+        assert_eq!(emu.vga.cursor_address(), 83);
+        assert_eq!(emu.vga.screen().cursor, Some((1, 3)));
+        assert_eq!(emu.port_in8(0x3D5), 83);
+
+        // a cursor that starts at scan line 0x20 is hidden
+        emu.port_out8(0x3D4, 0x0A);
+        emu.port_out8(0x3D5, 0x20);
+        assert_eq!(emu.vga.screen().cursor, None);
+
+        // retrace comes and goes as it's polled
+        let status = emu.port_in8(0x3DA) & 0x08;
+        assert_ne!(emu.port_in8(0x3DA) & 0x08, status);
+    }
+
+    #[test]
+    fn vga_teletype() {
+        let mut emu = teletype(b"hello\r\nworld\x07\r\nab\x08c");
+        run(&mut emu);
+        let screen = emu.vga.screen();
+        assert_eq!(screen.text(), "hello\nworld\nac");
+        assert_eq!(screen.cursor, Some((2, 2)));
+        assert_eq!(emu.read_physical8(0x450), 2);
+        assert_eq!(emu.read_physical8(0x451), 2);
+
+        // the cursor through the CRTC
+        emu.port_out8(0x3D4, 0x0F);
+        assert_eq!(emu.port_in8(0x3D5), 162);
+    }
+
+    #[test]
+    fn vga_teletype_wrap_and_scroll() {
+        let mut emu = teletype(b"xy\r\nz");
+        emu.vga.put(0, 1, 0, b'1', 0x07);
+        emu.vga.put(0, 2, 0, b'2', 0x07);
+        emu.vga.put(0, 24, 79, b' ', 0x4F);
+        // the cursor at the last cell of the screen
+        emu.write_physical8(0x450, 79);
+        emu.write_physical8(0x451, 24);
+        run(&mut emu);
+
+        let screen = emu.vga.screen();
+        // two lines scrolled off the top
+        assert_eq!(screen.row(0), "2");
+        assert_eq!(screen.row(21), "");
+        assert_eq!(screen.row(22), format!("{:>80}", "x"));
+        assert_eq!(screen.row(23), "y");
+        assert_eq!(screen.row(24), "z");
+        assert_eq!(screen.attribute(22, 79), 0x4F);
+        // the blank lines scrolled in take the attribute under the cursor
+        assert_eq!(screen.attribute(24, 1), 0x07);
+        assert_eq!(screen.cursor, Some((24, 1)));
+    }
+
+    #[test]
+    fn vga_render() {
+        let mut vga = Vga::default();
+        assert!(vga.take_dirty());
+        vga.put(0, 0, 0, b'o', 0x1F);
+        vga.put(0, 0, 1, b'k', 0x1F);
+        vga.put(0, 0, 2, 0xDB, 0x07);
+        assert!(vga.take_dirty());
+        assert!(!vga.take_dirty());
+
+        let mut out = Vec::new();
+        vga.render(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\x1b[97;44mok\x1b[37;40m█"));
+        assert!(out.ends_with("\x1b[1;1H\x1b[?25h"));
+    }
+
+    #[test]
+    fn vga_cp437() {
+        assert_eq!(cp437(b'A'), 'A');
+        assert_eq!(cp437(0x01), '☺');
+        assert_eq!(cp437(0x7F), '⌂');
+        assert_eq!(cp437(0xB0), '░');
+        assert_eq!(cp437(0xE1), 'ß');
+        assert_eq!(cp437(0xFE), '■');
+        assert_eq!(cp437(0xFF), '\u{a0}');
+    }
+}
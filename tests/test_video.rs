@@ -0,0 +1,154 @@
+extern crate aria;
+
+mod common;
+
+#[cfg(test)]
+mod video {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use aria::emulator::{
+        *,
+        uart::Buffer,
+        vga::*,
+    };
+    use crate::common::*;
+
+    fn emulator() -> Emulator {
+        with_bios(0x10000)
+    }
+
+    /// INT 10h with AX, BX, CX and DX, returning them afterwards.
+    fn int10(emu: &mut Emulator, ax: u16, bx: u16, cx: u16, dx: u16) -> [u16; 4] {
+        bios_call(emu, Emulator::bios_video, &[ax as u32, cx as u32, dx as u32, bx as u32]);
+        [emu.get_register16(0), emu.get_register16(3), emu.get_register16(1), emu.get_register16(2)]
+    }
+
+    #[test]
+    fn video_mode() {
+        let mut emu = emulator();
+        assert_eq!(int10(&mut emu, 0x0F00, 0, 0, 0)[..2], [0x5003, 0x0000]);
+        assert_eq!(emu.read_physical8(0x484), 24);
+
+        emu.vga.put(0, 3, 3, b'x', 0x1F);
+        int10(&mut emu, 0x0200, 0x0000, 0, 0x0505);
+        // bit 7 keeps what's on the screen
+        int10(&mut emu, 0x0082, 0, 0, 0);
+        assert_eq!(emu.vga.screen().text(), "\n\n\n   x");
+        assert_eq!(emu.vga.screen().cursor, Some((0, 0)));
+        assert_eq!(int10(&mut emu, 0x0F00, 0, 0, 0)[0], 0x5002);
+
+        int10(&mut emu, 0x0003, 0, 0, 0);
+        assert_eq!(emu.vga.screen().text(), "");
+
+        // graphics modes aren't there
+        int10(&mut emu, 0x0013, 0, 0, 0);
+        assert_eq!(int10(&mut emu, 0x0F00, 0, 0, 0)[0], 0x5003);
+    }
+
+    #[test]
+    fn video_cursor() {
+        let mut emu = emulator();
+        assert_eq!(int10(&mut emu, 0x0300, 0, 0, 0)[2..], [0x0607, 0x0000]);
+
+        int10(&mut emu, 0x0200, 0x0000, 0, 0x0A14);
+        int10(&mut emu, 0x0100, 0, 0x000F, 0);
+        assert_eq!(int10(&mut emu, 0x0300, 0, 0, 0)[2..], [0x000F, 0x0A14]);
+        assert_eq!(emu.vga.cursor_shape(), (0x00, 0x0F));
+        assert_eq!(emu.vga.screen().cursor, Some((10, 20)));
+
+        // the cursor of a page that isn't displayed doesn't move the one on the screen
+        int10(&mut emu, 0x0200, 0x0100, 0, 0x0102);
+        assert_eq!(emu.vga.screen().cursor, Some((10, 20)));
+        int10(&mut emu, 0x0501, 0, 0, 0);
+        assert_eq!(emu.vga.screen().cursor, Some((1, 2)));
+        assert_eq!(int10(&mut emu, 0x0F00, 0, 0, 0)[1], 0x0100);
+        assert_eq!(emu.vga.start_address(), 0x800);
+
+        int10(&mut emu, 0x0100, 0, 0x2000, 0);
+        assert_eq!(emu.vga.screen().cursor, None);
+    }
+
+    #[test]
+    fn video_character() {
+        let mut emu = emulator();
+        int10(&mut emu, 0x0200, 0, 0, 0x004E);
+        // four of them from column 78, on to the next line
+        int10(&mut emu, 0x0941, 0x001E, 4, 0);
+        let screen = emu.vga.screen();
+        assert_eq!(screen.row(0), format!("{:>80}", "AA"));
+        assert_eq!(screen.row(1), "AA");
+        assert_eq!(screen.attribute(1, 1), 0x1E);
+        assert_eq!(screen.cursor, Some((0, 78)));
+
+        // the character only, and back
+        int10(&mut emu, 0x0A42, 0, 1, 0);
+        assert_eq!(int10(&mut emu, 0x0800, 0, 0, 0)[0], 0x1E42);
+        int10(&mut emu, 0x0200, 0, 0, 0x0000);
+        assert_eq!(int10(&mut emu, 0x0800, 0, 0, 0)[0], 0x0720);
+    }
+
+    #[test]
+    fn video_scroll() {
+        let mut emu = emulator();
+        for row in 0..5 {
+            emu.vga.put(0, row, 1, b'0' + row as u8, DEFAULT_ATTRIBUTE);
+        }
+        // up a line, in the window of rows 1-3 and columns 0-9
+        int10(&mut emu, 0x0601, 0x1F00, 0x0100, 0x0309);
+        assert_eq!(emu.vga.screen().text(), " 0\n 2\n 3\n\n 4");
+        assert_eq!(emu.vga.screen().attribute(3, 9), 0x1F);
+        assert_eq!(emu.vga.screen().attribute(3, 10), DEFAULT_ATTRIBUTE);
+
+        // down two in the whole screen, which the window is clipped to
+        int10(&mut emu, 0x0702, 0x0700, 0x0000, 0xFFFF);
+        assert_eq!(emu.vga.screen().text(), "\n\n 0\n 2\n 3\n\n 4");
+
+        // 0 lines blanks the window
+        int10(&mut emu, 0x0600, 0x4700, 0x0000, 0x184F);
+        let screen = emu.vga.screen();
+        assert_eq!(screen.text(), "");
+        assert!(screen.attributes.iter().all(|attribute| *attribute == 0x47));
+    }
+
+    #[test]
+    fn video_write_string() {
+        let mut emu = emulator();
+        emu.load_segment(SegmentRegister::ES, 0x100);
+        emu.set_register16(Register::EBP as usize, 0x10);
+        emu.memory[0x1010 .. 0x1017].copy_from_slice(b"ab\r\ncd!");
+
+        // attribute BL, the cursor back where it was
+        int10(&mut emu, 0x1300, 0x002F, 7, 0x0203);
+        let screen = emu.vga.screen();
+        assert_eq!(screen.text(), "\n\n   ab\ncd!");
+        assert_eq!(screen.attribute(3, 0), 0x2F);
+        assert_eq!(screen.cursor, Some((0, 0)));
+
+        // characters and attributes, the cursor after them
+        emu.memory[0x1010 .. 0x1014].copy_from_slice(&[b'x', 0x4E, b'y', 0x1F]);
+        int10(&mut emu, 0x1303, 0x0000, 2, 0x1800);
+        let screen = emu.vga.screen();
+        assert_eq!(screen.row(24), "xy");
+        assert_eq!(screen.attribute(24, 0), 0x4E);
+        assert_eq!(screen.attribute(24, 1), 0x1F);
+        assert_eq!(screen.cursor, Some((24, 2)));
+    }
+
+    #[test]
+    fn video_console() {
+        let mut emu = emulator();
+        let buffer = Rc::new(RefCell::new(Buffer::default()));
+        emu.serial.ports[0].attach(buffer.clone());
+        // the guest is programming the divisor
+        emu.port_out8(0x3FB, 0x80);
+        emu.port_out8(0x3F8, 0x0C);
+
+        int10(&mut emu, 0x0E41, 0x0000, 0, 0);
+        emu.load_segment(SegmentRegister::ES, 0x100);
+        emu.memory[0x1000 .. 0x1002].copy_from_slice(b"bc");
+        int10(&mut emu, 0x1300, 0x0007, 2, 0);
+        assert_eq!(buffer.borrow().output, b"Abc");
+        assert_eq!(emu.port_in8(0x3F8), 0x0C);
+        assert_eq!(emu.vga.screen().text(), "bc");
+    }
+}